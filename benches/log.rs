@@ -0,0 +1,69 @@
+//! Timings of parsing (copying records, and borrowing them), fixing and serializing a synthetic
+//! 100,000-record log, to catch performance regressions.
+//!
+//! Run with `cargo bench`. Each step runs `BENCH_RUNS` times (10 by default) and the fastest and
+//! median times are printed; with `BENCH_OUTPUT=PATH` they're also written there as TSV, for CI
+//! to keep.
+
+use std::time::{Duration, Instant};
+
+use scrobble_fix::generate::Generator;
+use scrobble_fix::{parse_borrowed_records, parse_log, serialize_log, Pipeline};
+
+/// Time `step` over `runs` runs, giving the fastest and the median.
+fn time<T>(runs: usize, mut step: impl FnMut() -> T) -> (Duration, Duration) {
+    let mut times: Vec<Duration> = (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(step());
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    (times[0], times[times.len() / 2])
+}
+
+fn main() {
+    let runs = std::env::var("BENCH_RUNS")
+        .ok()
+        .and_then(|runs| runs.parse().ok())
+        .filter(|&runs| runs > 0)
+        .unwrap_or(10);
+    let log = Generator {
+        scrobbles: 100_000,
+        resets: 3,
+        ..Generator::default()
+    }
+    .generate();
+    let scrobbles = parse_log(&log).expect("the generated log parses");
+    let pipeline = Pipeline::default();
+
+    let results = [
+        ("parse", time(runs, || parse_log(&log))),
+        (
+            "borrow",
+            time(runs, || parse_borrowed_records(&log).count()),
+        ),
+        ("fix", time(runs, || pipeline.run(scrobbles.clone()))),
+        ("serialize", time(runs, || serialize_log(&scrobbles))),
+    ];
+    let mut tsv = String::from("step\trecords\tfastest_ms\tmedian_ms\n");
+    for (step, (fastest, median)) in results {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        println!(
+            "{step:<10} {:>10.2} ms fastest {:>10.2} ms median ({runs} runs of {} records)",
+            ms(fastest),
+            ms(median),
+            scrobbles.len()
+        );
+        tsv.push_str(&format!(
+            "{step}\t{}\t{:.3}\t{:.3}\n",
+            scrobbles.len(),
+            ms(fastest),
+            ms(median)
+        ));
+    }
+    if let Some(path) = std::env::var_os("BENCH_OUTPUT") {
+        std::fs::write(&path, tsv).expect("benchmark results written");
+    }
+}
@@ -0,0 +1,37 @@
+/* C bindings for scrobble-fix, built with `cargo build --release --features ffi`. */
+#ifndef SCROBBLE_FIX_H
+#define SCROBBLE_FIX_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opaque parsed scrobble record. */
+typedef struct Scrobble Scrobble;
+
+/* Parse one scrobbler.log line. Returns NULL if the line is not a valid scrobble. */
+Scrobble *scrobble_parse(const char *line);
+
+/* Fix a scrobble against a cutoff in seconds since the Unix epoch.
+ * Always consumes `scrobble`; returns the fixed scrobble, or NULL on failure. */
+Scrobble *scrobble_fix(Scrobble *scrobble, int64_t cutoff);
+
+/* Serialize a scrobble back into a scrobbler.log line. */
+char *scrobble_serialize(const Scrobble *scrobble);
+
+/* Release a scrobble returned by scrobble_parse or scrobble_fix. */
+void scrobble_free(Scrobble *scrobble);
+
+/* Fix a complete scrobbler.log, returning the corrected log or NULL on failure. */
+char *scrobble_fix_log(const char *log);
+
+/* Release a string returned by this library. */
+void scrobble_string_free(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SCROBBLE_FIX_H */
@@ -0,0 +1,349 @@
+//! Reports that point at regions of a log needing attention.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+
+use crate::{json, Scrobble};
+
+/// Seconds in a calendar day.
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A calendar day holding more music than it plausibly could.
+#[derive(Debug, PartialEq)]
+pub struct BusyDay {
+    pub date: NaiveDate,
+    pub scrobbles: usize,
+    /// Total duration of the day's scrobbles, in seconds.
+    pub seconds: u64,
+    /// Index of the day's first scrobble in the input.
+    pub first: usize,
+    /// Index of the day's last scrobble in the input.
+    pub last: usize,
+}
+
+impl BusyDay {
+    /// Music played as a fraction of the day's length.
+    pub fn fill(&self) -> f64 {
+        self.seconds as f64 / SECONDS_PER_DAY as f64
+    }
+}
+
+/// Days whose scrobbles add up to more than `threshold` times the length of a day.
+///
+/// A threshold of `1.0` flags days with more seconds of music than seconds in the day, which
+/// usually means the device's clock collapsed many plays onto the same timestamps.
+pub fn busy_days(scrobbles: &[Scrobble], threshold: f64) -> Vec<BusyDay> {
+    let mut days: BTreeMap<NaiveDate, BusyDay> = BTreeMap::new();
+    for (index, scrobble) in scrobbles.iter().enumerate() {
+        let date = scrobble.timestamp.date_naive();
+        let day = days.entry(date).or_insert(BusyDay {
+            date,
+            scrobbles: 0,
+            seconds: 0,
+            first: index,
+            last: index,
+        });
+        day.scrobbles += 1;
+        day.seconds += u64::from(scrobble.song_duration);
+        day.last = index;
+    }
+    days.into_values()
+        .filter(|day| day.fill() > threshold)
+        .collect()
+}
+
+/// Normalized names at least this similar (1.0 is identical) are clustered together.
+pub const ARTIST_SIMILARITY: f64 = 0.85;
+
+/// Scrobble counts per artist, most scrobbled first.
+pub fn artist_counts(scrobbles: &[Scrobble]) -> Vec<(String, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for scrobble in scrobbles {
+        *counts.entry(&scrobble.artist).or_default() += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts
+        .into_iter()
+        .map(|(artist, count)| (artist.to_string(), count))
+        .collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    counts
+}
+
+/// Spellings of what is probably one artist.
+#[derive(Debug, PartialEq)]
+pub struct ArtistCluster {
+    /// The most scrobbled spelling.
+    pub canonical: String,
+    /// Every spelling with its scrobble count, most scrobbled (the canonical one) first.
+    pub variants: Vec<(String, usize)>,
+}
+
+impl ArtistCluster {
+    /// Rewrite rules mapping every other spelling onto the canonical one.
+    pub fn rules(&self) -> String {
+        self.variants
+            .iter()
+            .skip(1)
+            .map(|(variant, _)| {
+                format!(
+                    "[[rule]]\nfield = \"artist\"\nmatch = {}\nreplace = {}\n",
+                    json::string(variant),
+                    json::string(&self.canonical)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Case-fold, drop featured artists and trailing periods, and collapse whitespace.
+fn normalize_artist(artist: &str) -> String {
+    let mut artist = artist.to_lowercase();
+    for marker in [" feat. ", " feat ", " ft. ", " featuring ", " (feat. "] {
+        if let Some(start) = artist.find(marker) {
+            artist.truncate(start);
+        }
+    }
+    let artist = artist.split_whitespace().collect::<Vec<&str>>().join(" ");
+    artist.trim_end_matches('.').to_string()
+}
+
+/// Levenshtein distance, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// How alike two normalized names are, from 0.0 to 1.0.
+fn similarity(a: &str, b: &str) -> f64 {
+    let longest = a.chars().count().max(b.chars().count());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(a, b) as f64 / longest as f64
+}
+
+/// Group artist spellings whose normalized forms are at least `min_similarity` alike, keeping
+/// only groups with more than one spelling.
+pub fn artist_clusters(scrobbles: &[Scrobble], min_similarity: f64) -> Vec<ArtistCluster> {
+    let counts = artist_counts(scrobbles);
+    let normalized: Vec<String> = counts.iter().map(|(a, _)| normalize_artist(a)).collect();
+    // Union-find over spellings, indexed like `counts`.
+    let mut parent: Vec<usize> = (0..counts.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for i in 0..counts.len() {
+        for j in i + 1..counts.len() {
+            if similarity(&normalized[i], &normalized[j]) >= min_similarity {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+    let mut clusters: BTreeMap<usize, Vec<(String, usize)>> = BTreeMap::new();
+    for (i, variant) in counts.iter().enumerate() {
+        clusters
+            .entry(root(&mut parent, i))
+            .or_default()
+            .push(variant.clone());
+    }
+    clusters
+        .into_values()
+        .filter(|variants| variants.len() > 1)
+        .map(|variants| ArtistCluster {
+            canonical: variants[0].0.clone(),
+            variants,
+        })
+        .collect()
+}
+
+/// How much people typically listen at each local hour, from midnight: least overnight, most
+/// from noon through the evening.
+const WAKING_HOURS: [f64; 24] = [
+    0.45, 0.25, 0.12, 0.06, 0.04, 0.05, 0.15, 0.45, 0.75, 0.85, 0.9, 0.95, 1.0, 0.95, 0.95, 0.95,
+    1.0, 1.0, 1.0, 1.0, 1.0, 0.95, 0.85, 0.65,
+];
+
+/// A guess at the UTC offset of the clock that logged a `#TZ/UNKNOWN` log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockGuess {
+    /// The clock's offset from UTC, in hours.
+    pub offset: i32,
+    /// How likely the guess is, against the others; the guesses add up to 1.
+    pub confidence: f64,
+}
+
+/// Guess the UTC offset of the clock that logged `scrobbles`, most likely first, by finding how
+/// far its hours must move to fit typical waking hours in the listener's timezone, which is
+/// `listener_offset` hours from UTC.
+///
+/// A clock kept in the listener's time needs no move, so the best guess is then
+/// `listener_offset`. Confidence grows with the number of scrobbles.
+pub fn guess_clock_offset(scrobbles: &[Scrobble], listener_offset: i32) -> Vec<ClockGuess> {
+    let mut hours = [0.0; 24];
+    for scrobble in scrobbles {
+        let seconds = scrobble
+            .timestamp
+            .timestamp()
+            .rem_euclid(SECONDS_PER_DAY as i64);
+        hours[(seconds / 3600) as usize] += 1.0;
+    }
+    let norm = |values: &[f64; 24]| values.iter().map(|v| v * v).sum::<f64>().sqrt();
+    let (played, waking) = (norm(&hours), norm(&WAKING_HOURS));
+    if played == 0.0 {
+        return Vec::new();
+    }
+    // How well the hours fit moved by each amount, as the cosine of the angle between the two.
+    let fits: Vec<(i32, f64)> = (-12..=12)
+        .map(|shift: i32| {
+            let dot: f64 = (0..24)
+                .map(|hour| {
+                    hours[hour] * WAKING_HOURS[(hour as i32 + shift).rem_euclid(24) as usize]
+                })
+                .sum();
+            (shift, dot / played / waking)
+        })
+        .collect();
+    let sharpness = (scrobbles.len() as f64).sqrt() * 4.0;
+    let best = fits.iter().map(|&(_, fit)| fit).fold(f64::MIN, f64::max);
+    let weights: Vec<f64> = fits
+        .iter()
+        .map(|&(_, fit)| ((fit - best) * sharpness).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+    let mut guesses: Vec<ClockGuess> = fits
+        .iter()
+        .zip(weights)
+        .map(|(&(shift, _), weight)| ClockGuess {
+            offset: listener_offset - shift,
+            confidence: weight / total,
+        })
+        .collect();
+    guesses.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    guesses
+}
+
+/// The records logged by a reset clock, and when the fixes put them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResetLoss {
+    pub records: usize,
+    /// Music in them, in seconds.
+    pub seconds: u64,
+    /// Those the fixes left out.
+    pub dropped: usize,
+    /// The earliest and latest corrected timestamps: the real time they were likely played in.
+    pub span: Option<(DateTime<Local>, DateTime<Local>)>,
+    /// Corrected records per year and month.
+    pub months: BTreeMap<(i32, u32), usize>,
+}
+
+/// Sum up the suspicious records, each with what the fixes made of it (`None` for one they
+/// left out).
+pub fn reset_loss<'a>(
+    suspicious: impl IntoIterator<Item = (&'a Scrobble, Option<&'a Scrobble>)>,
+) -> ResetLoss {
+    let mut loss = ResetLoss::default();
+    for (original, corrected) in suspicious {
+        loss.records += 1;
+        loss.seconds += u64::from(original.song_duration);
+        let Some(corrected) = corrected else {
+            loss.dropped += 1;
+            continue;
+        };
+        let timestamp = corrected.timestamp;
+        loss.span = Some(match loss.span {
+            Some((first, last)) => (first.min(timestamp), last.max(timestamp)),
+            None => (timestamp, timestamp),
+        });
+        *loss
+            .months
+            .entry((timestamp.year(), timestamp.month()))
+            .or_default() += 1;
+    }
+    loss
+}
+
+#[test]
+fn cluster_artist_spellings() {
+    let scrobbles: Vec<Scrobble> = [
+        "Knxwledge",
+        "Knxwledge",
+        "knxwledge.",
+        "Low",
+        "Knxwledge feat. Mndsgn",
+    ]
+    .map(|artist| Scrobble::new(&format!("{artist}\tA\tT\t1\t100\tL\t1699413807\t")).unwrap())
+    .into();
+    let clusters = artist_clusters(&scrobbles, ARTIST_SIMILARITY);
+    assert_eq!(clusters.len(), 1);
+    assert_eq!(clusters[0].canonical, "Knxwledge");
+    assert_eq!(clusters[0].variants.len(), 3);
+}
+
+#[test]
+fn flag_overfull_days() {
+    let line = |duration| format!("Artist\tAlbum\tTrack\t1\t{duration}\tL\t1699413807\t");
+    let scrobbles = [
+        Scrobble::new(&line(50_000)).unwrap(),
+        Scrobble::new(&line(50_000)).unwrap(),
+    ];
+    let days = busy_days(&scrobbles, 1.0);
+    assert_eq!(days.len(), 1);
+    assert_eq!((days[0].scrobbles, days[0].first, days[0].last), (2, 0, 1));
+    assert!(busy_days(&scrobbles[..1], 1.0).is_empty());
+}
+
+#[test]
+fn guess_clocks() {
+    // A typical day's plays, as logged by a clock five hours behind the listener.
+    let scrobbles: Vec<Scrobble> = (0..24)
+        .flat_map(|hour: i64| {
+            let plays = (WAKING_HOURS[hour as usize] * 20.0) as i64;
+            (0..plays).map(move |play| 1699401600 + play * 86400 + (hour - 5) * 3600)
+        })
+        .map(|timestamp| {
+            Scrobble::new(&format!("Low\t\tBreaker\t\t187\tL\t{timestamp}\t")).unwrap()
+        })
+        .collect();
+    let guesses = guess_clock_offset(&scrobbles, 1);
+    assert_eq!(guesses.len(), 25);
+    assert_eq!(guesses[0].offset, -4);
+    assert!(guesses[0].confidence > 0.5);
+    let total: f64 = guesses.iter().map(|guess| guess.confidence).sum();
+    assert!((total - 1.0).abs() < 1e-9);
+    assert!(guess_clock_offset(&[], 0).is_empty());
+}
+
+#[test]
+fn sum_up_reset_records() {
+    let reset = Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t187\tL\t987099892\t").unwrap();
+    let fixed = |timestamp| {
+        let mut scrobble = reset.clone();
+        scrobble.timestamp = chrono::TimeZone::timestamp_opt(&Local, timestamp, 0).unwrap();
+        scrobble
+    };
+    let (november, december) = (fixed(1699467892), fixed(1702641600));
+    let loss = reset_loss([
+        (&reset, Some(&november)),
+        (&reset, Some(&december)),
+        (&reset, None),
+    ]);
+    assert_eq!((loss.records, loss.seconds, loss.dropped), (3, 561, 1));
+    assert_eq!(loss.span, Some((november.timestamp, december.timestamp)));
+    assert_eq!(loss.months.len(), 2);
+}
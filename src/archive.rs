@@ -0,0 +1,358 @@
+//! Finding scrobbler.log files inside tarballs, zip archives, and FAT disk images, and the
+//! listens inside ListenBrainz export archives; and writing zip archives.
+//!
+//! Documented here:
+//! - <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/pax.html#tag_20_92_13_06>
+//! - <https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT>
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{fat, inflate};
+
+/// A log found in a container, or a file to write to one.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// Where the log is inside the container.
+    pub path: String,
+    pub contents: Vec<u8>,
+}
+
+/// Kinds of container a log can be extracted from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Tar,
+    /// A gzipped tarball.
+    TarGz,
+    Zip,
+    /// A raw FAT filesystem, or a disk image partitioned into them.
+    Fat,
+}
+
+/// Recognize a container from its first 512 bytes, if it is one.
+pub fn detect(header: &[u8]) -> Option<Kind> {
+    if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+        Some(Kind::Zip)
+    } else if header.starts_with(&[0x1f, 0x8b]) {
+        Some(Kind::TarGz)
+    } else if header.get(257..262) == Some(b"ustar") {
+        Some(Kind::Tar)
+    } else if header.get(510..512) == Some(&[0x55, 0xaa]) {
+        Some(Kind::Fat)
+    } else {
+        None
+    }
+}
+
+/// Whether a path names a scrobbler.log, whatever directory it's in.
+pub fn is_scrobbler_log(path: &str) -> bool {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    name.eq_ignore_ascii_case(".scrobbler.log") || name.eq_ignore_ascii_case("scrobbler.log")
+}
+
+/// Whether a path names the listens of a ListenBrainz export: `listens/YEAR/MONTH.jsonl`.
+pub fn is_listenbrainz_listens(path: &str) -> bool {
+    let mut parts = path.rsplit('/');
+    let name = parts.next().unwrap_or(path);
+    name.ends_with(".jsonl") && parts.nth(1) == Some("listens")
+}
+
+/// Whether a path names something a container's scrobbles can be read from.
+fn is_input(path: &str) -> bool {
+    is_scrobbler_log(path) || is_listenbrainz_listens(path)
+}
+
+/// Every scrobbler.log (or ListenBrainz listens file) in a container, in the order they're
+/// stored.
+pub fn scrobbler_logs<R: Read + Seek>(mut reader: R) -> Result<Vec<Entry>, String> {
+    let mut header = [0; 512];
+    reader.rewind().map_err(|e| e.to_string())?;
+    let read = read_up_to(&mut reader, &mut header)?;
+    reader.rewind().map_err(|e| e.to_string())?;
+    match detect(&header[..read]) {
+        Some(Kind::Tar) => tar(reader),
+        Some(Kind::TarGz) => {
+            let mut compressed = Vec::new();
+            reader
+                .read_to_end(&mut compressed)
+                .map_err(|e| e.to_string())?;
+            tar(std::io::Cursor::new(inflate::gunzip(&compressed)?))
+        }
+        Some(Kind::Zip) => zip(reader),
+        Some(Kind::Fat) => fat::scrobbler_logs(reader),
+        None => Err("not a tarball, zip archive, or FAT image".to_string()),
+    }
+}
+
+/// Fill as much of `buffer` as the reader has.
+fn read_up_to(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize, String> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader
+            .read(&mut buffer[filled..])
+            .map_err(|e| e.to_string())?
+        {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// A NUL-terminated string field of a tar header.
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// An octal number field of a tar header.
+fn tar_number(field: &[u8]) -> Result<u64, String> {
+    let digits = tar_string(field);
+    let digits = digits.trim_matches([' ', '\0']);
+    match digits {
+        "" => Ok(0),
+        digits => u64::from_str_radix(digits, 8).map_err(|e| format!("tar header: {e}")),
+    }
+}
+
+/// The logs in a tarball, including names too long for the header (GNU and pax extensions).
+fn tar(mut reader: impl Read) -> Result<Vec<Entry>, String> {
+    let mut logs = Vec::new();
+    let mut long_name = None;
+    loop {
+        let mut header = [0; 512];
+        if read_up_to(&mut reader, &mut header)? < 512 || header.iter().all(|&b| b == 0) {
+            return Ok(logs);
+        }
+        let size = tar_number(&header[124..136])?;
+        let mut contents = Vec::new();
+        (&mut reader)
+            .take(size.next_multiple_of(512))
+            .read_to_end(&mut contents)
+            .map_err(|e| e.to_string())?;
+        if (contents.len() as u64) < size {
+            return Err("tarball ends in the middle of a file".to_string());
+        }
+        contents.truncate(size as usize);
+        let mut path = tar_string(&header[..100]);
+        if &header[257..262] == b"ustar" && header[345] != 0 {
+            path = format!("{}/{path}", tar_string(&header[345..500]));
+        }
+        match header[156] {
+            b'L' => long_name = Some(tar_string(&contents)),
+            b'x' => {
+                // Records of `length key=value\n`; only the path matters here.
+                long_name = String::from_utf8_lossy(&contents)
+                    .lines()
+                    .find_map(|record| record.split_once(" path=").map(|(_, path)| path))
+                    .map(str::to_string)
+                    .or(long_name);
+            }
+            b'0' | 0 => {
+                let path = long_name.take().unwrap_or(path);
+                if is_input(&path) {
+                    logs.push(Entry { path, contents });
+                }
+            }
+            _ => long_name = None,
+        }
+    }
+}
+
+pub(crate) fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+pub(crate) fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("four bytes"))
+}
+
+/// Read `length` bytes at `offset`, failing if the file ends first. Lengths come from the file,
+/// so the bytes are read as they come rather than making room for `length` up front.
+pub(crate) fn read_at(
+    reader: &mut (impl Read + Seek),
+    offset: u64,
+    length: usize,
+) -> Result<Vec<u8>, String> {
+    reader
+        .seek(SeekFrom::Start(offset))
+        .map_err(|e| e.to_string())?;
+    let mut bytes = Vec::new();
+    reader
+        .take(length as u64)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("reading {length} bytes at {offset}: {e}"))?;
+    if bytes.len() < length {
+        return Err(format!(
+            "reading {length} bytes at {offset}: the file ends after {}",
+            bytes.len()
+        ));
+    }
+    Ok(bytes)
+}
+
+/// The logs in a zip archive, found through its central directory.
+fn zip(mut reader: impl Read + Seek) -> Result<Vec<Entry>, String> {
+    let length = reader.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+    // The end of central directory record is 22 bytes, followed by a comment of up to 64KiB.
+    let tail_length = length.min(22 + 0xffff);
+    let tail = read_at(&mut reader, length - tail_length, tail_length as usize)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| tail[i..].starts_with(b"PK\x05\x06"))
+        .ok_or("zip archive has no central directory")?;
+    let entries = u16_at(&tail, end + 10);
+    let directory_length = u32_at(&tail, end + 12);
+    let directory_offset = u32_at(&tail, end + 16);
+    if directory_offset == u32::MAX {
+        return Err("zip64 archives aren't supported".to_string());
+    }
+    let directory = read_at(
+        &mut reader,
+        u64::from(directory_offset),
+        directory_length as usize,
+    )?;
+    let mut logs = Vec::new();
+    let mut position = 0;
+    for _ in 0..entries {
+        let header = directory
+            .get(position..position + 46)
+            .filter(|header| header.starts_with(b"PK\x01\x02"))
+            .ok_or("zip central directory is truncated")?;
+        let method = u16_at(header, 10);
+        let compressed_size = u32_at(header, 20) as usize;
+        let name_length = usize::from(u16_at(header, 28));
+        let extra_length = usize::from(u16_at(header, 30));
+        let comment_length = usize::from(u16_at(header, 32));
+        let local_offset = u64::from(u32_at(header, 42));
+        let name = directory
+            .get(position + 46..position + 46 + name_length)
+            .ok_or("zip central directory is truncated")?;
+        let path = String::from_utf8_lossy(name).into_owned();
+        position += 46 + name_length + extra_length + comment_length;
+        if !is_input(&path) {
+            continue;
+        }
+        let local = read_at(&mut reader, local_offset, 30)?;
+        let data_offset =
+            local_offset + 30 + u64::from(u16_at(&local, 26)) + u64::from(u16_at(&local, 28));
+        let data = read_at(&mut reader, data_offset, compressed_size)?;
+        let contents = match method {
+            0 => data,
+            8 => inflate::inflate(&data).map_err(|e| format!("{path}: {e}"))?,
+            method => return Err(format!("{path}: unsupported compression method {method}")),
+        };
+        logs.push(Entry { path, contents });
+    }
+    Ok(logs)
+}
+
+/// The CRC-32 zip archives check their contents against.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// A zip archive of `entries`, stored without compression. Every entry is dated 1980-01-01, so
+/// the same entries always make the same archive.
+pub fn write_zip(entries: &[Entry]) -> Result<Vec<u8>, String> {
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    let fits = |n: usize| u32::try_from(n).map_err(|_| "too big for a zip archive".to_string());
+    for entry in entries {
+        let offset = fits(archive.len())?;
+        let length = fits(entry.contents.len())?;
+        let name = entry.path.as_bytes();
+        let name_length = u16::try_from(name.len()).map_err(|_| "name too long for zip")?;
+        // Version 2.0, UTF-8 names, stored, midnight 1980-01-01, CRC and both sizes.
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&20u16.to_le_bytes());
+        fields.extend_from_slice(&0x0800u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&0x0021u16.to_le_bytes());
+        fields.extend_from_slice(&crc32(&entry.contents).to_le_bytes());
+        fields.extend_from_slice(&length.to_le_bytes());
+        fields.extend_from_slice(&length.to_le_bytes());
+        fields.extend_from_slice(&name_length.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+
+        archive.extend_from_slice(b"PK\x03\x04");
+        archive.extend_from_slice(&fields);
+        archive.extend_from_slice(name);
+        archive.extend_from_slice(&entry.contents);
+
+        directory.extend_from_slice(b"PK\x01\x02");
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&fields);
+        // No comment, disk 0, no attributes, then where the local header is.
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name);
+    }
+    let count = u16::try_from(entries.len()).map_err(|_| "too many files for zip")?;
+    let directory_offset = fits(archive.len())?;
+    let directory_length = fits(directory.len())?;
+    archive.extend_from_slice(&directory);
+    archive.extend_from_slice(b"PK\x05\x06");
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&count.to_le_bytes());
+    archive.extend_from_slice(&count.to_le_bytes());
+    archive.extend_from_slice(&directory_length.to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    Ok(archive)
+}
+
+#[test]
+fn find_logs_in_tarball() {
+    let mut tarball = Vec::new();
+    for (path, contents) in [
+        ("ipod/.rockbox/notes.txt", "x"),
+        ("ipod/.scrobbler.log", "log"),
+    ] {
+        let mut header = [0; 512];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", contents.len()).as_bytes());
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        tarball.extend_from_slice(&header);
+        let mut data = contents.as_bytes().to_vec();
+        data.resize(512, 0);
+        tarball.extend_from_slice(&data);
+    }
+    tarball.resize(tarball.len() + 1024, 0);
+    assert_eq!(detect(&tarball), Some(Kind::Tar));
+    let logs = scrobbler_logs(std::io::Cursor::new(tarball)).unwrap();
+    assert_eq!(
+        logs,
+        [Entry {
+            path: "ipod/.scrobbler.log".to_string(),
+            contents: b"log".to_vec()
+        }]
+    );
+}
+
+#[test]
+fn write_and_read_zip() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    let entries = [
+        Entry {
+            path: "user.json".to_string(),
+            contents: b"{}".to_vec(),
+        },
+        Entry {
+            path: "listens/2023/11.jsonl".to_string(),
+            contents: b"{\"listened_at\": 1699413807}\n".to_vec(),
+        },
+    ];
+    let zip = write_zip(&entries).unwrap();
+    assert_eq!(detect(&zip), Some(Kind::Zip));
+    let found = scrobbler_logs(std::io::Cursor::new(zip)).unwrap();
+    assert_eq!(found, entries[1..]);
+}
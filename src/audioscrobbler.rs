@@ -0,0 +1,257 @@
+//! The Audioscrobbler 1.2 submission protocol, which came before the Last.fm API and which
+//! self-hosted servers like GNU FM still speak.
+//!
+//! A client shakes hands for a session, then posts now-playing notices and batches of
+//! submissions to the URLs the handshake gave it. Replies are plain text: a status line, then any
+//! values, one per line, as the Audioscrobbler Realtime Submission Protocol v1.2.1 describes.
+
+use crate::{json, md5, rules, url, Scrobble};
+
+/// The protocol version sent in the handshake.
+pub const PROTOCOL: &str = "1.2.1";
+
+/// Most scrobbles accepted by one submission.
+pub const BATCH_SIZE: usize = 50;
+
+/// The client id servers accept from clients they don't know: the protocol's test id.
+pub const DEFAULT_CLIENT: &str = "tst";
+
+/// The settings for a server, from the `[audioscrobbler]` table of the config file:
+///
+/// ```toml
+/// [audioscrobbler]
+/// handshake = "http://turtle.libre.fm/"
+/// user = "alice"
+/// password_md5 = "5f4dcc3b5aa765d61d8327deb882cf99"
+/// client = "tst"
+/// ```
+///
+/// `client` is optional. So is `password_md5`, the MD5 of the password in hex, which can come
+/// from elsewhere instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Server {
+    /// Where to shake hands.
+    pub handshake: String,
+    pub user: String,
+    pub password_md5: Option<String>,
+    /// The client id to shake hands as.
+    pub client: String,
+}
+
+impl Server {
+    /// Read the `[audioscrobbler]` table of a config file, leaving other tables alone. `None` if
+    /// the file has no such table.
+    ///
+    /// Only this much TOML is understood: `[table]` headers, `key = "string"` lines and `#`
+    /// comments.
+    pub fn parse_config(text: &str) -> Result<Option<Server>, String> {
+        let (mut table, mut found) = (String::new(), false);
+        let (mut handshake, mut user, mut password_md5, mut client) = (None, None, None, None);
+        for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+            let line = rules::strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                table = name.trim().to_string();
+                found |= table == "audioscrobbler";
+                continue;
+            }
+            if table != "audioscrobbler" {
+                continue;
+            }
+            let error = |message: String| format!("line {number}: {message}");
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(error("expected `key = value`".to_string()))?;
+            let value = json::parse(value.trim())
+                .map_err(error)?
+                .as_str()
+                .map(str::to_string)
+                .ok_or(error(format!("{} must be a string", key.trim())))?;
+            match key.trim() {
+                "handshake" => handshake = Some(value),
+                "user" => user = Some(value),
+                "password_md5" => password_md5 = Some(value.to_lowercase()),
+                "client" => client = Some(value),
+                other => Err(error(format!("unknown key: {other}")))?,
+            }
+        }
+        if !found {
+            return Ok(None);
+        }
+        let missing = |key| format!("[audioscrobbler] has no {key}");
+        Ok(Some(Server {
+            handshake: handshake.ok_or(missing("handshake"))?,
+            user: user.ok_or(missing("user"))?,
+            password_md5,
+            client: client.unwrap_or(DEFAULT_CLIENT.to_string()),
+        }))
+    }
+
+    /// The handshake request for a client at `version`, made at `timestamp`, signed with
+    /// `password_md5`: its token is the MD5 of the password's MD5 followed by the timestamp.
+    pub fn handshake_url(&self, password_md5: &str, version: &str, timestamp: i64) -> String {
+        let timestamp = timestamp.to_string();
+        let token = md5::hex_digest(format!("{password_md5}{timestamp}").as_bytes());
+        let separator = match self.handshake.contains('?') {
+            true => '&',
+            false => '?',
+        };
+        format!(
+            "{}{separator}{}",
+            self.handshake,
+            url::query([
+                ("hs", "true"),
+                ("p", PROTOCOL),
+                ("c", self.client.as_str()),
+                ("v", version),
+                ("u", self.user.as_str()),
+                ("t", timestamp.as_str()),
+                ("a", token.as_str()),
+            ])
+        )
+    }
+}
+
+/// A session from a handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: String,
+    pub now_playing_url: String,
+    pub submission_url: String,
+}
+
+/// Read a handshake reply, turning a refusal into `Err`.
+pub fn parse_handshake(response: &str) -> Result<Session, String> {
+    let mut lines = response.lines().map(str::trim);
+    match lines.next().unwrap_or_default() {
+        "OK" => {}
+        "BANNED" => Err("the server has banned this client")?,
+        "BADAUTH" => Err("the server refused the user name or password")?,
+        "BADTIME" => Err("the server says the system clock is wrong")?,
+        status => Err(failure(status))?,
+    }
+    let mut line = |name| {
+        lines
+            .next()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .ok_or(format!("handshake reply has no {name}"))
+    };
+    Ok(Session {
+        id: line("session id")?,
+        now_playing_url: line("now-playing URL")?,
+        submission_url: line("submission URL")?,
+    })
+}
+
+/// A `FAILED reason` status, or any other the protocol doesn't have, as an error message.
+fn failure(status: &str) -> String {
+    match status.strip_prefix("FAILED") {
+        Some(reason) if !reason.trim().is_empty() => format!("server failed: {}", reason.trim()),
+        Some(_) => "server failed".to_string(),
+        None => format!("unexpected reply: {status}"),
+    }
+}
+
+/// Form parameters telling the server `scrobble` is playing now.
+pub fn now_playing_params(session: &Session, scrobble: &Scrobble) -> Vec<(String, String)> {
+    vec![
+        ("s".to_string(), session.id.clone()),
+        ("a".to_string(), scrobble.artist.clone()),
+        ("t".to_string(), scrobble.track.clone()),
+        ("b".to_string(), scrobble.album.clone()),
+        ("l".to_string(), scrobble.song_duration.to_string()),
+        (
+            "n".to_string(),
+            scrobble
+                .track_position
+                .map_or(String::new(), |p| p.to_string()),
+        ),
+        (
+            "m".to_string(),
+            scrobble.track_id.clone().unwrap_or_default(),
+        ),
+    ]
+}
+
+/// Form parameters submitting `batch`, chosen by the user (`o` is `P`) and unrated: the
+/// protocol's `L` rating is a love, unlike the log's.
+pub fn submission_params(session: &Session, batch: &[&Scrobble]) -> Vec<(String, String)> {
+    let mut params = vec![("s".to_string(), session.id.clone())];
+    for (i, scrobble) in batch.iter().enumerate() {
+        let mut field = |name: &str, value: String| params.push((format!("{name}[{i}]"), value));
+        field("a", scrobble.artist.clone());
+        field("t", scrobble.track.clone());
+        field("i", scrobble.timestamp.timestamp().to_string());
+        field("o", "P".to_string());
+        field("r", String::new());
+        field("l", scrobble.song_duration.to_string());
+        field("b", scrobble.album.clone());
+        field(
+            "n",
+            scrobble
+                .track_position
+                .map_or(String::new(), |p| p.to_string()),
+        );
+        field("m", scrobble.track_id.clone().unwrap_or_default());
+    }
+    params
+}
+
+/// What the server made of a now-playing notice or a submission.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Ok,
+    /// The session has expired; shake hands again and retry.
+    BadSession,
+}
+
+/// Read a now-playing or submission reply, turning a failure into `Err`.
+pub fn parse_reply(response: &str) -> Result<Reply, String> {
+    match response.lines().next().unwrap_or_default().trim() {
+        "OK" => Ok(Reply::Ok),
+        "BADSESSION" => Ok(Reply::BadSession),
+        status => Err(failure(status)),
+    }
+}
+
+#[test]
+fn legacy_protocol() {
+    let config = "# a GNU FM server\n[other]\nuser = \"x\"\n\n[audioscrobbler]\n\
+                  handshake = \"http://turtle.libre.fm/\"\nuser = \"alice\"  # me\n";
+    let server = Server::parse_config(config).unwrap().unwrap();
+    assert_eq!(
+        (server.user.as_str(), server.client.as_str()),
+        ("alice", "tst")
+    );
+    assert_eq!(Server::parse_config("[other]\nkey = \"value\"\n"), Ok(None));
+    assert!(Server::parse_config("[audioscrobbler]\nuser = \"alice\"\n").is_err());
+    // The token is md5(md5("password") + "1699413807").
+    let url = server.handshake_url("5f4dcc3b5aa765d61d8327deb882cf99", "1.0", 1699413807);
+    assert_eq!(
+        url,
+        "http://turtle.libre.fm/?hs=true&p=1.2.1&c=tst&v=1.0&u=alice&t=1699413807&a=".to_string()
+            + &md5::hex_digest(b"5f4dcc3b5aa765d61d8327deb882cf991699413807")
+    );
+
+    let session = parse_handshake("OK\nabc123\nhttp://np.example/\nhttp://sub.example/\n").unwrap();
+    assert_eq!(session.submission_url, "http://sub.example/");
+    assert!(parse_handshake("BADAUTH\n").is_err());
+    assert_eq!(
+        parse_handshake("FAILED Plugin bug: Not all request variables are set\n"),
+        Err("server failed: Plugin bug: Not all request variables are set".to_string())
+    );
+
+    let scrobble = Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t").unwrap();
+    let params = submission_params(&session, &[&scrobble]);
+    assert_eq!(
+        crate::lastfm::form_body(&params[..5]),
+        "s=abc123&a%5B0%5D=Low&t%5B0%5D=Breaker&i%5B0%5D=1699413807&o%5B0%5D=P"
+    );
+    assert_eq!(now_playing_params(&session, &scrobble)[5].1, "5");
+    assert_eq!(parse_reply("OK\n"), Ok(Reply::Ok));
+    assert_eq!(parse_reply("BADSESSION\n"), Ok(Reply::BadSession));
+    assert!(parse_reply("FAILED\n").is_err());
+}
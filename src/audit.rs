@@ -0,0 +1,90 @@
+//! The audit log: a line of JSON for every file written and every batch submitted, by every run,
+//! appended to `$XDG_STATE_HOME/scrobble-fix/audit.jsonl` and never rewritten, to tell long after
+//! whether some scrobbles were already imported or sent.
+//!
+//! Each line has `at` (when, in RFC 3339), `command`, `action` (`write` or `submit`) and
+//! `sha256`; writes add `path` and `bytes`, submissions `backend`; and both add `scrobbles`,
+//! `first` and `last` when they hold scrobbles.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{Local, SecondsFormat};
+use scrobble_fix::{json, sha256, Scrobble};
+
+use crate::{dirs, files, log};
+
+/// The command running, as typed.
+static COMMAND: Mutex<&str> = Mutex::new("fix");
+
+/// Where the audit log is kept.
+pub fn path() -> io::Result<PathBuf> {
+    Ok(dirs::state()?.join("audit.jsonl"))
+}
+
+/// Name the command in every entry from now on.
+pub fn set_command(command: &'static str) {
+    *COMMAND.lock().unwrap_or_else(|e| e.into_inner()) = command;
+}
+
+/// How many scrobbles there are, and the earliest and latest of their timestamps.
+fn span(scrobbles: &[&Scrobble]) -> Vec<(&'static str, String)> {
+    let timestamps = scrobbles.iter().map(|scrobble| scrobble.timestamp);
+    let (Some(first), Some(last)) = (timestamps.clone().min(), timestamps.max()) else {
+        return Vec::new();
+    };
+    let time = |time: chrono::DateTime<Local>| {
+        json::string(&time.to_rfc3339_opts(SecondsFormat::Secs, false))
+    };
+    vec![
+        ("scrobbles", scrobbles.len().to_string()),
+        ("first", time(first)),
+        ("last", time(last)),
+    ]
+}
+
+/// Append an entry, only warning if that fails: what it records has already happened.
+fn record(action: &str, fields: Vec<(&'static str, String)>) {
+    let command = *COMMAND.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Local::now().to_rfc3339_opts(SecondsFormat::Secs, false);
+    let mut entry = vec![
+        ("at", json::string(&now)),
+        ("command", json::string(command)),
+        ("action", json::string(action)),
+    ];
+    entry.extend(fields);
+    let line = json::object(entry) + "\n";
+    if let Err(e) = path().and_then(|path| files::append(&path, line.as_bytes())) {
+        log::warn(format_args!("couldn't add to the audit log: {e}"));
+    }
+}
+
+/// Record writing `contents`, a log of `scrobbles` or some other file, to `path`.
+pub fn wrote(path: &Path, contents: &[u8], scrobbles: &[Scrobble]) {
+    let mut fields = vec![
+        ("path", json::string(&path.to_string_lossy())),
+        ("sha256", json::string(&sha256::hex_digest(contents))),
+        ("bytes", contents.len().to_string()),
+    ];
+    fields.extend(span(&scrobbles.iter().collect::<Vec<_>>()));
+    record("write", fields);
+}
+
+/// Record submitting a batch to `backend`, hashing the [`Scrobble::fingerprint`]s in it, one per
+/// line.
+pub fn submitted(backend: &str, batch: &[&Scrobble]) {
+    let fingerprints: String = batch
+        .iter()
+        .map(|scrobble| scrobble.fingerprint() + "\n")
+        .collect();
+    let mut fields = vec![
+        ("backend", json::string(backend)),
+        (
+            "sha256",
+            json::string(&sha256::hex_digest(fingerprints.as_bytes())),
+        ),
+    ];
+    fields.extend(span(batch));
+    record("submit", fields);
+}
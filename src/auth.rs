@@ -0,0 +1,232 @@
+//! Logging in to scrobbling services, keeping the credentials in the OS keyring.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::OnceLock;
+
+use scrobble_fix::lastfm::{self, Credentials};
+
+use crate::cli::Service;
+use crate::error::Error;
+use crate::keyring;
+use crate::log;
+use crate::net;
+
+const USER_AGENT: &str = concat!("scrobble-fix/", env!("CARGO_PKG_VERSION"));
+
+/// Keyring accounts holding the Last.fm API key, shared secret, session key and user name.
+const LASTFM_API_KEY: &str = "lastfm-api-key";
+const LASTFM_API_SECRET: &str = "lastfm-api-secret";
+const LASTFM_SESSION_KEY: &str = "lastfm-session-key";
+const LASTFM_USER: &str = "lastfm-user";
+
+/// Keyring account holding the ListenBrainz user token.
+const LISTENBRAINZ_TOKEN: &str = "listenbrainz-token";
+
+/// Settings a `--user` profile falls back to the default ones for: one API account can serve
+/// every user.
+const SHARED: [&str; 2] = [LASTFM_API_KEY, LASTFM_API_SECRET];
+
+/// The `--user` profile, whose credentials are kept apart from the default ones.
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Use the credentials of the profile `name` instead of the default ones, from now on.
+pub fn set_profile(name: &str) {
+    let _ = PROFILE.set(name.to_string());
+}
+
+/// The `--user` profile, if one was chosen.
+pub fn profile() -> Option<&'static str> {
+    PROFILE.get().map(String::as_str)
+}
+
+/// The keyring account of a setting, prefixed with the profile's name if there is one.
+fn account(name: &str) -> String {
+    match profile() {
+        Some(profile) => format!("{profile}:{name}"),
+        None => name.to_string(),
+    }
+}
+
+/// A setting from the environment variable `var`, or else the keyring. A profile only uses its
+/// own keyring entries, besides the [`SHARED`] ones.
+fn setting(var: &str, name: &str) -> Result<Option<String>, Error> {
+    if profile().is_some() {
+        let stored = keyring::get(&account(name)).map_err(Error::Usage)?;
+        if stored.is_some() || !SHARED.contains(&name) {
+            return Ok(stored);
+        }
+    }
+    match std::env::var(var).ok().filter(|value| !value.is_empty()) {
+        Some(value) => Ok(Some(value)),
+        None => keyring::get(name).map_err(Error::Usage),
+    }
+}
+
+/// Last.fm credentials, from `$LASTFM_API_KEY`, `$LASTFM_API_SECRET` and `$LASTFM_SESSION_KEY`,
+/// or from the keyring after `auth login lastfm` (with `--user`, the profile's login).
+pub fn lastfm_credentials() -> Result<Credentials, Error> {
+    let required = |var, account| {
+        setting(var, account)?.ok_or(Error::Usage(match profile() {
+            Some(profile) => format!(
+                "no Last.fm credentials for {profile}: run `scrobble-fix auth login lastfm --user {profile}`"
+            ),
+            None => format!(
+                "no Last.fm credentials: run `scrobble-fix auth login lastfm` or set ${var}"
+            ),
+        }))
+    };
+    Ok(Credentials {
+        api_key: required("LASTFM_API_KEY", LASTFM_API_KEY)?,
+        api_secret: required("LASTFM_API_SECRET", LASTFM_API_SECRET)?,
+        session_key: required("LASTFM_SESSION_KEY", LASTFM_SESSION_KEY)?,
+    })
+}
+
+/// The Last.fm user name, from `$LASTFM_USER` or the keyring.
+pub fn lastfm_user() -> Result<Option<String>, Error> {
+    setting("LASTFM_USER", LASTFM_USER)
+}
+
+/// Ask a question on stderr and read the answer, without echoing it if `secret` is set and the
+/// terminal allows it.
+pub fn prompt(question: &str, secret: bool) -> Result<String, Error> {
+    eprint!("{question}: ");
+    io::stderr().flush()?;
+    let hide = secret && io::stdin().is_terminal();
+    let stty = |setting| {
+        std::process::Command::new("stty")
+            .arg(setting)
+            .stdin(std::fs::File::open("/dev/tty")?)
+            .status()
+    };
+    if hide {
+        stty("-echo")?;
+    }
+    let mut answer = String::new();
+    let read = io::stdin().lock().read_line(&mut answer);
+    if hide {
+        stty("echo")?;
+        eprintln!();
+    }
+    read?;
+    match answer.trim() {
+        "" => Err(Error::Usage(format!("{question}: no answer given"))),
+        answer => Ok(answer.to_string()),
+    }
+}
+
+/// A setting from the environment or keyring, asking for it when it's missing.
+fn setting_or_prompt(var: &str, account: &str, question: &str) -> Result<String, Error> {
+    match setting(var, account)? {
+        Some(value) => Ok(value),
+        None => prompt(question, true),
+    }
+}
+
+/// Seconds between checks on whether the user has granted access.
+const POLL_INTERVAL: u64 = 5;
+
+/// Show a URL in the user's browser, if there's a way to.
+fn open_browser(url: &str) {
+    let opener = match std::env::consts::OS {
+        "macos" => "open",
+        _ => "xdg-open",
+    };
+    let opened = std::process::Command::new(opener)
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+    if !opened.is_ok_and(|status| status.success()) {
+        log::info("couldn't open a browser; visit the address above");
+    }
+}
+
+/// Exchange a token for a session key, waiting until the user grants it access or it expires.
+fn wait_for_session(
+    api_key: &str,
+    api_secret: &str,
+    token: &str,
+) -> Result<lastfm::Session, Error> {
+    let url = lastfm::session_url(api_key, api_secret, token);
+    let started = std::time::Instant::now();
+    log::info("waiting for access to be allowed (Ctrl-C to give up)");
+    loop {
+        let response = net::get_api(&url, USER_AGENT).map_err(Error::Network)?;
+        if lastfm::error_code(&response) != Some(lastfm::UNAUTHORIZED_TOKEN) {
+            return lastfm::parse_session(&response).map_err(Error::Network);
+        }
+        if started.elapsed().as_secs() >= lastfm::TOKEN_LIFETIME {
+            return Err(Error::Usage(
+                "access wasn't allowed before the token expired".to_string(),
+            ));
+        }
+        std::thread::sleep(std::time::Duration::from_secs(POLL_INTERVAL));
+    }
+}
+
+/// Authorize scrobble-fix on Last.fm with the desktop flow: get a token, have the user grant it
+/// access in a browser, then exchange it for a session key once they have.
+fn login_lastfm() -> Result<(), Error> {
+    eprintln!("API accounts can be created at https://www.last.fm/api/account/create");
+    let api_key = setting_or_prompt("LASTFM_API_KEY", LASTFM_API_KEY, "Last.fm API key")?;
+    let api_secret = setting_or_prompt(
+        "LASTFM_API_SECRET",
+        LASTFM_API_SECRET,
+        "Last.fm shared secret",
+    )?;
+    let response = net::get_api(&lastfm::token_url(&api_key, &api_secret), USER_AGENT)
+        .map_err(Error::Network)?;
+    let token = lastfm::parse_token(&response).map_err(Error::Network)?;
+    let authorize_url = lastfm::authorize_url(&api_key, &token);
+    eprintln!("Allow access at {authorize_url}");
+    open_browser(&authorize_url);
+    let session = wait_for_session(&api_key, &api_secret, &token)?;
+    for (name, secret) in [
+        (LASTFM_API_KEY, &api_key),
+        (LASTFM_API_SECRET, &api_secret),
+        (LASTFM_SESSION_KEY, &session.key),
+        (LASTFM_USER, &session.user),
+    ] {
+        keyring::set(&account(name), secret).map_err(Error::Usage)?;
+    }
+    log::info(format_args!("logged in to Last.fm as {}", session.user));
+    Ok(())
+}
+
+/// Store a ListenBrainz user token, which is copied from the settings page.
+fn login_listenbrainz() -> Result<(), Error> {
+    eprintln!("Your user token is shown at https://listenbrainz.org/settings/");
+    let token = prompt("ListenBrainz user token", true)?;
+    keyring::set(&account(LISTENBRAINZ_TOKEN), &token).map_err(Error::Usage)?;
+    log::info("stored ListenBrainz token");
+    Ok(())
+}
+
+pub fn login(service: Service) -> Result<(), Error> {
+    match service {
+        Service::LastFm => login_lastfm(),
+        Service::ListenBrainz => login_listenbrainz(),
+    }
+}
+
+/// Forget a service's credentials.
+pub fn logout(service: Service) -> Result<(), Error> {
+    let accounts: &[&str] = match service {
+        Service::LastFm => &[
+            LASTFM_API_KEY,
+            LASTFM_API_SECRET,
+            LASTFM_SESSION_KEY,
+            LASTFM_USER,
+        ],
+        Service::ListenBrainz => &[LISTENBRAINZ_TOKEN],
+    };
+    for name in accounts {
+        keyring::delete(&account(name)).map_err(Error::Usage)?;
+    }
+    match profile() {
+        Some(profile) => log::info(format_args!("logged {profile} out of {}", service.name())),
+        None => log::info(format_args!("logged out of {}", service.name())),
+    }
+    Ok(())
+}
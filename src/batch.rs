@@ -0,0 +1,86 @@
+//! Gathering logs for `batch` from directories, archives, and disk images.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use scrobble_fix::archive;
+
+use crate::error::Error;
+use crate::log;
+
+/// Text of a log, which must be UTF-8.
+fn text(contents: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(contents).map_err(|e| Error::Parse(e.to_string()))
+}
+
+/// A log found, named by where it was found, or why it couldn't be read.
+pub type Found = (String, Result<String, Error>);
+
+/// Collect the logs under `path`: itself if it's a log, the scrobbler.logs inside it if it's a
+/// container, or those in and below it if it's a directory. What's wrong with a file or container
+/// goes with its name; only a directory that can't be listed fails.
+fn collect(path: &Path, logs: &mut Vec<Found>) -> Result<(), Error> {
+    let name = path.display().to_string();
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            let is_log = entry
+                .file_name()
+                .is_some_and(|file| archive::is_scrobbler_log(&file.to_string_lossy()));
+            if entry.is_dir() || is_log {
+                collect(&entry, logs)?;
+            }
+        }
+        return Ok(());
+    }
+    if let Err(e) = collect_file(path, &name, logs) {
+        logs.push((name, Err(e)));
+    }
+    Ok(())
+}
+
+/// Collect the log at `path`, or those inside it.
+fn collect_file(path: &Path, name: &str, logs: &mut Vec<Found>) -> Result<(), Error> {
+    let mut file = File::open(path)?;
+    let mut header = Vec::with_capacity(512);
+    (&mut file).take(512).read_to_end(&mut header)?;
+    if archive::detect(&header).is_none() {
+        logs.push((name.to_string(), text(std::fs::read(path)?)));
+        return Ok(());
+    }
+    let found = archive::scrobbler_logs(file).map_err(Error::Parse)?;
+    if found.is_empty() {
+        log::warn(format_args!("{name}: no scrobbler.log found"));
+    }
+    for entry in found {
+        let name = format!("{name}:{}", entry.path);
+        logs.push((name, text(entry.contents)));
+    }
+    Ok(())
+}
+
+/// Every log found in `paths`, each with what's wrong with it if it can't be read (not naming
+/// it).
+pub fn found(paths: &[impl AsRef<Path>]) -> Result<Vec<Found>, Error> {
+    let mut logs = Vec::new();
+    for path in paths {
+        collect(path.as_ref(), &mut logs)?;
+    }
+    Ok(logs)
+}
+
+/// Every log found in `paths`, named by where it was found.
+pub fn logs(paths: &[impl AsRef<Path>]) -> Result<Vec<(String, String)>, Error> {
+    found(paths)?
+        .into_iter()
+        .map(|(name, log)| match log {
+            Ok(log) => Ok((name, log)),
+            Err(Error::Parse(message)) => Err(Error::Parse(format!("{name}: {message}"))),
+            Err(e) => Err(e),
+        })
+        .collect()
+}
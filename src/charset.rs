@@ -0,0 +1,184 @@
+//! Repairing names garbled by being decoded in the wrong character set, as by rippers that read
+//! Shift-JIS or UTF-8 tags as Latin-1: `ƒ\u{81}ƒ‹ƒg` back to `メルト`, `CafÃ©` back to `Café`.
+//!
+//! A garbled name is turned back into the bytes it was decoded from, as Latin-1 or Windows-1252,
+//! and those bytes are decoded again: as UTF-8 if they are UTF-8, or else as Shift-JIS if that
+//! makes Japanese of them. Shift-JIS is only tried on names with a character no name would have
+//! on purpose, like a control character or `‹`, so `Françoise` stays as it is.
+//!
+//! `cp932.bin` holds Shift-JIS as Windows (code page 932) decodes it, as made by decoding each
+//! two-byte code with Python's `cp932` codec: a big-endian `u16` per code, rows of 94, two rows
+//! for each lead byte from 0x81 to 0x9F and 0xE0 to 0xFC, and 0 for codes with no character or a
+//! private-use one.
+
+use std::collections::BTreeMap;
+
+use crate::pipeline::Fixer;
+use crate::Scrobble;
+
+const CP932: &[u8; 120 * 94 * 2] = include_bytes!("cp932.bin");
+
+/// Windows-1252's characters for 0x80 to 0x9F, and the control characters Latin-1 has there
+/// for the bytes it leaves out.
+const CP1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// Whether to repair garbled names, or only list the repairs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CharsetRepair {
+    Preview,
+    Apply,
+}
+
+impl std::str::FromStr for CharsetRepair {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preview" => Ok(CharsetRepair::Preview),
+            "apply" => Ok(CharsetRepair::Apply),
+            other => Err(format!("unknown charset repair: {other}")),
+        }
+    }
+}
+
+/// The byte `c` is decoded from, as Windows-1252 or Latin-1.
+fn byte(c: char) -> Option<u8> {
+    match CP1252.iter().position(|&special| special == c) {
+        Some(i) => Some(0x80 + i as u8),
+        None => u8::try_from(u32::from(c)).ok(),
+    }
+}
+
+/// `bytes` decoded as Shift-JIS, if they are Shift-JIS.
+fn shift_jis(bytes: &[u8]) -> Option<String> {
+    let mut text = String::new();
+    let mut bytes = bytes.iter().copied();
+    while let Some(lead) = bytes.next() {
+        let c = match lead {
+            0x00..=0x7f => char::from(lead),
+            // Half-width katakana.
+            0xa1..=0xdf => char::from_u32(0xff61 + u32::from(lead - 0xa1))?,
+            0x81..=0x9f | 0xe0..=0xfc => {
+                let trail = bytes.next()?;
+                let cell = match trail {
+                    0x40..=0x7e => trail - 0x40,
+                    0x80..=0x9e => trail - 0x41,
+                    0x9f..=0xfc => trail - 0x9f,
+                    _ => return None,
+                };
+                let pair = if lead < 0xa0 {
+                    lead - 0x81
+                } else {
+                    lead - 0xc1
+                };
+                let row = usize::from(pair) * 2 + usize::from(trail >= 0x9f);
+                let at = (row * 94 + usize::from(cell)) * 2;
+                match u16::from_be_bytes([CP932[at], CP932[at + 1]]) {
+                    0 => return None,
+                    code => char::from_u32(code.into())?,
+                }
+            }
+            _ => return None,
+        };
+        text.push(c);
+    }
+    Some(text)
+}
+
+fn is_japanese(c: char) -> bool {
+    // CJK punctuation, kana, ideographs, and full- and half-width forms.
+    matches!(c, '\u{3000}'..='\u{30ff}' | '\u{4e00}'..='\u{9fff}' | '\u{ff00}'..='\u{ffef}')
+}
+
+/// `name` decoded as it should have been, if it's garbled.
+pub fn repair(name: &str) -> Option<String> {
+    let bytes: Vec<u8> = name.chars().map(byte).collect::<Option<_>>()?;
+    if bytes.is_ascii() {
+        return None;
+    }
+    if let Ok(text) = String::from_utf8(bytes.clone()) {
+        return Some(text);
+    }
+    if !name.chars().any(|c| !c.is_ascii() && !c.is_alphabetic()) {
+        return None;
+    }
+    shift_jis(&bytes).filter(|text| text.chars().filter(|&c| is_japanese(c)).count() >= 2)
+}
+
+/// A garbled name, what it's repaired to, and how many records have it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Repair {
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+    pub records: usize,
+}
+
+/// The repairs [`CharsetFixer`] would make, once for each field and name, by field and name.
+pub fn repairs(scrobbles: &[Scrobble]) -> Vec<Repair> {
+    let mut repairs: BTreeMap<(&'static str, &str), Repair> = BTreeMap::new();
+    for scrobble in scrobbles {
+        for (field, name) in [
+            ("artist", &scrobble.artist),
+            ("album", &scrobble.album),
+            ("track", &scrobble.track),
+        ] {
+            if let Some(repair) = repairs.get_mut(&(field, name.as_str())) {
+                repair.records += 1;
+            } else if let Some(after) = repair(name) {
+                let repair = Repair {
+                    field,
+                    before: name.clone(),
+                    after,
+                    records: 1,
+                };
+                repairs.insert((field, name), repair);
+            }
+        }
+    }
+    repairs.into_values().collect()
+}
+
+/// Repairs garbled artist, album and track names.
+#[derive(Debug, Clone, Default)]
+pub struct CharsetFixer;
+
+impl Fixer for CharsetFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        for scrobble in &mut scrobbles {
+            for field in [
+                &mut scrobble.artist,
+                &mut scrobble.album,
+                &mut scrobble.track,
+            ] {
+                if let Some(repaired) = repair(field) {
+                    *field = repaired;
+                }
+            }
+        }
+        Ok(scrobbles)
+    }
+}
+
+#[test]
+fn repair_mojibake() {
+    // メルト in Shift-JIS, read as Windows-1252.
+    assert_eq!(repair("ƒ\u{81}ƒ‹ƒg").as_deref(), Some("メルト"));
+    assert_eq!(repair("CafÃ©").as_deref(), Some("Café"));
+    assert_eq!(repair("Françoise"), None);
+    assert_eq!(repair("Sigur Rós"), None);
+    assert_eq!(repair("メルト"), None);
+    assert_eq!(repair("Low"), None);
+
+    let line = "ryo\tƒ\u{81}ƒ‹ƒg\tƒ\u{81}ƒ‹ƒg\t1\t262\tL\t1699413807\t";
+    let scrobbles = vec![Scrobble::new(line).unwrap(); 2];
+    let found = repairs(&scrobbles);
+    assert_eq!(found.len(), 2);
+    assert_eq!((found[0].field, found[0].records), ("album", 2));
+    let fixed = CharsetFixer.fix(scrobbles).unwrap();
+    assert_eq!((&*fixed[0].album, &*fixed[0].track), ("メルト", "メルト"));
+    assert_eq!(fixed[0].artist, "ryo");
+}
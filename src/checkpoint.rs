@@ -0,0 +1,211 @@
+//! A batch run's checkpoint: how each log went, kept as the run goes, so a run cut short, or one
+//! that met logs it couldn't fix, can be run again without fixing the others again.
+//!
+//! The checkpoint is a directory. `checkpoint.tsv` has a line per log, `status\tsha256\toptions\t
+//! name\tdetail`: `ok` with `changed/total` records, or `failed` with why, and an empty hash when
+//! the log couldn't be read. `options` is a digest of the options it was fixed with, as a log
+//! fixed with others has to be fixed again. Each log fixed is kept beside it as JSON Lines, in
+//! `SHA256.jsonl`.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use scrobble_fix::{input, Scrobble};
+
+use crate::{files, log};
+
+const FILE_NAME: &str = "checkpoint.tsv";
+
+/// A log's name as a field of a line.
+fn key(name: &str) -> String {
+    name.replace(['\t', '\n'], " ")
+}
+
+/// How a log went, as recorded.
+#[derive(Debug, Clone)]
+struct Entry {
+    ok: bool,
+    hash: String,
+    options: String,
+    detail: String,
+}
+
+/// A log fixed by an earlier run.
+pub struct Done {
+    pub scrobbles: Vec<Scrobble>,
+    /// Records the fixes changed, and records read.
+    pub changed: usize,
+    pub total: usize,
+}
+
+/// The checkpoint of a batch run.
+pub struct Checkpoint {
+    dir: PathBuf,
+    /// Each log's entry, by name, in the order they were recorded.
+    entries: Vec<(String, Entry)>,
+    index: HashMap<String, usize>,
+    /// The digest of this run's options.
+    options: String,
+    /// Only logs that failed before are fixed again.
+    retry_failed: bool,
+    /// Held while the run goes, so two runs can't record into it at once.
+    _lock: files::Lock,
+}
+
+impl Checkpoint {
+    /// Load the checkpoint in `dir`, or start one there, for a run whose options have the digest
+    /// `options`.
+    pub fn load(dir: &Path, options: &str, retry_failed: bool) -> io::Result<Self> {
+        let path = dir.join(FILE_NAME);
+        let lock = files::lock(&path)?;
+        let lines = match std::fs::read_to_string(&path) {
+            Ok(lines) => lines,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut checkpoint = Checkpoint {
+            dir: dir.to_path_buf(),
+            entries: Vec::new(),
+            index: HashMap::new(),
+            options: options.to_string(),
+            retry_failed,
+            _lock: lock,
+        };
+        for line in lines.lines() {
+            let fields: Vec<&str> = line.splitn(5, '\t').collect();
+            let [status @ ("ok" | "failed"), hash, options, name, detail] = fields[..] else {
+                continue;
+            };
+            let entry = Entry {
+                ok: status == "ok",
+                hash: hash.to_string(),
+                options: options.to_string(),
+                detail: detail.to_string(),
+            };
+            checkpoint.set(name, entry);
+        }
+        Ok(checkpoint)
+    }
+
+    fn set(&mut self, name: &str, entry: Entry) {
+        match self.index.get(name) {
+            Some(&i) => self.entries[i].1 = entry,
+            None => {
+                self.index.insert(name.to_string(), self.entries.len());
+                self.entries.push((name.to_string(), entry));
+            }
+        }
+    }
+
+    fn entry(&self, name: &str) -> Option<&Entry> {
+        self.index.get(&key(name)).map(|&i| &self.entries[i].1)
+    }
+
+    fn fixed_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{hash}.jsonl"))
+    }
+
+    /// The log named `name`, with SHA-256 `hash`, as an earlier run fixed it, if one did with
+    /// these options and it hasn't changed since.
+    pub fn done(&self, name: &str, hash: &str) -> Option<Done> {
+        let entry = self
+            .entry(name)
+            .filter(|entry| entry.ok && entry.hash == hash && entry.options == self.options)?;
+        let (changed, total) = entry.detail.split_once('/')?;
+        let text = std::fs::read_to_string(self.fixed_path(hash)).ok()?;
+        match input::parse_json_lines(&text) {
+            Ok(scrobbles) => Some(Done {
+                scrobbles,
+                changed: changed.parse().ok()?,
+                total: total.parse().ok()?,
+            }),
+            Err(e) => {
+                log::warn(format_args!(
+                    "{name}: the checkpoint's copy is bad ({e}); fixing it again"
+                ));
+                None
+            }
+        }
+    }
+
+    /// Whether the log named `name`, which isn't [`done`](Checkpoint::done), is left for
+    /// another run: with `--retry-failed`, any not seen before. Those that failed, or that
+    /// changed or were fixed with other options since, are fixed again.
+    pub fn passes_over(&self, name: &str) -> bool {
+        self.retry_failed && self.entry(name).is_none()
+    }
+
+    /// Record the log named `name` fixed into `scrobbles`.
+    pub fn succeeded(
+        &mut self,
+        name: &str,
+        hash: &str,
+        scrobbles: &[Scrobble],
+        changed: usize,
+        total: usize,
+    ) -> io::Result<()> {
+        let lines: String = scrobbles
+            .iter()
+            .map(|scrobble| scrobble.to_json() + "\n")
+            .collect();
+        files::replace(&self.fixed_path(hash), lines)?;
+        let detail = format!("{changed}/{total}");
+        self.record(name, true, hash, &detail)
+    }
+
+    /// Record that the log named `name` couldn't be read or fixed, and why.
+    pub fn failed(&mut self, name: &str, hash: &str, error: &str) -> io::Result<()> {
+        self.record(name, false, hash, error)
+    }
+
+    fn record(&mut self, name: &str, ok: bool, hash: &str, detail: &str) -> io::Result<()> {
+        let entry = Entry {
+            ok,
+            hash: hash.to_string(),
+            options: self.options.clone(),
+            detail: detail.replace(['\t', '\n'], " "),
+        };
+        self.set(&key(name), entry);
+        let lines: String = self
+            .entries
+            .iter()
+            .map(|(name, entry)| {
+                let status = if entry.ok { "ok" } else { "failed" };
+                let (hash, options) = (&entry.hash, &entry.options);
+                format!("{status}\t{hash}\t{options}\t{name}\t{}\n", entry.detail)
+            })
+            .collect();
+        files::replace(&self.dir.join(FILE_NAME), lines)
+    }
+}
+
+#[test]
+fn resume_from_checkpoint() {
+    let dir = std::env::temp_dir().join(format!("scrobble-fix-checkpoint-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let line = "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t";
+    let scrobbles = [Scrobble::new(line).unwrap()];
+    let mut checkpoint = Checkpoint::load(&dir, "a", false).unwrap();
+    assert!(checkpoint.done("one", "1").is_none() && !checkpoint.passes_over("one"));
+    checkpoint.succeeded("one", "1", &scrobbles, 1, 2).unwrap();
+    checkpoint.failed("two\tlogs", "2", "bad\nrecord").unwrap();
+    drop(checkpoint);
+
+    let checkpoint = Checkpoint::load(&dir, "a", true).unwrap();
+    let done = checkpoint.done("one", "1").unwrap();
+    assert_eq!((done.scrobbles.len(), done.changed, done.total), (1, 1, 2));
+    // Changed since, or failed: fixed again.
+    assert!(checkpoint.done("one", "3").is_none() && !checkpoint.passes_over("one"));
+    assert!(checkpoint.done("two\tlogs", "2").is_none());
+    assert!(!checkpoint.passes_over("two\tlogs"));
+    // Not seen before: left for a run without --retry-failed.
+    assert!(checkpoint.passes_over("three"));
+    drop(checkpoint);
+
+    // Fixed with other options: fixed again.
+    let checkpoint = Checkpoint::load(&dir, "b", false).unwrap();
+    assert!(checkpoint.done("one", "1").is_none());
+    drop(checkpoint);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
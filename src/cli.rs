@@ -0,0 +1,1443 @@
+//! Command line parsing.
+
+use std::path::PathBuf;
+
+use chrono::FixedOffset;
+use scrobble_fix::charset::CharsetRepair;
+use scrobble_fix::collate::Collation;
+use scrobble_fix::generate::{Encoding, Generator};
+use scrobble_fix::input::InputFormat;
+use scrobble_fix::merge::{DuplicatePolicy, OverlapPolicy};
+use scrobble_fix::metadata::{CasePolicy, DurationUnit, FeaturingPolicy};
+use scrobble_fix::podcast::PodcastPolicy;
+use scrobble_fix::query::Query;
+use scrobble_fix::report::Period;
+use scrobble_fix::serialize::EmitTz;
+use scrobble_fix::template::Template;
+use scrobble_fix::timestamps::{
+    parse_utc_offset, Detector, Jitter, ListeningHours, Nudge, SuspiciousPolicy,
+};
+use scrobble_fix::{SkipPolicy, SCROBBLE_DAYS_OFFSET};
+
+use crate::log;
+
+pub const USAGE: &str = "usage: scrobble-fix [COMMAND] [OPTIONS] [FILE]
+
+Reads FILE (default: scrobbler.log) and prints it with fixed timestamps.
+
+commands:
+  fix (default)       print the log with suspicious timestamps fixed
+  analyze days        list days holding more music than the day is long
+  analyze artists     count scrobbles per artist; with --fuzzy, group near-identical
+                      spellings and print rewrite rules mapping them onto the most used one
+  analyze timeline    chart scrobbles per day as logged above the same once fixed, as SVG,
+                      or to the file of --output (SVG, or an HTML page if it ends in .html)
+  analyze timezone    for a log whose clock was set to an unknown timezone, guess its UTC
+                      offset from the hours it was played at, as likely --input-tz values
+  analyze playcounts  with --tagcache DIR, compare each track's plays in the log with the play
+                      count of Rockbox's runtime database, to estimate how many plays were
+                      lost to crashes before being logged: prints counted, logged and lost
+                      plays, artist and track, most lost first (for a log cleared after
+                      uploads, run it on the archive, which holds every log)
+  analyze resets      sum up the records a reset clock logged (those --detect flags): how
+                      many, the music in them, the real time they likely span once fixed,
+                      and how many the fixes put in each month
+  report              like fix, then sum up what was played each --period: plays, hours of
+                      music, and the top artists and albums, as Markdown (or --format json)
+  enrich              like fix, also looking up missing track ids on MusicBrainz
+  batch PATH...       like fix, for every scrobbler.log found in the PATHs, combined into one
+                      log without duplicates; a PATH can be a log, a directory to search, a
+                      tarball (optionally gzipped), a zip archive, or a FAT disk image
+  submit              like fix, then scrobble the listened tracks to Last.fm (or --backend),
+                      skipping any sent before; needs `auth login lastfm`, or
+                      $LASTFM_API_KEY, $LASTFM_API_SECRET and $LASTFM_SESSION_KEY
+  review              like fix, first showing each record beside its corrected form to accept
+                      or reject (j/k move, tab next change, a/r accept/reject, / search,
+                      w write, q quit without writing)
+  rules test RULES    apply the rewrite rules in the file RULES (see --rules) to the log
+                      without writing it, and report how many records each rule rewrote and
+                      which rules never matched
+  db import FILE...   fix the FILEs (of any --input-format) and add their scrobbles to the
+                      archive, a scrobbler.log kept at $XDG_DATA_HOME/scrobble-fix/archive.log
+                      or --db PATH, sorted and without duplicates; FILEs with the same
+                      SHA-256 as one imported before, listed in the archive's manifest (see
+                      paths), are skipped
+  db query QUERY      print the archived scrobbles QUERY matches, in any --format, e.g.
+                      `artist = 'Low' AND year = 2007`: compare artist, album, track or rating
+                      (L or S) with =, != or ~ (contains, ignoring case, or matches a glob
+                      with *, ? or [...]) and a quoted value; duration, position, timestamp,
+                      year, month, day or hour with =, !=, <, <=, > or >= and a number;
+                      combine with AND, OR, NOT (or &&, ||, !) and parentheses
+  db export           print the whole archive, in any --format
+  lint                check the log against the AUDIOSCROBBLER/1.1 format: the header, field
+                      counts, ratings, numbers, UTF-8, and timestamps going backwards; prints
+                      each finding as FILE:LINE: error|warning: MESSAGE
+  show --line N       print line N of the log field by field: its bytes, each field's text and
+                      column, the values parsed from them, and what the fixes change in it
+  fmt [FILE...]       rewrite each log in canonical form, to keep logs in git without noise:
+                      the AUDIOSCROBBLER/1.1 header, every record written the same way, LF
+                      line endings and UTF-8 (a log that isn't is read as Latin-1), no blank
+                      lines; timestamps and text are left as they are. Stops at a line that
+                      isn't a record. With --check, write nothing, list the logs that aren't
+                      canonical, and fail if there are any, as a pre-commit hook would
+  doctor              run every check there is on the log: lint, encoding, suspicious and
+                      future dates, clock resets, duplicates and shared timestamps; prints what
+                      it finds, worst first, each with the options that fix it
+  paths               print where the files kept between runs are: the default rewrite
+                      rules ($XDG_CONFIG_HOME/scrobble-fix/rules.toml, read without --rules),
+                      the default ignore file (see --no-ignore), the archive, Last.fm
+                      submission progress, the MusicBrainz cache, and the audit log
+                      ($XDG_STATE_HOME/scrobble-fix/audit.jsonl), which gains a JSON line for
+                      every file written and every batch submitted, with its SHA-256 and the
+                      dates of the scrobbles in it
+  schema NAME         print the JSON Schema of what scrobble-fix writes as JSON, to validate
+                      or generate code against: record (a line of --format json, and of
+                      --input-format jsonl), summary (what --post-hook and --notify-webhook
+                      are given), or audit (a line of the audit log)
+  generate            print a synthetic scrobbler.log, for tests and bug reports (see --count,
+                      --resets, --corrupt, --seed and --encoding); the same options always
+                      print the same log
+  self-update         replace this binary with the latest GitHub release for its platform,
+                      once the release's SHA256SUMS is verified against the key it was built
+                      with ($SCROBBLE_FIX_RELEASE_KEY) and the download matches it; with
+                      --dry-run, only say whether there is a newer release
+  auth login lastfm|listenbrainz
+                      authorize scrobble-fix and keep the credentials in the OS keyring; for
+                      Last.fm, opens the authorization page and waits until access is allowed
+  auth logout lastfm|listenbrainz
+                      remove the stored credentials
+
+options:
+  --preset NAME       give the options of a preset, for a common job, in its place; options
+                      after it override them, and repeatable ones add to them:
+                        lastfm-backfill       for submit, sending an old log to Last.fm:
+                                              --nudge-collisions duration --check-existing
+                                              --max-changes 50%
+                        listenbrainz-migrate  for fix, batch and db export, moving a history
+                                              to ListenBrainz: --format listenbrainz
+                                              --skipped listenbrainz=flag --fix-shifted-titles
+                                              --max-changes 50%
+                        archive-merge         for batch, db import and --append, combining
+                                              logs into one: --strict --read-only --sort
+                                              --dedupe-policy merge --overlap-policy keep-first
+  --escape            fields in the input (and --append MASTER) have tabs, newlines and
+                      backslashes escaped as \\t, \\n and \\\\; escape them the same way in log output
+  --strict            stop at the first record that can't be parsed, instead of leaving it out
+  --max-changes N|P%  stop before writing or submitting anything if the fixes would change
+                      or drop more than N records, or more than P percent of them, as a
+                      misconfigured fix might
+  --suspicious-action shift|drop|keep|reconstruct
+                      what to do with suspicious scrobbles (see --detect): add the offset
+                      (default), leave them out, leave them alone, or rebuild their timestamps
+                      back to back from the neighbouring trustworthy scrobbles
+  --offset-days DAYS  days to add to suspicious scrobbles with --suspicious-action shift;
+                      negative to subtract them (default 8245)
+  --listening-hours HH:MM-HH:MM|auto
+                      with --suspicious-action reconstruct, only start reconstructed plays
+                      between these local times, skipping nights instead of filling them; with
+                      auto, the fewest hours holding 95% of the log's trustworthy plays
+  --jitter MIN..MAX   with --suspicious-action reconstruct, leave MIN to MAX more seconds, at
+                      most 30, between reconstructed plays, drawn from --seed so runs repeat
+  --detect DETECTOR   how to tell a timestamp is suspicious; repeat to combine (any of them
+                      flags it). The default is `cutoff`:
+                        cutoff                 at or before 2005-01-01
+                        near-reset:DATE[:DAYS] within DAYS (default 30) of DATE, a date the
+                                               clock resets to (YYYY-MM-DD, RFC 3339, or epoch
+                                               seconds)
+                        backwards:SECONDS      more than SECONDS earlier than the last
+                                               trustworthy scrobble before it
+                        zero                   exactly 0, an unset clock
+                        after:DATE             at or after DATE, from a clock that ran ahead
+                                               (pull them back with a negative --offset-days)
+  --nudge-collisions duration|second
+                      move scrobbles sharing a timestamp forward until each is unique: to
+                      when the previous scrobble finished, or one second at a time
+  --listened-threshold P%
+                      rate a play listened (L) if at least P percent of the track was heard,
+                      and skipped (S) if not, instead of Rockbox's 50%; only plays whose
+                      next one started before the track's end are rated again, since a longer
+                      gap could be a pause
+  --tagcache DIR      respell the artist, album and track of every record matching a track in
+                      the Rockbox database in DIR (the device's .rockbox directory, holding
+                      database_idx.tcd), ignoring case and spacing, as the device tagged it
+  --backfill-albums LIBRARY
+                      fill in the album of every record without one from the ID3 tags of the
+                      MP3 files under the directory LIBRARY, matching artist and title
+                      ignoring case and spacing (left empty when copies are on several albums)
+  --rules RULES       rewrite fields with the rules in the TOML file RULES: [[rule]] tables of
+                      `field` (artist, album or track), `match` (a string, or an array of
+                      them) and `replace`, as `analyze artists --fuzzy` prints; without it,
+                      the default rules (see paths) are used if that file exists
+  --no-ignore         keep the records ignore files leave out: the default one (see paths,
+                      applying to every log) and the .scrobbleignore beside FILE, each line a
+                      pattern like `artist:Nickelback`, `track:*(Interlude)*` or
+                      `album:/^now [0-9]+/`, a glob or a /regular expression/, of any case, on
+                      one field or on any; a leading ! keeps what earlier lines left out, and
+                      the last line matching a record decides, as in .gitignore
+  --where EXPR        keep only the records EXPR matches, once fixed, in the language of db
+                      query, like `duration > 120 && rating == 'L' && artist ~ 'Boards*'`
+  --fix-shifted-titles
+                      for records with an album but no track, as some encoders write when a
+                      file has no album tag, take the album for the track instead; without an
+                      artist either, split an `Artist - Title` album between the two
+  --repair-charset preview|apply
+                      repair artist, album and track names a ripper garbled by reading
+                      Shift-JIS or UTF-8 tags as Latin-1 (like `CafÃ©` for `Café`); with
+                      preview, list each name and its repair, with the records having it,
+                      instead of fixing the log
+  --duration-unit auto|s|ms
+                      what unit the input's durations are in, writing seconds either way:
+                      milliseconds if most are over 3 hours (default), seconds as the format
+                      says, or milliseconds, as some third-party players log them
+  --featuring move|strip
+                      take featured artists (`Artist feat. Guest`, ft., featuring) out of
+                      the artist field: into the track title as `(feat. Guest)`, or dropped
+  --case-policy keep|title|lastfm
+                      recapitalize artist, album and track names: leave them (default), title
+                      case (`The Lord of the Rings`), or capitalize every word without touching
+                      the rest of it (`The Lord Of The Rings`, `JPEGMAFIA`)
+  --case-exception WORD
+                      with --case-policy, always write WORD exactly like this (for stylized
+                      names like `deadmau5`); repeat for several words
+  --podcast-artist-list FILE
+                      take every record by an artist listed in FILE, one per line, ignoring
+                      case, for a podcast episode
+  --podcast-duration SECONDS
+                      take records at least SECONDS long without a track position for
+                      podcast episodes too
+  --podcasts tag|drop|only
+                      with either of those, tag episodes with a `# podcast` comment above
+                      their record (default), leave them out, or leave out everything else
+  --podcast-output PATH
+                      with either of those, write episodes to PATH as a scrobbler.log,
+                      leaving them out of everything else
+  --format log|table|listenbrainz|listenbrainz-zip|json|markdown|bulk-edit|openscrobbler
+                      output AUDIOSCROBBLER/1.1 (default), an aligned table, a
+                      ListenBrainz import payload, a zip archive laid out like a ListenBrainz
+                      export (in a listens/YEAR/MONTH.jsonl per month; stdout must not be a
+                      terminal), JSON Lines with every field, including how each timestamp
+                      was corrected, or CSV for Open Scrobbler's bulk scrobbler (these three
+                      with listened scrobbles only, unless --skipped says otherwise);
+                      bulk-edit lists, for fixing one FILE, each artist, track and album
+                      change with how many scrobbles it makes, as tab-separated rows to go
+                      through with lastfm-bulk-edit; report writes markdown (default) or json
+  --period weekly|monthly
+                      with report, sum up each week, from Monday, or each month (default)
+  --input-format, --from auto|log|jsonl|csv|lastfm|listenbrainz
+                      read FILE (or each batch input) as: whatever its first line shows
+                      (default); a scrobbler.log, AUDIOSCROBBLER/1.1 or 1.0; JSON Lines as
+                      --format json writes; CSV, with a header row naming the artist, track
+                      and timestamp columns (album, track_position, duration, rating and
+                      track_id are optional), or headerless artist,album,track,date rows;
+                      saved Last.fm user.getRecentTracks JSON: one page, an array of pages, or
+                      a page per line; or ListenBrainz listens, one per line or an array.
+                      A ListenBrainz export zip is read whole, from its listens/ files
+  --input-tz OFFSET   the UTC offset, like +01:00 or -8, the device's clock was set to, for
+                      a log with #TZ/UNKNOWN: timestamps are moved to UTC, and a log written
+                      says #TZ/UTC
+  --emit-tz utc|local|fixed:OFFSET
+                      write timestamps as seconds since the epoch (utc, the default), or as
+                      the time on a clock in this system's timezone (local, following its
+                      daylight saving changes) or at OFFSET from UTC, like fixed:+02:00, read
+                      as if it were UTC, as a #TZ/UNKNOWN log keeps them; with --format log
+  --tz ZONE           the timezone local times are in, instead of $TZ or /etc/localtime: a
+                      name like Europe/Berlin, an offset like +02:00, UTC, or a POSIX TZ
+                      string; a zone that can't be found is an error, and with neither
+                      there, local times are in UTC, with a warning
+  --template TEMPLATE write each scrobble as a line of TEMPLATE, like
+                      `{artist} - {track} ({date})`, instead of in a --format: fields in
+                      braces are artist, album, track, album_artist, position, duration
+                      (seconds), length (m:ss), rating, track_id, timestamp (epoch seconds),
+                      and the local date and time, which take a strftime format, like
+                      `{date:%B %Y}`; write {{ and }} for braces
+  --wide              with --format table, never truncate fields to the terminal width
+  --preserve-lines    with --format log, copy the header, line endings and every record the
+                      fix leaves alone from FILE byte for byte, rewriting only changed records,
+                      for the smallest diff against it
+  --read-only         never write to FILE (or the batch PATHs, or anything inside them), for
+                      pointing scrobble-fix at the only copy of a log, on the device itself;
+                      rejects --append onto an input, and says so on stderr
+  --db PATH           with db, keep the archive at PATH instead
+  --append MASTER     add the fixed scrobbles missing from MASTER to it, instead of printing
+  --sort              with --append, sort MASTER by timestamp before rewriting it; with batch,
+                      sort the combined log; with analyze artists, list artists alphabetically
+  --collate LANG      with analyze artists, put names in alphabetical order as LANG does (en,
+                      de, fr, es, sv, da, ... or root): accents and case after the letters,
+                      Édith Piaf among the Es, ignoring a leading The; it breaks ties in
+                      play counts too, and without it names are compared byte by byte
+  --checkpoint DIR    with batch, record in the directory DIR how each log went as the run
+                      goes, keeping a copy of each fixed; a rerun takes the logs fixed before
+                      from there, unless they've changed or the fixes asked for (or the
+                      files they read) have, and fixes only the others; a log that can't be
+                      read or fixed is left out with a warning, instead of ending the run
+  --retry-failed      with --checkpoint, fix again only the logs that failed before, or that
+                      changed or were fixed with other options since, leaving out any not
+                      seen before
+  --dedupe-policy keep-first|keep-last|merge
+                      with --append, batch and db import, which of two records of the same
+                      play to keep: the one there first (default), the one coming in, or the
+                      one with the most complete metadata (say, the one with a MusicBrainz
+                      id), with any gaps filled from the other
+  --overlap-policy keep-first|keep-last|keep-both|ask
+                      with --append, batch and db import, what to do with a session logged in
+                      both logs at different times, as by two devices or by a log copied
+                      before it was cleared (3 or more of the same tracks in a row at times
+                      that overlap): keep the records there first (default), the ones coming
+                      in, or both, or ask about each
+  --refresh-cache     with enrich, ignore cached MusicBrainz lookups and redo them
+  --split-loops       with enrich, split a record lasting several times as long as the track
+                      MusicBrainz says it is, as when it was left on repeat, into a scrobble
+                      per loop, each a track length after the one before
+  --backend lastfm|audioscrobbler
+                      with submit, send to Last.fm (default), or to a server speaking the old
+                      Audioscrobbler 1.2 handshake protocol, like a self-hosted GNU FM: set
+                      in the [audioscrobbler] table of $XDG_CONFIG_HOME/scrobble-fix/config.toml
+                      with `handshake` (its URL), `user`, and optionally `client` (an id, tst by
+                      default) and `password_md5`, else the password is $AUDIOSCROBBLER_PASSWORD
+  --skipped [OUTPUT=]drop|listen|flag
+                      what outputs without skips, the listenbrainz, listenbrainz-zip and
+                      openscrobbler formats and every --backend, do with skipped scrobbles:
+                      leave them out (default), send them as listens, or send listens marked
+                      `skipped` in their additional_info (ListenBrainz only; elsewhere, left
+                      out); OUTPUT, one of listenbrainz, openscrobbler, lastfm or
+                      audioscrobbler, sets it for that output alone, and can be given for each
+  --max-submit N      with submit, send at most N scrobbles, leaving the rest for later
+  --schedule daily    with --max-submit, send N a day, waiting for midnight until all are sent
+  --dry-run           with submit, print each batch's signed request (session key redacted)
+                      instead of sending it, and remember nothing as submitted; with
+                      self-update, only look for a newer release
+  --check             with fmt, rewrite nothing; list the logs that aren't canonical, failing
+                      if there are any
+  --jobs N            with submit, send up to N batches of 50 at once (default: 1), still
+                      within Last.fm's rate limit
+  --check-existing    with submit, leave out scrobbles already on the Last.fm profile of
+                      the logged in user, or $LASTFM_USER (the same track within 5 minutes)
+  --clear-source-after-submit
+                      with submit, once every record of FILE (a scrobbler.log) is submitted,
+                      or in the archive for skipped plays, empty FILE down to its header, as
+                      official clients do; asks twice on a terminal first, and keeps a copy in
+                      $XDG_STATE_HOME/scrobble-fix/cleared
+  --profile-snapshot FILE
+                      with --check-existing, compare against saved user.getRecentTracks pages
+                      instead of fetching the profile; repeat for several files
+  --user NAME         with submit and auth, use the credentials of the profile NAME (letters,
+                      digits, - and _), kept apart from the default ones and logged in with
+                      `auth login lastfm --user NAME`, and remember its submissions apart too;
+                      a profile shares the default Last.fm API key and secret if it has none,
+                      and ignores the $LASTFM_ variables otherwise
+  --fuzzy             with analyze artists, cluster near-identical artist names
+  --output PATH       with analyze timeline, write the chart to PATH instead of stdout
+  --pre-hook CMD      before fixing, pipe the records to the shell command CMD as JSON Lines,
+                      one object per record, and carry on with the records it prints back
+  --post-hook CMD     after the run, pipe the JSON summary to the shell command CMD
+  --notify-webhook URL
+                      after the run, POST a JSON summary (including any error) to URL
+  -v, --verbose       say more on stderr: -v adds each web request, -vv every record as it's
+                      parsed and corrected, or why its timestamp was trusted; messages about a
+                      record name its line or its artist, track and timestamp
+  -q, --quiet         say nothing on stderr but errors, and the questions of prompts; stdout
+                      only ever has what was asked for
+  --log-format text|json
+                      write stderr messages as text (default), or as JSON Lines with `time`,
+                      `level` and `message`, plus `line` and `record` for a record's messages
+  --timeout SECONDS   give up on a web request after SECONDS; requests that time out, can't
+                      connect, or get a 429 or 5xx answer are retried a few times, waiting
+                      longer each time
+  --proxy URL         send web requests through the proxy at URL (http://, https://, socks4://,
+                      socks5://, or socks5h:// to resolve names through it too, as for Tor);
+                      without it, $https_proxy, $http_proxy ($HTTP_PROXY) and $ALL_PROXY are
+                      used, except for the hosts in $NO_PROXY
+  --count N           with generate, write N good records (default: 1000)
+  --resets N          with generate, cut the log into N stretches logged by a clock reset to
+                      2001, which the default fix puts right (default: 1)
+  --corrupt N         with generate, mix in N lines that can't be parsed (default: 0)
+  --seed N            with generate, pick tracks, gaps and corruptions from seed N; with
+                      --jitter, the gaps (default: 1)
+  --encoding utf-8|latin1
+                      with generate, write the log as UTF-8 (default) or ISO-8859-1
+  --threshold FILL    with analyze days, flag days over FILL days of music (default: 1.0)
+
+exit status:
+  0  success
+  1  bad arguments, or a file couldn't be read or written
+  2  partial: some records couldn't be parsed and were left out; with lint or doctor, only warnings
+  3  nothing to do: no scrobbles in the input, or none new for --append
+  4  the input couldn't be parsed (with --strict, any bad record; with lint or doctor, any error)
+  5  network failure: a web service couldn't be reached";
+
+/// A bundle of options for a common job, which `--preset` gives by name.
+#[derive(Debug)]
+pub struct Preset {
+    pub name: &'static str,
+    pub options: &'static [&'static str],
+    /// The commands it's for.
+    pub commands: &'static [Command],
+}
+
+/// Every preset, as `--preset` names them.
+pub const PRESETS: [Preset; 3] = [
+    Preset {
+        name: "lastfm-backfill",
+        options: &[
+            "--nudge-collisions",
+            "duration",
+            "--check-existing",
+            "--max-changes",
+            "50%",
+        ],
+        commands: &[Command::Submit],
+    },
+    Preset {
+        name: "listenbrainz-migrate",
+        options: &[
+            "--format",
+            "listenbrainz",
+            "--skipped",
+            "listenbrainz=flag",
+            "--fix-shifted-titles",
+            "--max-changes",
+            "50%",
+        ],
+        commands: &[Command::Fix, Command::Batch, Command::DbExport],
+    },
+    Preset {
+        name: "archive-merge",
+        options: &[
+            "--strict",
+            "--read-only",
+            "--sort",
+            "--dedupe-policy",
+            "merge",
+            "--overlap-policy",
+            "keep-first",
+        ],
+        // Fix with --append, which --dedupe-policy checks for.
+        commands: &[Command::Batch, Command::DbImport, Command::Fix],
+    },
+];
+
+/// Replace each `--preset NAME` with the options of that preset, where it's given, so options
+/// after it override them; and the preset, for checking what it's given with.
+fn expand_presets(
+    args: impl Iterator<Item = String>,
+) -> Result<(Vec<String>, Option<&'static Preset>), String> {
+    let mut args = args;
+    let (mut expanded, mut preset) = (Vec::new(), None);
+    while let Some(arg) = args.next() {
+        if arg != "--preset" {
+            expanded.push(arg);
+            continue;
+        }
+        let name = value(&mut args, "--preset")?;
+        let found = PRESETS
+            .iter()
+            .find(|preset| preset.name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = PRESETS.iter().map(|preset| preset.name).collect();
+                format!("unknown preset: {name} ({})", names.join(", "))
+            })?;
+        if preset.is_some_and(|preset: &Preset| preset.name != found.name) {
+            Err("only one --preset can be given")?;
+        }
+        expanded.extend(found.options.iter().map(|option| option.to_string()));
+        preset = Some(found);
+    }
+    Ok((expanded, preset))
+}
+
+/// How many records a run may change, from `--max-changes`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeLimit {
+    Records(usize),
+    Percent(f64),
+}
+
+impl std::str::FromStr for ChangeLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |e| format!("--max-changes: {s}: {e}");
+        match s.strip_suffix('%') {
+            Some(percent) => match percent.parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => {
+                    Ok(ChangeLimit::Percent(percent))
+                }
+                Ok(_) => Err(error("not a percentage".to_string())),
+                Err(e) => Err(error(e.to_string())),
+            },
+            None => s
+                .parse()
+                .map(ChangeLimit::Records)
+                .map_err(|e| error(e.to_string())),
+        }
+    }
+}
+
+impl ChangeLimit {
+    /// The most records out of `total` that may change.
+    pub fn allowed(self, total: usize) -> usize {
+        match self {
+            ChangeLimit::Records(records) => records,
+            ChangeLimit::Percent(percent) => (total as f64 * percent / 100.0).floor() as usize,
+        }
+    }
+}
+
+/// Output formats for the fixed scrobbles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// AUDIOSCROBBLER/1.1, ready to be uploaded.
+    Log,
+    /// Aligned columns for reading in a terminal.
+    Table,
+    /// A ListenBrainz `import` payload.
+    ListenBrainz,
+    /// A zip archive like a ListenBrainz export.
+    ListenBrainzZip,
+    /// One JSON object per scrobble, provenance included.
+    Json,
+    /// A Markdown document, for `report`.
+    Markdown,
+    /// The metadata edits the fixes made, to apply with lastfm-bulk-edit.
+    BulkEdit,
+    /// CSV for Open Scrobbler's bulk scrobbler (listened scrobbles only).
+    OpenScrobbler,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(Format::Log),
+            "table" => Ok(Format::Table),
+            "listenbrainz" => Ok(Format::ListenBrainz),
+            "listenbrainz-zip" => Ok(Format::ListenBrainzZip),
+            "json" => Ok(Format::Json),
+            "markdown" => Ok(Format::Markdown),
+            "bulk-edit" => Ok(Format::BulkEdit),
+            "openscrobbler" => Ok(Format::OpenScrobbler),
+            other => Err(format!("unknown format: {other}")),
+        }
+    }
+}
+
+/// A service credentials can be stored for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Service {
+    LastFm,
+    ListenBrainz,
+}
+
+impl Service {
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::LastFm => "Last.fm",
+            Service::ListenBrainz => "ListenBrainz",
+        }
+    }
+}
+
+impl std::str::FromStr for Service {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lastfm" => Ok(Service::LastFm),
+            "listenbrainz" => Ok(Service::ListenBrainz),
+            other => Err(format!("unknown service: {other}")),
+        }
+    }
+}
+
+/// Where `submit` sends scrobbles.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Backend {
+    /// The Last.fm API.
+    #[default]
+    LastFm,
+    /// A server speaking the Audioscrobbler 1.2 protocol, set up in the config file.
+    Audioscrobbler,
+}
+
+impl Backend {
+    /// The backend as typed.
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::LastFm => "lastfm",
+            Backend::Audioscrobbler => "audioscrobbler",
+        }
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lastfm" => Ok(Backend::LastFm),
+            "audioscrobbler" => Ok(Backend::Audioscrobbler),
+            other => Err(format!("unknown backend: {other}")),
+        }
+    }
+}
+
+/// How often `submit` may send another `--max-submit` scrobbles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Schedule {
+    /// Once per local day.
+    Daily,
+}
+
+impl std::str::FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(Schedule::Daily),
+            other => Err(format!("unknown schedule: {other}")),
+        }
+    }
+}
+
+/// What to do with the input log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Print the log with fixed timestamps.
+    Fix,
+    /// Report days with implausibly many scrobbles.
+    AnalyzeDays,
+    /// Report scrobble counts, or clusters of similar names, per artist.
+    AnalyzeArtists,
+    /// Chart scrobbles per day, before and after fixing.
+    AnalyzeTimeline,
+    /// Guess the UTC offset of a log's clock from when it was played.
+    AnalyzeTimezone,
+    /// Compare the plays logged with the device's play counts.
+    AnalyzePlaycounts,
+    /// Sum up the records logged by a reset clock, and where the fixes put them.
+    AnalyzeResets,
+    /// Fix, then sum up each period's listening.
+    Report,
+    /// Fix, then fill in missing track ids from MusicBrainz.
+    Enrich,
+    /// Fix every log found in several files, directories, archives or disk images.
+    Batch,
+    /// Fix, then scrobble to Last.fm.
+    Submit,
+    /// Fix, letting the user accept or reject each correction on a terminal screen.
+    Review,
+    /// Report how many records each rewrite rule matches.
+    RulesTest,
+    /// Fix logs and add them to the archive.
+    DbImport,
+    /// Print the archived scrobbles a query matches.
+    DbQuery,
+    /// Print the whole archive.
+    DbExport,
+    /// Check the log against the format, without fixing it.
+    Lint,
+    /// Rewrite logs in canonical form.
+    Fmt,
+    /// Run every check on the log, suggesting how to fix what they find.
+    Doctor,
+    /// Print one line of the log field by field, and what the fixes do to it.
+    Show,
+    /// Print where files kept between runs are.
+    Paths,
+    /// Print the JSON Schema of an output.
+    Schema,
+    /// Print a synthetic log.
+    Generate,
+    /// Replace the binary with the latest release.
+    SelfUpdate,
+    /// Store credentials for a service in the keyring.
+    AuthLogin(Service),
+    /// Remove a service's credentials from the keyring.
+    AuthLogout(Service),
+}
+
+impl Command {
+    /// The command as typed, for run summaries.
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Fix => "fix",
+            Command::AnalyzeDays => "analyze days",
+            Command::AnalyzeArtists => "analyze artists",
+            Command::AnalyzeTimeline => "analyze timeline",
+            Command::AnalyzeTimezone => "analyze timezone",
+            Command::AnalyzePlaycounts => "analyze playcounts",
+            Command::AnalyzeResets => "analyze resets",
+            Command::Enrich => "enrich",
+            Command::Batch => "batch",
+            Command::Submit => "submit",
+            Command::Review => "review",
+            Command::RulesTest => "rules test",
+            Command::DbImport => "db import",
+            Command::DbQuery => "db query",
+            Command::DbExport => "db export",
+            Command::Lint => "lint",
+            Command::Fmt => "fmt",
+            Command::Doctor => "doctor",
+            Command::Show => "show",
+            Command::Paths => "paths",
+            Command::Report => "report",
+            Command::Schema => "schema",
+            Command::Generate => "generate",
+            Command::SelfUpdate => "self-update",
+            Command::AuthLogin(_) => "auth login",
+            Command::AuthLogout(_) => "auth logout",
+        }
+    }
+}
+
+/// Command line options.
+#[derive(Debug)]
+pub struct Args {
+    pub command: Command,
+    pub input: PathBuf,
+    /// Every FILE given, for `batch`.
+    pub inputs: Vec<PathBuf>,
+    pub suspicious_action: SuspiciousPolicy,
+    /// Days the shift moves suspicious scrobbles by.
+    pub offset_days: i64,
+    /// Suspicious-date detectors; empty for the default.
+    pub detect: Vec<Detector>,
+    pub listening_hours: Option<ListeningHours>,
+    pub jitter: Option<Jitter>,
+    pub nudge_collisions: Option<Nudge>,
+    /// The part of a track heard that makes a play listened, from `--listened-threshold`.
+    pub listened_threshold: Option<f64>,
+    /// Directory holding the device's tagcache database.
+    pub tagcache: Option<PathBuf>,
+    /// Music directory whose tags fill in missing albums.
+    pub backfill_albums: Option<PathBuf>,
+    /// Rewrite rules file.
+    pub rules: Option<PathBuf>,
+    pub featuring: Option<FeaturingPolicy>,
+    /// Move titles logged in the album field to the track field.
+    pub fix_shifted_titles: bool,
+    /// Repair names decoded in the wrong character set, or list the repairs.
+    pub repair_charset: Option<CharsetRepair>,
+    /// What unit the input's durations are in.
+    pub duration_unit: DurationUnit,
+    pub case_policy: CasePolicy,
+    /// Words `--case-policy` writes exactly as given.
+    pub case_exceptions: Vec<String>,
+    /// Artists whose records are podcast episodes.
+    pub podcast_artist_list: Option<PathBuf>,
+    /// Records this long without a track position are podcast episodes.
+    pub podcast_duration: Option<u32>,
+    pub podcasts: PodcastPolicy,
+    /// Where to write podcast episodes instead.
+    pub podcast_output: Option<PathBuf>,
+    pub format: Format,
+    /// Write each scrobble as this template, instead of in `format`.
+    pub template: Option<Template>,
+    /// What each `report` digest covers.
+    pub period: Period,
+    /// `None` to detect each input's format.
+    pub input_format: Option<InputFormat>,
+    /// The UTC offset the device's clock was set to, for logs that don't say.
+    pub input_tz: Option<FixedOffset>,
+    /// What time the timestamps of a written log give.
+    pub emit_tz: EmitTz,
+    /// The timezone of local times, instead of the system's.
+    pub tz: Option<String>,
+    pub wide: bool,
+    /// Copy untouched records from FILE as they were.
+    pub preserve_lines: bool,
+    pub append: Option<PathBuf>,
+    /// Where `batch` records how each log went.
+    pub checkpoint: Option<PathBuf>,
+    pub retry_failed: bool,
+    /// Where the archive is, if not in the default place.
+    pub db: Option<PathBuf>,
+    /// The credentials profile of `--user`.
+    pub user: Option<String>,
+    /// What `db query` looks for.
+    pub query: Option<Query>,
+    /// The JSON Schema `schema` prints.
+    pub schema: &'static str,
+    /// What `--where` keeps.
+    pub filter: Option<Query>,
+    pub sort: bool,
+    /// Alphabetical order for names in reports.
+    pub collate: Option<Collation>,
+    pub dedupe_policy: DuplicatePolicy,
+    /// Leave ignore files alone.
+    pub no_ignore: bool,
+    /// How to settle sessions logged in two logs; `None` to ask about each.
+    pub overlap_policy: Option<OverlapPolicy>,
+    pub threshold: f64,
+    /// What `generate` writes.
+    pub generator: Generator,
+    pub encoding: Encoding,
+    pub refresh_cache: bool,
+    /// Split records of a track left on repeat into a scrobble per loop.
+    pub split_loops: bool,
+    pub fuzzy: bool,
+    /// Where `analyze timeline` writes its chart.
+    pub output: Option<PathBuf>,
+    pub backend: Backend,
+    /// Each `--skipped`, with the output it's for, if it names one.
+    pub skipped: Vec<(Option<&'static str>, SkipPolicy)>,
+    pub max_submit: Option<usize>,
+    pub schedule: Option<Schedule>,
+    pub check_existing: bool,
+    /// Empty the input log once everything in it is submitted.
+    pub clear_source: bool,
+    /// Saved Last.fm profile pages for `--check-existing` to use instead of the API.
+    pub profile_snapshot: Vec<PathBuf>,
+    /// Batches submitted at once.
+    pub jobs: usize,
+    pub max_changes: Option<ChangeLimit>,
+    pub dry_run: bool,
+    /// With `fmt`, only report the logs that aren't canonical.
+    pub check: bool,
+    pub notify_webhook: Option<String>,
+    /// Seconds each try at a web request may take.
+    pub timeout: Option<u64>,
+    /// Proxy for web requests, instead of the environment's.
+    pub proxy: Option<String>,
+    /// Times `-v` was given.
+    pub verbosity: u8,
+    /// Only errors on stderr.
+    pub quiet: bool,
+    /// The line `show` prints, 1-based.
+    pub line: Option<usize>,
+    pub log_format: log::Format,
+    pub pre_hook: Option<String>,
+    pub post_hook: Option<String>,
+    pub strict: bool,
+    /// Refuse to write to the inputs.
+    pub read_only: bool,
+    pub escape: bool,
+}
+
+impl Args {
+    /// Parse options from the command line, excluding the program name.
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let (args, preset) = expand_presets(args)?;
+        let mut args = args.into_iter().peekable();
+        let mut parsed = Args {
+            command: Command::Fix,
+            input: PathBuf::from("scrobbler.log"),
+            inputs: Vec::new(),
+            suspicious_action: SuspiciousPolicy::default(),
+            offset_days: SCROBBLE_DAYS_OFFSET as i64,
+            detect: Vec::new(),
+            listening_hours: None,
+            jitter: None,
+            nudge_collisions: None,
+            listened_threshold: None,
+            tagcache: None,
+            backfill_albums: None,
+            rules: None,
+            featuring: None,
+            fix_shifted_titles: false,
+            repair_charset: None,
+            duration_unit: DurationUnit::Auto,
+            case_policy: CasePolicy::default(),
+            case_exceptions: Vec::new(),
+            podcast_artist_list: None,
+            podcast_duration: None,
+            podcasts: PodcastPolicy::default(),
+            podcast_output: None,
+            format: Format::Log,
+            template: None,
+            period: Period::default(),
+            input_format: None,
+            input_tz: None,
+            emit_tz: EmitTz::Utc,
+            tz: None,
+            wide: false,
+            preserve_lines: false,
+            append: None,
+            checkpoint: None,
+            retry_failed: false,
+            db: None,
+            query: None,
+            schema: "",
+            filter: None,
+            user: None,
+            sort: false,
+            collate: None,
+            dedupe_policy: DuplicatePolicy::default(),
+            no_ignore: false,
+            overlap_policy: Some(OverlapPolicy::default()),
+            threshold: 1.0,
+            generator: Generator::default(),
+            encoding: Encoding::Utf8,
+            refresh_cache: false,
+            split_loops: false,
+            fuzzy: false,
+            output: None,
+            backend: Backend::default(),
+            max_submit: None,
+            schedule: None,
+            check_existing: false,
+            clear_source: false,
+            profile_snapshot: Vec::new(),
+            jobs: 1,
+            max_changes: None,
+            dry_run: false,
+            check: false,
+            notify_webhook: None,
+            timeout: None,
+            proxy: None,
+            verbosity: 0,
+            quiet: false,
+            line: None,
+            skipped: Vec::new(),
+            log_format: log::Format::Text,
+            pre_hook: None,
+            post_hook: None,
+            strict: false,
+            read_only: false,
+            escape: false,
+        };
+        match args.peek().map(String::as_str) {
+            Some("fix") => {
+                args.next();
+            }
+            Some("enrich") => {
+                args.next();
+                parsed.command = Command::Enrich;
+            }
+            Some("batch") => {
+                args.next();
+                parsed.command = Command::Batch;
+            }
+            Some("submit") => {
+                args.next();
+                parsed.command = Command::Submit;
+            }
+            Some("review") => {
+                args.next();
+                parsed.command = Command::Review;
+            }
+            Some("db") => {
+                args.next();
+                parsed.command = match args.next().as_deref() {
+                    Some("import") => Command::DbImport,
+                    Some("query") => {
+                        let query = args.next().ok_or("db query needs a QUERY")?;
+                        parsed.query = Some(query.parse()?);
+                        Command::DbQuery
+                    }
+                    Some("export") => Command::DbExport,
+                    _ => Err("db needs an action: import, query or export")?,
+                };
+            }
+            Some("lint") => {
+                args.next();
+                parsed.command = Command::Lint;
+            }
+            Some("fmt") => {
+                args.next();
+                parsed.command = Command::Fmt;
+            }
+            Some("doctor") => {
+                args.next();
+                parsed.command = Command::Doctor;
+            }
+            Some("show") => {
+                args.next();
+                parsed.command = Command::Show;
+            }
+            Some("report") => {
+                args.next();
+                parsed.command = Command::Report;
+            }
+            Some("paths") => {
+                args.next();
+                parsed.command = Command::Paths;
+            }
+            Some("schema") => {
+                args.next();
+                let name = args
+                    .next()
+                    .ok_or("schema needs a NAME: record, summary or audit")?;
+                parsed.schema = scrobble_fix::schema::get(&name)?;
+                parsed.command = Command::Schema;
+            }
+            Some("generate") => {
+                args.next();
+                parsed.command = Command::Generate;
+            }
+            Some("self-update") => {
+                args.next();
+                parsed.command = Command::SelfUpdate;
+            }
+            Some("auth") => {
+                args.next();
+                let action = args.next();
+                let service = args
+                    .next()
+                    .ok_or("auth needs a service: lastfm or listenbrainz")?
+                    .parse()?;
+                parsed.command = match action.as_deref() {
+                    Some("login") => Command::AuthLogin(service),
+                    Some("logout") => Command::AuthLogout(service),
+                    _ => Err("auth needs an action: login or logout")?,
+                };
+            }
+            Some("rules") => {
+                args.next();
+                if args.next().as_deref() != Some("test") {
+                    Err("rules needs an action: test")?;
+                }
+                parsed.rules = Some(args.next().ok_or("rules test needs a RULES file")?.into());
+                parsed.command = Command::RulesTest;
+            }
+            Some("analyze") => {
+                args.next();
+                parsed.command = match args.next().as_deref() {
+                    Some("days") => Command::AnalyzeDays,
+                    Some("artists") => Command::AnalyzeArtists,
+                    Some("timeline") => Command::AnalyzeTimeline,
+                    Some("timezone") => Command::AnalyzeTimezone,
+                    Some("playcounts") => Command::AnalyzePlaycounts,
+                    Some("resets") => Command::AnalyzeResets,
+                    Some(other) => Err(format!("unknown analysis: {other}"))?,
+                    None => Err("analyze needs an analysis, e.g. `analyze days`")?,
+                };
+            }
+            _ => {}
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                "--suspicious-action" => {
+                    parsed.suspicious_action = value(&mut args, "--suspicious-action")?.parse()?
+                }
+                "--offset-days" => {
+                    parsed.offset_days = value(&mut args, "--offset-days")?
+                        .parse()
+                        .map_err(|e| format!("--offset-days: {e}"))?
+                }
+                "--listening-hours" => {
+                    parsed.listening_hours = Some(value(&mut args, "--listening-hours")?.parse()?)
+                }
+                "--jitter" => parsed.jitter = Some(value(&mut args, "--jitter")?.parse()?),
+                "--detect" => parsed.detect.push(value(&mut args, "--detect")?.parse()?),
+                "--nudge-collisions" => {
+                    parsed.nudge_collisions = Some(value(&mut args, "--nudge-collisions")?.parse()?)
+                }
+                "--listened-threshold" => {
+                    let threshold = value(&mut args, "--listened-threshold")?;
+                    let percent = threshold.strip_suffix('%').unwrap_or(&threshold);
+                    match percent.parse::<f64>() {
+                        Ok(percent) if (0.0..=100.0).contains(&percent) => {
+                            parsed.listened_threshold = Some(percent / 100.0)
+                        }
+                        _ => Err(format!(
+                            "--listened-threshold: {threshold} isn't a percentage from 0% to 100%"
+                        ))?,
+                    }
+                }
+                "--rules" => parsed.rules = Some(value(&mut args, "--rules")?.into()),
+                "--tagcache" => parsed.tagcache = Some(value(&mut args, "--tagcache")?.into()),
+                "--backfill-albums" => {
+                    parsed.backfill_albums = Some(value(&mut args, "--backfill-albums")?.into())
+                }
+                "--featuring" => parsed.featuring = Some(value(&mut args, "--featuring")?.parse()?),
+                "--fix-shifted-titles" => parsed.fix_shifted_titles = true,
+                "--repair-charset" => {
+                    parsed.repair_charset = Some(value(&mut args, "--repair-charset")?.parse()?)
+                }
+                "--duration-unit" => {
+                    parsed.duration_unit = value(&mut args, "--duration-unit")?.parse()?
+                }
+                "--case-policy" => {
+                    parsed.case_policy = value(&mut args, "--case-policy")?.parse()?
+                }
+                "--case-exception" => parsed
+                    .case_exceptions
+                    .push(value(&mut args, "--case-exception")?),
+                "--podcast-artist-list" => {
+                    parsed.podcast_artist_list =
+                        Some(value(&mut args, "--podcast-artist-list")?.into())
+                }
+                "--podcast-duration" => {
+                    parsed.podcast_duration = Some(
+                        value(&mut args, "--podcast-duration")?
+                            .parse()
+                            .map_err(|e| format!("--podcast-duration: {e}"))?,
+                    )
+                }
+                "--podcasts" => parsed.podcasts = value(&mut args, "--podcasts")?.parse()?,
+                "--podcast-output" => {
+                    parsed.podcast_output = Some(value(&mut args, "--podcast-output")?.into())
+                }
+                "--format" => parsed.format = value(&mut args, "--format")?.parse()?,
+                "--template" => parsed.template = Some(value(&mut args, "--template")?.parse()?),
+                "--input-format" | "--from" => {
+                    parsed.input_format = match value(&mut args, &arg)?.as_str() {
+                        "auto" => None,
+                        format => Some(format.parse()?),
+                    }
+                }
+                "--input-tz" => {
+                    parsed.input_tz = Some(parse_utc_offset(&value(&mut args, "--input-tz")?)?)
+                }
+                "--emit-tz" => parsed.emit_tz = value(&mut args, "--emit-tz")?.parse()?,
+                "--tz" => parsed.tz = Some(value(&mut args, "--tz")?),
+                "--wide" => parsed.wide = true,
+                "--preserve-lines" => parsed.preserve_lines = true,
+                "--append" => parsed.append = Some(value(&mut args, "--append")?.into()),
+                "--db" => parsed.db = Some(value(&mut args, "--db")?.into()),
+                "--user" => {
+                    let name = value(&mut args, "--user")?;
+                    if name.is_empty()
+                        || !name
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                    {
+                        Err(format!("--user: {name:?} isn't letters, digits, - and _"))?;
+                    }
+                    parsed.user = Some(name);
+                }
+                "--sort" => parsed.sort = true,
+                "--overlap-policy" => {
+                    parsed.overlap_policy = match value(&mut args, "--overlap-policy")?.as_str() {
+                        "ask" => None,
+                        policy => Some(policy.parse()?),
+                    }
+                }
+                "--dedupe-policy" => {
+                    parsed.dedupe_policy = value(&mut args, "--dedupe-policy")?.parse()?
+                }
+                "--threshold" => {
+                    parsed.threshold = value(&mut args, "--threshold")?
+                        .parse()
+                        .map_err(|e| format!("--threshold: {e}"))?
+                }
+                "--count" => parsed.generator.scrobbles = number(&mut args, "--count")?,
+                "--resets" => parsed.generator.resets = number(&mut args, "--resets")?,
+                "--corrupt" => parsed.generator.corrupt = number(&mut args, "--corrupt")?,
+                "--seed" => {
+                    parsed.generator.seed = value(&mut args, "--seed")?
+                        .parse()
+                        .map_err(|e| format!("--seed: {e}"))?
+                }
+                "--encoding" => parsed.encoding = value(&mut args, "--encoding")?.parse()?,
+                "--refresh-cache" => parsed.refresh_cache = true,
+                "--split-loops" => parsed.split_loops = true,
+                "--fuzzy" => parsed.fuzzy = true,
+                "--period" => parsed.period = value(&mut args, "--period")?.parse()?,
+                "--output" => parsed.output = Some(value(&mut args, "--output")?.into()),
+                "--max-submit" => {
+                    parsed.max_submit = Some(
+                        value(&mut args, "--max-submit")?
+                            .parse()
+                            .map_err(|e| format!("--max-submit: {e}"))?,
+                    )
+                }
+                "--timeout" => {
+                    parsed.timeout = Some(
+                        value(&mut args, "--timeout")?
+                            .parse()
+                            .map_err(|e| format!("--timeout: {e}"))?,
+                    )
+                }
+                "--proxy" => parsed.proxy = Some(proxy(value(&mut args, "--proxy")?)?),
+                "--check-existing" => parsed.check_existing = true,
+                "--clear-source-after-submit" => parsed.clear_source = true,
+                "--backend" => parsed.backend = value(&mut args, "--backend")?.parse()?,
+                "--dry-run" => parsed.dry_run = true,
+                "--check" => parsed.check = true,
+                "--checkpoint" => {
+                    parsed.checkpoint = Some(value(&mut args, "--checkpoint")?.into())
+                }
+                "--retry-failed" => parsed.retry_failed = true,
+                "--no-ignore" => parsed.no_ignore = true,
+                "--collate" => parsed.collate = Some(value(&mut args, "--collate")?.parse()?),
+                "--where" => {
+                    let filter = value(&mut args, "--where")?;
+                    parsed.filter = Some(filter.parse().map_err(|e| format!("--where: {e}"))?)
+                }
+                "--profile-snapshot" => parsed
+                    .profile_snapshot
+                    .push(value(&mut args, "--profile-snapshot")?.into()),
+                "--max-changes" => {
+                    parsed.max_changes = Some(value(&mut args, "--max-changes")?.parse()?)
+                }
+                "--jobs" => {
+                    parsed.jobs = value(&mut args, "--jobs")?
+                        .parse()
+                        .map_err(|e| format!("--jobs: {e}"))?
+                }
+                "--schedule" => parsed.schedule = Some(value(&mut args, "--schedule")?.parse()?),
+                "-v" | "--verbose" => parsed.verbosity += 1,
+                "-vv" => parsed.verbosity += 2,
+                "-q" | "--quiet" => parsed.quiet = true,
+                "--skipped" => parsed
+                    .skipped
+                    .push(skipped(value(&mut args, "--skipped")?)?),
+                "--line" => parsed.line = Some(number(&mut args, "--line")?),
+                "--log-format" => parsed.log_format = value(&mut args, "--log-format")?.parse()?,
+                "--strict" => parsed.strict = true,
+                "--read-only" => parsed.read_only = true,
+                "--escape" => parsed.escape = true,
+                "--pre-hook" => parsed.pre_hook = Some(value(&mut args, "--pre-hook")?),
+                "--post-hook" => parsed.post_hook = Some(value(&mut args, "--post-hook")?),
+                "--notify-webhook" => {
+                    parsed.notify_webhook = Some(value(&mut args, "--notify-webhook")?)
+                }
+                flag if flag.starts_with('-') => Err(format!("unknown option: {flag}"))?,
+                path => {
+                    parsed.input = PathBuf::from(path);
+                    parsed.inputs.push(parsed.input.clone());
+                }
+            }
+        }
+        if parsed.command == Command::Batch && parsed.inputs.is_empty() {
+            Err("batch needs at least one PATH")?;
+        }
+        if parsed.command == Command::DbImport && parsed.inputs.is_empty() {
+            Err("db import needs at least one FILE")?;
+        }
+        let many = [Command::Batch, Command::DbImport, Command::Fmt];
+        if !many.contains(&parsed.command) && parsed.inputs.len() > 1 {
+            Err("only batch, db import and fmt take more than one FILE")?;
+        }
+        if let Some(preset) = preset.filter(|preset| !preset.commands.contains(&parsed.command)) {
+            let commands: Vec<&str> = preset.commands.iter().map(|c| c.name()).collect();
+            Err(format!(
+                "--preset {} is for {}, not {}",
+                preset.name,
+                commands.join(", "),
+                parsed.command.name()
+            ))?;
+        }
+        if parsed.checkpoint.is_some() && parsed.command != Command::Batch {
+            Err("only batch takes --checkpoint")?;
+        }
+        if parsed.retry_failed && parsed.checkpoint.is_none() {
+            Err("--retry-failed needs --checkpoint, which records what failed")?;
+        }
+        if parsed.check && parsed.command != Command::Fmt {
+            Err("only fmt takes --check")?;
+        }
+        if matches!(parsed.command, Command::DbQuery | Command::DbExport)
+            && !parsed.inputs.is_empty()
+        {
+            Err("db query and db export read the archive, not a FILE")?;
+        }
+        match (parsed.command == Command::Show, parsed.line) {
+            (true, None | Some(0)) => Err("show needs --line N, counting from 1")?,
+            (false, Some(_)) => Err("only show takes --line")?,
+            _ => {}
+        }
+        if parsed.quiet && parsed.verbosity > 0 {
+            Err("--quiet and --verbose can't both be given")?;
+        }
+        if parsed.command == Command::Generate && !parsed.inputs.is_empty() {
+            Err("generate takes no FILE")?;
+        }
+        if parsed.command == Command::Schema && !parsed.inputs.is_empty() {
+            Err("schema takes no FILE")?;
+        }
+        if parsed.command == Command::Paths && !parsed.inputs.is_empty() {
+            Err("paths takes no FILE")?;
+        }
+        if parsed.command == Command::SelfUpdate && !parsed.inputs.is_empty() {
+            Err("self-update takes no FILE")?;
+        }
+        let one_file = !many.contains(&parsed.command)
+            && !matches!(parsed.command, Command::DbQuery | Command::DbExport);
+        if parsed.preserve_lines
+            && (parsed.format != Format::Log || !one_file || parsed.append.is_some())
+        {
+            Err("--preserve-lines needs one FILE printed with --format log, without --append")?;
+        }
+        if parsed.command == Command::Report
+            && !matches!(parsed.format, Format::Log | Format::Markdown | Format::Json)
+        {
+            Err("report writes --format markdown or json")?;
+        }
+        if parsed.format == Format::Markdown && parsed.command != Command::Report {
+            Err("only report writes --format markdown")?;
+        }
+        if parsed.template.is_some()
+            && (parsed.format != Format::Log
+                || parsed.append.is_some()
+                || parsed.preserve_lines
+                || !matches!(
+                    parsed.command,
+                    Command::Fix
+                        | Command::Review
+                        | Command::Enrich
+                        | Command::Batch
+                        | Command::DbQuery
+                        | Command::DbExport
+                ))
+        {
+            Err("--template replaces --format, for printing fixed or archived scrobbles")?;
+        }
+        if parsed.emit_tz != EmitTz::Utc
+            && (parsed.format != Format::Log
+                || parsed.template.is_some()
+                || parsed.preserve_lines
+                || parsed.append.is_some())
+        {
+            Err("--emit-tz needs --format log, without --template, --preserve-lines or --append")?;
+        }
+        let classified = parsed.podcast_artist_list.is_some() || parsed.podcast_duration.is_some();
+        if (parsed.podcasts != PodcastPolicy::Tag || parsed.podcast_output.is_some()) && !classified
+        {
+            Err(
+                "--podcasts and --podcast-output need --podcast-artist-list or --podcast-duration",
+            )?;
+        }
+        if parsed.podcast_output.is_some() && parsed.podcasts != PodcastPolicy::Tag {
+            Err("--podcast-output takes the episodes out already; leave out --podcasts")?;
+        }
+        if parsed.dedupe_policy != DuplicatePolicy::KeepFirst
+            && parsed.append.is_none()
+            && !matches!(parsed.command, Command::Batch | Command::DbImport)
+        {
+            Err("--dedupe-policy is for combining logs: with --append, batch or db import")?;
+        }
+        if parsed.overlap_policy != Some(OverlapPolicy::KeepFirst)
+            && parsed.append.is_none()
+            && !matches!(parsed.command, Command::Batch | Command::DbImport)
+        {
+            Err("--overlap-policy is for combining logs: with --append, batch or db import")?;
+        }
+        if parsed.split_loops && parsed.command != Command::Enrich {
+            Err("--split-loops needs enrich, which looks up how long each track is")?;
+        }
+        if parsed.format == Format::BulkEdit
+            && (parsed.command != Command::Fix || parsed.append.is_some())
+        {
+            Err("--format bulk-edit lists the fixes made to one FILE, without --append")?;
+        }
+        if parsed.repair_charset == Some(CharsetRepair::Preview)
+            && (parsed.command != Command::Fix || parsed.append.is_some())
+        {
+            Err("--repair-charset preview lists the repairs in one FILE, without --append")?;
+        }
+        if parsed.listening_hours.is_some()
+            && parsed.suspicious_action != SuspiciousPolicy::Reconstruct
+        {
+            Err("--listening-hours needs --suspicious-action reconstruct")?;
+        }
+        if parsed.jitter.is_some() && parsed.suspicious_action != SuspiciousPolicy::Reconstruct {
+            Err("--jitter needs --suspicious-action reconstruct")?;
+        }
+        if parsed.backend == Backend::Audioscrobbler && parsed.check_existing {
+            Err("--check-existing only works with Last.fm")?;
+        }
+        if parsed.schedule.is_some() && parsed.max_submit.is_none() {
+            Err("--schedule needs --max-submit")?;
+        }
+        let analyze = matches!(
+            parsed.command,
+            Command::AnalyzeDays
+                | Command::AnalyzeArtists
+                | Command::AnalyzeTimeline
+                | Command::AnalyzeTimezone
+                | Command::AnalyzePlaycounts
+                | Command::AnalyzeResets
+        );
+        if parsed.collate.is_some() && parsed.command != Command::AnalyzeArtists {
+            Err("--collate orders the names analyze artists lists")?;
+        }
+        if parsed.filter.is_some() && analyze {
+            Err("--where picks fixed records; analyze looks at the log as it is")?;
+        }
+        if parsed.command == Command::AnalyzePlaycounts && parsed.tagcache.is_none() {
+            Err("analyze playcounts needs --tagcache DIR, the database to compare with")?;
+        }
+        if parsed.clear_source && parsed.command != Command::Submit {
+            Err("--clear-source-after-submit only works with submit")?;
+        }
+        if !parsed.profile_snapshot.is_empty() && !parsed.check_existing {
+            Err("--profile-snapshot needs --check-existing")?;
+        }
+        Ok(parsed)
+    }
+
+    /// What `output` does with skipped scrobbles: what the last `--skipped` for it, or for
+    /// every output, says. Outputs with no way to mark a skip leave out what `flag` would mark.
+    pub fn skip_policy(&self, output: &str) -> SkipPolicy {
+        let policy = self
+            .skipped
+            .iter()
+            .rev()
+            .find(|(named, _)| match named {
+                Some(named) => *named == output,
+                None => true,
+            })
+            .map_or(SkipPolicy::Drop, |&(_, policy)| policy);
+        match policy {
+            SkipPolicy::Flag if output != "listenbrainz" => SkipPolicy::Drop,
+            policy => policy,
+        }
+    }
+}
+
+/// A `--skipped` value, and the output it names, if any.
+fn skipped(value: String) -> Result<(Option<&'static str>, SkipPolicy), String> {
+    const OUTPUTS: [&str; 4] = ["listenbrainz", "openscrobbler", "lastfm", "audioscrobbler"];
+    let Some((output, policy)) = value.split_once('=') else {
+        return Ok((None, value.parse()?));
+    };
+    let output = OUTPUTS
+        .into_iter()
+        .find(|&name| name == output)
+        .ok_or(format!(
+            "--skipped: unknown output {output} ({})",
+            OUTPUTS.join(", ")
+        ))?;
+    let policy = policy.parse()?;
+    if policy == SkipPolicy::Flag && output != "listenbrainz" {
+        Err(format!(
+            "--skipped: {output} has no way to mark a skip; use listen or drop"
+        ))?;
+    }
+    Ok((Some(output), policy))
+}
+
+/// A `--proxy` URL, of a scheme curl knows.
+fn proxy(url: String) -> Result<String, String> {
+    const SCHEMES: [&str; 6] = ["http", "https", "socks4", "socks4a", "socks5", "socks5h"];
+    match url.split_once("://") {
+        Some((scheme, rest)) if SCHEMES.contains(&scheme) && !rest.is_empty() => Ok(url),
+        _ => Err(format!(
+            "--proxy needs a URL like socks5h://127.0.0.1:9050 ({})",
+            SCHEMES.join(", ")
+        )),
+    }
+}
+
+/// The value following an option.
+fn value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    args.next().ok_or(format!("{option} needs a value"))
+}
+
+/// The count following an option.
+fn number(args: &mut impl Iterator<Item = String>, option: &str) -> Result<usize, String> {
+    value(args, option)?
+        .parse()
+        .map_err(|e| format!("{option}: {e}"))
+}
+
+#[test]
+fn flag_skips_only_where_outputs_can() {
+    let args = |line: &str| Args::parse(line.split(' ').map(str::to_string)).unwrap();
+    let parsed = args("submit --skipped flag");
+    assert_eq!(parsed.skip_policy("listenbrainz"), SkipPolicy::Flag);
+    assert_eq!(parsed.skip_policy("lastfm"), SkipPolicy::Drop);
+    assert_eq!(parsed.skip_policy("openscrobbler"), SkipPolicy::Drop);
+    let parsed = args("submit --skipped listen --skipped listenbrainz=flag");
+    assert_eq!(parsed.skip_policy("lastfm"), SkipPolicy::Listen);
+    assert_eq!(parsed.skip_policy("listenbrainz"), SkipPolicy::Flag);
+    assert!(Args::parse(["--skipped", "lastfm=flag"].map(str::to_string).into_iter()).is_err());
+}
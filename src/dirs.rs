@@ -0,0 +1,35 @@
+//! Where files that outlive a run are kept.
+
+use std::io;
+use std::path::PathBuf;
+
+/// `$name`, or `~/fallback` when it's unset or not an absolute path (as the XDG base directory
+/// spec says to treat relative ones), followed by `scrobble-fix`.
+fn base(name: &str, fallback: &str) -> io::Result<PathBuf> {
+    let base = match std::env::var_os(name).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => PathBuf::from(std::env::var_os("HOME").ok_or(io::Error::other("HOME is not set"))?)
+            .join(fallback),
+    };
+    Ok(base.join("scrobble-fix"))
+}
+
+/// Lookups that can be redone: `$XDG_CACHE_HOME/scrobble-fix`.
+pub fn cache() -> io::Result<PathBuf> {
+    base("XDG_CACHE_HOME", ".cache")
+}
+
+/// Settings the user writes, like default rewrite rules: `$XDG_CONFIG_HOME/scrobble-fix`.
+pub fn config() -> io::Result<PathBuf> {
+    base("XDG_CONFIG_HOME", ".config")
+}
+
+/// Data the user keeps, like the scrobble archive: `$XDG_DATA_HOME/scrobble-fix`.
+pub fn data() -> io::Result<PathBuf> {
+    base("XDG_DATA_HOME", ".local/share")
+}
+
+/// Progress that must survive between runs: `$XDG_STATE_HOME/scrobble-fix`.
+pub fn state() -> io::Result<PathBuf> {
+    base("XDG_STATE_HOME", ".local/state")
+}
@@ -0,0 +1,249 @@
+//! Every check on a log at once, for a log nobody knows anything about yet: what's wrong with
+//! it, worst first, and what to run to fix each problem.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+
+use crate::header::Timezone;
+use crate::lint::{self, Severity};
+use crate::{analyze, LogHeader, Scrobble, SCROBBLE_CUTOFF};
+
+/// How much a problem matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Worth knowing, but nothing is wrong.
+    Note,
+    /// Some scrobbles would be wrong on Last.fm.
+    Warning,
+    /// Some of the log can't be read.
+    Error,
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Priority::Note => write!(f, "note"),
+            Priority::Warning => write!(f, "warning"),
+            Priority::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One problem with a log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnosis {
+    pub priority: Priority,
+    pub message: String,
+    /// 1-based lines showing the problem, for the first few.
+    pub lines: Vec<usize>,
+    /// What to run about it, like `--nudge-collisions duration`.
+    pub suggestion: String,
+}
+
+/// Lines named in a diagnosis.
+const EXAMPLES: usize = 3;
+
+/// A backwards jump this long within a log is a clock reset rather than a resumed shuffle.
+const RESET_JUMP_SECONDS: i64 = 24 * 60 * 60;
+
+/// The lines of the records `test` is true of, in log order.
+fn flagged(records: &[(usize, Scrobble)], mut test: impl FnMut(&Scrobble) -> bool) -> Vec<usize> {
+    records
+        .iter()
+        .filter(|(_, scrobble)| test(scrobble))
+        .map(|&(line, _)| line)
+        .collect()
+}
+
+/// Check `log` every way there is, at `now`, worst problems first.
+pub fn diagnose(log: &[u8], now: DateTime<Utc>) -> Vec<Diagnosis> {
+    let mut diagnoses = Vec::new();
+    let mut found = |priority, message: String, lines: Vec<usize>, suggestion: &str| {
+        diagnoses.push(Diagnosis {
+            priority,
+            message,
+            lines: lines.into_iter().take(EXAMPLES).collect(),
+            suggestion: suggestion.to_string(),
+        })
+    };
+
+    let findings = lint::lint(log);
+    let lines = |matching: &dyn Fn(&lint::Finding) -> bool| -> Vec<usize> {
+        findings
+            .iter()
+            .filter(|finding| matching(finding))
+            .map(|finding| finding.line)
+            .collect()
+    };
+    let not_utf8 = lines(&|finding| finding.message == "not valid UTF-8");
+    if !not_utf8.is_empty() {
+        found(
+            Priority::Error,
+            format!("{} lines aren't valid UTF-8", not_utf8.len()),
+            not_utf8,
+            "convert the log to UTF-8 first, as from Latin-1 with `iconv -f latin1 -t utf-8`",
+        );
+    }
+    let broken = lines(&|finding| {
+        finding.severity == Severity::Error && finding.message != "not valid UTF-8"
+    });
+    if !broken.is_empty() {
+        found(
+            Priority::Error,
+            format!("{} lines break the AUDIOSCROBBLER/1.1 format", broken.len()),
+            broken,
+            "`lint` says what's wrong with each; fixing leaves them out, or fails with --strict",
+        );
+    }
+
+    let text = String::from_utf8_lossy(log);
+    let header = LogHeader::parse(&text).unwrap_or_default();
+    let records: Vec<(usize, Scrobble)> = crate::parse_numbered_records(&text)
+        .filter_map(|(line, record)| Some((line, record.ok()?)))
+        .collect();
+
+    let cutoff = DateTime::parse_from_rfc3339(SCROBBLE_CUTOFF).expect("valid cutoff");
+    let zero = flagged(&records, |scrobble| scrobble.timestamp.timestamp() == 0);
+    let early = flagged(&records, |scrobble| {
+        scrobble.timestamp <= cutoff && scrobble.timestamp.timestamp() != 0
+    });
+    if !early.is_empty() {
+        found(
+            Priority::Warning,
+            format!(
+                "{} scrobbles are dated before {}, as after the clock reset",
+                early.len(),
+                cutoff.date_naive()
+            ),
+            early,
+            "fixing shifts them by --offset-days; with --suspicious-action reconstruct, \
+             they're rebuilt from the scrobbles around them instead",
+        );
+    }
+    if !zero.is_empty() {
+        found(
+            Priority::Warning,
+            format!(
+                "{} scrobbles are at the epoch, from an unset clock",
+                zero.len()
+            ),
+            zero,
+            "--detect zero --suspicious-action reconstruct",
+        );
+    }
+    let future = flagged(&records, |scrobble| scrobble.timestamp > now);
+    if !future.is_empty() {
+        found(
+            Priority::Warning,
+            format!("{} scrobbles are in the future", future.len()),
+            future,
+            &format!(
+                "--detect after:{} with a negative --offset-days, or --suspicious-action \
+                 reconstruct",
+                now.date_naive()
+            ),
+        );
+    }
+    let mut latest: Option<i64> = None;
+    // Among the rest, which would be flagged twice over otherwise.
+    let resets = flagged(&records, |scrobble| {
+        let timestamp = scrobble.timestamp.timestamp();
+        if scrobble.timestamp <= cutoff {
+            return false;
+        }
+        let reset = latest.is_some_and(|latest| timestamp < latest - RESET_JUMP_SECONDS);
+        latest = Some(latest.map_or(timestamp, |latest| latest.max(timestamp)));
+        reset
+    });
+    if !resets.is_empty() {
+        found(
+            Priority::Warning,
+            format!(
+                "the clock goes back more than a day {} times in the log",
+                resets.len()
+            ),
+            resets,
+            &format!("--detect backwards:{RESET_JUMP_SECONDS} --suspicious-action reconstruct"),
+        );
+    }
+
+    let mut seen = HashSet::new();
+    let duplicates = flagged(&records, |scrobble| !seen.insert(scrobble.fingerprint()));
+    if !duplicates.is_empty() {
+        found(
+            Priority::Warning,
+            format!("{} scrobbles are logged twice", duplicates.len()),
+            duplicates,
+            "`batch FILE` leaves out the second of each",
+        );
+    }
+    let mut taken = HashSet::new();
+    let mut seen = HashSet::new();
+    let collisions = flagged(&records, |scrobble| {
+        let first = seen.insert(scrobble.fingerprint());
+        !taken.insert(scrobble.timestamp) && first
+    });
+    if !collisions.is_empty() {
+        found(
+            Priority::Warning,
+            format!(
+                "{} scrobbles share their timestamp with another, and Last.fm takes only one",
+                collisions.len()
+            ),
+            collisions,
+            "--nudge-collisions duration",
+        );
+    }
+
+    let scrobbles: Vec<Scrobble> = records
+        .iter()
+        .map(|(_, scrobble)| scrobble.clone())
+        .collect();
+    let busy = analyze::busy_days(&scrobbles, 1.0);
+    if !busy.is_empty() {
+        found(
+            Priority::Note,
+            format!("{} days hold more music than a day is long", busy.len()),
+            busy.iter().map(|day| records[day.first].0).collect(),
+            "`analyze days` lists them, and `analyze timeline` charts them",
+        );
+    }
+    if header.tz == Timezone::Unknown && !scrobbles.is_empty() {
+        found(
+            Priority::Note,
+            "the log doesn't say what timezone its clock was in".to_string(),
+            Vec::new(),
+            "`analyze timezone` guesses it, and --input-tz moves the timestamps to UTC",
+        );
+    }
+
+    diagnoses.sort_by_key(|diagnosis| std::cmp::Reverse(diagnosis.priority));
+    diagnoses
+}
+
+#[test]
+fn diagnose_log() {
+    let log = b"#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/Rockbox ipodvideo $Revision$\n\
+        Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t\n\
+        Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t\n\
+        Low\tDrums and Guns\tBelarus\t6\t192\tL\t1699413807\t\n\
+        Low\tDrums and Guns\tDragonfly\t7\t192\tL\t1000000000\t\n\
+        Low\tDrums and Guns\t\xff\t8\t192\tL\t1999999999\t\n";
+    let now = DateTime::from_timestamp(1700000000, 0).unwrap();
+    let diagnoses: Vec<(Priority, Vec<usize>)> = diagnose(log, now)
+        .into_iter()
+        .map(|diagnosis| (diagnosis.priority, diagnosis.lines))
+        .collect();
+    assert_eq!(
+        diagnoses,
+        [
+            (Priority::Error, vec![8]),
+            (Priority::Warning, vec![7]),
+            (Priority::Warning, vec![8]),
+            (Priority::Warning, vec![5]),
+            (Priority::Warning, vec![6]),
+        ]
+    );
+    assert!(diagnose(crate::HEADER.as_bytes(), now).is_empty());
+}
@@ -0,0 +1,201 @@
+//! Filling in MusicBrainz ids, with a persistent lookup cache.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+
+use scrobble_fix::musicbrainz::{self, Recording};
+use scrobble_fix::Scrobble;
+
+use crate::dirs;
+use crate::error::Error;
+use crate::files;
+use crate::log;
+use crate::net;
+
+/// Cached lookups: the recording found for each key, or `None` when the search found nothing.
+struct Cache {
+    path: PathBuf,
+    entries: HashMap<[String; 3], Option<Recording>>,
+    /// Held while the cache is loaded, so another run's lookups aren't overwritten.
+    _lock: files::Lock,
+}
+
+/// Where MusicBrainz lookups are cached.
+pub fn cache_path() -> io::Result<PathBuf> {
+    Cache::path()
+}
+
+impl Cache {
+    /// Where lookups are kept between runs: `$XDG_CACHE_HOME/scrobble-fix/musicbrainz.tsv`.
+    fn path() -> io::Result<PathBuf> {
+        Ok(dirs::cache()?.join("musicbrainz.tsv"))
+    }
+
+    /// Load the cache, which has one line per lookup:
+    /// `artist\ttrack\talbum\trecording id\tartist ids\trelease id\tlength`, with artist ids
+    /// separated by commas and the length in seconds. Lines from before artist and release ids
+    /// were cached have just the recording id, and lines from before lengths were, no length.
+    fn load() -> io::Result<Self> {
+        let path = Cache::path()?;
+        let lock = files::lock(&path)?;
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let entries = contents
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                let (key, id, artists, release, length) = match fields[..] {
+                    [artist, track, album, id] => ([artist, track, album], id, "", "", ""),
+                    [artist, track, album, id, artists, release] => {
+                        ([artist, track, album], id, artists, release, "")
+                    }
+                    [artist, track, album, id, artists, release, length] => {
+                        ([artist, track, album], id, artists, release, length)
+                    }
+                    _ => return None,
+                };
+                let recording = Some(id).filter(|id| !id.is_empty()).map(|id| Recording {
+                    id: id.to_string(),
+                    artist_ids: artists
+                        .split(',')
+                        .filter(|id| !id.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                    release_id: Some(release)
+                        .filter(|id| !id.is_empty())
+                        .map(str::to_string),
+                    length: length.parse().ok(),
+                });
+                Some((key.map(str::to_string), recording))
+            })
+            .collect();
+        Ok(Cache {
+            path,
+            entries,
+            _lock: lock,
+        })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(key, _)| *key);
+        let mut contents = String::new();
+        for (key, recording) in entries {
+            let (id, artists, release, length) = match recording {
+                Some(recording) => (
+                    recording.id.as_str(),
+                    recording.artist_ids.join(","),
+                    recording.release_id.as_deref().unwrap_or(""),
+                    recording.length.map_or(String::new(), |l| l.to_string()),
+                ),
+                None => ("", String::new(), "", String::new()),
+            };
+            contents.push_str(&format!(
+                "{}\t{id}\t{artists}\t{release}\t{length}\n",
+                key.join("\t")
+            ));
+        }
+        files::replace(&self.path, contents)
+    }
+}
+
+/// Query MusicBrainz for a scrobble: by id when the log has one, otherwise by searching.
+fn lookup(scrobble: &Scrobble) -> Result<Option<Recording>, String> {
+    match &scrobble.track_id {
+        Some(id) => net::get(&musicbrainz::lookup_url(id), musicbrainz::USER_AGENT)
+            .and_then(|response| musicbrainz::recording(&response, &scrobble.album))
+            .map(Some),
+        None => net::get(&musicbrainz::search_url(scrobble), musicbrainz::USER_AGENT)
+            .and_then(|response| musicbrainz::best_recording(&response, &scrobble.album)),
+    }
+}
+
+/// What enriching found out besides the ids it filled in.
+pub struct Enriched {
+    /// Lookups that failed.
+    pub failed: usize,
+    /// How long each recording is, in seconds, by id, for those MusicBrainz knows.
+    pub lengths: HashMap<String, u32>,
+}
+
+/// Look up recording, artist, and release ids for every scrobble missing them.
+///
+/// Cached lookups are reused unless `refresh` is set. A failed request leaves that scrobble
+/// unchanged (and uncached) rather than aborting the run; the number of failures is returned.
+/// If every request failed, MusicBrainz is taken to be unreachable and that's an error.
+pub fn enrich(scrobbles: &mut [Scrobble], refresh: bool) -> Result<Enriched, Error> {
+    let mut cache = Cache::load()?;
+    // Keys looked up during this run, which are fresh even when refreshing.
+    let mut looked_up = HashSet::new();
+    let (mut found, mut failed) = (0, 0);
+    let mut lengths = HashMap::new();
+    let mut last_error = None;
+    let incomplete = |s: &&mut Scrobble| s.track_id.is_none() || s.artist_mbids.is_empty();
+    for scrobble in scrobbles.iter_mut().filter(incomplete) {
+        let key = musicbrainz::cache_key(scrobble);
+        let cached = match refresh && !looked_up.contains(&key) {
+            true => None,
+            // A cached match for a different recording than the log names doesn't apply.
+            false => cache.entries.get(&key).cloned().filter(|recording| {
+                scrobble.track_id.is_none()
+                    || recording.as_ref().map(|r| &r.id) == scrobble.track_id.as_ref()
+            }),
+        };
+        let recording = match cached {
+            Some(recording) => recording,
+            None => {
+                looked_up.insert(key.clone());
+                match lookup(scrobble) {
+                    Ok(recording) => {
+                        cache.entries.insert(key, recording.clone());
+                        recording
+                    }
+                    Err(e) => {
+                        let _span = log::record(scrobble, None);
+                        log::warn(format_args!("MusicBrainz lookup failed: {e}"));
+                        failed += 1;
+                        last_error = Some(e);
+                        None
+                    }
+                }
+            }
+        };
+        if let Some(recording) = recording {
+            found += 1;
+            if let Some(length) = recording.length {
+                lengths.insert(recording.id.clone(), length);
+            }
+            scrobble.track_id = Some(recording.id);
+            scrobble.artist_mbids = recording.artist_ids;
+            scrobble.release_mbid = recording.release_id;
+        }
+    }
+    // The lengths of complete records' recordings, which were only looked up by earlier runs.
+    for scrobble in scrobbles.iter() {
+        let Some(id) = &scrobble.track_id else {
+            continue;
+        };
+        let cached = cache
+            .entries
+            .get(&musicbrainz::cache_key(scrobble))
+            .and_then(Option::as_ref)
+            .filter(|recording| &recording.id == id);
+        if let Some(length) = cached.and_then(|recording| recording.length) {
+            lengths.entry(id.clone()).or_insert(length);
+        }
+    }
+    cache.save()?;
+    if let Some(e) = last_error.filter(|_| failed == looked_up.len()) {
+        return Err(Error::Network(format!(
+            "every MusicBrainz lookup failed: {e}"
+        )));
+    }
+    log::info(format_args!(
+        "enriched {found} scrobbles ({failed} lookups failed)"
+    ));
+    Ok(Enriched { failed, lengths })
+}
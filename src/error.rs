@@ -0,0 +1,54 @@
+//! Failures, grouped into the categories scripts can branch on via the exit code.
+
+use std::fmt;
+use std::io;
+use std::process::ExitCode;
+
+/// Everything worked.
+pub const SUCCESS: u8 = 0;
+/// Bad arguments, or a failure reading or writing files.
+pub const FAILURE: u8 = 1;
+/// The run finished, but some records couldn't be parsed and were left out.
+pub const PARTIAL: u8 = 2;
+/// The input held no scrobbles, or none that weren't already in the master log.
+pub const NOTHING_TO_DO: u8 = 3;
+/// The input couldn't be parsed (in `--strict` mode, any bad record).
+pub const PARSE_FATAL: u8 = 4;
+/// A web service couldn't be reached or gave an unusable answer.
+pub const NETWORK: u8 = 5;
+
+/// Why a run stopped.
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    Io(io::Error),
+    Parse(String),
+    Network(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Error::Usage(_) | Error::Io(_) => FAILURE,
+            Error::Parse(_) => PARSE_FATAL,
+            Error::Network(_) => NETWORK,
+        })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(message) | Error::Parse(message) | Error::Network(message) => {
+                write!(f, "{message}")
+            }
+            Error::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,65 @@
+//! Backslash escapes for metadata containing the characters that delimit scrobbler.log.
+//!
+//! A tab or newline inside a field would split it into two fields or two records. Escaping writes
+//! them as `\t` and `\n` (and `\r`, and a backslash as `\\`) so they survive the text format.
+//! Logs written by Rockbox aren't escaped, so either direction is only applied on request.
+
+use crate::Scrobble;
+
+/// Escape one field.
+pub fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Undo [`escape`]. A backslash not starting a known escape is kept as it is.
+pub fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars().peekable();
+    while let Some(c) = chars.next() {
+        let replacement = match (c, chars.peek()) {
+            ('\\', Some('\\')) => '\\',
+            ('\\', Some('t')) => '\t',
+            ('\\', Some('n')) => '\n',
+            ('\\', Some('r')) => '\r',
+            (c, _) => {
+                unescaped.push(c);
+                continue;
+            }
+        };
+        chars.next();
+        unescaped.push(replacement);
+    }
+    unescaped
+}
+
+/// Escape a scrobble's metadata in place, ready to be written.
+pub fn escape_scrobble(scrobble: &mut Scrobble) {
+    for field in scrobble.text_fields_mut() {
+        *field = escape(field);
+    }
+}
+
+/// Unescape a scrobble's metadata in place, after it was read.
+pub fn unescape_scrobble(scrobble: &mut Scrobble) {
+    for field in scrobble.text_fields_mut() {
+        *field = unescape(field);
+    }
+}
+
+#[test]
+fn round_trip_delimiters() {
+    let field = "Live\tat\nthe \\ Roundhouse \\q";
+    assert_eq!(escape(field), "Live\\tat\\nthe \\\\ Roundhouse \\\\q");
+    assert_eq!(unescape(&escape(field)), field);
+    assert_eq!(unescape("C:\\music"), "C:\\music");
+}
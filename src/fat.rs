@@ -0,0 +1,272 @@
+//! Reading files out of FAT12/16/32 disk images, such as a dump of an iPod's data partition.
+//!
+//! Documented here:
+//! - <https://academy.cba.mit.edu/classes/networking_communications/SD/FAT.pdf>
+
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+
+use crate::archive::{self, read_at, u16_at, u32_at, Entry};
+
+/// MBR partition types holding a FAT filesystem.
+const FAT_PARTITION_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0b, 0x0c, 0x0e];
+
+/// Sector size MBR partition offsets are counted in.
+const MBR_SECTOR: u64 = 512;
+
+/// Directory entry attributes.
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Width {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// A FAT filesystem starting `offset` bytes into the image.
+struct Volume<R> {
+    reader: R,
+    offset: u64,
+    width: Width,
+    bytes_per_sector: u64,
+    sectors_per_cluster: u64,
+    /// Sector of the FAT12/16 root directory.
+    root_start: u64,
+    root_entries: u64,
+    /// FAT32 root directory cluster.
+    root_cluster: u32,
+    /// Sector of cluster 2, the first data cluster.
+    data_start: u64,
+    clusters: u32,
+    fat: Vec<u8>,
+}
+
+/// Whether a boot sector describes a FAT filesystem.
+fn is_boot_sector(sector: &[u8]) -> bool {
+    let bytes_per_sector = u16_at(sector, 11);
+    let sectors_per_cluster = sector[13];
+    matches!(sector[0], 0xeb | 0xe9)
+        && matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+        && sectors_per_cluster.is_power_of_two()
+        && u16_at(sector, 14) > 0
+        && sector[16] > 0
+}
+
+impl<R: Read + Seek> Volume<R> {
+    fn open(mut reader: R, offset: u64) -> Result<Self, String> {
+        let boot = read_at(&mut reader, offset, 512)?;
+        if !is_boot_sector(&boot) {
+            return Err("not a FAT boot sector".to_string());
+        }
+        let bytes_per_sector = u64::from(u16_at(&boot, 11));
+        let sectors_per_cluster = u64::from(boot[13]);
+        let reserved = u64::from(u16_at(&boot, 14));
+        let fats = u64::from(boot[16]);
+        let root_entries = u64::from(u16_at(&boot, 17));
+        let total_sectors = match u16_at(&boot, 19) {
+            0 => u64::from(u32_at(&boot, 32)),
+            total => u64::from(total),
+        };
+        let fat_sectors = match u16_at(&boot, 22) {
+            0 => u64::from(u32_at(&boot, 36)),
+            sectors => u64::from(sectors),
+        };
+        let root_sectors = (root_entries * 32).div_ceil(bytes_per_sector);
+        let root_start = reserved + fats * fat_sectors;
+        let data_start = root_start + root_sectors;
+        let clusters = total_sectors.saturating_sub(data_start) / sectors_per_cluster;
+        let width = match clusters {
+            0..4085 => Width::Fat12,
+            4085..65525 => Width::Fat16,
+            _ => Width::Fat32,
+        };
+        let fat = read_at(
+            &mut reader,
+            offset + reserved * bytes_per_sector,
+            (fat_sectors * bytes_per_sector) as usize,
+        )?;
+        Ok(Volume {
+            reader,
+            offset,
+            width,
+            bytes_per_sector,
+            sectors_per_cluster,
+            root_start,
+            root_entries,
+            root_cluster: u32_at(&boot, 44),
+            data_start,
+            clusters: clusters as u32,
+            fat,
+        })
+    }
+
+    /// The cluster after `cluster` in its chain, or `None` at the end of the chain.
+    fn next_cluster(&self, cluster: u32) -> Option<u32> {
+        let n = cluster as usize;
+        let next = match self.width {
+            Width::Fat12 => {
+                let pair = u32::from(u16_at(self.fat.get(..n + n / 2 + 2)?, n + n / 2));
+                if n % 2 == 1 {
+                    pair >> 4
+                } else {
+                    pair & 0xfff
+                }
+            }
+            Width::Fat16 => u32::from(u16_at(self.fat.get(..n * 2 + 2)?, n * 2)),
+            Width::Fat32 => u32_at(self.fat.get(..n * 4 + 4)?, n * 4) & 0x0fff_ffff,
+        };
+        // Free, reserved, bad, and end-of-chain markers all end the chain.
+        Some(next).filter(|&next| next >= 2 && next < self.clusters + 2)
+    }
+
+    /// The contents of a cluster chain.
+    fn read_chain(&mut self, first: u32) -> Result<Vec<u8>, String> {
+        let cluster_bytes = self.sectors_per_cluster * self.bytes_per_sector;
+        let mut contents = Vec::new();
+        let mut visited = HashSet::new();
+        let mut cluster = Some(first).filter(|&first| first >= 2);
+        while let Some(current) = cluster {
+            if !visited.insert(current) {
+                return Err(format!("cluster chain loops at {current}"));
+            }
+            let sector = self.data_start + u64::from(current - 2) * self.sectors_per_cluster;
+            let offset = self.offset + sector * self.bytes_per_sector;
+            contents.extend(read_at(&mut self.reader, offset, cluster_bytes as usize)?);
+            cluster = self.next_cluster(current);
+        }
+        Ok(contents)
+    }
+
+    fn root_directory(&mut self) -> Result<Vec<u8>, String> {
+        match self.width {
+            Width::Fat32 => self.read_chain(self.root_cluster),
+            Width::Fat12 | Width::Fat16 => read_at(
+                &mut self.reader,
+                self.offset + self.root_start * self.bytes_per_sector,
+                (self.root_entries * 32) as usize,
+            ),
+        }
+    }
+
+    /// Walk a directory and everything below it, collecting scrobbler.logs.
+    fn walk(
+        &mut self,
+        directory: &[u8],
+        path: &str,
+        visited: &mut HashSet<u32>,
+        logs: &mut Vec<Entry>,
+    ) -> Result<(), String> {
+        for (name, attributes, cluster, size) in entries(directory) {
+            if name == "." || name == ".." || attributes & ATTR_VOLUME_ID != 0 {
+                continue;
+            }
+            let path = format!("{path}/{name}");
+            if attributes & ATTR_DIRECTORY != 0 {
+                if visited.insert(cluster) {
+                    let directory = self.read_chain(cluster)?;
+                    self.walk(&directory, &path, visited, logs)?;
+                }
+            } else if archive::is_scrobbler_log(&path) {
+                let mut contents = self.read_chain(cluster)?;
+                contents.truncate(size as usize);
+                logs.push(Entry { path, contents });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A directory's entries: name (the long name where there is one), attributes, first cluster,
+/// and size.
+fn entries(directory: &[u8]) -> Vec<(String, u8, u32, u32)> {
+    let mut entries = Vec::new();
+    let mut long_name: Vec<u16> = Vec::new();
+    for entry in directory.chunks_exact(32) {
+        match entry[0] {
+            0 => break,
+            0xe5 => {
+                long_name.clear();
+                continue;
+            }
+            _ => {}
+        }
+        let attributes = entry[11];
+        if attributes & ATTR_LONG_NAME == ATTR_LONG_NAME {
+            // Long name pieces come last piece first, 13 UTF-16 units each.
+            let piece = [1..11, 14..26, 28..32]
+                .into_iter()
+                .flat_map(|range| {
+                    entry[range]
+                        .chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                })
+                .take_while(|&unit| unit != 0 && unit != 0xffff);
+            let mut name: Vec<u16> = piece.collect();
+            name.extend_from_slice(&long_name);
+            long_name = name;
+            continue;
+        }
+        let name = match long_name.is_empty() {
+            false => String::from_utf16_lossy(&long_name),
+            true => short_name(&entry[..11]),
+        };
+        long_name.clear();
+        let cluster = u32::from(u16_at(entry, 20)) << 16 | u32::from(u16_at(entry, 26));
+        entries.push((name, attributes, cluster, u32_at(entry, 28)));
+    }
+    entries
+}
+
+/// An 8.3 name, as `NAME.EXT`.
+fn short_name(name: &[u8]) -> String {
+    let base = String::from_utf8_lossy(&name[..8]).trim_end().to_string();
+    let extension = String::from_utf8_lossy(&name[8..11]).trim_end().to_string();
+    match extension.is_empty() {
+        true => base,
+        false => format!("{base}.{extension}"),
+    }
+}
+
+/// Every scrobbler.log in a FAT image: either a bare filesystem, or a disk with an MBR whose FAT
+/// partitions are each searched.
+pub fn scrobbler_logs<R: Read + Seek>(mut reader: R) -> Result<Vec<Entry>, String> {
+    let sector = read_at(&mut reader, 0, 512)?;
+    let offsets: Vec<u64> = match is_boot_sector(&sector) {
+        true => vec![0],
+        false => (0..4)
+            .map(|i| &sector[446 + i * 16..462 + i * 16])
+            .filter(|partition| FAT_PARTITION_TYPES.contains(&partition[4]))
+            .map(|partition| u64::from(u32_at(partition, 8)) * MBR_SECTOR)
+            .collect(),
+    };
+    if offsets.is_empty() {
+        return Err("disk image has no FAT partitions".to_string());
+    }
+    let mut logs = Vec::new();
+    for offset in offsets {
+        let mut volume = Volume::open(&mut reader, offset)?;
+        let root = volume.root_directory()?;
+        volume.walk(&root, "", &mut HashSet::new(), &mut logs)?;
+    }
+    Ok(logs)
+}
+
+#[test]
+fn refuse_sizes_past_the_end() {
+    let mut image = vec![0; 512];
+    image[0] = 0xeb;
+    image[11..13].copy_from_slice(&512u16.to_le_bytes());
+    image[13] = 1;
+    image[14..16].copy_from_slice(&1u16.to_le_bytes());
+    image[16] = 1;
+    // A FAT of 2^32 - 1 sectors, in a 512-byte image.
+    image[36..40].copy_from_slice(&u32::MAX.to_le_bytes());
+    let error = scrobbler_logs(std::io::Cursor::new(image)).unwrap_err();
+    assert_eq!(
+        error,
+        "reading 2199023255040 bytes at 512: the file ends after 0"
+    );
+}
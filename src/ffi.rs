@@ -0,0 +1,114 @@
+//! C-compatible bindings for embedding the parser, e.g. from a `cdylib` or a wasm module.
+//!
+//! Strings crossing the boundary are NUL-terminated UTF-8. Every pointer returned by this module
+//! is owned by the caller and must be released with the matching `*_free` function. The
+//! declarations are mirrored in `include/scrobble_fix.h`.
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use chrono::{TimeZone, Utc};
+
+use crate::Scrobble;
+
+/// Borrow a C string as `&str`, rejecting null pointers and invalid UTF-8.
+///
+/// # Safety
+///
+/// `input` must be null or point to a NUL-terminated string that outlives the returned slice.
+unsafe fn borrow_str<'a>(input: *const c_char) -> Option<&'a str> {
+    if input.is_null() {
+        return None;
+    }
+    CStr::from_ptr(input).to_str().ok()
+}
+
+/// Hand a Rust string to the caller, or null if it contains an interior NUL.
+fn into_c_string(output: String) -> *mut c_char {
+    CString::new(output).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Parse one scrobbler.log line. Returns null if the line is not a valid scrobble.
+///
+/// # Safety
+///
+/// `line` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn scrobble_parse(line: *const c_char) -> *mut Scrobble {
+    match borrow_str(line).map(Scrobble::new) {
+        Some(Ok(scrobble)) => Box::into_raw(Box::new(scrobble)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Fix a parsed scrobble against a cutoff given in seconds since the Unix epoch.
+///
+/// Always consumes `scrobble`; returns the fixed scrobble, or null on failure.
+///
+/// # Safety
+///
+/// `scrobble` must be null or a pointer returned by this module that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn scrobble_fix(scrobble: *mut Scrobble, cutoff: i64) -> *mut Scrobble {
+    if scrobble.is_null() {
+        return ptr::null_mut();
+    }
+    let scrobble = Box::from_raw(scrobble);
+    let Some(cutoff) = Utc.timestamp_opt(cutoff, 0).single() else {
+        return ptr::null_mut();
+    };
+    match scrobble.fix(cutoff.fixed_offset()) {
+        Ok(fixed) => Box::into_raw(Box::new(fixed)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Serialize a scrobble back into a scrobbler.log line.
+///
+/// # Safety
+///
+/// `scrobble` must be null or a live pointer returned by this module.
+#[no_mangle]
+pub unsafe extern "C" fn scrobble_serialize(scrobble: *const Scrobble) -> *mut c_char {
+    match scrobble.as_ref() {
+        Some(scrobble) => into_c_string(scrobble.to_string()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Release a scrobble returned by [`scrobble_parse`] or [`scrobble_fix`].
+///
+/// # Safety
+///
+/// `scrobble` must be null or a live pointer returned by this module.
+#[no_mangle]
+pub unsafe extern "C" fn scrobble_free(scrobble: *mut Scrobble) {
+    if !scrobble.is_null() {
+        drop(Box::from_raw(scrobble));
+    }
+}
+
+/// Fix a complete scrobbler.log, returning the corrected log or null on failure.
+///
+/// # Safety
+///
+/// `log` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn scrobble_fix_log(log: *const c_char) -> *mut c_char {
+    match borrow_str(log).map(crate::fix_log) {
+        Some(Ok(fixed)) => into_c_string(fixed),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Release a string returned by this module.
+///
+/// # Safety
+///
+/// `string` must be null or a live pointer returned by this module.
+#[no_mangle]
+pub unsafe extern "C" fn scrobble_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
@@ -0,0 +1,140 @@
+//! Writing files, which the binary only ever does through [`replace`] (or [`append`], for the
+//! audit log), so `--read-only` can guard every write in one place, and every one is audited.
+//!
+//! Files kept between runs are read, changed and replaced under a [`lock`], so two runs at once
+//! (a scheduled one and one by hand, say) can't lose each other's changes.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use scrobble_fix::Scrobble;
+
+use crate::{audit, log};
+
+/// Files, and directories whose contents, nothing may be written to.
+static PROTECTED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Where a path really points, following symlinks, even if the file doesn't exist yet.
+fn resolve(path: &Path) -> PathBuf {
+    if let Ok(path) = path.canonicalize() {
+        return path;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => resolve(match parent.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => parent,
+        })
+        .join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Refuse every write to `paths`, or inside them if they're directories, from now on.
+pub fn protect(paths: &[PathBuf]) {
+    let mut protected = PROTECTED.lock().unwrap_or_else(|e| e.into_inner());
+    protected.extend(paths.iter().map(|path| resolve(path)));
+}
+
+/// Fail if `path` is protected.
+pub fn check(path: &Path) -> io::Result<()> {
+    let target = resolve(path);
+    let protected = PROTECTED.lock().unwrap_or_else(|e| e.into_inner());
+    match protected.iter().find(|input| target.starts_with(input)) {
+        Some(input) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{}: refusing to write with --read-only, since {} is an input",
+                path.display(),
+                input.display()
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Replace the file at `path` with `contents`, creating its directory if needed, and keeping its
+/// permissions if it exists.
+///
+/// The contents go to `PATH.tmp` first, renamed over it, so a failure never truncates it.
+pub fn replace(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    replace_log(path, contents, &[])
+}
+
+/// Like [`replace`], for a log of `scrobbles`, whose dates the audit log records.
+pub fn replace_log(
+    path: &Path,
+    contents: impl AsRef<[u8]>,
+    scrobbles: &[Scrobble],
+) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    check(path)?;
+    check(&temporary)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&temporary, &contents)?;
+    let moved = match std::fs::metadata(path) {
+        Ok(metadata) => std::fs::set_permissions(&temporary, metadata.permissions()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+    .and_then(|()| std::fs::rename(&temporary, path));
+    if let Err(e) = moved {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(e);
+    }
+    audit::wrote(&resolve(path), contents.as_ref(), scrobbles);
+    Ok(())
+}
+
+/// Add `contents` to the end of the file at `path`, creating it and its directory if needed. Only
+/// the audit log grows this way.
+pub fn append(path: &Path, contents: &[u8]) -> io::Result<()> {
+    check(path)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(contents)
+}
+
+/// An exclusive lock on a file, held until dropped.
+pub struct Lock {
+    _file: File,
+}
+
+/// Lock `path` against other runs until the lock is dropped, first waiting for any run holding
+/// it. The lock is taken on `PATH.lock`, which is left in place.
+pub fn lock(path: &Path) -> io::Result<Lock> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    check(&lock_path)?;
+    if let Some(dir) = lock_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            log::info(format_args!(
+                "waiting for another scrobble-fix to finish with {}",
+                path.display()
+            ));
+            file.lock()?;
+        }
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+    Ok(Lock { _file: file })
+}
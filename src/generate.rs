@@ -0,0 +1,219 @@
+//! Synthetic scrobbler.logs, for tests and for reproducing bugs without sharing a real history.
+//!
+//! The same [`Generator`] always writes the same log, so a seed is enough to pass a log around.
+
+use crate::{HEADER, SCROBBLE_DAYS_OFFSET};
+
+/// Albums to pick from: artist, album, and its tracks. Some names aren't ASCII, to exercise
+/// encodings.
+const ALBUMS: [(&str, &str, &[&str]); 5] = [
+    (
+        "Boards of Canada",
+        "Geogaddi",
+        &[
+            "Ready Lets Go",
+            "Music Is Math",
+            "Beware the Friendly Stranger",
+            "Dawn Chorus",
+        ],
+    ),
+    (
+        "Sigur Rós",
+        "Ágætis byrjun",
+        &[
+            "Svefn-g-englar",
+            "Starálfur",
+            "Flugufrelsarinn",
+            "Olsen Olsen",
+        ],
+    ),
+    (
+        "Björk",
+        "Homogenic",
+        &["Hunter", "Jóga", "Unravel", "Bachelorette"],
+    ),
+    (
+        "Motörhead",
+        "Ace of Spades",
+        &[
+            "Ace of Spades",
+            "Love Me Like a Reptile",
+            "Shoot You in the Back",
+        ],
+    ),
+    (
+        "Low",
+        "Drums and Guns",
+        &["Pretty People", "Breaker", "Belarus", "Dragonfly"],
+    ),
+];
+
+/// Ways to break a record, each turning a good line into one that won't parse.
+const CORRUPTIONS: [fn(&str) -> String; 4] = [
+    // Cut off partway, as when the battery dies mid-write.
+    |line| line.chars().take(line.chars().count() / 2).collect(),
+    |line| {
+        line.replacen("\tL\t", "\tX\t", 1)
+            .replacen("\tS\t", "\tX\t", 1)
+    },
+    |line| line.replace('\t', " "),
+    |_| "\u{0}\u{0}\u{0}\u{0}".to_string(),
+];
+
+/// A small xorshift generator, so logs don't depend on a random number crate.
+pub(crate) struct Random(u64);
+
+impl Random {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Zero would stay zero forever.
+        Random(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number below `n`.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+/// What to put in a synthetic log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generator {
+    /// Good records to write.
+    pub scrobbles: usize,
+    /// Stretches of records logged by a clock reset to 2001, spread evenly through the log. Adding
+    /// [`SCROBBLE_DAYS_OFFSET`] to them gives their real timestamps back.
+    pub resets: usize,
+    /// Unparseable lines to mix in, on top of the good records.
+    pub corrupt: usize,
+    pub seed: u64,
+    /// Real time of the first record, in seconds since the epoch.
+    pub start: i64,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator {
+            scrobbles: 1000,
+            resets: 1,
+            corrupt: 0,
+            seed: 1,
+            // 2023-11-08.
+            start: 1_699_413_807,
+        }
+    }
+}
+
+impl Generator {
+    /// Whether the record at `index` falls in a reset stretch: the log is cut into `2 * resets + 1`
+    /// equal parts, and every other one, starting from the second, is logged by a reset clock.
+    pub fn is_reset(&self, index: usize) -> bool {
+        let parts = 2 * self.resets + 1;
+        self.resets > 0 && (index * parts / self.scrobbles.max(1)) % 2 == 1
+    }
+
+    /// Write the log.
+    pub fn generate(&self) -> String {
+        let mut random = Random::new(self.seed);
+        let offset = SCROBBLE_DAYS_OFFSET as i64 * 24 * 60 * 60;
+        let mut lines = Vec::new();
+        let mut time = self.start;
+        let (mut album, mut track) = (0, 0);
+        for index in 0..self.scrobbles {
+            if track == ALBUMS[album].2.len() {
+                // Finished the album; pick another after a break of up to a day.
+                (album, track) = (random.below(ALBUMS.len()), 0);
+                time += random.below(24 * 60 * 60) as i64;
+            }
+            let (artist, title, tracks) = ALBUMS[album];
+            let duration = 120 + random.below(300) as i64;
+            let rating = if random.below(10) == 0 { 'S' } else { 'L' };
+            let logged = if self.is_reset(index) {
+                time - offset
+            } else {
+                time
+            };
+            lines.push(format!(
+                "{artist}\t{title}\t{}\t{}\t{duration}\t{rating}\t{logged}\t",
+                tracks[track],
+                track + 1
+            ));
+            time += duration;
+            track += 1;
+        }
+        for _ in 0..self.corrupt {
+            let good = lines
+                .get(random.below(lines.len()))
+                .cloned()
+                .unwrap_or_default();
+            let broken = CORRUPTIONS[random.below(CORRUPTIONS.len())](&good);
+            lines.insert(random.below(lines.len() + 1), broken);
+        }
+        format!("{HEADER}{}\n", lines.join("\n"))
+    }
+}
+
+/// Character encodings a log can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO-8859-1, as older firmware wrote; characters it lacks become `?`.
+    Latin1,
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            other => Err(format!("unknown encoding: {other}")),
+        }
+    }
+}
+
+impl Encoding {
+    /// Encode text for writing.
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+}
+
+#[test]
+fn generate_reproducible_logs() {
+    let generator = Generator {
+        scrobbles: 300,
+        resets: 2,
+        corrupt: 7,
+        seed: 42,
+        ..Generator::default()
+    };
+    let log = generator.generate();
+    assert_eq!(log, generator.generate());
+    let records: Vec<_> = crate::parse_records(&log).collect();
+    let good: Vec<_> = records.iter().filter_map(|r| r.as_ref().ok()).collect();
+    assert_eq!(good.len(), 300);
+    assert_eq!(records.len(), 307);
+    let cutoff: chrono::DateTime<chrono::Utc> = crate::SCROBBLE_CUTOFF.parse().unwrap();
+    let suspicious = good.iter().filter(|s| s.timestamp <= cutoff).count();
+    assert_eq!(
+        suspicious,
+        (0..300).filter(|&i| generator.is_reset(i)).count()
+    );
+    assert!(suspicious > 100 && suspicious < 200);
+    let latin1 = Encoding::Latin1.encode("Sigur Rós – Jóga");
+    assert_eq!(latin1, b"Sigur R\xf3s ? J\xf3ga");
+}
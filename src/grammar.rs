@@ -0,0 +1,249 @@
+//! The grammar of an AUDIOSCROBBLER/1.1 record, as nom parsers, for tools that need to know where
+//! in a line each field is, like syntax highlighters and linters.
+//!
+//! A record is a line of tab-separated fields:
+//!
+//! ```text
+//! record    = artist TAB album TAB track TAB position TAB duration TAB rating TAB timestamp
+//!             TAB track-id *(TAB extra)
+//! position  = *DIGIT           ; empty when the track has none
+//! duration  = 1*DIGIT          ; seconds
+//! rating    = "L" / "S"        ; listened or skipped
+//! timestamp = ["-"] 1*DIGIT    ; seconds since the epoch
+//! track-id  = [uuid]           ; a MusicBrainz recording id
+//! uuid      = 8HEXDIG "-" 4HEXDIG "-" 4HEXDIG "-" 4HEXDIG "-" 12HEXDIG
+//! ```
+//!
+//! [`record`] takes any text as the track id; whoever reads it decides what to make of one that
+//! isn't a [`uuid`].
+//!
+//! Every other field is any text without a tab. Each parser takes the input left to parse and
+//! returns nom's usual `(rest, matched)`; [`record`] parses a whole line, locating each field by
+//! a [`Span`] of byte offsets into it.
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_till, take_while_m_n},
+    character::complete::{char, digit0, digit1},
+    combinator::{all_consuming, opt, recognize},
+    multi::separated_list1,
+    sequence::{pair, tuple},
+    IResult, Offset,
+};
+
+/// Fields before the track id, which every record has.
+pub const REQUIRED_FIELDS: usize = 7;
+
+/// Where some text is in a line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span<'a> {
+    pub text: &'a str,
+    /// Byte offset of the text's start in the line.
+    pub offset: usize,
+}
+
+impl<'a> Span<'a> {
+    /// The span of `text`, which must be a slice of `line`.
+    pub fn within(line: &str, text: &'a str) -> Span<'a> {
+        Span {
+            text,
+            offset: line.offset(text),
+        }
+    }
+
+    /// Byte offset just past the text.
+    pub fn end(&self) -> usize {
+        self.offset + self.text.len()
+    }
+
+    /// The 1-based column the text starts at, counting characters.
+    pub fn column(&self, line: &str) -> usize {
+        column(line, self.offset)
+    }
+}
+
+/// The 1-based column of byte `offset` in `line`, counting characters.
+pub fn column(line: &str, offset: usize) -> usize {
+    line[..offset.min(line.len())].chars().count() + 1
+}
+
+/// The tab between two fields.
+pub fn separator(input: &str) -> IResult<&str, char> {
+    char('\t')(input)
+}
+
+/// A free-text field: anything up to the next tab, possibly nothing.
+pub fn text(input: &str) -> IResult<&str, &str> {
+    take_till(|c| c == '\t')(input)
+}
+
+/// A track position, empty for a track without one.
+pub fn position(input: &str) -> IResult<&str, &str> {
+    digit0(input)
+}
+
+/// A duration in seconds.
+pub fn duration(input: &str) -> IResult<&str, &str> {
+    digit1(input)
+}
+
+/// `L` for a listened track, `S` for a skipped one.
+pub fn rating(input: &str) -> IResult<&str, &str> {
+    alt((tag("L"), tag("S")))(input)
+}
+
+/// Seconds since the epoch, negative for a clock set before 1970.
+pub fn timestamp(input: &str) -> IResult<&str, &str> {
+    recognize(pair(opt(char('-')), digit1))(input)
+}
+
+/// A MusicBrainz id, like `6ba4a7a0-c9a4-4d64-b3a1-0fb720d0cf4c`.
+pub fn uuid(input: &str) -> IResult<&str, &str> {
+    let hex = |digits| take_while_m_n(digits, digits, |c: char| c.is_ascii_hexdigit());
+    let dash = || char('-');
+    recognize(tuple((
+        hex(8),
+        dash(),
+        hex(4),
+        dash(),
+        hex(4),
+        dash(),
+        hex(4),
+        dash(),
+        hex(12),
+    )))(input)
+}
+
+/// Every field of a line, split at its tabs.
+pub fn fields(input: &str) -> IResult<&str, Vec<&str>> {
+    separated_list1(separator, text)(input)
+}
+
+/// The located fields of a record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record<'a> {
+    pub artist: Span<'a>,
+    pub album: Span<'a>,
+    pub track: Span<'a>,
+    pub position: Span<'a>,
+    pub duration: Span<'a>,
+    pub rating: Span<'a>,
+    pub timestamp: Span<'a>,
+    /// Empty for a record without one, as is most.
+    pub track_id: Span<'a>,
+    /// Columns some plugin forks add after the track id.
+    pub extras: Vec<Span<'a>>,
+}
+
+/// Why a line isn't a record, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    /// Byte offset in the line.
+    pub offset: usize,
+    /// 1-based column, counting characters.
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+/// Parse a line into a record's fields, checking those with a grammar of their own.
+pub fn record(line: &str) -> Result<Record<'_>, SyntaxError> {
+    located(line, false)
+}
+
+/// Like [`record`], for an AUDIOSCROBBLER/1.0 log, whose records end at the timestamp; the
+/// track id is an empty span at the end of the line.
+pub fn legacy_record(line: &str) -> Result<Record<'_>, SyntaxError> {
+    located(line, true)
+}
+
+/// A field's text it doesn't match, as a message.
+type Message = fn(&str) -> String;
+
+fn located(line: &str, legacy: bool) -> Result<Record<'_>, SyntaxError> {
+    let error = |offset: usize, message: String| SyntaxError {
+        offset,
+        column: column(line, offset),
+        message,
+    };
+    let (_, pieces) = fields(line).map_err(|e| error(0, e.to_string()))?;
+    let required = REQUIRED_FIELDS + usize::from(!legacy);
+    if pieces.len() < required {
+        Err(error(
+            line.len(),
+            format!(
+                "expected at least {required} fields, found {}",
+                pieces.len()
+            ),
+        ))?;
+    }
+    let spans: Vec<Span> = pieces
+        .iter()
+        .map(|piece| Span::within(line, piece))
+        .collect();
+    let check = |span: Span<'_>, parser: fn(&str) -> IResult<&str, &str>, message: Message| {
+        all_consuming(parser)(span.text)
+            .map(drop)
+            .map_err(|_| error(span.offset, message(span.text)))
+    };
+    check(spans[3], position, |text| {
+        format!("bad track position {text:?}")
+    })?;
+    check(spans[4], duration, |text| format!("bad duration {text:?}"))?;
+    check(spans[5], rating, |text| {
+        format!("rating {text:?} isn't L or S")
+    })?;
+    check(spans[6], timestamp, |text| {
+        format!("bad timestamp {text:?}")
+    })?;
+    let end = Span {
+        text: &line[line.len()..],
+        offset: line.len(),
+    };
+    Ok(Record {
+        artist: spans[0],
+        album: spans[1],
+        track: spans[2],
+        position: spans[3],
+        duration: spans[4],
+        rating: spans[5],
+        timestamp: spans[6],
+        track_id: spans.get(REQUIRED_FIELDS).copied().unwrap_or(end),
+        extras: spans
+            .get(REQUIRED_FIELDS + 1..)
+            .unwrap_or_default()
+            .to_vec(),
+    })
+}
+
+#[test]
+fn locate_fields() {
+    let line = "Björk\tHomogenic\tJóga\t2\t305\tL\t1699413807\t\tAlbum Artist";
+    let record = record(line).unwrap();
+    assert_eq!(record.track.text, "Jóga");
+    assert_eq!((record.rating.offset, record.rating.column(line)), (29, 28));
+    assert_eq!(
+        &line[record.timestamp.offset..record.timestamp.end()],
+        "1699413807"
+    );
+    assert_eq!(record.track_id.text, "");
+    assert_eq!(record.extras.len(), 1);
+
+    let error = self::record("Björk\tHomogenic\tJóga\t2\t305\tX\t1699413807\t").unwrap_err();
+    assert_eq!(error.to_string(), "column 28: rating \"X\" isn't L or S");
+    assert_eq!(error.offset, 29);
+    let error = self::record("Björk\tHomogenic\tJóga").unwrap_err();
+    assert_eq!(
+        (error.column, error.message.as_str()),
+        (21, "expected at least 8 fields, found 3")
+    );
+    assert_eq!(timestamp("-86400\t"), Ok(("\t", "-86400")));
+    assert!(duration("").is_err());
+    assert!(all_consuming(uuid)("6ba4a7a0-c9a4-4d64-b3a1-0fb720d0cf4c").is_ok());
+    assert!(all_consuming(uuid)("6ba4a7a0-c9a4-4d64-b3a1").is_err());
+}
@@ -0,0 +1,145 @@
+//! The header lines of a scrobbler.log: the format version, how timestamps are kept, and the
+//! client that wrote it, and any more `#KEY/value` lines a fork adds, like `#MODE/`.
+
+/// How a log's timestamps relate to UTC, from its `#TZ/` line.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Timezone {
+    /// The player didn't know its timezone, as on most Rockbox devices.
+    #[default]
+    Unknown,
+    /// Timestamps are in UTC.
+    Utc,
+}
+
+impl std::fmt::Display for Timezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timezone::Unknown => write!(f, "UNKNOWN"),
+            Timezone::Utc => write!(f, "UTC"),
+        }
+    }
+}
+
+/// A log's header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogHeader {
+    /// The format version, like `1.1`.
+    pub version: String,
+    pub tz: Timezone,
+    /// The client that wrote the log, like `Rockbox ipodvideo $Revision$`.
+    pub client: String,
+    /// Header lines other than those three, as key and value, in the order they came.
+    pub extensions: Vec<(String, String)>,
+}
+
+/// The key and value of a header line, `#KEY/value`, where the key is capitals, digits and `_`.
+pub(crate) fn header_line(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.strip_prefix('#')?.split_once('/')?;
+    let mut chars = key.chars();
+    let well_formed = chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    well_formed.then_some((key, value))
+}
+
+impl Default for LogHeader {
+    /// The header written by Rockbox on an iPod, [`crate::HEADER`].
+    fn default() -> Self {
+        LogHeader {
+            version: "1.1".to_string(),
+            tz: Timezone::Unknown,
+            client: "Rockbox ipodvideo $Revision$".to_string(),
+            extensions: Vec::new(),
+        }
+    }
+}
+
+impl LogHeader {
+    /// Read the header from the first lines of a log: `#AUDIOSCROBBLER/`, `#TZ/` and
+    /// `#CLIENT/`, then (or among them) any other `#KEY/value` lines.
+    pub fn parse(log: &str) -> Result<Self, String> {
+        let (mut version, mut tz, mut client) = (None, None, None);
+        let mut extensions = Vec::new();
+        let lines = log
+            .lines()
+            .map(|line| line.strip_suffix('\r').unwrap_or(line));
+        for (index, (key, value)) in lines.map_while(header_line).enumerate() {
+            match key {
+                "AUDIOSCROBBLER" if index == 0 => version = Some(value.to_string()),
+                "TZ" => {
+                    tz = Some(match value {
+                        "UNKNOWN" => Timezone::Unknown,
+                        "UTC" => Timezone::Utc,
+                        other => Err(format!("unknown timezone {other}"))?,
+                    })
+                }
+                "CLIENT" => client = Some(value.to_string()),
+                _ if index == 0 => break,
+                _ => extensions.push((key.to_string(), value.to_string())),
+            }
+        }
+        let missing = |prefix| format!("header has no {prefix} line");
+        Ok(LogHeader {
+            version: version.ok_or(missing("#AUDIOSCROBBLER/"))?,
+            tz: tz.ok_or(missing("#TZ/"))?,
+            client: client.ok_or(missing("#CLIENT/"))?,
+            extensions,
+        })
+    }
+
+    /// How many lines the header of `log` takes before its first record: the first
+    /// [`crate::HEADER_LINES`], and any extensions after them.
+    pub fn line_count(log: &str) -> usize {
+        let header = log
+            .lines()
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .take_while(|line| header_line(line).is_some())
+            .count();
+        header.max(crate::HEADER_LINES)
+    }
+
+    /// Whether the log is AUDIOSCROBBLER/1.0, whose records have no track id column.
+    pub fn is_legacy(&self) -> bool {
+        self.version == "1.0"
+    }
+}
+
+impl std::fmt::Display for LogHeader {
+    /// The header lines, each ending in a newline.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "#AUDIOSCROBBLER/{}", self.version)?;
+        writeln!(f, "#TZ/{}", self.tz)?;
+        writeln!(f, "#CLIENT/{}", self.client)?;
+        for (key, value) in &self.extensions {
+            writeln!(f, "#{key}/{value}")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn parse_headers() {
+    let header = LogHeader::parse(crate::HEADER).unwrap();
+    assert_eq!(header, LogHeader::default());
+    assert_eq!(header.to_string(), crate::HEADER);
+    let utc =
+        LogHeader::parse("#AUDIOSCROBBLER/1.0\n#TZ/UTC\n#CLIENT/Rockbox sansae200\n").unwrap();
+    assert!(utc.is_legacy());
+    assert_eq!(
+        (utc.tz, utc.client.as_str()),
+        (Timezone::Utc, "Rockbox sansae200")
+    );
+    assert!(LogHeader::parse("#AUDIOSCROBBLER/1.1\n#TZ/CET\n#CLIENT/x\n").is_err());
+    let fork = "#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#MODE/strict\n#CLIENT/x\n#RATE_LIMIT/50\n# trip\n";
+    let header = LogHeader::parse(fork).unwrap();
+    assert_eq!(
+        header.extensions[0],
+        ("MODE".to_string(), "strict".to_string())
+    );
+    assert_eq!(
+        header.to_string(),
+        "#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/x\n#MODE/strict\n#RATE_LIMIT/50\n"
+    );
+    assert_eq!(LogHeader::line_count(fork), 5);
+    assert_eq!(LogHeader::line_count(crate::HEADER), 3);
+    assert!(LogHeader::parse("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t").is_err());
+}
@@ -0,0 +1,66 @@
+//! User commands run around a run: `--pre-hook` rewrites the records, `--post-hook` hears how
+//! the run went.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use scrobble_fix::{json, Fixer, Scrobble};
+
+/// Run a command line through the shell, feeding it `input` and returning what it prints.
+fn run(command: &str, input: String) -> Result<String, String> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut child = Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{command}: {e}"))?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    // Write from another thread, so a hook printing as it reads can't fill its pipe and stall.
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("{command}: {e}"))?;
+    // A hook that exits without reading everything is fine; its exit status says how it went.
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(format!("{command}: {}", output.status));
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("{command}: {e}"))
+}
+
+/// Pipes every record to a command as JSON Lines (one [`Scrobble::to_json`] object per line),
+/// and carries on with the records it prints back in the same form. The command may change,
+/// drop, reorder or add records.
+pub struct PreHook {
+    pub command: String,
+}
+
+impl Fixer for PreHook {
+    fn fix(&self, scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        let input = scrobbles
+            .iter()
+            .map(|scrobble| scrobble.to_json() + "\n")
+            .collect();
+        let output = run(&self.command, input)?;
+        output
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                json::parse(line)
+                    .and_then(|value| Scrobble::from_json(&value))
+                    .map_err(|e| format!("{}: output line {}: {e}", self.command, index + 1))
+            })
+            .collect()
+    }
+}
+
+/// Pipe the run summary to a command as a JSON document.
+pub fn post_hook(command: &str, summary: &str) -> Result<(), String> {
+    run(command, summary.to_string() + "\n").map(drop)
+}
@@ -0,0 +1,300 @@
+//! Reading the artist, album and title from an MP3's ID3 tags, to fill in the albums of records
+//! logged from files that had none when they were played.
+//!
+//! An ID3v2 tag (2.2, 2.3 or 2.4) starts the file: a 10-byte header giving its size, then
+//! frames of one tag each. An ID3v1 tag is the file's last 128 bytes, in fixed-width fields;
+//! it's read when there's no ID3v2 tag.
+
+use std::collections::HashMap;
+
+use crate::pipeline::Fixer;
+use crate::tagcache::{normalize, Track};
+use crate::Scrobble;
+
+/// Bytes in an ID3v2 header, and in the ID3v1 tag.
+pub const HEADER: usize = 10;
+pub const V1_SIZE: usize = 128;
+
+const FLAG_UNSYNCHRONIZED: u8 = 0x80;
+const FLAG_EXTENDED_HEADER: u8 = 0x40;
+const FLAG_FOOTER: u8 = 0x10;
+
+/// A 28-bit number in four bytes of seven bits, as ID3v2 sizes are.
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, &byte| size << 7 | (byte & 0x7f) as usize)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, &byte| size << 8 | byte as usize)
+}
+
+/// The size, header and all, of the ID3v2 tag a file starts with, from its first [`HEADER`]
+/// bytes; `None` when it doesn't start with one.
+pub fn tag_size(start: &[u8]) -> Option<usize> {
+    match start.get(..HEADER)? {
+        [b'I', b'D', b'3', 2..=4, _, flags, size @ ..] => {
+            let footer = match flags & FLAG_FOOTER {
+                0 => 0,
+                _ => HEADER,
+            };
+            Some(HEADER + syncsafe(size) + footer)
+        }
+        _ => None,
+    }
+}
+
+/// Undo unsynchronization, which puts a zero after every 0xFF so no tag byte looks like the
+/// start of an MPEG frame.
+fn resynchronize(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len());
+    for (i, &byte) in data.iter().enumerate() {
+        if !(byte == 0 && i > 0 && data[i - 1] == 0xff) {
+            bytes.push(byte);
+        }
+    }
+    bytes
+}
+
+fn utf16(data: &[u8], big_endian: bool) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| match big_endian {
+            true => u16::from_be_bytes([pair[0], pair[1]]),
+            false => u16::from_le_bytes([pair[0], pair[1]]),
+        })
+        .take_while(|&unit| unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// A text frame's first value: an encoding byte, then the text, in ISO-8859-1, UTF-16 with a
+/// byte order mark, UTF-16BE or UTF-8.
+fn text(frame: &[u8]) -> String {
+    let Some((&encoding, data)) = frame.split_first() else {
+        return String::new();
+    };
+    let text = match encoding {
+        1 => match data {
+            [0xfe, 0xff, data @ ..] => utf16(data, true),
+            [0xff, 0xfe, data @ ..] => utf16(data, false),
+            _ => utf16(data, false),
+        },
+        2 => utf16(data, true),
+        _ => {
+            let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+            match encoding {
+                3 => String::from_utf8_lossy(&data[..end]).into_owned(),
+                _ => data[..end].iter().map(|&b| b as char).collect(),
+            }
+        }
+    };
+    text.trim().to_string()
+}
+
+/// Read an ID3v2 tag, header and all, as long as [`tag_size`] says. Frames Rockbox doesn't read
+/// either, compressed or encrypted ones, are skipped.
+pub fn parse(tag: &[u8]) -> Result<Track, String> {
+    let size = tag_size(tag).ok_or("no ID3v2 header")?;
+    let data = tag.get(HEADER..size).ok_or("ID3v2 tag runs past the end")?;
+    let (version, flags) = (tag[3], tag[5]);
+    let data = match version < 4 && flags & FLAG_UNSYNCHRONIZED != 0 {
+        true => resynchronize(data),
+        false => data.to_vec(),
+    };
+    let mut position = 0;
+    if version > 2 && flags & FLAG_EXTENDED_HEADER != 0 {
+        let size = data.get(..4).ok_or("truncated extended header")?;
+        position = match version {
+            3 => 4 + big_endian(size),
+            _ => syncsafe(size),
+        };
+    }
+    let (id_length, header_length) = match version {
+        2 => (3, 6),
+        _ => (4, HEADER),
+    };
+    let mut track = Track::default();
+    while let Some(header) = data.get(position..position + header_length) {
+        // Padding fills the rest.
+        if header[0] == 0 {
+            break;
+        }
+        let id = &header[..id_length];
+        let size = match version {
+            2 => big_endian(&header[3..6]),
+            3 => big_endian(&header[4..8]),
+            _ => syncsafe(&header[4..8]),
+        };
+        position += header_length;
+        let frame = data.get(position..position + size).ok_or(format!(
+            "frame {} runs past the end",
+            String::from_utf8_lossy(id)
+        ))?;
+        position += size;
+        let format = match version {
+            2 => 0,
+            _ => header[9],
+        };
+        let frame = match version {
+            3 if format & 0xc0 != 0 => continue,
+            4 if format & 0x0c != 0 => continue,
+            4 => {
+                let frame = match format & 0x02 != 0 || flags & FLAG_UNSYNCHRONIZED != 0 {
+                    true => resynchronize(frame),
+                    false => frame.to_vec(),
+                };
+                // A data length indicator comes first.
+                match format & 0x01 {
+                    0 => frame,
+                    _ => frame.get(4..).unwrap_or_default().to_vec(),
+                }
+            }
+            _ => frame.to_vec(),
+        };
+        let field = match id {
+            b"TPE1" | b"TP1" => &mut track.artist,
+            b"TALB" | b"TAL" => &mut track.album,
+            b"TIT2" | b"TT2" => &mut track.title,
+            b"TPE2" | b"TP2" => &mut track.album_artist,
+            _ => continue,
+        };
+        *field = text(&frame);
+    }
+    Ok(track)
+}
+
+/// Read the ID3v1 tag in a file's last [`V1_SIZE`] bytes, if it has one.
+pub fn parse_v1(trailer: &[u8]) -> Option<Track> {
+    let fields = trailer
+        .strip_prefix(b"TAG")
+        .filter(|_| trailer.len() == V1_SIZE)?;
+    // ISO-8859-1, padded with zeros or spaces.
+    let field = |start: usize| {
+        let data = &fields[start..start + 30];
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        data[..end]
+            .iter()
+            .map(|&b| b as char)
+            .collect::<String>()
+            .trim()
+            .to_string()
+    };
+    Some(Track {
+        title: field(0),
+        artist: field(30),
+        album: field(60),
+        ..Track::default()
+    })
+}
+
+/// Fills in the album of every scrobble without one from the tags of the track it played,
+/// matched by artist and title, ignoring case and spacing. When copies of the track are on
+/// several albums, the scrobble is left alone.
+#[derive(Debug, Clone, Default)]
+pub struct AlbumFixer {
+    /// The album of each normalized artist and title, or `None` when there's more than one.
+    albums: HashMap<(String, String), Option<String>>,
+}
+
+impl AlbumFixer {
+    pub fn new(tracks: Vec<Track>) -> Self {
+        let mut albums: HashMap<(String, String), Option<String>> = HashMap::new();
+        for track in tracks.into_iter().filter(|track| !track.album.is_empty()) {
+            let key = (normalize(&track.artist), normalize(&track.title));
+            albums
+                .entry(key)
+                .and_modify(|album| {
+                    if album
+                        .as_ref()
+                        .is_some_and(|album| normalize(album) != normalize(&track.album))
+                    {
+                        *album = None;
+                    }
+                })
+                .or_insert(Some(track.album));
+        }
+        AlbumFixer { albums }
+    }
+}
+
+impl Fixer for AlbumFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        for scrobble in &mut scrobbles {
+            if !scrobble.album.trim().is_empty() {
+                continue;
+            }
+            let key = (normalize(&scrobble.artist), normalize(&scrobble.track));
+            if let Some(Some(album)) = self.albums.get(&key) {
+                scrobble.album.clone_from(album);
+            }
+        }
+        Ok(scrobbles)
+    }
+}
+
+#[test]
+fn read_id3_tags() {
+    // An ID3v2.3 tag of text frames, in ISO-8859-1, then padding.
+    let frame = |id: &[u8], encoding: u8, text: &[u8]| {
+        let mut frame = id.to_vec();
+        frame.extend((text.len() as u32 + 1).to_be_bytes());
+        frame.extend([0, 0, encoding]);
+        frame.extend(text);
+        frame
+    };
+    let mut frames = frame(b"TPE1", 0, b"Bj\xf6rk");
+    frames.extend(frame(
+        b"TIT2",
+        1,
+        &[0xff, 0xfe, b'J', 0, 0xf3, 0, b'g', 0, b'a', 0],
+    ));
+    frames.extend(frame(b"TALB", 3, "Homogenic".as_bytes()));
+    frames.extend(frame(b"APIC", 0, b"\x89PNG"));
+    frames.resize(frames.len() + 32, 0);
+    let mut tag = b"ID3\x03\x00\x00".to_vec();
+    tag.extend((0..4).rev().map(|i| (frames.len() >> (7 * i)) as u8 & 0x7f));
+    tag.extend(frames);
+    assert_eq!(tag_size(&tag[..HEADER]), Some(tag.len()));
+    let track = parse(&tag).unwrap();
+    assert_eq!(
+        (
+            track.artist.as_str(),
+            track.title.as_str(),
+            track.album.as_str()
+        ),
+        ("Björk", "Jóga", "Homogenic")
+    );
+    assert!(parse(&tag[..40]).is_err());
+    assert_eq!(tag_size(b"\xff\xfb\x90\x64\0\0\0\0\0\0"), None);
+
+    let mut trailer = b"TAG".to_vec();
+    for field in [&b"Breaker"[..], b"Low", b"Drums and Guns"] {
+        let mut field = field.to_vec();
+        field.resize(30, b' ');
+        trailer.extend(field);
+    }
+    trailer.resize(V1_SIZE, 0);
+    let low = parse_v1(&trailer).unwrap();
+    assert_eq!(
+        (low.artist.as_str(), low.album.as_str()),
+        ("Low", "Drums and Guns")
+    );
+
+    let fixer = AlbumFixer::new(vec![track, low]);
+    let scrobbles = vec![
+        Scrobble::new("low\t\tBREAKER\t5\t187\tL\t1699413807\t").unwrap(),
+        Scrobble::new("Low\tThe Great Destroyer\tBreaker\t5\t187\tL\t1699413807\t").unwrap(),
+        Scrobble::new("Björk\t\tHunter\t1\t255\tL\t1699413807\t").unwrap(),
+    ];
+    let albums: Vec<String> = fixer
+        .fix(scrobbles)
+        .unwrap()
+        .into_iter()
+        .map(|scrobble| scrobble.album)
+        .collect();
+    assert_eq!(albums, ["Drums and Guns", "The Great Destroyer", ""]);
+}
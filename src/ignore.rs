@@ -0,0 +1,366 @@
+//! Ignore files: patterns for records to leave out of every run, like `.gitignore` for scrobbles.
+//!
+//! ```text
+//! # Comments and blank lines are skipped.
+//! artist:Nickelback
+//! track:*(Interlude)*
+//! album:/^now that's what i call music/
+//! *Sound Effects*
+//! !artist:Nickelback Tribute Band
+//! ```
+//!
+//! A pattern is a glob matching the whole field (`*`, `?` and `[...]`), or a regular expression
+//! between slashes found anywhere in it, and ignores case. `artist:`, `album:` or `track:` limits
+//! it to that field; otherwise any of them may match. A `!` re-includes what earlier lines
+//! ignored, and the last line matching a record decides, as in `.gitignore`. `\` escapes a leading
+//! `!`, `#` or `/`.
+//!
+//! The regular expressions have literals, `.`, `[...]` classes, `\d`, `\w` and `\s`, the `*`, `+`
+//! and `?` repetitions, `^` and `$`, and `|` alternatives in `(...)` groups.
+
+use crate::pipeline::Fixer;
+use crate::rules::Field;
+use crate::Scrobble;
+
+/// The ignore file looked for beside a log.
+pub const FILE_NAME: &str = ".scrobbleignore";
+
+/// One piece of a compiled pattern.
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Char(char),
+    Any,
+    /// Ranges of characters, or every character outside them.
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    /// Alternatives, each a sequence.
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+/// Whether `c` is `expected`, or is it in another case.
+fn same_letter(c: char, expected: char) -> bool {
+    c == expected || c.to_lowercase().eq(expected.to_lowercase())
+}
+
+impl Node {
+    /// Whether a node standing for one character matches `c`.
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Node::Char(expected) => same_letter(c, *expected),
+            Node::Any => true,
+            Node::Class(ranges, negated) => {
+                let cases = c.to_lowercase().chain(c.to_uppercase()).chain([c]);
+                let inside = cases
+                    .into_iter()
+                    .any(|c| ranges.iter().any(|&(low, high)| (low..=high).contains(&c)));
+                inside != *negated
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Match `nodes` against `text` from `at`, calling `then` with where each match ends until it
+/// accepts one.
+fn match_at(nodes: &[Node], text: &[char], at: usize, then: &mut dyn FnMut(usize) -> bool) -> bool {
+    let Some((first, rest)) = nodes.split_first() else {
+        return then(at);
+    };
+    match first {
+        Node::Start => at == 0 && match_at(rest, text, at, then),
+        Node::End => at == text.len() && match_at(rest, text, at, then),
+        Node::Group(alternatives) => alternatives.iter().any(|alternative| {
+            match_at(alternative, text, at, &mut |end| {
+                match_at(rest, text, end, then)
+            })
+        }),
+        Node::Repeat(node, min, max) => repeat(node, (*min, *max), 0, rest, text, at, then),
+        single => at < text.len() && single.matches(text[at]) && match_at(rest, text, at + 1, then),
+    }
+}
+
+/// Match as many more repetitions of `node` as allowed, after `count` of them, then `rest`.
+fn repeat(
+    node: &Node,
+    (min, max): (usize, Option<usize>),
+    count: usize,
+    rest: &[Node],
+    text: &[char],
+    at: usize,
+    then: &mut dyn FnMut(usize) -> bool,
+) -> bool {
+    let more = max.is_none_or(|max| count < max)
+        && match_at(std::slice::from_ref(node), text, at, &mut |end| {
+            // A repetition matching nothing would repeat forever.
+            end > at && repeat(node, (min, max), count + 1, rest, text, end, then)
+        });
+    more || (count >= min && match_at(rest, text, at, then))
+}
+
+/// A `[...]` class, after its `[`.
+fn class(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    negation: &[char],
+) -> Result<Node, String> {
+    let negated = chars.next_if(|c| negation.contains(c)).is_some();
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let low = match chars.next() {
+            None => Err("unclosed [")?,
+            Some(']') if !first => break,
+            Some('\\') => chars.next().ok_or("trailing \\")?,
+            Some(c) => c,
+        };
+        first = false;
+        let high = match chars.peek() {
+            Some('-') => {
+                chars.next();
+                match chars.next() {
+                    Some(']') => {
+                        ranges.push((low, low));
+                        ranges.push(('-', '-'));
+                        break;
+                    }
+                    Some(high) => high,
+                    None => Err("unclosed [")?,
+                }
+            }
+            _ => low,
+        };
+        ranges.push((low, high));
+    }
+    Ok(Node::Class(ranges, negated))
+}
+
+/// A compiled glob or regular expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern(Vec<Node>);
+
+impl Pattern {
+    /// A glob, which must match the whole text.
+    pub fn glob(glob: &str) -> Result<Pattern, String> {
+        let mut chars = glob.chars().peekable();
+        let mut nodes = vec![Node::Start];
+        while let Some(c) = chars.next() {
+            nodes.push(match c {
+                '*' => Node::Repeat(Box::new(Node::Any), 0, None),
+                '?' => Node::Any,
+                '[' => class(&mut chars, &['!', '^']).map_err(|e| format!("{glob}: {e}"))?,
+                '\\' => Node::Char(chars.next().ok_or(format!("{glob}: trailing \\"))?),
+                c => Node::Char(c),
+            });
+        }
+        nodes.push(Node::End);
+        Ok(Pattern(nodes))
+    }
+
+    /// A regular expression, which may match anywhere in the text.
+    pub fn regex(regex: &str) -> Result<Pattern, String> {
+        let mut chars = regex.chars().peekable();
+        let alternatives = alternatives(&mut chars).map_err(|e| format!("/{regex}/: {e}"))?;
+        match chars.next() {
+            Some(c) => Err(format!("/{regex}/: unexpected {c}")),
+            None => Ok(Pattern(vec![
+                Node::Repeat(Box::new(Node::Any), 0, None),
+                Node::Group(alternatives),
+            ])),
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        match_at(&self.0, &text, 0, &mut |_| true)
+    }
+}
+
+/// `|`-separated sequences, up to an unmatched `)` or the end.
+fn alternatives(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+) -> Result<Vec<Vec<Node>>, String> {
+    let mut sequences = vec![Vec::new()];
+    while let Some(&c) = chars.peek() {
+        if c == ')' {
+            break;
+        }
+        chars.next();
+        let sequence = sequences.last_mut().expect("an alternative");
+        let node = match c {
+            '|' => {
+                sequences.push(Vec::new());
+                continue;
+            }
+            '*' | '+' | '?' => {
+                let node = sequence
+                    .pop()
+                    .ok_or(format!("nothing to repeat before {c}"))?;
+                let (min, max) = match c {
+                    '*' => (0, None),
+                    '+' => (1, None),
+                    _ => (0, Some(1)),
+                };
+                Node::Repeat(Box::new(node), min, max)
+            }
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '[' => class(chars, &['^'])?,
+            '(' => {
+                let group = alternatives(chars)?;
+                chars.next().filter(|&c| c == ')').ok_or("unclosed (")?;
+                Node::Group(group)
+            }
+            '\\' => match chars.next().ok_or("trailing \\")? {
+                'd' => Node::Class(vec![('0', '9')], false),
+                'w' => Node::Class(vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], false),
+                's' => Node::Class(vec![(' ', ' '), ('\t', '\r')], false),
+                c => Node::Char(c),
+            },
+            c => Node::Char(c),
+        };
+        sequence.push(node);
+    }
+    Ok(sequences)
+}
+
+/// A line of an ignore file.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    /// The field it looks at, or `None` for any.
+    field: Option<Field>,
+    pattern: Pattern,
+    /// Whether it re-includes what it matches.
+    negated: bool,
+}
+
+/// The patterns of one or more ignore files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IgnoreList {
+    entries: Vec<Entry>,
+}
+
+impl IgnoreList {
+    /// Read an ignore file, naming the line of any bad pattern.
+    pub fn parse(text: &str) -> Result<IgnoreList, String> {
+        let mut entries = Vec::new();
+        for (number, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line)) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let (field, pattern) = match line.split_once(':') {
+                Some((field, pattern)) if field.parse::<Field>().is_ok() => {
+                    (field.parse().ok(), pattern.trim_start())
+                }
+                _ => (None, line),
+            };
+            let error = |e: String| format!("line {number}: {e}");
+            let pattern = match pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+                Some(regex) if !regex.is_empty() => Pattern::regex(regex).map_err(error)?,
+                _ => Pattern::glob(pattern).map_err(error)?,
+            };
+            entries.push(Entry {
+                field,
+                pattern,
+                negated,
+            });
+        }
+        Ok(IgnoreList { entries })
+    }
+
+    /// Add the patterns of a later file, which win over these.
+    pub fn extend(&mut self, later: IgnoreList) {
+        self.entries.extend(later.entries);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the last pattern matching the record ignores it.
+    pub fn is_ignored(&self, scrobble: &Scrobble) -> bool {
+        let field = |field| match field {
+            Field::Artist => &scrobble.artist,
+            Field::Album => &scrobble.album,
+            Field::Track => &scrobble.track,
+        };
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| match entry.field {
+                Some(name) => entry.pattern.matches(field(name)),
+                None => [Field::Artist, Field::Album, Field::Track]
+                    .into_iter()
+                    .any(|name| entry.pattern.matches(field(name))),
+            })
+            .is_some_and(|entry| !entry.negated)
+    }
+}
+
+/// Leaves out the records an [`IgnoreList`] ignores.
+#[derive(Debug, Clone)]
+pub struct IgnoreFixer {
+    pub list: IgnoreList,
+}
+
+impl Fixer for IgnoreFixer {
+    fn fix(&self, scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        Ok(scrobbles
+            .into_iter()
+            .filter(|scrobble| !self.list.is_ignored(scrobble))
+            .collect())
+    }
+}
+
+#[test]
+fn match_patterns() {
+    let glob = Pattern::glob("*(interlude)*").unwrap();
+    assert!(glob.matches("Outro (Interlude) [Live]"));
+    assert!(!glob.matches("Interlude"));
+    assert!(Pattern::glob("Track [0-9]?").unwrap().matches("track 12"));
+    assert!(!Pattern::glob("Track [!0-9]").unwrap().matches("Track 1"));
+    let regex = Pattern::regex(r"^(now|hits) \d+$").unwrap();
+    assert!(regex.matches("Now 48"));
+    assert!(!regex.matches("Now 48 (Disc 2)"));
+    assert!(Pattern::regex("sound ?effects?")
+        .unwrap()
+        .matches("BBC Sound Effects No. 1"));
+    assert!(Pattern::regex("a(b").is_err());
+    assert!(Pattern::regex("*a").is_err());
+}
+
+#[test]
+fn ignore_like_gitignore() {
+    let list = IgnoreList::parse(
+        "# a comment\n\nartist:Nickelback\n!artist:nickelback tribute*\n\
+         album:/^now that's what i call music/\n*Sound Effects*\ntrack:\\!Live\n",
+    )
+    .unwrap();
+    let ignored = |line: &str| list.is_ignored(&Scrobble::new(line).unwrap());
+    assert!(ignored(
+        "Nickelback\tSilver Side Up\tHow You Remind Me\t1\t223\tL\t1699413807\t"
+    ));
+    assert!(!ignored(
+        "Nickelback Tribute Band\tLive\tPhotograph\t1\t250\tL\t1699413807\t"
+    ));
+    assert!(ignored(
+        "Various\tNow That's What I Call Music! 48\tTrack\t1\t200\tL\t1699413807\t"
+    ));
+    assert!(ignored(
+        "BBC\tBBC Sound Effects No. 1\tRain\t1\t60\tL\t1699413807\t"
+    ));
+    assert!(ignored("Low\tOne\t!Live\t1\t60\tL\t1699413807\t"));
+    assert!(!ignored(
+        "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t"
+    ));
+    assert_eq!(
+        IgnoreList::parse("ok\ntrack:[a-").unwrap_err(),
+        "line 2: [a-: unclosed ["
+    );
+}
@@ -0,0 +1,271 @@
+//! DEFLATE decompression, for zip archives and gzipped tarballs.
+//!
+//! Documented here:
+//! - <https://www.rfc-editor.org/rfc/rfc1951>
+//! - <https://www.rfc-editor.org/rfc/rfc1952>
+
+/// Longest Huffman code, in bits.
+const MAX_BITS: usize = 15;
+
+/// Base lengths for length symbols 257..=285, and their extra bits.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances for distance symbols 0..=29, and their extra bits.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order code length code lengths are sent in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Reads bits least significant first, as DEFLATE packs them.
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or("compressed data ends early")?;
+            self.position += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drop the rest of the current byte.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code: how many codes there are of each length, and the symbols in code
+/// order.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; MAX_BITS + 1];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[usize::from(symbol)] != 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[usize::from(symbol)]);
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+/// The codes of a block compressed with fixed Huffman codes.
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// Read the codes at the start of a block compressed with dynamic Huffman codes.
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+    let mut lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[symbol] = bits.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths);
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or("repeat with no previous length")?;
+                (previous, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err("code lengths overrun".to_string());
+    }
+    let (literal_lengths, distance_lengths) = lengths.split_at(literals);
+    Ok((
+        Huffman::new(literal_lengths),
+        Huffman::new(distance_lengths),
+    ))
+}
+
+/// Decompress a block's symbols until its end-of-block code.
+fn inflate_block(
+    bits: &mut Bits,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = usize::from(literals.decode(bits)?);
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                let base = *LENGTH_BASE.get(index).ok_or("invalid length symbol")?;
+                let length =
+                    usize::from(base) + bits.bits(u32::from(LENGTH_EXTRA[index]))? as usize;
+                let index = usize::from(distances.decode(bits)?);
+                let base = *DISTANCE_BASE.get(index).ok_or("invalid distance symbol")?;
+                let distance =
+                    usize::from(base) + bits.bits(u32::from(DISTANCE_EXTRA[index]))? as usize;
+                if distance > output.len() {
+                    return Err("distance reaches before the start of the data".to_string());
+                }
+                // The copy may overlap what it produces, so go a byte at a time.
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+    }
+}
+
+/// Decompress raw DEFLATE data.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bits = Bits {
+        data,
+        position: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut output = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = data
+                    .get(bits.position..bits.position + 4)
+                    .ok_or("stored block ends early")?;
+                let length = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                if u16::from_le_bytes([header[2], header[3]]) != !(length as u16) {
+                    return Err("stored block length doesn't match its complement".to_string());
+                }
+                let start = bits.position + 4;
+                let stored = data
+                    .get(start..start + length)
+                    .ok_or("stored block ends early")?;
+                output.extend_from_slice(stored);
+                bits.position = start + length;
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut bits, &mut output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut output, &literals, &distances)?;
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+/// Decompress a gzip file.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 18 || data[..3] != [0x1f, 0x8b, 8] {
+        return Err("not a gzip file".to_string());
+    }
+    let flags = data[3];
+    let mut position = 10;
+    if flags & 0x04 != 0 {
+        let extra = data
+            .get(position..position + 2)
+            .ok_or("gzip header ends early")?;
+        position += 2 + usize::from(u16::from_le_bytes([extra[0], extra[1]]));
+    }
+    // The original file name and a comment, each terminated by a zero byte.
+    for flag in [0x08, 0x10] {
+        if flags & flag != 0 {
+            let end = data[position.min(data.len())..]
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or("gzip header ends early")?;
+            position += end + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        position += 2;
+    }
+    inflate(data.get(position..).ok_or("gzip header ends early")?)
+}
+
+#[test]
+fn inflate_each_block_type() {
+    // Raw zlib output for a stored block, a fixed Huffman block, and a dynamic Huffman block.
+    assert_eq!(inflate(&[1, 3, 0, 252, 255, 97, 98, 99]).unwrap(), b"abc");
+    assert_eq!(
+        inflate(&[75, 76, 74, 78, 196, 134, 0]).unwrap(),
+        b"abcabcabcabcabcabcabcabc"
+    );
+    let dynamic = [
+        5, 193, 137, 1, 128, 32, 12, 3, 192, 85, 50, 128, 75, 69, 41, 165, 62, 173, 34, 248, 77,
+        239, 93, 43, 130, 163, 219, 180, 96, 172, 113, 59, 114, 60, 152, 251, 182, 159, 136, 75,
+        42, 90, 17, 172, 252, 94, 164, 208, 1, 84, 154, 131, 158, 64, 165, 57, 232, 9, 84, 154,
+        255,
+    ];
+    assert_eq!(
+        inflate(&dynamic).unwrap(),
+        b"the quick brown fox jumps over the lazy dog, again and again and again"
+    );
+}
@@ -0,0 +1,243 @@
+//! Telling input formats apart, and the formats besides scrobbler.log that records can be read
+//! from.
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+
+use crate::{json, Rating, Scrobble};
+
+/// What an input file holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// A Rockbox scrobbler.log, AUDIOSCROBBLER/1.1 or 1.0.
+    Log,
+    /// One [`Scrobble::to_json`] object per line, as `--format json` writes.
+    JsonLines,
+    /// Comma-separated values: a header row naming the columns, or headerless
+    /// `artist,album,track,date` rows as Last.fm CSV exporters write.
+    Csv,
+    /// Saved `user.getRecentTracks` JSON pages from Last.fm.
+    LastFm,
+    /// ListenBrainz listens, as its exports and API hold them.
+    ListenBrainz,
+}
+
+impl std::str::FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(InputFormat::Log),
+            "jsonl" => Ok(InputFormat::JsonLines),
+            "csv" => Ok(InputFormat::Csv),
+            "lastfm" => Ok(InputFormat::LastFm),
+            "listenbrainz" => Ok(InputFormat::ListenBrainz),
+            other => Err(format!("unknown input format: {other}")),
+        }
+    }
+}
+
+/// Guess an input's format from its first line. An empty input is taken for an empty log.
+pub fn detect(text: &str) -> Option<InputFormat> {
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    let Some(first) = text.lines().next() else {
+        return Some(InputFormat::Log);
+    };
+    if first.starts_with("#AUDIOSCROBBLER/") {
+        Some(InputFormat::Log)
+    } else if first.contains("\"listened_at\"") {
+        Some(InputFormat::ListenBrainz)
+    } else if first.starts_with('[') || first.contains("\"recenttracks\"") {
+        Some(InputFormat::LastFm)
+    } else if first.starts_with('{') {
+        Some(InputFormat::JsonLines)
+    } else if first.contains(',') && !first.contains('\t') {
+        Some(InputFormat::Csv)
+    } else {
+        None
+    }
+}
+
+/// Read records written one [`Scrobble::to_json`] object per line.
+pub fn parse_json_lines(text: &str) -> Result<Vec<Scrobble>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            json::parse(line)
+                .and_then(|value| Scrobble::from_json(&value))
+                .map_err(|e| format!("line {}: {e}", index + 1))
+        })
+        .collect()
+}
+
+/// Split a CSV row into fields, undoing quoting. Quoted fields can't span lines.
+fn csv_fields(row: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("a field");
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    fields
+}
+
+/// A CSV date: seconds since the epoch, RFC 3339, or `08 Nov 2023 03:23` in UTC as Last.fm
+/// exporters write.
+fn csv_date(date: &str) -> Result<DateTime<Local>, String> {
+    let utc = match date.parse::<i64>() {
+        Ok(seconds) => chrono::Utc.timestamp_opt(seconds, 0).single(),
+        Err(_) => DateTime::parse_from_rfc3339(date)
+            .map(|date| date.with_timezone(&chrono::Utc))
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(date, "%d %b %Y %H:%M").map(|date| date.and_utc())
+            })
+            .ok(),
+    };
+    utc.map(|date| date.with_timezone(&Local))
+        .ok_or(format!("bad date: {date}"))
+}
+
+/// Read records from CSV. With a header row, `artist`, `track` and `timestamp` (or `date`)
+/// columns are needed, and `album`, `track_position`, `duration`, `rating` and `track_id` (or
+/// `mbid`) are read if present; other columns are ignored.
+pub fn parse_csv(text: &str) -> Result<Vec<Scrobble>, String> {
+    let mut rows = text
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .enumerate()
+        .filter(|(_, row)| !row.trim().is_empty())
+        .map(|(index, row)| (index + 1, csv_fields(row)));
+    let Some((_, first)) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    let names: Vec<String> = first
+        .iter()
+        .map(|name| name.trim().to_lowercase())
+        .collect();
+    let header = names.iter().any(|name| name == "artist");
+    let columns: Vec<String> = match header {
+        true => names,
+        false => ["artist", "album", "track", "date"]
+            .map(String::from)
+            .into(),
+    };
+    let column = |names: &[&str]| columns.iter().position(|c| names.contains(&c.as_str()));
+    let artist = column(&["artist"]).ok_or("CSV has no artist column")?;
+    let track = column(&["track", "title"]).ok_or("CSV has no track column")?;
+    let date = column(&["timestamp", "date", "uts"]).ok_or("CSV has no timestamp column")?;
+    let album = column(&["album"]);
+    let position = column(&["track_position", "position"]);
+    let duration = column(&["duration", "song_duration"]);
+    let rating = column(&["rating"]);
+    let track_id = column(&["track_id", "mbid"]);
+    let first = match header {
+        true => None,
+        false => Some((1, first)),
+    };
+    first
+        .into_iter()
+        .chain(rows)
+        .map(|(line, fields)| {
+            let error = |message: String| format!("line {line}: {message}");
+            let field = |index: Option<usize>| {
+                index
+                    .and_then(|index| fields.get(index))
+                    .map(|field| field.trim())
+                    .filter(|field| !field.is_empty())
+            };
+            let number = |index, name| {
+                field(index)
+                    .map(|value| {
+                        value
+                            .parse::<u32>()
+                            .map_err(|e| error(format!("{name}: {e}")))
+                    })
+                    .transpose()
+            };
+            Ok(Scrobble {
+                artist: field(Some(artist))
+                    .ok_or(error("no artist".to_string()))?
+                    .to_string(),
+                album: field(album).unwrap_or_default().to_string(),
+                track: field(Some(track))
+                    .ok_or(error("no track".to_string()))?
+                    .to_string(),
+                track_position: number(position, "track position")?,
+                song_duration: number(duration, "duration")?.unwrap_or(0),
+                rating: match field(rating) {
+                    Some("S") => Rating::Skipped,
+                    Some("L") | None => Rating::Listened,
+                    Some(other) => Err(error(format!("unknown rating: {other}")))?,
+                },
+                timestamp: csv_date(field(Some(date)).ok_or(error("no date".to_string()))?)
+                    .map_err(error)?,
+                track_id: field(track_id).map(str::to_string),
+                artist_mbids: Vec::new(),
+                release_mbid: None,
+                extras: Vec::new(),
+                unknown_suffix: None,
+                comments: Vec::new(),
+                trailing_comments: Vec::new(),
+                provenance: None,
+                index: None,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn detect_and_read_formats() {
+    let log = format!(
+        "{}Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t",
+        crate::HEADER
+    );
+    assert_eq!(detect(&log), Some(InputFormat::Log));
+    assert_eq!(detect("{\"recenttracks\":{}}"), Some(InputFormat::LastFm));
+    assert_eq!(
+        detect("\n[{\"recenttracks\":{}}]"),
+        Some(InputFormat::LastFm)
+    );
+    assert_eq!(
+        detect("{\"listened_at\": 1699413807, \"track_metadata\": {}}"),
+        Some(InputFormat::ListenBrainz)
+    );
+    assert_eq!(detect("not a log"), None);
+    assert_eq!(detect(" \n"), Some(InputFormat::Log));
+    assert!(crate::parse_log("").unwrap().is_empty());
+    assert!(crate::parse_log(crate::HEADER).unwrap().is_empty());
+    let scrobble = Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t").unwrap();
+    let jsonl = scrobble.to_json() + "\n";
+    assert_eq!(detect(&jsonl), Some(InputFormat::JsonLines));
+    assert_eq!(
+        parse_json_lines(&jsonl).unwrap()[0].to_string(),
+        scrobble.to_string()
+    );
+
+    let csv = "Artist,Album,Track,Position,Timestamp,Duration\n\
+               Low,Drums and Guns,Breaker,5,1699413807,187\n\
+               \"Crosby, Stills & Nash\",,\"Suite: Judy Blue Eyes\",,2023-11-08T03:30:00Z,\n";
+    assert_eq!(detect(csv), Some(InputFormat::Csv));
+    let scrobbles = parse_csv(csv).unwrap();
+    assert_eq!(scrobbles[0].to_string(), scrobble.to_string());
+    assert_eq!(scrobbles[1].artist, "Crosby, Stills & Nash");
+    assert_eq!(scrobbles[1].timestamp.timestamp(), 1699414200);
+    let headerless = parse_csv("Low,Drums and Guns,Breaker,08 Nov 2023 03:23").unwrap();
+    assert_eq!(headerless[0].timestamp.timestamp(), 1699413780);
+    assert!(parse_csv("Low,Drums and Guns,Breaker,yesterday").is_err());
+
+    let legacy = log
+        .replace("/1.1", "/1.0")
+        .trim_end_matches('\t')
+        .to_string();
+    assert_eq!(detect(&legacy), Some(InputFormat::Log));
+    assert_eq!(crate::fix_log(&legacy).unwrap(), log);
+}
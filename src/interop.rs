@@ -0,0 +1,143 @@
+//! Output for community Last.fm tools, for submitting or correcting scrobbles without API access.
+//!
+//! Open Scrobbler's bulk scrobbler takes CSV rows to scrobble. lastfm-bulk-edit corrects
+//! scrobbles already on a profile from its web pages and doesn't take an upload. For it, the
+//! output is instead the list of metadata edits the fixes made, to go through in its dialog.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use chrono::{SecondsFormat, Utc};
+
+use crate::{Scrobble, SkipPolicy};
+
+/// A CSV field, quoted with any quotes doubled.
+fn csv_field(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// Open Scrobbler CSV: one headerless `artist,track,album,timestamp,album artist,duration` row
+/// per scrobble `policy` keeps, with the timestamp in RFC 3339 UTC. The rows have no way to
+/// mark a skip.
+pub fn open_scrobbler_csv(scrobbles: &[Scrobble], policy: SkipPolicy) -> String {
+    scrobbles
+        .iter()
+        .filter(|scrobble| policy.keeps(scrobble))
+        .map(|scrobble| {
+            let time = scrobble
+                .timestamp
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Secs, true);
+            [
+                csv_field(&scrobble.artist),
+                csv_field(&scrobble.track),
+                csv_field(&scrobble.album),
+                csv_field(&time),
+                csv_field(scrobble.album_artist().unwrap_or_default()),
+                scrobble.song_duration.to_string(),
+            ]
+            .join(",")
+                + "\n"
+        })
+        .collect()
+}
+
+/// The artist, track and album of a record, as lastfm-bulk-edit shows and edits them.
+type Names = (String, String, String);
+
+fn names(scrobble: &Scrobble) -> Names {
+    (
+        scrobble.artist.clone(),
+        scrobble.track.clone(),
+        scrobble.album.clone(),
+    )
+}
+
+/// One edit to make with lastfm-bulk-edit: every scrobble named `from` should be named `to`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub from: Names,
+    pub to: Names,
+    /// How many scrobbles it applies to.
+    pub scrobbles: usize,
+}
+
+/// The metadata edits the fixes made, one per distinct change, in order of the names changed.
+/// Timestamp corrections are left out, since Last.fm can't edit them.
+///
+/// Records are paired by the timestamp they were logged with, which fixes keep in the corrected
+/// record's provenance, in log order among those sharing one.
+pub fn bulk_edits(original: &[Scrobble], corrected: &[Scrobble]) -> Vec<Edit> {
+    let mut logged: HashMap<i64, VecDeque<&Scrobble>> = HashMap::new();
+    for scrobble in original {
+        let timestamp = scrobble.timestamp.timestamp();
+        logged.entry(timestamp).or_default().push_back(scrobble);
+    }
+    let mut edits: BTreeMap<(Names, Names), usize> = BTreeMap::new();
+    for scrobble in corrected {
+        let timestamp = scrobble
+            .provenance
+            .as_ref()
+            .map_or(scrobble.timestamp, |provenance| {
+                provenance.original_timestamp
+            });
+        let Some(original) = logged
+            .get_mut(&timestamp.timestamp())
+            .and_then(VecDeque::pop_front)
+        else {
+            continue;
+        };
+        let (from, to) = (names(original), names(scrobble));
+        if from != to {
+            *edits.entry((from, to)).or_default() += 1;
+        }
+    }
+    edits
+        .into_iter()
+        .map(|((from, to), scrobbles)| Edit {
+            from,
+            to,
+            scrobbles,
+        })
+        .collect()
+}
+
+/// The edits as tab-separated rows under a header naming the columns.
+pub fn bulk_edit_tsv(edits: &[Edit]) -> String {
+    let mut tsv =
+        String::from("scrobbles\tartist\ttrack\talbum\tnew artist\tnew track\tnew album\n");
+    for edit in edits {
+        let (artist, track, album) = &edit.from;
+        let (new_artist, new_track, new_album) = &edit.to;
+        tsv.push_str(&format!(
+            "{}\t{artist}\t{track}\t{album}\t{new_artist}\t{new_track}\t{new_album}\n",
+            edit.scrobbles
+        ));
+    }
+    tsv
+}
+
+#[test]
+fn community_tool_output() {
+    let original: Vec<Scrobble> = [
+        "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t",
+        "Low\tDrums and Guns\tBreaker\t5\t187\tS\t1699414807\t",
+        "Crosby, Stills & Nash\t\tSuite: \"Judy\"\t\t442\tL\t1699415807\t",
+    ]
+    .map(|line| Scrobble::new(line).unwrap())
+    .into();
+    assert_eq!(
+        open_scrobbler_csv(&original, SkipPolicy::Drop),
+        "\"Low\",\"Breaker\",\"Drums and Guns\",\"2023-11-08T03:23:27Z\",\"\",187\n\
+         \"Crosby, Stills & Nash\",\"Suite: \"\"Judy\"\"\",\"\",\"2023-11-08T03:56:47Z\",\"\",442\n"
+    );
+    let mut corrected = original.clone();
+    for scrobble in &mut corrected[..2] {
+        scrobble.artist = "LOW".to_string();
+    }
+    corrected[2].timestamp += chrono::Duration::seconds(1);
+    let edits = bulk_edits(&original, &corrected);
+    assert_eq!(edits.len(), 1);
+    assert_eq!((edits[0].to.0.as_str(), edits[0].scrobbles), ("LOW", 2));
+    assert!(bulk_edit_tsv(&edits)
+        .ends_with("\n2\tLow\tBreaker\tDrums and Guns\tLOW\tBreaker\tDrums and Guns\n"));
+}
@@ -0,0 +1,198 @@
+//! Just enough JSON for the export formats and the web APIs we read from.
+
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{char, multispace0},
+    combinator::{map, value},
+    multi::separated_list0,
+    number::complete::double,
+    sequence::{delimited, preceded, separated_pair},
+    IResult,
+};
+
+/// A parsed JSON document.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Members in document order.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The member named `key`, if this is an object that has one.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a complete JSON document.
+pub fn parse(input: &str) -> Result<Value, String> {
+    match delimited(multispace0, json_value, multispace0)(input) {
+        Ok(("", value)) => Ok(value),
+        Ok((rest, _)) => Err(format!("unexpected trailing JSON: {:.20}", rest)),
+        Err(e) => Err(format!("invalid JSON: {e}")),
+    }
+}
+
+fn json_value(input: &str) -> IResult<&str, Value> {
+    alt((
+        value(Value::Null, tag("null")),
+        value(Value::Bool(true), tag("true")),
+        value(Value::Bool(false), tag("false")),
+        map(double, Value::Number),
+        map(json_string, Value::String),
+        map(
+            delimited(
+                char('['),
+                separated_list0(char(','), delimited(multispace0, json_value, multispace0)),
+                preceded(multispace0, char(']')),
+            ),
+            Value::Array,
+        ),
+        map(
+            delimited(
+                char('{'),
+                separated_list0(
+                    char(','),
+                    separated_pair(
+                        delimited(multispace0, json_string, multispace0),
+                        char(':'),
+                        delimited(multispace0, json_value, multispace0),
+                    ),
+                ),
+                preceded(multispace0, char('}')),
+            ),
+            Value::Object,
+        ),
+    ))(input)
+}
+
+/// A string literal, with escapes (including UTF-16 surrogate pairs) decoded.
+fn json_string(input: &str) -> IResult<&str, String> {
+    let error = |at| nom::Err::Error(nom::error::Error::new(at, nom::error::ErrorKind::Escaped));
+    let (mut rest, _) = char('"')(input)?;
+    let mut decoded = String::new();
+    loop {
+        let mut chars = rest.chars();
+        match chars.next().ok_or_else(|| error(rest))? {
+            '"' => return Ok((chars.as_str(), decoded)),
+            '\\' => {
+                let escape = chars.next().ok_or_else(|| error(rest))?;
+                decoded.push(match escape {
+                    '"' => '"',
+                    '\\' => '\\',
+                    '/' => '/',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let hex = |s: &str| {
+                            s.get(..4)
+                                .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                        };
+                        let mut unit = hex(chars.as_str()).ok_or_else(|| error(rest))?;
+                        chars = chars.as_str()[4..].chars();
+                        if (0xD800..0xDC00).contains(&unit) {
+                            let low = chars
+                                .as_str()
+                                .strip_prefix("\\u")
+                                .and_then(hex)
+                                .ok_or_else(|| error(rest))?;
+                            chars = chars.as_str()[6..].chars();
+                            unit = 0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00);
+                        }
+                        char::from_u32(unit).ok_or_else(|| error(rest))?
+                    }
+                    _ => return Err(error(rest)),
+                });
+            }
+            c => decoded.push(c),
+        }
+        rest = chars.as_str();
+    }
+}
+
+/// A JSON string literal.
+pub fn string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if u32::from(c) < 0x20 => quoted.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A JSON array from already-encoded values.
+pub fn array(items: impl IntoIterator<Item = String>) -> String {
+    format!("[{}]", items.into_iter().collect::<Vec<String>>().join(","))
+}
+
+/// A JSON object from already-encoded values, in the given order.
+pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, String)>) -> String {
+    let members = members
+        .into_iter()
+        .map(|(key, value)| format!("{}:{value}", string(key)))
+        .collect::<Vec<String>>();
+    format!("{{{}}}", members.join(","))
+}
+
+#[test]
+fn escape_strings() {
+    assert_eq!(string("say \"hi\"\t\u{1}"), r#""say \"hi\"\t\u0001""#);
+}
+
+#[test]
+fn parse_documents() {
+    let document = parse(r#"{"recordings": [{"id": "abc", "score": 100, "title": "caf\u00e9 \ud83c\udfb5"}], "ok": true}"#).unwrap();
+    let recording = &document.get("recordings").unwrap().as_array().unwrap()[0];
+    assert_eq!(recording.get("id").and_then(Value::as_str), Some("abc"));
+    assert_eq!(recording.get("score").and_then(Value::as_f64), Some(100.0));
+    assert_eq!(
+        recording.get("title").and_then(Value::as_str),
+        Some("café 🎵")
+    );
+    assert_eq!(document.get("ok"), Some(&Value::Bool(true)));
+}
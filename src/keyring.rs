@@ -0,0 +1,132 @@
+//! Secrets kept in the OS keyring, by running its command line tool: `secret-tool` (Secret
+//! Service) on Linux and the BSDs, `security` (Keychain) on macOS.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Service name every secret is filed under.
+const SERVICE: &str = "scrobble-fix";
+
+fn run(command: &mut Command, input: Option<&str>) -> Result<Output, String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run {program}: {e}"))?;
+    if let Some(input) = input {
+        child
+            .stdin
+            .take()
+            .expect("piped stdin")
+            .write_all(input.as_bytes())
+            .map_err(|e| format!("{program}: {e}"))?;
+    }
+    child
+        .wait_with_output()
+        .map_err(|e| format!("{program}: {e}"))
+}
+
+fn failure(output: &Output) -> String {
+    format!(
+        "keyring: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    )
+}
+
+/// The secret stored for `account`, if there is one.
+pub fn get(account: &str) -> Result<Option<String>, String> {
+    let output = if cfg!(target_os = "macos") {
+        run(
+            Command::new("security").args([
+                "find-generic-password",
+                "-s",
+                SERVICE,
+                "-a",
+                account,
+                "-w",
+            ]),
+            None,
+        )?
+    } else if cfg!(unix) {
+        run(
+            Command::new("secret-tool").args(["lookup", "service", SERVICE, "account", account]),
+            None,
+        )?
+    } else {
+        return Ok(None);
+    };
+    // Both tools exit unsuccessfully when nothing is stored.
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let secret = String::from_utf8(output.stdout).map_err(|e| format!("keyring: {e}"))?;
+    Ok(Some(secret.trim_end_matches('\n').to_string()).filter(|secret| !secret.is_empty()))
+}
+
+/// Store `secret` for `account`, replacing any stored before.
+pub fn set(account: &str, secret: &str) -> Result<(), String> {
+    let output = if cfg!(target_os = "macos") {
+        // A last `-w` with no password has it prompted for, twice, and read from stdin, where it
+        // can't be seen in the process list as an argument could.
+        run(
+            Command::new("security").args([
+                "add-generic-password",
+                "-U",
+                "-s",
+                SERVICE,
+                "-a",
+                account,
+                "-w",
+            ]),
+            Some(&format!("{secret}\n{secret}\n")),
+        )?
+    } else if cfg!(unix) {
+        let label = format!("{SERVICE} {account}");
+        run(
+            Command::new("secret-tool").args([
+                "store", "--label", &label, "service", SERVICE, "account", account,
+            ]),
+            Some(secret),
+        )?
+    } else {
+        return Err("no supported keyring on this platform".to_string());
+    };
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(failure(&output)),
+    }
+}
+
+/// Forget the secret for `account`. Forgetting one that isn't stored is not an error.
+pub fn delete(account: &str) -> Result<(), String> {
+    if get(account)?.is_none() {
+        return Ok(());
+    }
+    let output = if cfg!(target_os = "macos") {
+        run(
+            Command::new("security").args([
+                "delete-generic-password",
+                "-s",
+                SERVICE,
+                "-a",
+                account,
+            ]),
+            None,
+        )?
+    } else {
+        run(
+            Command::new("secret-tool").args(["clear", "service", SERVICE, "account", account]),
+            None,
+        )?
+    };
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(failure(&output)),
+    }
+}
@@ -0,0 +1,432 @@
+//! Last.fm API requests.
+//!
+//! Documented here:
+//! - <https://www.last.fm/api/show/track.scrobble>
+//! - <https://www.last.fm/api/show/user.getRecentTracks>
+//! - <https://www.last.fm/api/authspec#_8-signing-calls>
+//! - <https://www.last.fm/api/desktopauth>
+
+use chrono::TimeZone;
+
+use crate::{json, md5, url, Rating, Scrobble};
+
+/// Endpoint for every API method.
+pub const API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+
+/// Most scrobbles accepted by one `track.scrobble` call.
+pub const BATCH_SIZE: usize = 50;
+
+/// Scrobbles of the same track at most this many seconds apart are taken to be the same scrobble.
+pub const REPLAY_WINDOW: i64 = 5 * 60;
+
+/// Most tracks returned per page of `user.getRecentTracks`.
+pub const RECENT_TRACKS_PAGE_SIZE: usize = 200;
+
+/// Error code of `auth.getSession` while the user hasn't yet granted the token access.
+pub const UNAUTHORIZED_TOKEN: i64 = 14;
+
+/// How long a token from `auth.getToken` can be authorized for, in seconds.
+pub const TOKEN_LIFETIME: u64 = 60 * 60;
+
+/// What a signed call needs.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub api_key: String,
+    pub api_secret: String,
+    pub session_key: String,
+}
+
+/// `api_sig` for a call: the MD5 of every parameter name and value, sorted by name, followed by
+/// the shared secret. `format` and `callback` are not signed.
+pub fn sign(params: &[(String, String)], api_secret: &str) -> String {
+    let mut signed: Vec<&(String, String)> = params
+        .iter()
+        .filter(|(name, _)| name != "format" && name != "callback")
+        .collect();
+    signed.sort();
+    let mut payload: String = signed
+        .iter()
+        .map(|(name, value)| format!("{name}{value}"))
+        .collect();
+    payload.push_str(api_secret);
+    md5::hex_digest(payload.as_bytes())
+}
+
+/// Turn an API error response into `Err`.
+fn check_error(response: &json::Value) -> Result<(), String> {
+    match response.get("error").and_then(json::Value::as_f64) {
+        Some(code) => {
+            let message = response.get("message").and_then(json::Value::as_str);
+            Err(format!(
+                "Last.fm error {code}: {}",
+                message.unwrap_or("unknown")
+            ))
+        }
+        None => Ok(()),
+    }
+}
+
+/// The error code of an API response, if it's an error.
+pub fn error_code(response: &str) -> Option<i64> {
+    let response = json::parse(response).ok()?;
+    response
+        .get("error")
+        .and_then(json::Value::as_f64)
+        .map(|code| code as i64)
+}
+
+/// URL of a signed GET call to `method`.
+fn signed_url(method: &str, extra: &[(&str, &str)], api_key: &str, api_secret: &str) -> String {
+    let mut params = vec![
+        ("method".to_string(), method.to_string()),
+        ("api_key".to_string(), api_key.to_string()),
+    ];
+    params.extend(
+        extra
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string())),
+    );
+    let signature = sign(&params, api_secret);
+    params.push(("api_sig".to_string(), signature));
+    params.push(("format".to_string(), "json".to_string()));
+    format!("{API_URL}?{}", form_body(&params))
+}
+
+/// URL of an `auth.getToken` call, the first step of desktop authentication.
+pub fn token_url(api_key: &str, api_secret: &str) -> String {
+    signed_url("auth.getToken", &[], api_key, api_secret)
+}
+
+/// Where the user grants access to a token.
+pub fn authorize_url(api_key: &str, token: &str) -> String {
+    let query = url::query([("api_key", api_key), ("token", token)]);
+    format!("https://www.last.fm/api/auth/?{query}")
+}
+
+/// URL of an `auth.getSession` call, exchanging an authorized token for a session key.
+pub fn session_url(api_key: &str, api_secret: &str, token: &str) -> String {
+    signed_url("auth.getSession", &[("token", token)], api_key, api_secret)
+}
+
+/// Read an `auth.getToken` response.
+pub fn parse_token(response: &str) -> Result<String, String> {
+    let response = json::parse(response)?;
+    check_error(&response)?;
+    response
+        .get("token")
+        .and_then(json::Value::as_str)
+        .map(str::to_string)
+        .ok_or("token response has no token".to_string())
+}
+
+/// A user's authorization to act on their behalf.
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    pub user: String,
+    pub key: String,
+}
+
+/// Read an `auth.getSession` response.
+pub fn parse_session(response: &str) -> Result<Session, String> {
+    let response = json::parse(response)?;
+    check_error(&response)?;
+    let session = response
+        .get("session")
+        .ok_or("session response has no session")?;
+    let field = |name| {
+        session
+            .get(name)
+            .and_then(json::Value::as_str)
+            .map(str::to_string)
+            .ok_or(format!("session has no {name}"))
+    };
+    Ok(Session {
+        user: field("name")?,
+        key: field("key")?,
+    })
+}
+
+/// Parameters for a signed `track.scrobble` call submitting `batch`.
+pub fn scrobble_params(batch: &[&Scrobble], credentials: &Credentials) -> Vec<(String, String)> {
+    let mut params = vec![
+        ("method".to_string(), "track.scrobble".to_string()),
+        ("api_key".to_string(), credentials.api_key.clone()),
+        ("sk".to_string(), credentials.session_key.clone()),
+    ];
+    for (i, scrobble) in batch.iter().enumerate() {
+        let mut field = |name: &str, value: String| params.push((format!("{name}[{i}]"), value));
+        field("artist", scrobble.artist.clone());
+        field("track", scrobble.track.clone());
+        field("timestamp", scrobble.timestamp.timestamp().to_string());
+        field("duration", scrobble.song_duration.to_string());
+        if !scrobble.album.is_empty() {
+            field("album", scrobble.album.clone());
+        }
+        if let Some(position) = scrobble.track_position {
+            field("trackNumber", position.to_string());
+        }
+        if let Some(id) = &scrobble.track_id {
+            field("mbid", id.clone());
+        }
+        if let Some(album_artist) = scrobble.album_artist() {
+            field("albumArtist", album_artist.to_string());
+        }
+    }
+    let signature = sign(&params, &credentials.api_secret);
+    params.push(("api_sig".to_string(), signature));
+    params.push(("format".to_string(), "json".to_string()));
+    params
+}
+
+/// Parameters that grant access to the account, and are hidden by [`redact`].
+const SECRET_PARAMS: [&str; 1] = ["sk"];
+
+/// Parameters fit to show: the session key replaced, everything else (signature included) as
+/// it would be sent.
+pub fn redact(params: &[(String, String)]) -> Vec<(String, String)> {
+    params
+        .iter()
+        .map(
+            |(name, value)| match SECRET_PARAMS.contains(&name.as_str()) {
+                true => (name.clone(), "REDACTED".to_string()),
+                false => (name.clone(), value.clone()),
+            },
+        )
+        .collect()
+}
+
+/// A `application/x-www-form-urlencoded` request body.
+pub fn form_body(params: &[(String, String)]) -> String {
+    url::query(
+        params
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    )
+}
+
+/// How Last.fm treated a submitted batch.
+#[derive(Debug, PartialEq)]
+pub struct Submitted {
+    pub accepted: usize,
+    pub ignored: usize,
+}
+
+/// Read a `track.scrobble` response, turning API errors into `Err`.
+pub fn parse_scrobble_response(response: &str) -> Result<Submitted, String> {
+    let response = json::parse(response)?;
+    check_error(&response)?;
+    let attr = response
+        .get("scrobbles")
+        .and_then(|scrobbles| scrobbles.get("@attr"))
+        .ok_or("scrobble response has no counts")?;
+    let count = |name| {
+        attr.get(name)
+            .and_then(json::Value::as_f64)
+            .map_or(0, |count| count as usize)
+    };
+    Ok(Submitted {
+        accepted: count("accepted"),
+        ignored: count("ignored"),
+    })
+}
+
+/// A scrobble already on a user's profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentTrack {
+    pub artist: String,
+    pub album: String,
+    pub track: String,
+    /// MusicBrainz recording id, when Last.fm knows it.
+    pub mbid: Option<String>,
+    /// Seconds since the epoch.
+    pub timestamp: i64,
+}
+
+impl RecentTrack {
+    /// The scrobble as a log record. Last.fm doesn't say how long the track is, so the duration
+    /// is 0, and every scrobble on a profile was listened to.
+    pub fn to_scrobble(&self) -> Result<Scrobble, String> {
+        let timestamp = chrono::Local
+            .timestamp_opt(self.timestamp, 0)
+            .single()
+            .ok_or(format!("timestamp out of range: {}", self.timestamp))?;
+        Ok(Scrobble {
+            artist: self.artist.clone(),
+            album: self.album.clone(),
+            track: self.track.clone(),
+            track_position: None,
+            song_duration: 0,
+            rating: Rating::Listened,
+            timestamp,
+            track_id: self.mbid.clone(),
+            artist_mbids: Vec::new(),
+            release_mbid: None,
+            extras: Vec::new(),
+            unknown_suffix: None,
+            comments: Vec::new(),
+            trailing_comments: Vec::new(),
+            provenance: None,
+            index: None,
+        })
+    }
+}
+
+/// URL of one page of a user's scrobbles between `from` and `to` (seconds since the epoch).
+pub fn recent_tracks_url(user: &str, api_key: &str, from: i64, to: i64, page: usize) -> String {
+    let (from, to, page) = (from.to_string(), to.to_string(), page.to_string());
+    let limit = RECENT_TRACKS_PAGE_SIZE.to_string();
+    let query = url::query([
+        ("method", "user.getrecenttracks"),
+        ("user", user),
+        ("api_key", api_key),
+        ("from", from.as_str()),
+        ("to", to.as_str()),
+        ("limit", limit.as_str()),
+        ("page", page.as_str()),
+        ("format", "json"),
+    ]);
+    format!("{API_URL}?{query}")
+}
+
+/// The scrobbles on a parsed page of `user.getRecentTracks`, and the number of pages.
+fn recent_tracks_page(response: &json::Value) -> Result<(Vec<RecentTrack>, usize), String> {
+    check_error(response)?;
+    let recent = response
+        .get("recenttracks")
+        .ok_or("response has no recent tracks")?;
+    let pages = recent
+        .get("@attr")
+        .and_then(|attr| attr.get("totalPages"))
+        .and_then(json::Value::as_str)
+        .and_then(|pages| pages.parse().ok())
+        .unwrap_or(1);
+    // A single track comes back as an object rather than a one-element array.
+    let tracks = match recent.get("track") {
+        Some(json::Value::Array(tracks)) => tracks.as_slice(),
+        Some(track) => std::slice::from_ref(track),
+        None => &[],
+    };
+    let text = |track: &json::Value, name| {
+        track
+            .get(name)
+            .and_then(|field| field.get("#text"))
+            .and_then(json::Value::as_str)
+            .map(str::to_string)
+    };
+    let tracks = tracks
+        .iter()
+        .filter_map(|track| {
+            Some(RecentTrack {
+                artist: text(track, "artist")?,
+                album: text(track, "album").unwrap_or_default(),
+                track: track.get("name")?.as_str()?.to_string(),
+                mbid: track
+                    .get("mbid")
+                    .and_then(json::Value::as_str)
+                    .filter(|mbid| !mbid.is_empty())
+                    .map(str::to_string),
+                timestamp: track.get("date")?.get("uts")?.as_str()?.parse().ok()?,
+            })
+        })
+        .collect();
+    Ok((tracks, pages))
+}
+
+/// Read a page of `user.getRecentTracks`, returning its scrobbles and the number of pages.
+///
+/// The track playing right now has no date yet and is left out.
+pub fn parse_recent_tracks(response: &str) -> Result<(Vec<RecentTrack>, usize), String> {
+    recent_tracks_page(&json::parse(response)?)
+}
+
+/// Read saved `user.getRecentTracks` pages, oldest scrobble first: a single page, an array of
+/// pages, or one page per line.
+pub fn parse_recent_tracks_export(export: &str) -> Result<Vec<RecentTrack>, String> {
+    let pages = match json::parse(export) {
+        Ok(json::Value::Array(pages)) => pages,
+        Ok(page) => vec![page],
+        Err(_) => export
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| json::parse(line).map_err(|e| format!("page {}: {e}", i + 1)))
+            .collect::<Result<_, _>>()?,
+    };
+    let mut tracks = Vec::new();
+    for page in &pages {
+        tracks.extend(recent_tracks_page(page)?.0);
+    }
+    tracks.sort_by_key(|track| track.timestamp);
+    Ok(tracks)
+}
+
+/// Whether `existing` already has this scrobble: the same artist and track (ignoring case)
+/// within [`REPLAY_WINDOW`] of its timestamp.
+pub fn already_scrobbled(scrobble: &Scrobble, existing: &[RecentTrack]) -> bool {
+    let timestamp = scrobble.timestamp.timestamp();
+    existing.iter().any(|recent| {
+        (recent.timestamp - timestamp).abs() <= REPLAY_WINDOW
+            && recent.track.to_lowercase() == scrobble.track.to_lowercase()
+            && recent.artist.to_lowercase() == scrobble.artist.to_lowercase()
+    })
+}
+
+#[test]
+fn sign_sorted_params() {
+    let params = [
+        ("method", "auth.getSession"),
+        ("api_key", "xxx"),
+        ("token", "yyy"),
+    ]
+    .map(|(name, value)| (name.to_string(), value.to_string()));
+    assert_eq!(
+        sign(&params, "secret"),
+        md5::hex_digest(b"api_keyxxxmethodauth.getSessiontokenyyysecret")
+    );
+}
+
+#[test]
+fn redact_session_key() {
+    let credentials = Credentials {
+        api_key: "xxx".to_string(),
+        api_secret: "secret".to_string(),
+        session_key: "session".to_string(),
+    };
+    let line = "Boards of Canada\tGeogaddi\tDawn Chorus\t\t397\tL\t1700000000\t";
+    let scrobble = Scrobble::new(line).unwrap();
+    let params = scrobble_params(&[&scrobble], &credentials);
+    let shown = form_body(&redact(&params));
+    assert!(shown.contains("sk=REDACTED") && !shown.contains("session"));
+    let (_, signature) = params.iter().find(|(name, _)| name == "api_sig").unwrap();
+    assert!(shown.contains(&format!("api_sig={signature}")));
+    assert!(!shown.contains("secret"));
+}
+
+#[test]
+fn wait_for_unauthorized_token() {
+    let pending =
+        r#"{"error": 14, "message": "Unauthorized Token - This token has not been issued"}"#;
+    assert_eq!(error_code(pending), Some(UNAUTHORIZED_TOKEN));
+    assert!(parse_session(pending).is_err());
+    let session = r#"{"session": {"name": "djanatyn", "key": "d580d57f32848f5dcf574d1ce18d78b2", "subscriber": 0}}"#;
+    assert_eq!(error_code(session), None);
+    assert_eq!(parse_session(session).unwrap().user, "djanatyn");
+}
+
+#[test]
+fn skip_scrobbles_already_on_profile() {
+    let response = r##"{"recenttracks": {"track": [
+        {"artist": {"#text": "Boards of Canada"}, "name": "Roygbiv", "@attr": {"nowplaying": "true"}},
+        {"artist": {"#text": "boards of canada"}, "name": "Roygbiv", "date": {"uts": "1700000200"}}
+    ], "@attr": {"page": "1", "totalPages": "1"}}}"##;
+    let (existing, pages) = parse_recent_tracks(response).unwrap();
+    assert_eq!((existing.len(), pages), (1, 1));
+    let page = response.replace('\n', "");
+    let export = format!("{page}\n{page}\n");
+    assert_eq!(parse_recent_tracks_export(&export).unwrap().len(), 2);
+    let line = "Boards of Canada\tMusic Has the Right to Children\tRoygbiv\t\t151\tL\t1700000000\t";
+    let mut scrobble = Scrobble::new(line).unwrap();
+    assert!(already_scrobbled(&scrobble, &existing));
+    scrobble.timestamp += chrono::Duration::seconds(200 + REPLAY_WINDOW + 1);
+    assert!(!already_scrobbled(&scrobble, &existing));
+}
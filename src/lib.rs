@@ -0,0 +1,379 @@
+//! Parse the Rockbox scrobbler.log file, identify scrobbles with suspicious dates, and fix them.
+//!
+//! The library never touches the filesystem: it works on strings, so it can be embedded in other
+//! programs (see the `ffi` feature) as well as driving the `scrobble-fix` binary.
+//!
+//! AUDIOSCROBBLER/1.1 format is documented here:
+//! - <https://github.com/Rockbox/rockbox/blob/3c89adbdbdd036baf313786b0694632c8e7e2bb3/apps/plugins/lastfm_scrobbler.c#L29>
+
+pub mod analyze;
+pub mod archive;
+pub mod audioscrobbler;
+pub mod charset;
+pub mod collate;
+pub mod doctor;
+pub mod escape;
+mod fat;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod generate;
+pub mod grammar;
+pub mod header;
+pub mod id3;
+pub mod ignore;
+pub mod inflate;
+pub mod input;
+pub mod interop;
+pub mod json;
+pub mod lastfm;
+pub mod lint;
+pub mod listenbrainz;
+pub mod md5;
+pub mod merge;
+pub mod metadata;
+pub mod musicbrainz;
+pub mod pipeline;
+pub mod podcast;
+pub mod query;
+pub mod release;
+pub mod repeat;
+pub mod report;
+pub mod review;
+pub mod rules;
+pub mod schema;
+mod scrobble;
+pub mod serialize;
+pub mod sha256;
+pub mod show;
+pub mod table;
+pub mod tagcache;
+pub mod template;
+pub mod timeline;
+pub mod timestamps;
+pub mod url;
+
+use std::collections::{HashMap, VecDeque};
+
+pub use header::LogHeader;
+pub use pipeline::{Fixer, Pipeline};
+pub use scrobble::{Provenance, Rating, Scrobble, ScrobbleRef, SkipPolicy};
+pub use serialize::ScrobbleSerializer;
+
+/// Anything older than this needs an offset applied.
+pub const SCROBBLE_CUTOFF: &str = "2005-01-01T00:00:00Z";
+
+/// Number of days to add to the suspicious scrobbles.
+pub const SCROBBLE_DAYS_OFFSET: u64 = (365 * 22) + 215;
+
+/// Lines taken by the header, before the first scrobble, unless a fork adds more; see
+/// [`LogHeader::line_count`].
+pub const HEADER_LINES: usize = 3;
+
+/// Header for AUDIOSCROBBLER/1.1 format, [`LogHeader::default`] written out.
+pub const HEADER: &str = r#"#AUDIOSCROBBLER/1.1
+#TZ/UNKNOWN
+#CLIENT/Rockbox ipodvideo $Revision$
+"#;
+
+/// A scrobbler.log line that isn't a valid scrobble.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// 1-based line number in the log.
+    pub line: usize,
+    /// 1-based column in the line, counting characters, where it shows.
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "line {}, column {column}: {}", self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+/// Parse each scrobble in a scrobbler.log, skipping the header, without stopping at bad lines.
+///
+/// `#` comment lines in the body are kept with the scrobble below them, or with the last scrobble
+/// when nothing follows them.
+pub fn parse_records(log: &str) -> impl Iterator<Item = Result<Scrobble, ParseError>> + '_ {
+    parse_numbered_records(log).map(|(_, record)| record)
+}
+
+/// Like [`parse_records`], with the 1-based line number of each record.
+///
+/// AUDIOSCROBBLER/1.0 logs, which predate the track id column, are read too.
+pub fn parse_numbered_records(
+    log: &str,
+) -> impl Iterator<Item = (usize, Result<Scrobble, ParseError>)> + '_ {
+    parse_borrowed_records(log).map(|(line, record)| (line, record.map(|r| r.to_owned())))
+}
+
+/// The line each record of `log` is on, in order: those [`parse_numbered_records`] reads, less
+/// the unfinished last one [`split_unfinished`] leaves out, so the records a lenient read keeps
+/// can be found in the log again.
+pub fn record_lines(log: &str) -> Vec<usize> {
+    parse_borrowed_records(split_unfinished(log).0)
+        .filter_map(|(line, record)| record.ok().map(|_| line))
+        .collect()
+}
+
+/// Like [`parse_numbered_records`], borrowing each record's text from the log rather than
+/// copying it, for reading records that won't be changed.
+pub fn parse_borrowed_records(
+    log: &str,
+) -> impl Iterator<Item = (usize, Result<ScrobbleRef<'_>, ParseError>)> {
+    Records {
+        lines: log.lines().enumerate().skip(LogHeader::line_count(log)),
+        legacy: LogHeader::parse(log).is_ok_and(|header| header.is_legacy()),
+        comments: Vec::new(),
+        parsed: None,
+    }
+}
+
+/// A log in pieces of at least `size` bytes that each end at a line end, but the last, which ends
+/// where the log does; for reading one too large to hold a piece at a time.
+pub fn line_chunks(log: &str, size: usize) -> impl Iterator<Item = &str> {
+    let mut rest = log;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        // A newline is a byte of its own in UTF-8, so the chunk ends on a character.
+        let end = rest
+            .as_bytes()
+            .get(size..)
+            .and_then(|after| after.iter().position(|&byte| byte == b'\n'))
+            .map_or(rest.len(), |newline| size + newline + 1);
+        let (chunk, after) = rest.split_at(end);
+        rest = after;
+        Some(chunk)
+    })
+}
+
+/// Like [`parse_numbered_records`], reading the log's lines from `chunks` of it, in order, as
+/// [`line_chunks`] splits it; so whoever hands out the chunks learns how far parsing has got.
+pub fn parse_numbered_chunks<'a>(
+    log: &'a str,
+    chunks: impl Iterator<Item = &'a str> + 'a,
+) -> impl Iterator<Item = (usize, Result<Scrobble, ParseError>)> + 'a {
+    let records = Records {
+        lines: chunks
+            .flat_map(str::lines)
+            .enumerate()
+            .skip(LogHeader::line_count(log)),
+        legacy: LogHeader::parse(log).is_ok_and(|header| header.is_legacy()),
+        comments: Vec::new(),
+        parsed: None,
+    };
+    records.map(|(line, record)| (line, record.map(|r| r.to_owned())))
+}
+
+/// Split a log that may still be being written into what's been written completely, and the
+/// unfinished record after it: a last line with no line ending that isn't a whole record yet.
+/// The unfinished part is empty when there isn't one.
+pub fn split_unfinished(log: &str) -> (&str, &str) {
+    let written = log.rfind('\n').map_or(0, |end| end + 1);
+    let (complete, last) = log.split_at(written);
+    let legacy = LogHeader::parse(log).is_ok_and(|header| header.is_legacy());
+    let in_body = complete.lines().count() >= LogHeader::line_count(complete);
+    match in_body && !last.starts_with('#') && ScrobbleRef::parse(last, legacy).is_err() {
+        true => (complete, last),
+        false => (log, ""),
+    }
+}
+
+/// Iterator behind [`parse_records`], holding back one record so trailing comments can be
+/// attached to it.
+struct Records<'a, I> {
+    lines: I,
+    /// Records have no track id column.
+    legacy: bool,
+    /// Comments waiting for the next scrobble.
+    comments: Vec<&'a str>,
+    parsed: Option<(usize, Result<ScrobbleRef<'a>, ParseError>)>,
+}
+
+impl<'a, I: Iterator<Item = (usize, &'a str)>> Iterator for Records<'a, I> {
+    type Item = (usize, Result<ScrobbleRef<'a>, ParseError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (index, line) in self.lines.by_ref() {
+            if line.starts_with('#') {
+                self.comments.push(line);
+                continue;
+            }
+            let record = ScrobbleRef::parse(line, self.legacy)
+                .map(|mut scrobble| {
+                    scrobble.comments = std::mem::take(&mut self.comments);
+                    scrobble
+                })
+                .map_err(|e| ParseError {
+                    line: index + 1,
+                    column: Some(e.column),
+                    message: e.message,
+                });
+            if let Some(previous) = self.parsed.replace((index + 1, record)) {
+                return Some(previous);
+            }
+        }
+        let mut last = self.parsed.take()?;
+        if let (_, Ok(scrobble)) = &mut last {
+            scrobble.trailing_comments = std::mem::take(&mut self.comments);
+        }
+        Some(last)
+    }
+}
+
+/// Parse every scrobble in a scrobbler.log, skipping the header.
+pub fn parse_log(log: &str) -> Result<Vec<Scrobble>, String> {
+    parse_records(log)
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())
+}
+
+/// Parse a whole scrobbler.log and fix every scrobble with the default [`Pipeline`].
+pub fn fix_scrobbles(log: &str) -> Result<Vec<Scrobble>, String> {
+    Pipeline::default().run(parse_log(log)?)
+}
+
+/// Serialize scrobbles as a complete scrobbler.log, with their comments.
+pub fn serialize_log(scrobbles: &[Scrobble]) -> String {
+    serialize_log_with_header(&LogHeader::default(), scrobbles)
+}
+
+/// Like [`serialize_log`], keeping the timezone and client of `header` (say, the input's). The
+/// version is always 1.1, the format records are written in.
+pub fn serialize_log_with_header(header: &LogHeader, scrobbles: &[Scrobble]) -> String {
+    ScrobbleSerializer::default()
+        .log(header, scrobbles)
+        .expect("fields written as they are can't be refused")
+}
+
+/// Like [`serialize_log`], but copying from the `original` log its header, its line endings, and
+/// every record still exactly as it was parsed from it, byte for byte (`\r\n`, a zero-padded
+/// track position), so a diff against it shows only what changed. Other
+/// records are written as [`serialize_log`] would. AUDIOSCROBBLER/1.0 logs are rewritten whole,
+/// since their records can't be mixed with 1.1 ones.
+pub fn serialize_log_preserving(original: &str, scrobbles: &[Scrobble]) -> String {
+    if let Some(header) = LogHeader::parse(original).ok().filter(LogHeader::is_legacy) {
+        return serialize_log_with_header(&header, scrobbles) + "\n";
+    }
+    let lines: Vec<&str> = original.lines().collect();
+    let ending = match original.split_once('\n') {
+        Some((first, _)) if first.ends_with('\r') => "\r\n",
+        _ => "\n",
+    };
+    // Where each record's canonical form was, so repeated records each get their own line back.
+    let mut originals: HashMap<String, VecDeque<&str>> = HashMap::new();
+    for (line, record) in parse_borrowed_records(original) {
+        if let (Ok(scrobble), Some(raw)) = (record, lines.get(line - 1)) {
+            originals
+                .entry(scrobble.to_string())
+                .or_default()
+                .push_back(raw);
+        }
+    }
+    let header = match lines.get(..LogHeader::line_count(original)) {
+        Some(header) if header.iter().all(|line| line.starts_with('#')) => header.to_vec(),
+        _ => HEADER.lines().collect(),
+    };
+    let mut output: Vec<String> = header.into_iter().map(str::to_string).collect();
+    for scrobble in scrobbles {
+        output.extend(scrobble.comments.iter().cloned());
+        let record = scrobble.to_string();
+        let raw = originals.get_mut(&record).and_then(VecDeque::pop_front);
+        output.push(raw.map_or(record, str::to_string));
+        output.extend(scrobble.trailing_comments.iter().cloned());
+    }
+    let mut log = output.join(ending);
+    if original.is_empty() || original.ends_with('\n') {
+        log.push_str(ending);
+    }
+    log
+}
+
+/// Parse a whole scrobbler.log, fix every scrobble, and serialize the result under its header.
+pub fn fix_log(log: &str) -> Result<String, String> {
+    let header = LogHeader::parse(log).unwrap_or_default();
+    fix_scrobbles(log).map(|scrobbles| serialize_log_with_header(&header, &scrobbles))
+}
+
+#[test]
+fn keep_comments_in_place() {
+    let log = format!(
+        "{HEADER}# road trip\n{}\n# still driving\nnot a record\n{}\n# home",
+        "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t",
+        "Low\tDrums and Guns\tBelarus\t6\t192\tL\t1699414000\t"
+    );
+    let scrobbles: Vec<Scrobble> = parse_records(&log).filter_map(Result::ok).collect();
+    assert_eq!(scrobbles[0].comments, ["# road trip"]);
+    assert_eq!(scrobbles[1].comments, ["# still driving"]);
+    let expected = log.replace("not a record\n", "");
+    assert_eq!(serialize_log(&scrobbles), expected);
+}
+
+#[test]
+fn preserve_untouched_lines() {
+    let log = format!(
+        "{}\r\nLow\tDrums and Guns\tBreaker\t05\t187\tL\t1699413807\t\r\n\
+         Low\tC'mon\tNothing but Heart\t\t480\tL\t962790469\t\r\n",
+        HEADER.trim_end().replace('\n', "\r\n")
+    );
+    let scrobbles = fix_scrobbles(&log).unwrap();
+    let preserved = serialize_log_preserving(&log, &scrobbles);
+    let lines: Vec<&str> = preserved.split_inclusive('\n').collect();
+    assert_eq!(
+        lines[..4],
+        log.split_inclusive('\n').collect::<Vec<_>>()[..4]
+    );
+    assert_eq!(lines[4], format!("{}\r\n", scrobbles[1]));
+    assert_eq!(lines.len(), 5);
+    assert_eq!(
+        serialize_log_preserving("", &scrobbles[..1]),
+        serialize_log(&scrobbles[..1]) + "\n"
+    );
+}
+
+#[test]
+fn hold_back_unfinished_record() {
+    let record = "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t";
+    let log = format!("{HEADER}{record}\nLow\tDrums and Guns\tBel");
+    assert_eq!(
+        split_unfinished(&log),
+        (
+            format!("{HEADER}{record}\n").as_str(),
+            "Low\tDrums and Guns\tBel"
+        )
+    );
+    let finished = format!("{HEADER}{record}");
+    assert_eq!(split_unfinished(&finished), (finished.as_str(), ""));
+    assert_eq!(
+        split_unfinished("#AUDIOSCROBBLER/1.1\n#TZ/UNK"),
+        ("#AUDIOSCROBBLER/1.1\n#TZ/UNK", "")
+    );
+}
+
+#[test]
+fn parse_in_chunks() {
+    let log = format!(
+        "{HEADER}# road trip\n{}\r\nnot a record\n{}",
+        "Björk\tHomogenic\tJóga\t2\t305\tL\t1699413807\t",
+        "Low\tDrums and Guns\tBelarus\t6\t192\tL\t1699414000\t"
+    );
+    let chunks: Vec<&str> = line_chunks(&log, 10).collect();
+    assert_eq!(chunks.concat(), log);
+    assert!(chunks[..chunks.len() - 1]
+        .iter()
+        .all(|chunk| chunk.len() >= 10 && chunk.ends_with('\n')));
+    assert_eq!(line_chunks(&log, log.len()).count(), 1);
+    for size in [0, 7, 60, 1 << 20] {
+        let chunked: Vec<_> = parse_numbered_chunks(&log, line_chunks(&log, size)).collect();
+        assert_eq!(chunked, parse_numbered_records(&log).collect::<Vec<_>>());
+    }
+    // After the header, the comment and the line that isn't a record.
+    assert_eq!(record_lines(&log), [5, 7]);
+    assert_eq!(record_lines(&log[..log.len() - 8]), [5]);
+}
@@ -0,0 +1,198 @@
+//! Checking a log against the AUDIOSCROBBLER/1.1 format, more strictly than parsing does.
+//!
+//! The format is described at the top of the Rockbox plugin:
+//! <https://github.com/Rockbox/rockbox/blob/3c89adbdbdd036baf313786b0694632c8e7e2bb3/apps/plugins/lastfm_scrobbler.c#L29>
+
+use crate::header::header_line;
+use crate::HEADER_LINES;
+
+/// Fields in a record, counting the (possibly empty) track id.
+const FIELDS: usize = 8;
+
+/// How bad a finding is.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// Odd, but something Last.fm would still take.
+    Warning,
+    /// Breaks the format.
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Something wrong with one line of a log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// 1-based line number in the log.
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.severity, self.message)
+    }
+}
+
+/// Problems with one of the three header lines.
+fn header(number: usize, line: &str) -> Option<String> {
+    match number {
+        1 if line == "#AUDIOSCROBBLER/1.1" => None,
+        1 if line.starts_with("#AUDIOSCROBBLER/") => Some(format!("unsupported version {line}")),
+        1 => Some("missing #AUDIOSCROBBLER/1.1 header".to_string()),
+        2 if line == "#TZ/UNKNOWN" || line == "#TZ/UTC" => None,
+        2 => Some("expected #TZ/UNKNOWN or #TZ/UTC".to_string()),
+        _ if line.starts_with("#CLIENT/") && line.len() > "#CLIENT/".len() => None,
+        // A fork's own header line, before its #CLIENT/.
+        _ if !line.starts_with("#CLIENT/") && header_line(line).is_some() => None,
+        _ => Some("expected #CLIENT/ and the client's name".to_string()),
+    }
+}
+
+/// Check a whole log, returning what's wrong with it in line order.
+///
+/// Timestamps should never go backwards within a listening session; each time one does, it's
+/// flagged once, and the records after it are checked against it as a new session.
+pub fn lint(log: &[u8]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut previous: Option<i64> = None;
+    let mut lines = log.split(|&b| b == b'\n').enumerate().peekable();
+    while let Some((index, line)) = lines.next() {
+        let number = index + 1;
+        let mut found = |severity, message: String| {
+            findings.push(Finding {
+                line: number,
+                severity,
+                message,
+            })
+        };
+        // The newline ending the last line leaves an empty piece after it.
+        if line.is_empty() && lines.peek().is_none() {
+            break;
+        }
+        let Ok(line) = std::str::from_utf8(line) else {
+            found(Severity::Error, "not valid UTF-8".to_string());
+            continue;
+        };
+        let line = match line.strip_suffix('\r') {
+            Some(line) => {
+                found(Severity::Warning, "line ends with \\r".to_string());
+                line
+            }
+            None => line,
+        };
+        if number <= HEADER_LINES {
+            if let Some(message) = header(number, line) {
+                found(Severity::Error, message);
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < FIELDS {
+            found(
+                Severity::Error,
+                format!("expected {FIELDS} fields, found {}", fields.len()),
+            );
+            continue;
+        }
+        if fields.len() > FIELDS {
+            found(
+                Severity::Warning,
+                format!(
+                    "{} fields after the track id, which only some plugin forks write",
+                    fields.len() - FIELDS
+                ),
+            );
+        }
+        for (field, name) in [(fields[0], "artist"), (fields[2], "track")] {
+            if field.is_empty() {
+                found(Severity::Error, format!("empty {name}"));
+            }
+        }
+        if !fields[3].is_empty() && fields[3].parse::<u32>().is_err() {
+            found(
+                Severity::Error,
+                format!("bad track position {:?}", fields[3]),
+            );
+        }
+        match fields[4].parse::<u32>() {
+            Ok(0) => found(Severity::Warning, "duration of 0 seconds".to_string()),
+            Ok(_) => {}
+            Err(_) => found(Severity::Error, format!("bad duration {:?}", fields[4])),
+        }
+        if fields[5] != "L" && fields[5] != "S" {
+            found(
+                Severity::Error,
+                format!("rating {:?} isn't L or S", fields[5]),
+            );
+        }
+        let Ok(timestamp) = fields[6].parse::<i64>() else {
+            found(Severity::Error, format!("bad timestamp {:?}", fields[6]));
+            continue;
+        };
+        if chrono::DateTime::from_timestamp(timestamp, 0).is_none() {
+            found(
+                Severity::Error,
+                format!("timestamp {timestamp} is out of the range of dates"),
+            );
+            continue;
+        }
+        if let Some(previous) = previous.filter(|&previous| timestamp < previous) {
+            found(
+                Severity::Warning,
+                format!(
+                    "timestamp goes back {}s from the record before",
+                    previous - timestamp
+                ),
+            );
+        }
+        previous = Some(timestamp);
+    }
+    findings
+}
+
+#[test]
+fn lint_log() {
+    let log = b"#AUDIOSCROBBLER/1.0\n#TZ/UNKNOWN\n#CLIENT/Rockbox ipodvideo $Revision$\n\
+        Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t\n\
+        Low\tDrums and Guns\tBelarus\t6\t192\tX\t1699414000\t\n\
+        Low\tDrums and Guns\tDragonfly\t\t192\tS\t1699000000\t\n\
+        Low\tDrums and Guns\t\xff\t7\t192\tL\t1699414400\t\n\
+        Low\tDrums and Guns\tPretty People\t8\t192\tL\t99999999999999999\t\n\
+        Low\tDrums and Guns\n";
+    let findings: Vec<(usize, Severity)> = lint(log)
+        .iter()
+        .map(|finding| (finding.line, finding.severity))
+        .collect();
+    assert_eq!(
+        findings,
+        [
+            (1, Severity::Error),
+            (5, Severity::Error),
+            (6, Severity::Warning),
+            (7, Severity::Error),
+            (8, Severity::Error),
+            (9, Severity::Error),
+        ]
+    );
+    assert_eq!(
+        lint(log)[2].to_string(),
+        "line 6: warning: timestamp goes back 414000s from the record before"
+    );
+    assert_eq!(
+        lint(log)[4].message,
+        "timestamp 99999999999999999 is out of the range of dates"
+    );
+    assert!(lint(crate::HEADER.as_bytes()).is_empty());
+}
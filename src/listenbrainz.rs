@@ -0,0 +1,219 @@
+//! ListenBrainz `submit-listens` payloads, and the listens in ListenBrainz exports.
+//!
+//! Documented here:
+//! - <https://listenbrainz.readthedocs.io/en/latest/users/json.html>
+
+use chrono::{Datelike, TimeZone, Utc};
+
+use crate::archive::Entry;
+use crate::{json, Rating, Scrobble, SkipPolicy};
+
+/// The `track_metadata` object for one scrobble, saying it was `skipped` if so.
+fn track_metadata(scrobble: &Scrobble, skipped: bool) -> String {
+    let mut additional_info = vec![
+        ("duration", scrobble.song_duration.to_string()),
+        ("media_player", json::string("Rockbox")),
+        ("submission_client", json::string(env!("CARGO_PKG_NAME"))),
+        (
+            "submission_client_version",
+            json::string(env!("CARGO_PKG_VERSION")),
+        ),
+    ];
+    if let Some(position) = scrobble.track_position {
+        additional_info.push(("tracknumber", position.to_string()));
+    }
+    if let Some(id) = &scrobble.track_id {
+        additional_info.push(("recording_mbid", json::string(id)));
+    }
+    if !scrobble.artist_mbids.is_empty() {
+        let ids = scrobble.artist_mbids.iter().map(|id| json::string(id));
+        additional_info.push(("artist_mbids", json::array(ids)));
+    }
+    if let Some(id) = &scrobble.release_mbid {
+        additional_info.push(("release_mbid", json::string(id)));
+    }
+    if let Some(album_artist) = scrobble.album_artist() {
+        additional_info.push(("release_artist_name", json::string(album_artist)));
+    }
+    if skipped {
+        additional_info.push(("skipped", "true".to_string()));
+    }
+    let mut metadata = vec![
+        ("artist_name", json::string(&scrobble.artist)),
+        ("track_name", json::string(&scrobble.track)),
+    ];
+    if !scrobble.album.is_empty() {
+        metadata.push(("release_name", json::string(&scrobble.album)));
+    }
+    metadata.push(("additional_info", json::object(additional_info)));
+    json::object(metadata)
+}
+
+/// One listen: when the scrobble was heard, and what.
+fn listen(scrobble: &Scrobble, policy: SkipPolicy) -> String {
+    json::object([
+        ("listened_at", scrobble.timestamp.timestamp().to_string()),
+        (
+            "track_metadata",
+            track_metadata(scrobble, policy.flags(scrobble)),
+        ),
+    ])
+}
+
+/// The scrobbles `policy` keeps; ListenBrainz has no skips, but [`SkipPolicy::Flag`] marks them
+/// with `skipped` in `additional_info`.
+fn listened(scrobbles: &[Scrobble], policy: SkipPolicy) -> impl Iterator<Item = &Scrobble> {
+    scrobbles
+        .iter()
+        .filter(move |scrobble| policy.keeps(scrobble))
+}
+
+/// An `import` payload with one listen per scrobble `policy` keeps.
+pub fn import_payload(scrobbles: &[Scrobble], policy: SkipPolicy) -> String {
+    let listens = listened(scrobbles, policy)
+        .map(|scrobble| listen(scrobble, policy))
+        .collect::<Vec<String>>();
+    format!(
+        "{{\"listen_type\":\"import\",\"payload\":[\n{}\n]}}",
+        listens.join(",\n")
+    )
+}
+
+/// A scrobble from a listen, as exports and the `listens` API hold them. The MusicBrainz ids
+/// ListenBrainz matched it to are used where the submitted ones are missing.
+fn from_listen(listen: &json::Value) -> Result<Scrobble, String> {
+    let metadata = listen
+        .get("track_metadata")
+        .ok_or("listen has no track_metadata")?;
+    let info = metadata.get("additional_info");
+    let mapping = metadata.get("mbid_mapping");
+    let field = |name| {
+        [Some(metadata), info, mapping]
+            .into_iter()
+            .flatten()
+            .find_map(|object| object.get(name))
+    };
+    let string = |name| {
+        field(name)
+            .and_then(json::Value::as_str)
+            .map(str::to_string)
+    };
+    // Submitting clients write numbers as strings often enough.
+    let number = |name| {
+        field(name).and_then(|value| match value {
+            json::Value::String(number) => number.parse::<f64>().ok(),
+            value => value.as_f64(),
+        })
+    };
+    let listened_at = listen
+        .get("listened_at")
+        .and_then(json::Value::as_f64)
+        .ok_or("listen has no listened_at")?;
+    let timestamp = Utc
+        .timestamp_opt(listened_at as i64, 0)
+        .single()
+        .ok_or("listen's listened_at is out of range")?;
+    let duration = match number("duration") {
+        Some(seconds) => seconds,
+        None => number("duration_ms").unwrap_or(0.0) / 1000.0,
+    };
+    Ok(Scrobble {
+        artist: string("artist_name").ok_or("listen has no artist_name")?,
+        album: string("release_name").unwrap_or_default(),
+        track: string("track_name").ok_or("listen has no track_name")?,
+        track_position: number("tracknumber").map(|position| position as u32),
+        song_duration: duration.round() as u32,
+        rating: Rating::Listened,
+        timestamp: timestamp.with_timezone(&chrono::Local),
+        track_id: string("recording_mbid"),
+        artist_mbids: field("artist_mbids")
+            .and_then(json::Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(json::Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        release_mbid: string("release_mbid"),
+        extras: string("release_artist_name").into_iter().collect(),
+        unknown_suffix: None,
+        comments: Vec::new(),
+        trailing_comments: Vec::new(),
+        provenance: None,
+        index: None,
+    })
+}
+
+/// Read listens, oldest first: one per line, as exports hold them, or a JSON array of them, as
+/// older exports and the API do.
+pub fn parse_listens(text: &str) -> Result<Vec<Scrobble>, String> {
+    let listens = match json::parse(text) {
+        Ok(json::Value::Array(listens)) => listens,
+        _ => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| json::parse(line).map_err(|e| format!("listen {}: {e}", i + 1)))
+            .collect::<Result<_, _>>()?,
+    };
+    let mut scrobbles = listens
+        .iter()
+        .map(from_listen)
+        .collect::<Result<Vec<_>, _>>()?;
+    crate::merge::sort(&mut scrobbles);
+    Ok(scrobbles)
+}
+
+/// The listened scrobbles as the files of a ListenBrainz export: `listens/YEAR/MONTH.jsonl`
+/// for each month (in UTC) they span, one listen per line.
+pub fn export_files(scrobbles: &[Scrobble], policy: SkipPolicy) -> Vec<Entry> {
+    let mut files: Vec<Entry> = Vec::new();
+    for scrobble in listened(scrobbles, policy) {
+        let date = scrobble.timestamp.with_timezone(&Utc);
+        let path = format!("listens/{}/{}.jsonl", date.year(), date.month());
+        let line = listen(scrobble, policy) + "\n";
+        match files.iter_mut().find(|file| file.path == path) {
+            Some(file) => file.contents.extend_from_slice(line.as_bytes()),
+            None => files.push(Entry {
+                path,
+                contents: line.into_bytes(),
+            }),
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+#[test]
+fn read_and_write_listens() {
+    let log = "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t\n\
+               Low\tDrums and Guns\tBelarus\t6\t192\tS\t1699414000\t\n\
+               Low\tC'mon\tTry to Sleep\t1\t230\tL\t1300000000\tb7ffd2af-1c8e-4d8d-8b4c-3a6e3c0f2f1e\tLow";
+    let scrobbles: Vec<Scrobble> = log.lines().map(|l| Scrobble::new(l).unwrap()).collect();
+    let files = export_files(&scrobbles, SkipPolicy::Drop);
+    let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+    assert_eq!(paths, ["listens/2011/3.jsonl", "listens/2023/11.jsonl"]);
+    let listens: String = files
+        .iter()
+        .map(|file| String::from_utf8(file.contents.clone()).unwrap())
+        .collect();
+    let read = parse_listens(&listens).unwrap();
+    assert_eq!(read.len(), 2);
+    assert_eq!(read[0].to_string(), scrobbles[2].to_string());
+    assert_eq!(read[1].to_string(), scrobbles[0].to_string());
+
+    let flagged = import_payload(&scrobbles, SkipPolicy::Flag);
+    assert_eq!(flagged.matches("\"listened_at\"").count(), 3);
+    assert_eq!(flagged.matches("\"skipped\":true").count(), 1);
+    let listened = import_payload(&scrobbles, SkipPolicy::Listen);
+    assert!(listened.contains("1699414000") && !listened.contains("skipped"));
+
+    let exported = r#"[{"listened_at": 1699413807, "track_metadata": {"artist_name": "Low",
+        "track_name": "Breaker", "additional_info": {"duration_ms": 187400, "tracknumber": "5"},
+        "mbid_mapping": {"recording_mbid": "b7ffd2af"}}}]"#;
+    let read = parse_listens(exported).unwrap();
+    assert_eq!(
+        (read[0].song_duration, read[0].track_position),
+        (187, Some(5))
+    );
+    assert_eq!(read[0].track_id.as_deref(), Some("b7ffd2af"));
+}
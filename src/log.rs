@@ -0,0 +1,186 @@
+//! Diagnostics on stderr, as text or JSON Lines, with the record they're about. Nothing but the
+//! output asked for goes to stdout, so it can be piped.
+//!
+//! Code working on one record enters a [`span`] naming it (its line, or artist, track and
+//! timestamp), and every message logged until the span is dropped carries those fields.
+
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use chrono::{SecondsFormat, Utc};
+use scrobble_fix::{json, Scrobble};
+
+/// How much a message matters; only those at or above the chosen verbosity are shown.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    /// What a run did, shown by default.
+    Info,
+    /// Shown with `-v`.
+    Debug,
+    /// Every record's progress, shown with `-vv`.
+    Trace,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+/// How messages are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// For people: the message, after its span fields and level when there are any.
+    Text,
+    /// One JSON object per message, for programs.
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            other => Err(format!("unknown log format: {other}")),
+        }
+    }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static JSON: AtomicU8 = AtomicU8::new(0);
+
+/// A span field's value.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Number(usize),
+    Text(String),
+}
+
+thread_local! {
+    /// Fields of the spans entered, innermost last.
+    static FIELDS: RefCell<Vec<(&'static str, Value)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Show messages up to `verbosity` (`-v` once for debug, twice for trace), or only errors when
+/// `quiet`, written as `format`.
+pub fn init(verbosity: u8, quiet: bool, format: Format) {
+    let level = match quiet {
+        true => Level::Error as u8,
+        false => (Level::Info as u8 + verbosity).min(Level::Trace as u8),
+    };
+    VERBOSITY.store(level, Ordering::Relaxed);
+    JSON.store((format == Format::Json) as u8, Ordering::Relaxed);
+}
+
+/// Whether messages at `level` are shown, to skip building ones that aren't.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// Context added to messages until it's dropped.
+#[must_use]
+pub struct Span {
+    fields: usize,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        FIELDS.with(|fields| {
+            let mut fields = fields.borrow_mut();
+            let kept = fields.len() - self.fields;
+            fields.truncate(kept);
+        });
+    }
+}
+
+/// Add `fields` to every message logged on this thread while the span lives.
+pub fn span(entered: Vec<(&'static str, Value)>) -> Span {
+    let count = entered.len();
+    FIELDS.with(|fields| fields.borrow_mut().extend(entered));
+    Span { fields: count }
+}
+
+/// A span for a record (including its line in the log, if known).
+pub fn record(scrobble: &Scrobble, line: Option<usize>) -> Span {
+    let mut fields = Vec::new();
+    if let Some(line) = line {
+        fields.push(("line", Value::Number(line)));
+    }
+    fields.push((
+        "record",
+        Value::Text(format!(
+            "{} – {} @ {}",
+            scrobble.artist,
+            scrobble.track,
+            scrobble.timestamp.timestamp()
+        )),
+    ));
+    span(fields)
+}
+
+/// Write a message, if its level is shown.
+pub fn log(level: Level, message: impl Display) {
+    if !enabled(level) {
+        return;
+    }
+    let fields = FIELDS.with(|fields| fields.borrow().clone());
+    if JSON.load(Ordering::Relaxed) == 1 {
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let mut members = vec![
+            ("time", json::string(&time)),
+            ("level", json::string(level.name())),
+            ("message", json::string(&message.to_string())),
+        ];
+        members.extend(fields.iter().map(|(name, value)| {
+            let value = match value {
+                Value::Number(n) => n.to_string(),
+                Value::Text(text) => json::string(text),
+            };
+            (*name, value)
+        }));
+        eprintln!("{}", json::object(members));
+        return;
+    }
+    let mut prefix = String::new();
+    for (name, value) in &fields {
+        match value {
+            Value::Number(n) => prefix.push_str(&format!("{name} {n}: ")),
+            Value::Text(text) => prefix.push_str(&format!("{text}: ")),
+        }
+    }
+    match level {
+        Level::Error | Level::Warn => eprintln!("{}: {prefix}{message}", level.name()),
+        _ => eprintln!("{prefix}{message}"),
+    }
+}
+
+pub fn error(message: impl Display) {
+    log(Level::Error, message)
+}
+
+pub fn warn(message: impl Display) {
+    log(Level::Warn, message)
+}
+
+pub fn info(message: impl Display) {
+    log(Level::Info, message)
+}
+
+pub fn debug(message: impl Display) {
+    log(Level::Debug, message)
+}
+
+pub fn trace(message: impl Display) {
+    log(Level::Trace, message)
+}
@@ -1,162 +1,1667 @@
-#![feature(iter_intersperse)]
-
 //! My iPod had it's clock reset to 2001, and scrobbles have the incorrect date.
 //!
 //! Parse the Rockbox scrobbler.log file, identify scrobbles with suspicious dates, and fix them.
-//!
-//! AUDIOSCROBBLER/1.1 format is documented here:
-//! - <https://github.com/Rockbox/rockbox/blob/3c89adbdbdd036baf313786b0694632c8e7e2bb3/apps/plugins/lastfm_scrobbler.c#L29>
-
-use chrono::{DateTime, Days, FixedOffset, Local, TimeZone};
-use nom::{
-    bytes::complete::{tag, take_until},
-    multi::separated_list1,
-    sequence::terminated,
-    IResult,
+
+mod audit;
+mod auth;
+mod batch;
+mod checkpoint;
+mod cli;
+mod dirs;
+mod enrich;
+mod error;
+mod files;
+mod hooks;
+mod keyring;
+mod log;
+mod manifest;
+mod mmap;
+mod net;
+mod submit;
+mod summary;
+mod tui;
+mod tz;
+mod update;
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use checkpoint::Checkpoint;
+use cli::{Args, Command, Format, USAGE};
+use error::Error;
+use mmap::Mapped;
+use scrobble_fix::charset::{CharsetFixer, CharsetRepair};
+use scrobble_fix::doctor::Priority;
+use scrobble_fix::escape;
+use scrobble_fix::header::Timezone;
+use scrobble_fix::id3::{self, AlbumFixer};
+use scrobble_fix::ignore::{self, IgnoreFixer, IgnoreList};
+use scrobble_fix::input::{self, InputFormat};
+use scrobble_fix::interop;
+use scrobble_fix::lint::Severity;
+use scrobble_fix::merge::{self, DuplicatePolicy, OverlapPolicy};
+use scrobble_fix::metadata::{
+    CaseFixer, CasePolicy, DurationFixer, DurationUnit, FeaturingFixer, RatingFixer,
+    ShiftedTitleFixer,
 };
+use scrobble_fix::podcast::{Classifier, PodcastFixer};
+use scrobble_fix::report;
+use scrobble_fix::rules::{Rule, RulesFixer};
+use scrobble_fix::serialize::{self, EmitTz};
+use scrobble_fix::tagcache::{PlayCount, Tag, TagcacheFixer, Track};
+use scrobble_fix::timeline;
+use scrobble_fix::timestamps::{ClockZoneFixer, CollisionFixer, Jitter, TimestampFixer};
+use scrobble_fix::{LogHeader, Pipeline, Scrobble, ScrobbleSerializer};
+use summary::Summary;
 
-/// Anything older than this needs an offset applied.
-const SCROBBLE_CUTOFF: &str = "2005-01-01T00:00:00Z";
+/// Width available for table output, if stdout is a terminal (or `$COLUMNS` says so).
+fn terminal_width() -> Option<usize> {
+    if let Some(columns) = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return Some(columns);
+    }
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let tty = std::fs::File::open("/dev/tty").ok()?;
+    let size = std::process::Command::new("stty")
+        .arg("size")
+        .stdin(tty)
+        .output()
+        .ok()?;
+    let size = String::from_utf8(size.stdout).ok()?;
+    size.split_whitespace().nth(1)?.parse().ok()
+}
 
-/// Number of days to add to the suspicious scrobbles.
-const SCROBBLE_DAYS_OFFSET: u64 = (365 * 22) + 215;
+/// A complete log of the scrobbles under `header`, ending in a newline; just the header if there
+/// are none. Timestamps are written in `timezone`.
+fn log_file(header: &LogHeader, scrobbles: &[Scrobble], timezone: EmitTz) -> String {
+    let serializer = ScrobbleSerializer {
+        timezone,
+        ..ScrobbleSerializer::default()
+    };
+    let log = serializer
+        .log(header, scrobbles)
+        .expect("fields written as they are can't be refused");
+    match scrobbles.is_empty() {
+        true => log,
+        false => log + "\n",
+    }
+}
 
-/// Header for AUDIOSCROBBLER/1.1 format.
-const HEADER: &str = r#"#AUDIOSCROBBLER/1.1
-#TZ/UNKNOWN
-#CLIENT/Rockbox ipodvideo $Revision$
-"#;
+/// Settle the sessions `incoming` logs again by `policy`, asking about each if it's `None`.
+fn settle_overlaps(
+    existing: Vec<Scrobble>,
+    incoming: Vec<Scrobble>,
+    policy: Option<OverlapPolicy>,
+) -> Result<(Vec<Scrobble>, Vec<Scrobble>), Error> {
+    if policy.is_none() && !io::stdin().is_terminal() {
+        Err(Error::Usage(
+            "--overlap-policy ask needs a terminal to ask on".to_string(),
+        ))?;
+    }
+    let mut failure = None;
+    let (existing, incoming, found) =
+        merge::resolve_overlaps(existing, incoming, |overlap, _, incoming| {
+            if let Some(policy) = policy {
+                return policy;
+            }
+            if failure.is_some() {
+                return OverlapPolicy::KeepFirst;
+            }
+            let first = &incoming[overlap.incoming.start];
+            // Part of the question, so it's asked even with --quiet.
+            let question = format!(
+                "{} plays from {} - {} at {} are logged again, {}s {} than before\n\
+                 keep the [f]irst, the [l]ast, or [b]oth",
+                overlap.incoming.len(),
+                first.artist,
+                first.track,
+                first.timestamp.format("%Y-%m-%d %H:%M"),
+                overlap.offset.abs(),
+                if overlap.offset < 0 {
+                    "earlier"
+                } else {
+                    "later"
+                },
+            );
+            loop {
+                match auth::prompt(&question, false) {
+                    Ok(answer) if answer == "f" => return OverlapPolicy::KeepFirst,
+                    Ok(answer) if answer == "l" => return OverlapPolicy::KeepLast,
+                    Ok(answer) if answer == "b" => return OverlapPolicy::KeepBoth,
+                    Ok(_) => {}
+                    Err(e) => {
+                        failure = Some(e);
+                        return OverlapPolicy::KeepFirst;
+                    }
+                }
+            }
+        });
+    if let Some(e) = failure {
+        return Err(e);
+    }
+    if found > 0 {
+        log::info(format_args!("{found} sessions were logged twice"));
+    }
+    Ok((existing, incoming))
+}
 
-/// Output scrobbler.log with fixed timestamps.
-fn main() -> std::io::Result<()> {
-    let cutoff =
-        DateTime::parse_from_rfc3339(SCROBBLE_CUTOFF).expect("failed to parse cutoff date");
-    let log = std::fs::read_to_string("scrobbler.log")?;
-    let scrobbles: String = log
-        .lines()
-        .skip(3)
-        .map(|input| {
-            Scrobble::new(input)
-                .and_then(|scrobble| scrobble.fix(cutoff).map(|fixed| fixed.to_string()))
-        })
-        .intersperse(Ok("\n".to_string()))
-        .collect::<Result<String, _>>()
-        .unwrap();
-    Ok(println!("{HEADER}{scrobbles}"))
-}
-
-#[derive(Debug)]
-enum Rating {
-    Listened,
-    Skipped,
-}
-
-impl std::fmt::Display for Rating {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self {
-            Rating::Listened => write!(f, "L"),
-            Rating::Skipped => write!(f, "S"),
-        }
-    }
-}
-
-/// Parsed scrobble record.
-#[derive(Debug)]
-struct Scrobble {
-    artist: String,
-    album: String,
-    track: String,
-    track_position: Option<u32>,
-    song_duration: u32, // seconds
-    rating: Rating,
-    timestamp: DateTime<Local>,
-    track_id: Option<String>,
-}
-
-impl std::fmt::Display for Scrobble {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            [
-                &self.artist,
-                &self.album,
-                &self.track,
-                &self
-                    .track_position
-                    .map_or("".to_string(), |p| p.to_string()),
-                &self.song_duration.to_string(),
-                &self.rating.to_string(),
-                &self.timestamp.timestamp().to_string(),
-                &self.track_id.clone().unwrap_or("".to_string())
-            ]
-            .into_iter()
-            .intersperse(&"\t".to_string())
-            .cloned()
-            .collect::<String>()
+/// Merge fixed scrobbles into the master log at `path`, creating it if needed.
+///
+/// With `escape`, the master log's fields are backslash-escaped, like the input's.
+fn append_to_master(
+    path: &Path,
+    scrobbles: Vec<Scrobble>,
+    sort: bool,
+    escape: bool,
+    policy: DuplicatePolicy,
+    overlaps: Option<OverlapPolicy>,
+    summary: &mut Summary,
+) -> Result<(), Error> {
+    let _lock = files::lock(path)?;
+    let (mut master, header) = match std::fs::read_to_string(path) {
+        Ok(log) => (
+            scrobble_fix::parse_log(&log)
+                .map_err(|e| Error::Parse(format!("{}: {e}", path.display())))?,
+            LogHeader::parse(&log).unwrap_or_default(),
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (Vec::new(), LogHeader::default()),
+        Err(e) => return Err(e.into()),
+    };
+    if escape {
+        master.iter_mut().for_each(escape::unescape_scrobble);
+    }
+    let (master, scrobbles) = settle_overlaps(master, scrobbles, overlaps)?;
+    let mut appended = merge::append_with(master, scrobbles, sort, policy);
+    if escape {
+        appended
+            .scrobbles
+            .iter_mut()
+            .for_each(escape::escape_scrobble);
+    }
+    let log = log_file(&header, &appended.scrobbles, EmitTz::default());
+    files::replace_log(path, log, &appended.scrobbles)?;
+    summary.written = appended.added;
+    summary.nothing_to_do = appended.added == 0;
+    log::info(format_args!(
+        "appended {} scrobbles to {} ({} already present)",
+        appended.added,
+        path.display(),
+        appended.duplicates
+    ));
+    Ok(())
+}
+
+/// Read the tracks of the Rockbox database in `dir`.
+fn tagcache(dir: &Path) -> Result<Vec<Track>, Error> {
+    let index = std::fs::read(dir.join("database_idx.tcd"))?;
+    let mut files = HashMap::new();
+    for tag in Tag::ALL {
+        match std::fs::read(dir.join(tag.file_name())) {
+            Ok(file) => files.insert(tag, file),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && tag == Tag::AlbumArtist => continue,
+            Err(e) => return Err(e.into()),
+        };
+    }
+    let tracks = scrobble_fix::tagcache::parse(&index, &files)
+        .map_err(|e| Error::Parse(format!("{}: {e}", dir.display())))?;
+    log::debug(format_args!("{} tracks in {}", tracks.len(), dir.display()));
+    Ok(tracks)
+}
+
+/// Read the ID3 tags of every MP3 file under `dir`, warning about those that can't be.
+fn library(dir: &Path) -> Result<Vec<Track>, Error> {
+    let mut tracks = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let mp3 = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("mp3"));
+            if !mp3 {
+                continue;
+            }
+            match read_id3(&path) {
+                Ok(Some(track)) => tracks.push(track),
+                Ok(None) => log::debug(format_args!("{} has no ID3 tag", path.display())),
+                Err(e) => log::warn(format_args!("{}: {e}", path.display())),
+            }
+        }
+    }
+    log::debug(format_args!(
+        "{} tagged tracks in {}",
+        tracks.len(),
+        dir.display()
+    ));
+    Ok(tracks)
+}
+
+/// Read an MP3's ID3v2 tag, or else its ID3v1 tag, without reading the audio.
+fn read_id3(path: &Path) -> Result<Option<Track>, Error> {
+    let mut file = std::fs::File::open(path)?;
+    let mut header = [0; id3::HEADER];
+    if file.read_exact(&mut header).is_ok() {
+        if let Some(size) = id3::tag_size(&header) {
+            if size as u64 > file.metadata()?.len() {
+                return Err(Error::Parse("ID3v2 tag runs past the end".to_string()));
+            }
+            let mut tag = header.to_vec();
+            tag.resize(size, 0);
+            file.read_exact(&mut tag[id3::HEADER..])?;
+            return id3::parse(&tag).map(Some).map_err(Error::Parse);
+        }
+    }
+    if file.metadata()?.len() < id3::V1_SIZE as u64 {
+        return Ok(None);
+    }
+    let mut trailer = [0; id3::V1_SIZE];
+    file.seek(SeekFrom::End(-(id3::V1_SIZE as i64)))?;
+    file.read_exact(&mut trailer)?;
+    Ok(id3::parse_v1(&trailer))
+}
+
+/// The rules file the user keeps, used without `--rules`.
+fn default_rules_path() -> io::Result<PathBuf> {
+    Ok(dirs::config()?.join("rules.toml"))
+}
+
+/// The rewrite rules file of `--rules` or `rules test`, or else the default one, if there is
+/// one.
+fn rules_path(args: &Args) -> Option<PathBuf> {
+    match &args.rules {
+        Some(path) => Some(path.clone()),
+        None => default_rules_path().ok().filter(|path| path.exists()),
+    }
+}
+
+/// Read the rules file of [`rules_path`].
+fn rules(args: &Args) -> Result<Option<Vec<Rule>>, Error> {
+    let Some(path) = rules_path(args) else {
+        return Ok(None);
+    };
+    log::debug(format_args!("rewrite rules from {}", path.display()));
+    let text = std::fs::read_to_string(&path)?;
+    let rules = scrobble_fix::rules::parse(&text)
+        .map_err(|e| Error::Usage(format!("{}: {e}", path.display())))?;
+    Ok(Some(rules))
+}
+
+/// The default ignore file, applying to every log: `$XDG_CONFIG_HOME/scrobble-fix/ignore`.
+fn default_ignore_path() -> io::Result<PathBuf> {
+    Ok(dirs::config()?.join("ignore"))
+}
+
+/// The ignore files that apply: the default one and, unless there are many logs, the one beside
+/// FILE. None with --no-ignore.
+fn ignore_paths(args: &Args) -> Vec<PathBuf> {
+    if args.no_ignore {
+        return Vec::new();
+    }
+    let mut paths: Vec<PathBuf> = default_ignore_path().into_iter().collect();
+    if !matches!(
+        args.command,
+        Command::Batch | Command::DbImport | Command::DbQuery | Command::DbExport
+    ) {
+        paths.push(args.input.with_file_name(ignore::FILE_NAME));
+    }
+    paths
+}
+
+/// The patterns of the files of [`ignore_paths`], later lines winning, or `None` if they have
+/// none.
+fn ignore_list(args: &Args) -> Result<Option<IgnoreList>, Error> {
+    let mut list = IgnoreList::default();
+    for path in ignore_paths(args).iter().filter(|path| path.is_file()) {
+        log::debug(format_args!("ignore patterns from {}", path.display()));
+        let text = std::fs::read_to_string(path)?;
+        list.extend(
+            IgnoreList::parse(&text)
+                .map_err(|e| Error::Usage(format!("{}: {e}", path.display())))?,
+        );
+    }
+    Ok(Some(list).filter(|list| !list.is_empty()))
+}
+
+/// The podcast classifier the options describe, if any.
+fn podcasts(args: &Args) -> Result<Option<Classifier>, Error> {
+    if args.podcast_artist_list.is_none() && args.podcast_duration.is_none() {
+        return Ok(None);
+    }
+    let artists = match &args.podcast_artist_list {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => String::new(),
+    };
+    Ok(Some(Classifier::new(&artists, args.podcast_duration)))
+}
+
+/// Write the podcast episodes among the scrobbles to `path`, returning the rest.
+fn route_podcasts(
+    path: &Path,
+    classifier: &Classifier,
+    scrobbles: Vec<Scrobble>,
+    args: &Args,
+) -> Result<Vec<Scrobble>, Error> {
+    let (episodes, music): (Vec<Scrobble>, Vec<Scrobble>) = scrobbles
+        .into_iter()
+        .partition(|scrobble| classifier.is_podcast(scrobble));
+    let header = match args.command {
+        Command::Batch => LogHeader::default(),
+        _ => header_of(&args.input),
+    };
+    let log = log_file(&header, &episodes, EmitTz::default());
+    files::replace_log(path, log, &episodes)?;
+    log::info(format_args!(
+        "wrote {} podcast episodes to {}",
+        episodes.len(),
+        path.display()
+    ));
+    Ok(music)
+}
+
+/// Apply the rules to the scrobbles without writing anything, and report which rules matched.
+fn rules_test(
+    rules: &[Rule],
+    mut scrobbles: Vec<Scrobble>,
+    summary: &mut Summary,
+) -> Result<(), Error> {
+    let counts = scrobble_fix::rules::check(rules, &mut scrobbles);
+    for (rule, count) in rules.iter().zip(&counts) {
+        println!("{count}\tline {}\t{rule}", rule.line);
+    }
+    let unmatched: Vec<&Rule> = rules
+        .iter()
+        .zip(&counts)
+        .filter(|(_, &count)| count == 0)
+        .map(|(rule, _)| rule)
+        .collect();
+    summary.written = counts.iter().filter(|&&count| count > 0).count();
+    log::info(format_args!(
+        "{} of {} rules matched; {} never did",
+        summary.written,
+        rules.len(),
+        unmatched.len()
+    ));
+    for rule in unmatched {
+        log::info(format_args!("never matched: line {}\t{rule}", rule.line));
+    }
+    Ok(())
+}
+
+/// Print the line `--line` of the log field by field, with what the fixes make of it, which
+/// needs every record fixed for the context some fixes go by.
+fn show(args: &Args, summary: &mut Summary) -> Result<(), Error> {
+    let number = args.line.expect("show needs --line");
+    let log = std::fs::read_to_string(&args.input)?;
+    let lines = log.lines().count();
+    let line = log.lines().nth(number - 1).ok_or(Error::Usage(format!(
+        "the log has {lines} lines, not {number}"
+    )))?;
+    let legacy = LogHeader::parse(&log).is_ok_and(|header| header.is_legacy());
+    if number <= LogHeader::line_count(&log) {
+        Err(Error::Usage(format!("line {number} is in the header")))?;
+    }
+    let mut index = None;
+    let mut original = Vec::new();
+    for (line, record) in scrobble_fix::parse_numbered_records(&log) {
+        let Ok(mut scrobble) = record else {
+            continue;
+        };
+        if args.escape {
+            escape::unescape_scrobble(&mut scrobble);
+        }
+        if line == number {
+            index = Some(original.len());
+        }
+        original.push(scrobble);
+    }
+    summary.read = original.len();
+    let corrected = pipeline(args)?
+        .run(original.clone())
+        .map_err(Error::Parse)?;
+    let rows = scrobble_fix::review::rows(original, corrected);
+    let row = index.and_then(|index| rows.iter().filter(|row| row.original.is_some()).nth(index));
+    print!(
+        "{}",
+        scrobble_fix::show::describe(number, line, legacy, row)
+    );
+    Ok(())
+}
+
+/// Print what's wrong with the log at `path`, failing if anything breaks the format.
+fn lint(path: &Path, summary: &mut Summary) -> Result<(), Error> {
+    let findings = scrobble_fix::lint::lint(&std::fs::read(path)?);
+    for finding in &findings {
+        println!(
+            "{}:{}: {}: {}",
+            path.display(),
+            finding.line,
+            finding.severity,
+            finding.message
+        );
+    }
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .count();
+    summary.warnings = findings.len() - errors;
+    if errors > 0 {
+        return Err(Error::Parse(format!(
+            "{errors} errors, {} warnings",
+            summary.warnings
+        )));
+    }
+    log::info(format_args!("{} warnings", summary.warnings));
+    Ok(())
+}
+
+/// Rewrite every input in canonical form, or with `--check`, list those that aren't.
+fn fmt(args: &Args, summary: &mut Summary) -> Result<(), Error> {
+    let paths = match args.inputs.is_empty() {
+        true => std::slice::from_ref(&args.input),
+        false => &args.inputs[..],
+    };
+    let (mut changed, mut failed) = (0, 0);
+    for path in paths {
+        let data = std::fs::read(path)?;
+        let text = match std::str::from_utf8(&data) {
+            Ok(text) => text.to_string(),
+            Err(_) => {
+                log::warn(format_args!(
+                    "{} isn't UTF-8; reading it as Latin-1",
+                    path.display()
+                ));
+                data.iter().map(|&byte| char::from(byte)).collect()
+            }
+        };
+        let canonical = match serialize::canonical(&text) {
+            Ok(canonical) => canonical,
+            Err(e) => {
+                log::error(format_args!("{}: {e}", path.display()));
+                failed += 1;
+                continue;
+            }
+        };
+        if canonical.as_bytes() == data {
+            continue;
+        }
+        changed += 1;
+        match args.check {
+            true => println!("{}", path.display()),
+            false => {
+                files::replace(path, &canonical)?;
+                log::info(format_args!("formatted {}", path.display()));
+            }
+        }
+    }
+    summary.written = changed;
+    if failed > 0 {
+        return Err(Error::Parse(format!(
+            "{failed} of {} logs have lines that aren't records",
+            paths.len()
+        )));
+    }
+    match args.check && changed > 0 {
+        true => Err(Error::Usage(format!(
+            "{changed} of {} logs aren't in canonical form; run fmt to rewrite them",
+            paths.len()
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Run every check on the log at `path`, printing what they find with what to do about it.
+fn doctor(path: &Path, summary: &mut Summary) -> Result<(), Error> {
+    let now = chrono::Utc::now();
+    let diagnoses = scrobble_fix::doctor::diagnose(&std::fs::read(path)?, now);
+    for diagnosis in &diagnoses {
+        let lines: Vec<String> = diagnosis.lines.iter().map(usize::to_string).collect();
+        match lines.is_empty() {
+            true => println!("{}: {}", diagnosis.priority, diagnosis.message),
+            false => println!(
+                "{}: {} (line {})",
+                diagnosis.priority,
+                diagnosis.message,
+                lines.join(", ")
+            ),
+        }
+        println!("  try: {}", diagnosis.suggestion);
+    }
+    let count = |priority| {
+        diagnoses
+            .iter()
+            .filter(|diagnosis| diagnosis.priority == priority)
+            .count()
+    };
+    let errors = count(Priority::Error);
+    summary.warnings = count(Priority::Warning);
+    if errors > 0 {
+        return Err(Error::Parse(format!(
+            "{errors} errors, {} warnings",
+            summary.warnings
+        )));
+    }
+    if diagnoses.is_empty() {
+        log::info(format_args!("{}: no problems found", path.display()));
+    }
+    Ok(())
+}
+
+/// The timestamp fix the options ask for.
+fn timestamp_fixer(args: &Args) -> TimestampFixer {
+    let mut timestamps = TimestampFixer {
+        offset: args.offset_days,
+        policy: args.suspicious_action,
+        listening_hours: args.listening_hours,
+        jitter: args.jitter.map(|jitter| Jitter {
+            seed: args.generator.seed,
+            ..jitter
+        }),
+        ..TimestampFixer::default()
+    };
+    if !args.detect.is_empty() {
+        timestamps.detectors = args.detect.clone();
+    }
+    timestamps
+}
+
+/// The fixes requested on the command line.
+fn pipeline(args: &Args) -> Result<Pipeline, Error> {
+    let mut pipeline = Pipeline::new();
+    let podcasts = podcasts(args)?;
+    if let Some(command) = &args.pre_hook {
+        pipeline = pipeline.with(hooks::PreHook {
+            command: command.clone(),
+        });
+    }
+    // Before the timestamps, whose fixes lean on durations.
+    if args.duration_unit != DurationUnit::Seconds {
+        pipeline = pipeline.with(DurationFixer {
+            unit: args.duration_unit,
+            podcasts: podcasts.clone().unwrap_or_default(),
+        });
+    }
+    if let Some(offset) = args.input_tz {
+        pipeline = pipeline.with(ClockZoneFixer { offset });
+    }
+    pipeline = pipeline.with(timestamp_fixer(args));
+    // Before nudging, which makes up the gaps between plays sharing a timestamp.
+    if let Some(threshold) = args.listened_threshold {
+        pipeline = pipeline.with(RatingFixer { threshold });
+    }
+    if let Some(nudge) = args.nudge_collisions {
+        pipeline = pipeline.with(CollisionFixer { nudge });
+    }
+    if args.fix_shifted_titles {
+        pipeline = pipeline.with(ShiftedTitleFixer);
+    }
+    // Before the tagcache and the rules, which match the names as they should read.
+    if args.repair_charset == Some(CharsetRepair::Apply) {
+        pipeline = pipeline.with(CharsetFixer);
+    }
+    // Before the rules, so they rewrite the device's spelling.
+    if let Some(dir) = &args.tagcache {
+        pipeline = pipeline.with(TagcacheFixer::new(tagcache(dir)?));
+    }
+    if let Some(dir) = &args.backfill_albums {
+        pipeline = pipeline.with(AlbumFixer::new(library(dir)?));
+    }
+    if let Some(rules) = rules(args)? {
+        pipeline = pipeline.with(RulesFixer { rules });
+    }
+    if let Some(policy) = args.featuring {
+        pipeline = pipeline.with(FeaturingFixer { policy });
+    }
+    if args.case_policy != CasePolicy::Keep {
+        pipeline = pipeline.with(CaseFixer {
+            policy: args.case_policy,
+            exceptions: args.case_exceptions.clone(),
+        });
+    }
+    if let Some(list) = ignore_list(args)? {
+        pipeline = pipeline.with(IgnoreFixer { list });
+    }
+    // Last, so artists are matched as the rules and policies leave them.
+    if let Some(classifier) = podcasts.filter(|_| args.podcast_output.is_none()) {
+        pipeline = pipeline.with(PodcastFixer {
+            classifier,
+            policy: args.podcasts,
+        });
+    }
+    Ok(pipeline)
+}
+
+/// Print each name `--repair-charset` would repair, what to, and how many records have it.
+fn preview_charset(scrobbles: &[Scrobble], summary: &mut Summary) -> Result<(), Error> {
+    let repairs = scrobble_fix::charset::repairs(scrobbles);
+    for repair in &repairs {
+        println!(
+            "{}\t{}\t{}\t{}",
+            repair.field, repair.before, repair.after, repair.records
+        );
+    }
+    summary.nothing_to_do = repairs.is_empty();
+    log::info(format_args!(
+        "{} names to repair; nothing written (fix them with --repair-charset apply)",
+        repairs.len()
+    ));
+    Ok(())
+}
+
+/// The line [`read_input`] read each record from, when the input is one log, read in order.
+fn record_lines(args: &Args) -> Result<Option<Vec<usize>>, Error> {
+    let mapped = Mapped::open(&args.input)?;
+    let bytes = mapped.bytes();
+    if scrobble_fix::archive::detect(&bytes[..bytes.len().min(512)]).is_some() {
+        return Ok(None);
+    }
+    let log = mapped.text()?;
+    let format = args.input_format.or_else(|| input::detect(log));
+    Ok((format == Some(InputFormat::Log)).then(|| scrobble_fix::record_lines(log)))
+}
+
+/// Print the days whose scrobbles don't fit in them, with the lines they span, or for input
+/// other than a log, which of its records.
+fn analyze_days(scrobbles: &[Scrobble], args: &Args) -> Result<(), Error> {
+    let lines = record_lines(args)?;
+    let span = |first: usize, last: usize| match &lines {
+        Some(lines) => format!("lines {}-{}", lines[first], lines[last]),
+        None => format!("records {}-{}", first + 1, last + 1),
+    };
+    for day in scrobble_fix::analyze::busy_days(scrobbles, args.threshold) {
+        println!(
+            "{}\t{} scrobbles\t{}h{:02}m of music ({:.0}%)\t{}",
+            day.date,
+            day.scrobbles,
+            day.seconds / 3600,
+            day.seconds % 3600 / 60,
+            day.fill() * 100.0,
+            span(day.first, day.last)
+        );
+    }
+    Ok(())
+}
+
+/// Print scrobble counts per artist, or with `fuzzy`, rewrite rules for similar spellings.
+fn analyze_artists(scrobbles: &[Scrobble], args: &Args) -> Result<(), Error> {
+    if !args.fuzzy {
+        let mut counts = scrobble_fix::analyze::artist_counts(scrobbles);
+        match (args.collate, args.sort) {
+            (Some(collation), true) => collation.sort(&mut counts, |(artist, _)| artist),
+            (None, true) => counts.sort(),
+            // Most played first still, ties in order.
+            (Some(collation), false) => counts.sort_by(|(a, a_count), (b, b_count)| {
+                b_count.cmp(a_count).then(collation.compare(a, b))
+            }),
+            (None, false) => {}
+        }
+        for (artist, count) in counts {
+            println!("{count}\t{artist}");
+        }
+        return Ok(());
+    }
+    let clusters =
+        scrobble_fix::analyze::artist_clusters(scrobbles, scrobble_fix::analyze::ARTIST_SIMILARITY);
+    for cluster in clusters {
+        let variants = cluster
+            .variants
+            .iter()
+            .map(|(variant, count)| format!("{variant} ({count})"))
+            .collect::<Vec<String>>();
+        println!("# {}\n{}", variants.join(", "), cluster.rules());
+    }
+    Ok(())
+}
+
+/// Print each track's play count beside its plays in the log, and how many plays went unlogged
+/// in all.
+fn analyze_playcounts(scrobbles: &[Scrobble], args: &Args) -> Result<(), Error> {
+    let dir = args.tagcache.as_deref().expect("checked by Args::parse");
+    let counts = scrobble_fix::tagcache::reconcile(tagcache(dir)?, scrobbles);
+    for count in &counts {
+        let track = &count.track;
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            track.playcount,
+            count.logged,
+            count.lost(),
+            track.artist,
+            track.title
+        );
+    }
+    let counted: usize = counts
+        .iter()
+        .map(|count| count.track.playcount as usize)
+        .sum();
+    let logged: usize = counts.iter().map(|count| count.logged).sum();
+    let lost: usize = counts.iter().map(PlayCount::lost).sum();
+    log::info(format_args!(
+        "{counted} plays counted, {logged} logged: about {lost} lost before logging"
+    ));
+    if counted == 0 {
+        log::warn("the database counted no plays; is the runtime database on?");
+    }
+    Ok(())
+}
+
+/// Sum up the records the timestamp detectors flag, with where the fixes put them, and list
+/// how many went in each month.
+fn analyze_resets(scrobbles: Vec<Scrobble>, args: &Args) -> Result<(), Error> {
+    let suspicious = timestamp_fixer(args).suspicious(&scrobbles);
+    let total = scrobbles.len();
+    let corrected = pipeline(args)?
+        .run(scrobbles.clone())
+        .map_err(Error::Parse)?;
+    let rows = scrobble_fix::review::rows(scrobbles, corrected);
+    let loss = scrobble_fix::analyze::reset_loss(
+        rows.iter()
+            .filter_map(|row| Some((row.original.as_ref()?, row.corrected.as_ref())))
+            .zip(&suspicious)
+            .filter(|(_, &suspicious)| suspicious)
+            .map(|(pair, _)| pair),
+    );
+    let minutes = loss.seconds / 60;
+    print!(
+        "{} of {total} records logged by a reset clock: {}h{:02}m of music",
+        loss.records,
+        minutes / 60,
+        minutes % 60
+    );
+    match loss.span {
+        Some((first, last)) => println!(
+            ", likely played over {} days, from {} to {}",
+            (last.date_naive() - first.date_naive()).num_days() + 1,
+            first.date_naive(),
+            last.date_naive()
+        ),
+        None => println!(),
+    }
+    if loss.dropped > 0 {
+        println!("{} of them left out by the fixes", loss.dropped);
+    }
+    for ((year, month), records) in &loss.months {
+        println!("{year}-{month:02}\t{records} records");
+    }
+    Ok(())
+}
+
+/// Chart scrobbles per day as logged and once fixed, to `--output` or stdout.
+fn analyze_timeline(scrobbles: Vec<Scrobble>, args: &Args) -> Result<(), Error> {
+    let corrected = pipeline(args)?
+        .run(scrobbles.clone())
+        .map_err(Error::Parse)?;
+    let series: [(&str, &[Scrobble]); 2] = [("As logged", &scrobbles), ("Fixed", &corrected)];
+    let Some(path) = &args.output else {
+        print!("{}", timeline::svg(&series));
+        return Ok(());
+    };
+    let chart = match path
+        .extension()
+        .is_some_and(|extension| extension == "html")
+    {
+        true => timeline::html("Scrobbles per day", &series),
+        false => timeline::svg(&series),
+    };
+    files::replace(path, chart)?;
+    log::info(format_args!("wrote {}", path.display()));
+    Ok(())
+}
+
+/// Print the likeliest UTC offsets of the log's clock, with how likely each is, taking the
+/// system's timezone for the listener's.
+fn analyze_timezone(scrobbles: &[Scrobble], args: &Args) -> Result<(), Error> {
+    if header_of(&args.input).tz == Timezone::Utc {
+        log::warn(format_args!(
+            "{}: the log says its timestamps are in UTC already",
+            args.input.display()
+        ));
+    }
+    let listener = chrono::Local::now().offset().local_minus_utc() / 3600;
+    let guesses = scrobble_fix::analyze::guess_clock_offset(scrobbles, listener);
+    for guess in guesses.iter().take(5) {
+        let sign = if guess.offset < 0 { '-' } else { '+' };
+        println!(
+            "{sign}{:02}:00\t{:.0}%",
+            guess.offset.abs(),
+            guess.confidence * 100.0
+        );
+    }
+    Ok(())
+}
+
+/// How many of the original records the fixes changed or dropped.
+fn changes(original: &[Scrobble], corrected: &[Scrobble]) -> usize {
+    scrobble_fix::review::rows(original.to_vec(), corrected.to_vec())
+        .iter()
+        .filter(|row| row.original.is_some() && row.changed())
+        .count()
+}
+
+/// Stop the run if the fixes changed more of the `total` records than `--max-changes` allows.
+fn check_changes(args: &Args, changed: usize, total: usize) -> Result<(), Error> {
+    let Some(limit) = args.max_changes else {
+        return Ok(());
+    };
+    let allowed = limit.allowed(total);
+    match changed > allowed {
+        true => Err(Error::Usage(format!(
+            "the fixes would change {changed} of {total} records, more than the {allowed} \
+             --max-changes allows; nothing was written"
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Log how the pipeline changed each record it changed, and why the timestamp fix left the
+/// others alone.
+fn trace_corrections(original: Vec<Scrobble>, corrected: &[Scrobble], timestamps: &TimestampFixer) {
+    let mut reasons = timestamps.explain(&original).into_iter();
+    for row in scrobble_fix::review::rows(original, corrected.to_vec()) {
+        // Rows hold the original records in log order, like the reasons.
+        let reason = match row.original {
+            Some(_) => reasons.next().flatten(),
+            None => None,
+        };
+        match (&row.original, &row.corrected) {
+            (Some(original), Some(_)) if !row.changed() => {
+                let _span = log::record(original, None);
+                match reason {
+                    Some(reason) => log::trace(format_args!("unchanged: {reason}")),
+                    None => log::trace("unchanged"),
+                }
+            }
+            (Some(original), Some(corrected)) if row.changed() => {
+                let _span = log::record(original, None);
+                match &corrected.provenance {
+                    Some(provenance) => log::trace(format_args!(
+                        "corrected by {}: {corrected}",
+                        provenance.rule
+                    )),
+                    None => log::trace(format_args!("corrected: {corrected}")),
+                }
+            }
+            (Some(original), None) => {
+                let _span = log::record(original, None);
+                log::trace("dropped");
+            }
+            (None, Some(corrected)) => {
+                let _span = log::record(corrected, None);
+                log::trace("added");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parse the log, leaving out (and reporting) bad records unless `strict` is set. A log that's
+/// `mapped` is parsed a chunk at a time.
+fn parse(
+    log: &str,
+    mapped: Option<&Mapped>,
+    strict: bool,
+    summary: &mut Summary,
+) -> Result<Vec<Scrobble>, Error> {
+    let (log, unfinished) = scrobble_fix::split_unfinished(log);
+    if !unfinished.is_empty() {
+        let line = log.lines().count() + 1;
+        let _span = log::span(vec![("line", log::Value::Number(line))]);
+        log::info("leaving out the unfinished last record, which may still be being written");
+    }
+    let chunks: Box<dyn Iterator<Item = &str>> = match mapped {
+        Some(mapped) => Box::new(mapped.chunks(log)),
+        None => Box::new(std::iter::once(log)),
+    };
+    let mut scrobbles = Vec::new();
+    for (line, record) in scrobble_fix::parse_numbered_chunks(log, chunks) {
+        match record {
+            Ok(scrobble) => {
+                let _span = log::record(&scrobble, Some(line));
+                log::trace("parsed");
+                if let Some(suffix) = &scrobble.unknown_suffix {
+                    log::warn(format_args!(
+                        "{suffix:?} after the timestamp isn't a track id; keeping it as it is"
+                    ));
+                }
+                scrobbles.push(scrobble);
+            }
+            Err(e) if strict => return Err(Error::Parse(e.to_string())),
+            Err(e) => {
+                let mut fields = vec![("line", log::Value::Number(line))];
+                fields.extend(
+                    e.column
+                        .map(|column| ("column", log::Value::Number(column))),
+                );
+                let _span = log::span(fields);
+                log::warn(format_args!("skipping record: {}", e.message));
+                summary.skipped += 1;
+            }
+        }
+    }
+    summary.read += scrobbles.len();
+    summary.nothing_to_do = summary.read == 0;
+    Ok(scrobbles)
+}
+
+/// Parse an input in the format `--input-format` names or its first line shows. Logs are parsed
+/// leniently or strictly, and unescaped with `--escape`; a chunk at a time if `mapped`.
+fn read(
+    log: &str,
+    mapped: Option<&Mapped>,
+    args: &Args,
+    summary: &mut Summary,
+) -> Result<Vec<Scrobble>, Error> {
+    let format = match args.input_format {
+        Some(format) => format,
+        None => input::detect(log).ok_or(Error::Parse(
+            "can't tell what format this is; name it with --input-format".to_string(),
+        ))?,
+    };
+    let scrobbles: Vec<Scrobble> = match format {
+        InputFormat::Log => {
+            let mut scrobbles = parse(log, mapped, args.strict, summary)?;
+            if args.escape {
+                scrobbles.iter_mut().for_each(escape::unescape_scrobble);
+            }
+            return Ok(scrobbles);
+        }
+        InputFormat::JsonLines => input::parse_json_lines(log).map_err(Error::Parse)?,
+        InputFormat::Csv => input::parse_csv(log).map_err(Error::Parse)?,
+        InputFormat::LastFm => scrobble_fix::lastfm::parse_recent_tracks_export(log)
+            .and_then(|tracks| tracks.iter().map(|track| track.to_scrobble()).collect())
+            .map_err(Error::Parse)?,
+        InputFormat::ListenBrainz => {
+            scrobble_fix::listenbrainz::parse_listens(log).map_err(Error::Parse)?
+        }
+    };
+    summary.read += scrobbles.len();
+    summary.nothing_to_do = summary.read == 0;
+    Ok(scrobbles)
+}
+
+/// Read FILE, or every input inside it if it's an archive (like a ListenBrainz export), oldest
+/// scrobble first.
+fn read_input(args: &Args, summary: &mut Summary) -> Result<Vec<Scrobble>, Error> {
+    let mut header = Vec::with_capacity(512);
+    std::fs::File::open(&args.input)?
+        .take(512)
+        .read_to_end(&mut header)?;
+    if scrobble_fix::archive::detect(&header).is_none() {
+        let mapped = Mapped::open(&args.input)?;
+        return read(mapped.text()?, Some(&mapped), args, summary);
+    }
+    let mut scrobbles = Vec::new();
+    for (name, text) in batch::logs(&[&args.input])? {
+        scrobbles.extend(read(&text, None, args, summary).map_err(|e| match e {
+            Error::Parse(message) => Error::Parse(format!("{name}: {message}")),
+            e => e,
+        })?);
+    }
+    merge::sort(&mut scrobbles);
+    Ok(scrobbles)
+}
+
+/// [`read_input`], saying so if it holds no scrobbles at all.
+fn read_scrobbles(args: &Args, summary: &mut Summary) -> Result<Vec<Scrobble>, Error> {
+    let scrobbles = read_input(args, summary)?;
+    if scrobbles.is_empty() && summary.skipped == 0 {
+        log::info(format_args!("{}: no scrobbles found", args.input.display()));
+    }
+    Ok(scrobbles)
+}
+
+/// Fix one of a batch's logs, with how many of its records changed, when `count` asks for it,
+/// and how many there were.
+fn fix_one(
+    args: &Args,
+    pipeline: &Pipeline,
+    log: &str,
+    count: bool,
+    summary: &mut Summary,
+) -> Result<(Vec<Scrobble>, usize, usize), Error> {
+    let scrobbles = read(log, None, args, summary)?;
+    let total = scrobbles.len();
+    if !count {
+        return Ok((pipeline.run(scrobbles).map_err(Error::Parse)?, 0, total));
+    }
+    let fixed = pipeline.run(scrobbles.clone()).map_err(Error::Parse)?;
+    let changed = changes(&scrobbles, &fixed);
+    Ok((fixed, changed, total))
+}
+
+/// A digest of the options deciding what fixing a log makes of it, and of the files they have
+/// read, like the rules, for a checkpoint to tell the logs it fixed the same way.
+fn fixes_digest(args: &Args) -> Result<String, Error> {
+    let options = format!(
+        "{:?}",
+        (
+            (
+                &args.input_format,
+                &args.strict,
+                &args.escape,
+                &args.pre_hook,
+                &args.tz,
+            ),
+            (
+                &args.duration_unit,
+                &args.input_tz,
+                &args.suspicious_action,
+                &args.offset_days,
+                &args.detect,
+                &args.listening_hours,
+                &args.jitter,
+                &args.generator.seed,
+            ),
+            (
+                &args.listened_threshold,
+                &args.nudge_collisions,
+                &args.fix_shifted_titles,
+                &args.repair_charset,
+                &args.tagcache,
+                &args.backfill_albums,
+                &args.rules,
+                &args.featuring,
+            ),
+            (
+                &args.case_policy,
+                &args.case_exceptions,
+                &args.no_ignore,
+                &args.podcast_artist_list,
+                &args.podcast_duration,
+                &args.podcasts,
+                &args.podcast_output,
+            ),
         )
+    );
+    let mut files: Vec<PathBuf> = rules_path(args).into_iter().collect();
+    files.extend(ignore_paths(args));
+    files.extend(args.podcast_artist_list.clone());
+    if let Some(dir) = &args.tagcache {
+        files.push(dir.join("database_idx.tcd"));
+        files.extend(Tag::ALL.map(|tag| dir.join(tag.file_name())));
     }
+    let mut hashed = options.into_bytes();
+    for path in files {
+        let contents = match std::fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => Err(e)?,
+        };
+        hashed.extend(format!("\n{}\t{}\n", path.display(), contents.len()).into_bytes());
+        hashed.extend(contents);
+    }
+    Ok(scrobble_fix::sha256::hex_digest(&hashed))
 }
 
-impl Scrobble {
-    /// Parse a scrobble from scrobbler.log
-    fn new(input: &str) -> Result<Self, String> {
-        let (rest, tokens) = match parse_scrobble_tokens(input) {
-            Ok((rest, tokens)) => (rest, tokens),
-            Err(e) => Err(e.to_string())?,
+/// Fix every log found in the batch inputs, and combine them without duplicates (sorting them
+/// by timestamp with `--sort`). With a `checkpoint`, logs it has fixed already are taken from
+/// it, and a log that can't be read or fixed is recorded there and left out, instead of ending
+/// the run.
+fn fix_batch<'a>(
+    args: &Args,
+    logs: impl IntoIterator<Item = (&'a str, Result<&'a str, String>)>,
+    mut checkpoint: Option<&mut Checkpoint>,
+    summary: &mut Summary,
+) -> Result<Vec<Scrobble>, Error> {
+    let mut combined = Vec::new();
+    let pipeline = pipeline(args)?;
+    let (mut changed, mut total) = (0, 0);
+    let count = args.max_changes.is_some() || checkpoint.is_some();
+    for (name, log) in logs {
+        let named = |e: Error| match e {
+            Error::Parse(message) => Error::Parse(format!("{name}: {message}")),
+            e => e,
         };
-        Ok(Scrobble {
-            artist: tokens[0].to_string(),
-            album: tokens[1].to_string(),
-            track: tokens[2].to_string(),
-            track_position: match tokens[3] {
-                "" => None,
-                pos => Some(pos.parse::<u32>().map_err(|e| e.to_string())?),
-            },
-            song_duration: tokens[4].parse::<u32>().map_err(|e| e.to_string())?,
-            rating: match tokens[5] {
-                "S" => Rating::Skipped,
-                "L" => Rating::Listened,
-                _ => Err("failed to parse rating")?,
-            },
-            timestamp: chrono::Local
-                .timestamp_opt(tokens[6].parse::<i64>().map_err(|e| e.to_string())?, 0)
-                .unwrap(),
-            track_id: match rest {
-                "" => None,
-                id => Some(id.to_string()),
+        let Some(checkpoint) = checkpoint.as_deref_mut() else {
+            let log = log.map_err(Error::Parse).map_err(named)?;
+            let (fixed, log_changed, log_total) =
+                fix_one(args, &pipeline, log, count, summary).map_err(named)?;
+            log::info(format_args!("{name}: {log_total} scrobbles"));
+            (changed, total) = (changed + log_changed, total + log_total);
+            let (existing, fixed) = settle_overlaps(combined, fixed, args.overlap_policy)?;
+            combined = merge::append_with(existing, fixed, args.sort, args.dedupe_policy).scrobbles;
+            continue;
+        };
+        let hash = log.as_ref().map_or(String::new(), |log| {
+            scrobble_fix::sha256::hex_digest(log.as_bytes())
+        });
+        let fixed = match checkpoint.done(name, &hash) {
+            Some(done) => {
+                log::info(format_args!(
+                    "{name}: {} scrobbles, fixed by an earlier run",
+                    done.total
+                ));
+                summary.read += done.total;
+                (changed, total) = (changed + done.changed, total + done.total);
+                done.scrobbles
+            }
+            None if checkpoint.passes_over(name) => {
+                log::info(format_args!(
+                    "{name}: leaving it out, since it didn't fail before"
+                ));
+                continue;
+            }
+            None => match log
+                .map_err(Error::Parse)
+                .and_then(|log| fix_one(args, &pipeline, log, count, summary))
+            {
+                Ok((fixed, log_changed, log_total)) => {
+                    log::info(format_args!("{name}: {log_total} scrobbles"));
+                    checkpoint.succeeded(name, &hash, &fixed, log_changed, log_total)?;
+                    (changed, total) = (changed + log_changed, total + log_total);
+                    fixed
+                }
+                Err(e) => {
+                    log::warn(format_args!("{name}: {e}; leaving it out"));
+                    checkpoint.failed(name, &hash, &e.to_string())?;
+                    summary.failed += 1;
+                    continue;
+                }
             },
-        })
+        };
+        let (existing, fixed) = settle_overlaps(combined, fixed, args.overlap_policy)?;
+        combined = merge::append_with(existing, fixed, args.sort, args.dedupe_policy).scrobbles;
     }
+    if summary.failed > 0 {
+        log::warn(format_args!(
+            "{} logs couldn't be fixed; once they're put right, rerun with --retry-failed to add \
+             them",
+            summary.failed
+        ));
+    }
+    check_changes(args, changed, total)?;
+    Ok(combined)
+}
 
-    /// Adjust the timestamps for suspicious scrobbles.
-    fn fix(self, cutoff: DateTime<FixedOffset>) -> Result<Self, String> {
-        if self.timestamp > cutoff {
-            return Ok(self);
-        }
-        let updated_timestamp = self
-            .timestamp
-            .checked_add_days(Days::new(SCROBBLE_DAYS_OFFSET))
-            .ok_or("failed to apply offset")?;
-        Ok(Self {
-            timestamp: updated_timestamp,
-            ..self
-        })
+/// The header of the log at `path`, or the default one if it isn't a log; so output keeps the
+/// input's timezone and client.
+fn header_of(path: &Path) -> LogHeader {
+    let Ok(file) = std::fs::File::open(path) else {
+        return LogHeader::default();
+    };
+    let lines: Vec<String> = io::BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .take_while(|line| line.starts_with('#'))
+        .collect();
+    LogHeader::parse(&lines.join("\n")).unwrap_or_default()
+}
+
+/// Where the archive of `db` is kept.
+fn archive_path(args: &Args) -> Result<PathBuf, Error> {
+    match &args.db {
+        Some(path) => Ok(path.clone()),
+        None => Ok(dirs::data()?.join("archive.log")),
+    }
+}
+
+/// Print where each file kept between runs is, whether or not it exists yet.
+fn paths(args: &Args) -> Result<(), Error> {
+    let paths = [
+        ("rules", default_rules_path()?),
+        ("ignore", default_ignore_path()?),
+        ("archive", archive_path(args)?),
+        ("manifest", manifest::path(&archive_path(args)?)),
+        ("submitted", submit::progress_path(args.backend)?),
+        ("config", submit::config_path()?),
+        ("musicbrainz-cache", enrich::cache_path()?),
+        ("audit", audit::path()?),
+    ];
+    for (name, path) in paths {
+        println!("{name}\t{}", path.display());
     }
+    Ok(())
 }
 
-/// Scrobble tokens are separated by tabs. Some fields are empty.
-fn parse_scrobble_tokens(input: &str) -> IResult<&str, Vec<&str>> {
-    terminated(separated_list1(tag("\t"), take_until("\t")), tag("\t"))(input)
+/// The archived scrobbles, or none if there's no archive yet. Archives grow for years, so this
+/// maps the file and parses it a chunk at a time rather than reading it whole.
+fn archive(path: &Path) -> Result<Vec<Scrobble>, Error> {
+    let mapped = match Mapped::open(path) {
+        Ok(mapped) => mapped,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => Err(e)?,
+    };
+    let log = mapped.text()?;
+    scrobble_fix::parse_numbered_chunks(log, mapped.chunks(log))
+        .map(|(_, record)| record)
+        .collect::<Result<_, _>>()
+        .map_err(|e| Error::Parse(format!("{}: {e}", path.display())))
 }
 
-#[test]
-fn parse_line() -> std::io::Result<()> {
-    let log = std::fs::read_to_string("scrobbler.log")?;
-    let scrobbles: Result<Vec<Scrobble>, String> = log
+/// Empty the submitted log down to its header, once nothing in it would be lost: every record
+/// parsed, and every one submitted or, for skipped ones `--skipped` left out, archived. Asks on
+/// a terminal twice, and keeps a copy.
+fn clear_source(
+    args: &Args,
+    scrobbles: &[Scrobble],
+    submitted: submit::Submitted,
+    summary: &Summary,
+) -> Result<(), Error> {
+    let path = &args.input;
+    let refuse = |why: String| Error::Usage(format!("not clearing {}: {why}", path.display()));
+    let log = std::fs::read_to_string(path)?;
+    if input::detect(&log) != Some(InputFormat::Log) {
+        Err(refuse("it isn't a scrobbler.log".to_string()))?;
+    }
+    if summary.skipped > 0 {
+        Err(refuse(format!(
+            "{} records couldn't be read",
+            summary.skipped
+        )))?;
+    }
+    if submitted.left > 0 {
+        Err(refuse(format!(
+            "{} scrobbles are left to submit",
+            submitted.left
+        )))?;
+    }
+    let archive = archive(&archive_path(args)?)?;
+    let archived: HashSet<&Scrobble> = archive.iter().collect();
+    let policy = args.skip_policy(args.backend.name());
+    let unarchived = scrobbles
+        .iter()
+        .filter(|scrobble| !policy.keeps(scrobble) && !archived.contains(scrobble))
+        .count();
+    if unarchived > 0 {
+        Err(refuse(format!(
+            "{unarchived} skipped plays aren't submitted or archived; `db import` it first"
+        )))?;
+    }
+    // The device may have logged more since.
+    let records = log
         .lines()
-        .skip(3)
-        .map(|input| Scrobble::new(input))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    if records.count() != summary.read + summary.skipped {
+        Err(refuse("it changed while submitting".to_string()))?;
+    }
+    let left_out = summary.read.saturating_sub(scrobbles.len());
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    log::info(format_args!(
+        "{}: {} records, every one submitted or archived",
+        path.display(),
+        summary.read
+    ));
+    if left_out > 0 {
+        log::info(format_args!(
+            "{left_out} of them were left out (ignored or podcasts) and won't be kept"
+        ));
+    }
+    if args.dry_run {
+        log::info(format_args!("would clear {}", path.display()));
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        Err(refuse(
+            "--clear-source-after-submit asks for confirmation on a terminal".to_string(),
+        ))?;
+    }
+    let answer = auth::prompt("clear them, keeping the log's header? [y/N]", false)?;
+    if !answer.eq_ignore_ascii_case("y") {
+        return Err(refuse("not confirmed".to_string()));
+    }
+    let answer = auth::prompt(&format!("type the file name, {name}, to confirm"), false)?;
+    if answer != name {
+        return Err(refuse("not confirmed".to_string()));
+    }
+    let stamp = chrono::Local::now().format("%Y%m%dT%H%M%S");
+    let copy = dirs::state()?
+        .join("cleared")
+        .join(format!("{stamp}-{name}"));
+    std::fs::create_dir_all(copy.parent().expect("a directory"))?;
+    files::replace_log(&copy, &log, scrobbles)?;
+    let header: String = log
+        .lines()
+        .take_while(|line| line.starts_with('#'))
+        .map(|line| format!("{line}\n"))
         .collect();
+    files::replace_log(path, header, &[])?;
+    log::info(format_args!(
+        "cleared {}; a copy is in {}",
+        path.display(),
+        copy.display()
+    ));
     Ok(())
 }
+
+/// The scrobbles `--where` keeps, if it's given.
+fn narrowed(args: &Args, scrobbles: Vec<Scrobble>) -> Vec<Scrobble> {
+    let Some(filter) = &args.filter else {
+        return scrobbles;
+    };
+    let before = scrobbles.len();
+    let kept: Vec<Scrobble> = scrobble_fix::query::filter(filter, scrobbles).collect();
+    log::debug(format_args!(
+        "--where kept {} of {before} records",
+        kept.len()
+    ));
+    kept
+}
+
+/// Carry out the command, recording what happened in `summary`.
+fn run(args: &Args, summary: &mut Summary) -> Result<(), Error> {
+    match args.command {
+        Command::AuthLogin(service) => return auth::login(service),
+        Command::AuthLogout(service) => return auth::logout(service),
+        Command::Lint => return lint(&args.input, summary),
+        Command::Doctor => return doctor(&args.input, summary),
+        Command::Fmt => return fmt(args, summary),
+        Command::Show => return show(args, summary),
+        Command::Paths => return paths(args),
+        Command::Schema => {
+            print!("{}", args.schema);
+            return Ok(());
+        }
+        Command::SelfUpdate => return update::self_update(args.dry_run),
+        Command::Generate => {
+            summary.written = args.generator.scrobbles;
+            let log = args.generator.generate();
+            return Ok(io::stdout().write_all(&args.encoding.encode(&log))?);
+        }
+        _ => {}
+    }
+    let mut scrobbles = match args.command {
+        Command::Batch => match &args.checkpoint {
+            Some(dir) => {
+                let mut checkpoint =
+                    Checkpoint::load(dir, &fixes_digest(args)?, args.retry_failed)?;
+                let found = batch::found(&args.inputs)?;
+                let logs = found
+                    .iter()
+                    .map(|(name, log)| (name.as_str(), log.as_deref().map_err(Error::to_string)));
+                fix_batch(args, logs, Some(&mut checkpoint), summary)?
+            }
+            None => {
+                let logs = batch::logs(&args.inputs)?;
+                let logs = logs
+                    .iter()
+                    .map(|(name, log)| (name.as_str(), Ok(log.as_str())));
+                fix_batch(args, logs, None, summary)?
+            }
+        },
+        Command::DbImport => {
+            let archive = archive_path(args)?;
+            let mut manifest = manifest::Manifest::load(&archive)?;
+            let logs = manifest.unseen(batch::logs(&args.inputs)?);
+            if logs.is_empty() {
+                log::warn("every log was imported before; nothing to do");
+                summary.nothing_to_do = true;
+                return Ok(());
+            }
+            let found = logs
+                .iter()
+                .map(|(name, log)| (name.as_str(), Ok(log.as_str())));
+            let scrobbles = narrowed(args, fix_batch(args, found, None, summary)?);
+            let (policy, overlaps) = (args.dedupe_policy, args.overlap_policy);
+            append_to_master(&archive, scrobbles, true, false, policy, overlaps, summary)?;
+            return Ok(manifest.record(&logs)?);
+        }
+        Command::DbQuery | Command::DbExport => {
+            let archived = archive(&archive_path(args)?)?;
+            summary.read = archived.len();
+            let scrobbles: Vec<Scrobble> = match &args.query {
+                Some(query) => scrobble_fix::query::filter(query, archived).collect(),
+                None => archived,
+            };
+            summary.nothing_to_do = scrobbles.is_empty();
+            scrobbles
+        }
+        _ => {
+            let scrobbles = read_scrobbles(args, summary)?;
+            match args.command {
+                Command::AnalyzeDays => return analyze_days(&scrobbles, args),
+                Command::AnalyzeArtists => return analyze_artists(&scrobbles, args),
+                Command::AnalyzeTimeline => return analyze_timeline(scrobbles, args),
+                Command::AnalyzeTimezone => return analyze_timezone(&scrobbles, args),
+                Command::AnalyzePlaycounts => return analyze_playcounts(&scrobbles, args),
+                Command::AnalyzeResets => return analyze_resets(scrobbles, args),
+                Command::Report => {
+                    let corrected = pipeline(args)?.run(scrobbles).map_err(Error::Parse)?;
+                    let corrected = narrowed(args, corrected);
+                    let digests = report::digests(&corrected, args.period).map_err(Error::Parse)?;
+                    match args.format {
+                        Format::Json => digests.iter().for_each(|d| println!("{}", d.to_json())),
+                        _ => print!("{}", report::markdown(&digests)),
+                    }
+                    return Ok(());
+                }
+                Command::Review => {
+                    let corrected = pipeline(args)?
+                        .run(scrobbles.clone())
+                        .map_err(Error::Parse)?;
+                    match tui::review(scrobbles, corrected)? {
+                        Some(reviewed) => reviewed,
+                        None => {
+                            log::info("quit without writing");
+                            summary.nothing_to_do = true;
+                            return Ok(());
+                        }
+                    }
+                }
+                _ if args.repair_charset == Some(CharsetRepair::Preview) => {
+                    return preview_charset(&scrobbles, summary);
+                }
+                Command::RulesTest => {
+                    let rules = rules(args)?.unwrap_or_default();
+                    return rules_test(&rules, scrobbles, summary);
+                }
+                _ if log::enabled(log::Level::Trace)
+                    || args.max_changes.is_some()
+                    || args.format == Format::BulkEdit =>
+                {
+                    let corrected = pipeline(args)?
+                        .run(scrobbles.clone())
+                        .map_err(Error::Parse)?;
+                    let (changed, total) = (changes(&scrobbles, &corrected), scrobbles.len());
+                    let edits = (args.format == Format::BulkEdit)
+                        .then(|| interop::bulk_edits(&scrobbles, &corrected));
+                    if log::enabled(log::Level::Trace) {
+                        trace_corrections(scrobbles, &corrected, &timestamp_fixer(args));
+                    }
+                    check_changes(args, changed, total)?;
+                    if let Some(edits) = edits {
+                        summary.written = edits.iter().map(|edit| edit.scrobbles).sum();
+                        summary.nothing_to_do = edits.is_empty();
+                        print!("{}", interop::bulk_edit_tsv(&edits));
+                        return Ok(());
+                    }
+                    corrected
+                }
+                _ => pipeline(args)?.run(scrobbles).map_err(Error::Parse)?,
+            }
+        }
+    };
+    scrobbles = narrowed(args, scrobbles);
+    if args.command == Command::Enrich {
+        let enriched = enrich::enrich(&mut scrobbles, args.refresh_cache)?;
+        summary.network_failures = enriched.failed;
+        if args.split_loops {
+            scrobbles = scrobble_fix::repeat::split_loops(scrobbles, |scrobble| {
+                let id = scrobble.track_id.as_ref()?;
+                enriched.lengths.get(id).copied()
+            });
+        }
+    }
+    if let (Some(path), Some(classifier)) = (&args.podcast_output, podcasts(args)?) {
+        scrobbles = route_podcasts(path, &classifier, scrobbles, args)?;
+    }
+    if args.command == Command::Submit {
+        let options = submit::Options {
+            backend: args.backend,
+            skipped: args.skip_policy(args.backend.name()),
+            max: args.max_submit,
+            schedule: args.schedule,
+            check_existing: args.check_existing,
+            snapshot: &args.profile_snapshot,
+            jobs: args.jobs,
+            dry_run: args.dry_run,
+        };
+        let submitted = submit::submit(&scrobbles, &options)?;
+        summary.written = submitted.sent;
+        summary.nothing_to_do = summary.written == 0;
+        if args.clear_source {
+            clear_source(args, &scrobbles, submitted, summary)?;
+        }
+        return Ok(());
+    }
+    if let Some(master) = &args.append {
+        return append_to_master(
+            master,
+            scrobbles,
+            args.sort,
+            args.escape,
+            args.dedupe_policy,
+            args.overlap_policy,
+            summary,
+        );
+    }
+    summary.written = scrobbles.len();
+    if let Some(template) = &args.template {
+        for scrobble in &scrobbles {
+            println!("{}", template.render(scrobble));
+        }
+        return Ok(());
+    }
+    match args.format {
+        Format::Log => {
+            if args.escape {
+                scrobbles.iter_mut().for_each(escape::escape_scrobble);
+            }
+            if args.preserve_lines {
+                let original = std::fs::read(&args.input)?;
+                let original = String::from_utf8(original)
+                    .ok()
+                    .filter(|log| input::detect(log) == Some(InputFormat::Log))
+                    .ok_or(Error::Usage(
+                        "--preserve-lines needs FILE to be a scrobbler.log".to_string(),
+                    ))?;
+                print!(
+                    "{}",
+                    scrobble_fix::serialize_log_preserving(&original, &scrobbles)
+                );
+            } else {
+                let mut header = match args.command {
+                    Command::Batch => LogHeader::default(),
+                    Command::DbQuery | Command::DbExport => header_of(&archive_path(args)?),
+                    _ => header_of(&args.input),
+                };
+                if args.input_tz.is_some() {
+                    header.tz = Timezone::Utc;
+                }
+                print!("{}", log_file(&header, &scrobbles, args.emit_tz))
+            }
+        }
+        Format::Json => {
+            for scrobble in &scrobbles {
+                println!("{}", scrobble.to_json());
+            }
+        }
+        Format::ListenBrainz => {
+            println!(
+                "{}",
+                scrobble_fix::listenbrainz::import_payload(
+                    &scrobbles,
+                    args.skip_policy("listenbrainz")
+                )
+            )
+        }
+        Format::ListenBrainzZip => {
+            if io::stdout().is_terminal() {
+                Err(Error::Usage(
+                    "won't write a zip archive to a terminal; redirect stdout to a file"
+                        .to_string(),
+                ))?;
+            }
+            let files = scrobble_fix::listenbrainz::export_files(
+                &scrobbles,
+                args.skip_policy("listenbrainz"),
+            );
+            let zip = scrobble_fix::archive::write_zip(&files).map_err(Error::Parse)?;
+            io::stdout().write_all(&zip)?;
+        }
+        Format::Table => {
+            let width = if args.wide { None } else { terminal_width() };
+            print!("{}", scrobble_fix::table::render(&scrobbles, width));
+        }
+        Format::OpenScrobbler => {
+            let policy = args.skip_policy("openscrobbler");
+            print!("{}", interop::open_scrobbler_csv(&scrobbles, policy))
+        }
+        // Refused for anything but report, and fix, when parsing the arguments.
+        Format::Markdown => unreachable!("only report writes markdown"),
+        Format::BulkEdit => unreachable!("bulk edits are listed once fixed"),
+    }
+    Ok(())
+}
+
+/// Output scrobbler.log with fixed timestamps.
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            let error = Error::Usage(message);
+            eprintln!("{error}\n\n{USAGE}");
+            return error.exit_code();
+        }
+    };
+    log::init(args.verbosity, args.quiet, args.log_format);
+    if let Err(e) = tz::init(args.tz.as_deref()) {
+        eprintln!("{e}");
+        return e.exit_code();
+    }
+    audit::set_command(args.command.name());
+    if args.read_only {
+        let inputs = match args.inputs.is_empty() {
+            true => vec![args.input.clone()],
+            false => args.inputs.clone(),
+        };
+        files::protect(&inputs);
+        let names: Vec<String> = inputs.iter().map(|p| p.display().to_string()).collect();
+        log::info(format_args!(
+            "read-only: nothing will be written to {}",
+            names.join(", ")
+        ));
+        if let Some(Err(e)) = args.append.as_deref().map(files::check) {
+            let error = Error::Usage(e.to_string());
+            eprintln!("{error}");
+            return error.exit_code();
+        }
+    }
+    if let Some(timeout) = args.timeout {
+        net::set_timeout(timeout);
+    }
+    if let Some(proxy) = &args.proxy {
+        net::set_proxy(proxy);
+    }
+    if let Some(user) = &args.user {
+        auth::set_profile(user);
+    }
+    let mut summary = Summary::new(args.command.name(), args.input.clone());
+    let result = run(&args, &mut summary);
+    summary.error = result.as_ref().err().map(Error::to_string);
+    if let Some(command) = &args.post_hook {
+        if let Err(e) = hooks::post_hook(command, &summary.to_json()) {
+            log::warn(format_args!("post-hook failed: {e}"));
+        }
+    }
+    if let Some(webhook) = &args.notify_webhook {
+        let user_agent = concat!("scrobble-fix/", env!("CARGO_PKG_VERSION"));
+        if let Err(e) = net::post_json(webhook, &summary.to_json(), user_agent) {
+            log::warn(format_args!("failed to notify webhook: {e}"));
+        }
+    }
+    match result {
+        Ok(()) => summary.exit_code(),
+        Err(e) => {
+            log::error(format_args!("{}: {e}", args.input.display()));
+            e.exit_code()
+        }
+    }
+}
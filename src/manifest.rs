@@ -0,0 +1,97 @@
+//! The archive's manifest: the SHA-256 of every log imported into it, so the same log isn't
+//! imported twice, as happens when a device's log wasn't cleared after the last sync.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, TimeZone};
+use scrobble_fix::sha256;
+
+use crate::{files, log};
+
+/// The manifest of the archive at `archive`, beside it: `archive.log.manifest`.
+pub fn path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(".manifest");
+    path.into()
+}
+
+/// Logs imported by earlier runs.
+pub struct Manifest {
+    path: PathBuf,
+    /// When each log was imported, by its hash.
+    imported: HashMap<String, i64>,
+    lines: String,
+    /// Held while the manifest is loaded, so two runs can't import the same log.
+    _lock: files::Lock,
+}
+
+impl Manifest {
+    /// Load the manifest of the archive at `archive`, which has one line per imported log:
+    /// `sha256\timported at\tname`, the time in seconds since the epoch.
+    pub fn load(archive: &Path) -> io::Result<Self> {
+        let path = path(archive);
+        let lock = files::lock(&path)?;
+        let lines = match std::fs::read_to_string(&path) {
+            Ok(lines) => lines,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut manifest = Manifest {
+            path,
+            imported: HashMap::new(),
+            lines: String::new(),
+            _lock: lock,
+        };
+        for line in lines.lines() {
+            let fields: Vec<&str> = line.splitn(3, '\t').collect();
+            let [hash, imported_at, _name] = fields[..] else {
+                continue;
+            };
+            let Ok(imported_at) = imported_at.parse() else {
+                continue;
+            };
+            manifest.imported.insert(hash.to_string(), imported_at);
+            manifest.lines.push_str(line);
+            manifest.lines.push('\n');
+        }
+        Ok(manifest)
+    }
+
+    /// The logs not imported before, saying which of the others were skipped and when they were
+    /// imported.
+    pub fn unseen(&self, logs: Vec<(String, String)>) -> Vec<(String, String)> {
+        logs.into_iter()
+            .filter(|(name, log)| {
+                let hash = sha256::hex_digest(log.as_bytes());
+                let Some(&imported_at) = self.imported.get(&hash) else {
+                    return true;
+                };
+                let when = Local
+                    .timestamp_opt(imported_at, 0)
+                    .single()
+                    .map_or(imported_at.to_string(), |time| {
+                        time.format("%Y-%m-%d %H:%M").to_string()
+                    });
+                log::warn(format_args!(
+                    "{name}: skipping, the same log was imported on {when} (sha256 {hash})"
+                ));
+                false
+            })
+            .collect()
+    }
+
+    /// Record the logs as imported now, and save the manifest.
+    pub fn record(&mut self, logs: &[(String, String)]) -> io::Result<()> {
+        let now = Local::now().timestamp();
+        for (name, log) in logs {
+            let hash = sha256::hex_digest(log.as_bytes());
+            if self.imported.insert(hash.clone(), now).is_none() {
+                let name = name.replace(['\t', '\n'], " ");
+                self.lines.push_str(&format!("{hash}\t{now}\t{name}\n"));
+            }
+        }
+        files::replace(&self.path, &self.lines)
+    }
+}
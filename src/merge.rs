@@ -0,0 +1,349 @@
+//! Merging newly fixed scrobbles into a long-lived master log.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::Scrobble;
+
+/// Outcome of appending scrobbles to a master log.
+#[derive(Debug)]
+pub struct Appended {
+    /// The master log's scrobbles followed by the new ones.
+    pub scrobbles: Vec<Scrobble>,
+    /// Number of scrobbles that were not already in the master log.
+    pub added: usize,
+    /// Number of scrobbles skipped because the master log already had them.
+    pub duplicates: usize,
+}
+
+/// Sort scrobbles by timestamp. Scrobbles sharing one, like an album whose plays were all
+/// corrected to the same time, are kept together by album and in track order.
+pub fn sort(scrobbles: &mut [Scrobble]) {
+    scrobbles.sort_by(|a, b| {
+        a.timestamp
+            .cmp(&b.timestamp)
+            .then_with(|| a.album.cmp(&b.album))
+            .then_with(|| a.track_position.cmp(&b.track_position))
+    });
+}
+
+/// Which of two records of the same play (by [`Scrobble::fingerprint`]) to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DuplicatePolicy {
+    /// The one already there.
+    #[default]
+    KeepFirst,
+    /// The one coming in, in the place of the first.
+    KeepLast,
+    /// The one with the most complete metadata, with any gaps in it filled from the other.
+    Merge,
+}
+
+impl std::str::FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-first" => Ok(DuplicatePolicy::KeepFirst),
+            "keep-last" => Ok(DuplicatePolicy::KeepLast),
+            "merge" => Ok(DuplicatePolicy::Merge),
+            other => Err(format!("unknown duplicate policy: {other}")),
+        }
+    }
+}
+
+/// How many of the metadata fields a log can leave out a record has.
+fn completeness(scrobble: &Scrobble) -> usize {
+    [
+        !scrobble.album.is_empty(),
+        scrobble.track_position.is_some(),
+        scrobble.song_duration > 0,
+        scrobble.track_id.is_some(),
+        !scrobble.artist_mbids.is_empty(),
+        scrobble.release_mbid.is_some(),
+        scrobble.album_artist().is_some(),
+    ]
+    .into_iter()
+    .filter(|&known| known)
+    .count()
+}
+
+/// The more complete of two records of one play, with what it lacks taken from the other.
+fn merge(first: Scrobble, second: Scrobble) -> Scrobble {
+    let (mut kept, other) = match completeness(&second) > completeness(&first) {
+        true => (second, first),
+        false => (first, second),
+    };
+    if kept.album.is_empty() {
+        kept.album = other.album;
+    }
+    kept.track_position = kept.track_position.or(other.track_position);
+    if kept.song_duration == 0 {
+        kept.song_duration = other.song_duration;
+    }
+    kept.track_id = kept.track_id.or(other.track_id);
+    if kept.artist_mbids.is_empty() {
+        kept.artist_mbids = other.artist_mbids;
+    }
+    kept.release_mbid = kept.release_mbid.or(other.release_mbid);
+    if kept.extras.is_empty() {
+        kept.extras = other.extras;
+    }
+    kept
+}
+
+/// Append the scrobbles the master log doesn't already contain (by
+/// [`Scrobble::fingerprint`]), optionally [`sort`]ing them.
+pub fn append(master: Vec<Scrobble>, new: Vec<Scrobble>, sort: bool) -> Appended {
+    append_with(master, new, sort, DuplicatePolicy::KeepFirst)
+}
+
+/// Like [`append`], settling each record the master log already has by `policy`.
+pub fn append_with(
+    master: Vec<Scrobble>,
+    new: Vec<Scrobble>,
+    sort: bool,
+    policy: DuplicatePolicy,
+) -> Appended {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (index, scrobble) in master.iter().enumerate() {
+        seen.entry(scrobble.fingerprint()).or_insert(index);
+    }
+    let mut scrobbles = master;
+    let (mut added, mut duplicates) = (0, 0);
+    for scrobble in new {
+        let Some(&index) = seen.get(&scrobble.fingerprint()) else {
+            seen.insert(scrobble.fingerprint(), scrobbles.len());
+            scrobbles.push(scrobble);
+            added += 1;
+            continue;
+        };
+        duplicates += 1;
+        match policy {
+            DuplicatePolicy::KeepFirst => {}
+            DuplicatePolicy::KeepLast => scrobbles[index] = scrobble,
+            DuplicatePolicy::Merge => scrobbles[index] = merge(scrobbles[index].clone(), scrobble),
+        }
+    }
+    if sort {
+        self::sort(&mut scrobbles);
+    }
+    Appended {
+        scrobbles,
+        added,
+        duplicates,
+    }
+}
+
+/// Fewest records in a row two logs must share to have logged the same session.
+pub const MIN_SESSION: usize = 3;
+
+/// Most the gap between two records may differ from one log to the other within a session, as
+/// when one of them was reconstructed.
+const SESSION_DRIFT: i64 = 60;
+
+/// A run of incoming records logging the same session as a run of existing ones, at timestamps
+/// `offset` seconds later: the same tracks in the same order, at overlapping times, so not a
+/// second listen. Two devices' logs of it, or a log copied before it was cleared and fixed
+/// differently since, would otherwise both be kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlap {
+    pub existing: Range<usize>,
+    pub incoming: Range<usize>,
+    pub offset: i64,
+}
+
+/// The sessions `incoming` shares with `existing`, in incoming order. Runs logged at just the
+/// same times are left to the [`DuplicatePolicy`].
+pub fn overlaps(existing: &[Scrobble], incoming: &[Scrobble]) -> Vec<Overlap> {
+    let key = |scrobble: &Scrobble| Scrobble::fingerprint_of(&scrobble.artist, &scrobble.track, 0);
+    let mut positions: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, scrobble) in existing.iter().enumerate() {
+        positions.entry(key(scrobble)).or_default().push(index);
+    }
+    let offset =
+        |i: usize, j: usize| incoming[i].timestamp.timestamp() - existing[j].timestamp.timestamp();
+    let run = |i: usize, j: usize| {
+        let length = (0..)
+            .take_while(|&k| {
+                i + k < incoming.len()
+                    && j + k < existing.len()
+                    && key(&incoming[i + k]) == key(&existing[j + k])
+                    && (offset(i + k, j + k) - offset(i, j)).abs() <= SESSION_DRIFT
+            })
+            .count();
+        let last = &existing[j + length - 1];
+        let span = last.timestamp.timestamp() + i64::from(last.song_duration)
+            - existing[j].timestamp.timestamp();
+        let same_times = (0..length).all(|k| offset(i + k, j + k) == 0);
+        (length >= MIN_SESSION && offset(i, j).abs() < span && !same_times).then_some(Overlap {
+            existing: j..j + length,
+            incoming: i..i + length,
+            offset: offset(i, j),
+        })
+    };
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < incoming.len() {
+        let candidates = positions
+            .get(&key(&incoming[i]))
+            .map_or(&[][..], Vec::as_slice);
+        match candidates
+            .iter()
+            .filter_map(|&j| run(i, j))
+            .max_by_key(|overlap| overlap.incoming.len())
+        {
+            Some(overlap) => {
+                i = overlap.incoming.end;
+                found.push(overlap);
+            }
+            None => i += 1,
+        }
+    }
+    found
+}
+
+/// Which log's records of a session both logged to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverlapPolicy {
+    /// The ones already there.
+    #[default]
+    KeepFirst,
+    /// The ones coming in.
+    KeepLast,
+    /// Both, as if they were different listens.
+    KeepBoth,
+}
+
+impl std::str::FromStr for OverlapPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-first" => Ok(OverlapPolicy::KeepFirst),
+            "keep-last" => Ok(OverlapPolicy::KeepLast),
+            "keep-both" => Ok(OverlapPolicy::KeepBoth),
+            other => Err(format!("unknown overlap policy: {other}")),
+        }
+    }
+}
+
+/// Settle every session `incoming` shares with `existing` as `decide` says, leaving out the
+/// records of it not kept from whichever log they're in. Returns the two logs left to
+/// [`append_with`], and how many sessions were found.
+pub fn resolve_overlaps(
+    existing: Vec<Scrobble>,
+    incoming: Vec<Scrobble>,
+    mut decide: impl FnMut(&Overlap, &[Scrobble], &[Scrobble]) -> OverlapPolicy,
+) -> (Vec<Scrobble>, Vec<Scrobble>, usize) {
+    let found = overlaps(&existing, &incoming);
+    let (mut drop_existing, mut drop_incoming) =
+        (vec![false; existing.len()], vec![false; incoming.len()]);
+    for overlap in &found {
+        match decide(overlap, &existing, &incoming) {
+            OverlapPolicy::KeepFirst => drop_incoming[overlap.incoming.clone()].fill(true),
+            OverlapPolicy::KeepLast => drop_existing[overlap.existing.clone()].fill(true),
+            OverlapPolicy::KeepBoth => {}
+        }
+    }
+    let kept = |scrobbles: Vec<Scrobble>, dropped: Vec<bool>| {
+        scrobbles
+            .into_iter()
+            .zip(dropped)
+            .filter(|(_, dropped)| !dropped)
+            .map(|(scrobble, _)| scrobble)
+            .collect()
+    };
+    (
+        kept(existing, drop_existing),
+        kept(incoming, drop_incoming),
+        found.len(),
+    )
+}
+
+#[test]
+fn append_skips_known_records() {
+    let line = "Low\tThings We Lost in the Fire\tSunflower\t1\t275\tL\t1699413807\t";
+    let master = vec![Scrobble::new(line).unwrap()];
+    let new = vec![Scrobble::new(line).unwrap()];
+    let appended = append(master, new, false);
+    assert_eq!((appended.added, appended.duplicates), (0, 1));
+    assert_eq!(appended.scrobbles.len(), 1);
+}
+
+#[test]
+fn settle_duplicates() {
+    let bare = Scrobble::new("low		Breaker		187	L	1699413807	").unwrap();
+    let mut tagged = Scrobble::new("Low	Drums and Guns	Breaker	5	0	L	1699413807	").unwrap();
+    tagged.track_id = Some("a1b2".to_string());
+    let settle = |policy| {
+        let appended = append_with(vec![bare.clone()], vec![tagged.clone()], false, policy);
+        assert_eq!((appended.added, appended.duplicates), (0, 1));
+        appended.scrobbles.into_iter().next().unwrap()
+    };
+    assert_eq!(settle(DuplicatePolicy::KeepFirst).artist, "low");
+    assert_eq!(settle(DuplicatePolicy::KeepLast).artist, "Low");
+    let merged = settle(DuplicatePolicy::Merge);
+    assert_eq!(
+        (merged.artist.as_str(), merged.track_id.as_deref()),
+        ("Low", Some("a1b2"))
+    );
+    assert_eq!(merged.song_duration, 187);
+    assert!("keep-both".parse::<DuplicatePolicy>().is_err());
+}
+
+#[test]
+fn settle_overlapping_sessions() {
+    let record = |track: &str, timestamp: i64| {
+        Scrobble::new(&format!(
+            "Low\tDrums and Guns\t{track}\t1\t200\tL\t{timestamp}\t"
+        ))
+        .unwrap()
+    };
+    let session = |start: i64| -> Vec<Scrobble> {
+        ["Pretty People", "Belarus", "Breaker", "Dragonfly"]
+            .iter()
+            .enumerate()
+            .map(|(i, track)| record(track, start + 200 * i as i64))
+            .collect()
+    };
+    let existing = [vec![record("Sandinista", 1699400000)], session(1699413807)].concat();
+    // The same session logged with a clock 5 minutes behind, and listened to again later.
+    let incoming = [session(1699413507), session(1699420000)].concat();
+    let found = overlaps(&existing, &incoming);
+    assert_eq!(
+        found,
+        [Overlap {
+            existing: 1..5,
+            incoming: 0..4,
+            offset: -300
+        }]
+    );
+    assert!(overlaps(&existing, &existing).is_empty());
+
+    let settle = |policy| resolve_overlaps(existing.clone(), incoming.clone(), |_, _, _| policy);
+    let (first, last, count) = settle(OverlapPolicy::KeepFirst);
+    assert_eq!((first.len(), last.len(), count), (5, 4, 1));
+    assert_eq!(last[0].timestamp.timestamp(), 1699420000);
+    let (first, last, _) = settle(OverlapPolicy::KeepLast);
+    assert_eq!((first.len(), last.len()), (1, 8));
+    let (first, last, _) = settle(OverlapPolicy::KeepBoth);
+    assert_eq!((first.len(), last.len()), (5, 8));
+}
+
+#[test]
+fn sort_ties_by_album_and_track() {
+    let scrobbles = [
+        "Low\tDrums and Guns\tBelarus\t6\t192\tL\t1699413807\t",
+        "Low\tC'mon\tTry to Sleep\t1\t230\tL\t1699413000\t",
+        "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t",
+        "Bedhead\tBeheaded\tLepidoptera\t1\t283\tL\t1699413807\t",
+    ];
+    let mut scrobbles: Vec<Scrobble> = scrobbles.map(|s| Scrobble::new(s).unwrap()).into();
+    sort(&mut scrobbles);
+    let tracks: Vec<&str> = scrobbles.iter().map(|s| s.track.as_str()).collect();
+    assert_eq!(
+        tracks,
+        ["Try to Sleep", "Lepidoptera", "Breaker", "Belarus"]
+    );
+}
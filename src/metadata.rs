@@ -0,0 +1,519 @@
+//! Fixing inconsistent artist, album, and track names, and durations in the wrong unit.
+
+use crate::pipeline::Fixer;
+use crate::podcast::Classifier;
+use crate::{Rating, Scrobble};
+
+/// Longest artist, album or track name kept whole, in characters; nothing real comes close, so
+/// longer ones are garbage from a corrupted log.
+pub const MAX_FIELD_LENGTH: usize = 512;
+
+/// Make a record's names safe to submit: control characters are taken out (tabs and line
+/// breaks become spaces) and names over [`MAX_FIELD_LENGTH`] are cut short. Returns a
+/// description of each change made.
+pub fn sanitize(scrobble: &mut Scrobble) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (name, field) in [
+        ("artist", &mut scrobble.artist),
+        ("album", &mut scrobble.album),
+        ("track", &mut scrobble.track),
+    ] {
+        let controls = field.chars().filter(|c| c.is_control()).count();
+        if controls > 0 {
+            *field = field
+                .chars()
+                .filter_map(|c| match c {
+                    '\t' | '\n' | '\r' => Some(' '),
+                    c if c.is_control() => None,
+                    c => Some(c),
+                })
+                .collect();
+            changes.push(format!(
+                "removed {controls} control characters from the {name}"
+            ));
+        }
+        let length = field.chars().count();
+        if length > MAX_FIELD_LENGTH {
+            *field = field.chars().take(MAX_FIELD_LENGTH).collect();
+            changes.push(format!(
+                "cut the {name} from {length} to {MAX_FIELD_LENGTH} characters"
+            ));
+        }
+    }
+    changes
+}
+
+/// Longest duration taken to be in seconds. Longer ones are common in logs from players that
+/// write milliseconds, and rare otherwise (a DJ mix, an audiobook chapter).
+pub const MAX_PLAUSIBLE_DURATION: u32 = 3 * 60 * 60;
+
+/// What unit a log's durations are in.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DurationUnit {
+    /// Milliseconds if most durations are over [`MAX_PLAUSIBLE_DURATION`] seconds, otherwise
+    /// seconds.
+    #[default]
+    Auto,
+    /// Seconds, as the format says.
+    Seconds,
+    Milliseconds,
+}
+
+impl std::str::FromStr for DurationUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(DurationUnit::Auto),
+            "s" => Ok(DurationUnit::Seconds),
+            "ms" => Ok(DurationUnit::Milliseconds),
+            other => Err(format!("unknown duration unit: {other}")),
+        }
+    }
+}
+
+/// Guess the unit of a log's durations: milliseconds if more than half of the non-zero ones are
+/// implausibly long in seconds.
+pub fn detect_duration_unit(scrobbles: &[Scrobble]) -> DurationUnit {
+    detect_unit(scrobbles.iter())
+}
+
+fn detect_unit<'a>(scrobbles: impl Iterator<Item = &'a Scrobble>) -> DurationUnit {
+    let durations = scrobbles.map(|s| s.song_duration).filter(|&d| d > 0);
+    let (known, long) = durations.fold((0, 0), |(known, long), duration| {
+        (
+            known + 1,
+            long + usize::from(duration > MAX_PLAUSIBLE_DURATION),
+        )
+    });
+    match long * 2 > known {
+        true => DurationUnit::Milliseconds,
+        false => DurationUnit::Seconds,
+    }
+}
+
+/// Converts durations logged in milliseconds to seconds.
+#[derive(Debug, Clone, Default)]
+pub struct DurationFixer {
+    pub unit: DurationUnit,
+    /// Artists left out of guessing the unit, since their episodes run long.
+    pub podcasts: Classifier,
+}
+
+impl Fixer for DurationFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        let unit = match self.unit {
+            DurationUnit::Auto => detect_unit(
+                scrobbles
+                    .iter()
+                    .filter(|s| !self.podcasts.is_podcast_artist(&s.artist)),
+            ),
+            unit => unit,
+        };
+        if unit == DurationUnit::Milliseconds {
+            for scrobble in &mut scrobbles {
+                scrobble.song_duration = scrobble.song_duration.saturating_add(500) / 1000;
+            }
+        }
+        Ok(scrobbles)
+    }
+}
+
+/// Moves titles an encoder shifted into the album field back into the track field.
+///
+/// Records with an album but no track get the album as their track, and no album. If they have
+/// no artist either, a title like `Artist - Title` is split between the two.
+#[derive(Debug, Clone, Default)]
+pub struct ShiftedTitleFixer;
+
+impl Fixer for ShiftedTitleFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        for scrobble in &mut scrobbles {
+            if !scrobble.track.is_empty() || scrobble.album.is_empty() {
+                continue;
+            }
+            let title = std::mem::take(&mut scrobble.album);
+            match title.split_once(" - ") {
+                Some((artist, track)) if scrobble.artist.is_empty() => {
+                    scrobble.artist = artist.trim().to_string();
+                    scrobble.track = track.trim().to_string();
+                }
+                _ => scrobble.track = title,
+            }
+        }
+        Ok(scrobbles)
+    }
+}
+
+/// Words that introduce a featured artist, each with the space after it.
+const FEATURING: [&str; 5] = ["featuring ", "feat. ", "feat ", "ft. ", "ft "];
+
+/// What to do with an artist credit like `Artist feat. Guest`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeaturingPolicy {
+    /// Credit just the main artist, and add `(feat. Guest)` to the track, as Last.fm does.
+    Move,
+    /// Credit just the main artist, and forget the guest.
+    Strip,
+}
+
+impl std::str::FromStr for FeaturingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "move" => Ok(FeaturingPolicy::Move),
+            "strip" => Ok(FeaturingPolicy::Strip),
+            other => Err(format!("unknown featuring policy: {other}")),
+        }
+    }
+}
+
+/// Where a featuring credit starts in a name, and where its guest's name starts.
+fn featuring_at(name: &str) -> Option<(usize, usize)> {
+    name.char_indices().find_map(|(i, c)| {
+        if !matches!(c, ' ' | '(' | '[') {
+            return None;
+        }
+        let rest = i + c.len_utf8();
+        FEATURING.iter().find_map(|marker| {
+            name.get(rest..rest + marker.len())
+                .filter(|word| word.eq_ignore_ascii_case(marker))
+                .map(|_| (i, rest + marker.len()))
+        })
+    })
+}
+
+/// Split `Artist feat. Guest` (or `Artist (ft. Guest)`) into the artist and the guest.
+pub fn split_featuring(artist: &str) -> Option<(&str, &str)> {
+    let (start, guest) = featuring_at(artist)?;
+    let main = artist[..start].trim_end();
+    let guest = artist[guest..].trim_end_matches([')', ']']).trim();
+    match main.is_empty() || guest.is_empty() {
+        true => None,
+        false => Some((main, guest)),
+    }
+}
+
+/// Takes featured artists out of the artist field.
+#[derive(Debug, Clone)]
+pub struct FeaturingFixer {
+    pub policy: FeaturingPolicy,
+}
+
+impl Fixer for FeaturingFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        for scrobble in &mut scrobbles {
+            let Some((main, guest)) = split_featuring(&scrobble.artist) else {
+                continue;
+            };
+            let (main, guest) = (main.to_string(), guest.to_string());
+            // Leave tracks that already credit someone alone, rather than credit them twice.
+            if self.policy == FeaturingPolicy::Move && featuring_at(&scrobble.track).is_none() {
+                scrobble.track = format!("{} (feat. {guest})", scrobble.track);
+            }
+            scrobble.artist = main;
+        }
+        Ok(scrobbles)
+    }
+}
+
+/// Words title case leaves in lowercase, except at the start of a field.
+pub const SMALL_WORDS: [&str; 15] = [
+    "a", "an", "and", "as", "at", "but", "by", "for", "in", "nor", "of", "on", "or", "the", "to",
+];
+
+/// How to capitalize artist, album, and track names.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CasePolicy {
+    /// Leave names as logged.
+    #[default]
+    Keep,
+    /// Capitalize each word and lowercase the rest of it, except [`SMALL_WORDS`] in the middle.
+    Title,
+    /// Capitalize every word, as Last.fm's style guide asks, leaving the rest of each word alone
+    /// so stylized names like `JPEGMAFIA` survive.
+    LastFm,
+}
+
+impl std::str::FromStr for CasePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(CasePolicy::Keep),
+            "title" => Ok(CasePolicy::Title),
+            "lastfm" => Ok(CasePolicy::LastFm),
+            other => Err(format!("unknown case policy: {other}")),
+        }
+    }
+}
+
+/// Recapitalizes artist, album, and track names.
+#[derive(Debug, Clone, Default)]
+pub struct CaseFixer {
+    pub policy: CasePolicy,
+    /// Words always written exactly like this, wherever they appear (matched ignoring case).
+    pub exceptions: Vec<String>,
+}
+
+impl CaseFixer {
+    /// One word under the policy; `first` is the field's first word.
+    fn word(&self, word: &str, first: bool) -> String {
+        if let Some(exception) = self
+            .exceptions
+            .iter()
+            .find(|exception| exception.to_lowercase() == word.to_lowercase())
+        {
+            return exception.clone();
+        }
+        let small = SMALL_WORDS.contains(&word.to_lowercase().as_str());
+        if self.policy == CasePolicy::Title && small && !first {
+            return word.to_lowercase();
+        }
+        let mut cased = String::new();
+        let mut capital = true;
+        for c in word.chars() {
+            match (c.is_alphabetic(), capital, self.policy) {
+                (true, true, _) => cased.extend(c.to_uppercase()),
+                (true, false, CasePolicy::Title) => cased.extend(c.to_lowercase()),
+                _ => cased.push(c),
+            }
+            // Capitalize again after a hyphen, slash, or opening bracket or quote, as in
+            // `Jay-Z` or `(Remix)`, but not after an apostrophe.
+            capital = match c {
+                '-' | '/' | '(' | '[' | '"' => true,
+                c if c.is_alphanumeric() || c == '\'' => false,
+                _ => capital,
+            };
+        }
+        cased
+    }
+
+    /// A whole name under the policy.
+    pub fn name(&self, name: &str) -> String {
+        if self.policy == CasePolicy::Keep {
+            return name.to_string();
+        }
+        name.split(' ')
+            .enumerate()
+            .map(|(i, word)| self.word(word, i == 0))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+impl Fixer for CaseFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        for scrobble in &mut scrobbles {
+            for field in [
+                &mut scrobble.artist,
+                &mut scrobble.album,
+                &mut scrobble.track,
+            ] {
+                *field = self.name(field);
+            }
+        }
+        Ok(scrobbles)
+    }
+}
+
+/// Rates plays again by how much of the track was heard, where Rockbox rates one listened once
+/// half of it has played.
+///
+/// A play is heard until the next one starts, so only a record followed by one less than its
+/// duration later gets a new rating: `L` if the gap is at least `threshold` of the duration,
+/// `S` if not. Longer gaps could be a track heard to the end or a pause, so those are left as
+/// they are, as are the last record and any sharing a timestamp with the next.
+#[derive(Debug, Clone, Copy)]
+pub struct RatingFixer {
+    /// The part of a track that has to be heard, from 0 to 1.
+    pub threshold: f64,
+}
+
+impl RatingFixer {
+    /// Seconds of the track at `index` played before the next one started, when that's known.
+    pub fn played(scrobbles: &[Scrobble], index: usize) -> Option<u32> {
+        let (scrobble, next) = (&scrobbles[index], scrobbles.get(index + 1)?);
+        let gap = (next.timestamp - scrobble.timestamp).num_seconds();
+        let gap = u32::try_from(gap).ok().filter(|&gap| gap > 0)?;
+        (gap < scrobble.song_duration).then_some(gap)
+    }
+}
+
+impl Fixer for RatingFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        for index in 0..scrobbles.len() {
+            let Some(played) = Self::played(&scrobbles, index) else {
+                continue;
+            };
+            let heard = f64::from(played) / f64::from(scrobbles[index].song_duration);
+            scrobbles[index].rating = match heard >= self.threshold {
+                true => Rating::Listened,
+                false => Rating::Skipped,
+            };
+        }
+        Ok(scrobbles)
+    }
+}
+
+#[test]
+fn sanitize_fields() {
+    let mut scrobble =
+        Scrobble::new("Low\tDrums\u{1}and Guns\tBreaker\t5\t187\tL\t1699413807\t").unwrap();
+    scrobble.track = format!("Breaker\n{}", "!".repeat(600));
+    let changes = sanitize(&mut scrobble);
+    assert_eq!(scrobble.album, "Drumsand Guns");
+    assert!(scrobble.track.starts_with("Breaker !!"));
+    assert_eq!(scrobble.track.chars().count(), MAX_FIELD_LENGTH);
+    assert_eq!(changes.len(), 3);
+    assert!(sanitize(&mut scrobble).is_empty());
+}
+
+#[test]
+fn move_featured_artists() {
+    assert_eq!(
+        split_featuring("Danny Brown feat. Purity Ring"),
+        Some(("Danny Brown", "Purity Ring"))
+    );
+    assert_eq!(
+        split_featuring("Gorillaz (Ft. De La Soul)"),
+        Some(("Gorillaz", "De La Soul"))
+    );
+    assert_eq!(split_featuring("Left Hand Feather"), None);
+    let line = |artist: &str, track: &str| {
+        Scrobble::new(&format!("{artist}\tXXX\t{track}\t1\t100\tL\t1699413807\t")).unwrap()
+    };
+    let scrobbles = vec![
+        line("Danny Brown featuring Purity Ring", "25 Bucks"),
+        line(
+            "JPEGMAFIA ft. Denzel Curry",
+            "BALD! REMIX (feat. Denzel Curry)",
+        ),
+    ];
+    let moved = FeaturingFixer {
+        policy: FeaturingPolicy::Move,
+    }
+    .fix(scrobbles)
+    .unwrap();
+    let names: Vec<(&str, &str)> = moved
+        .iter()
+        .map(|s| (s.artist.as_str(), s.track.as_str()))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            ("Danny Brown", "25 Bucks (feat. Purity Ring)"),
+            ("JPEGMAFIA", "BALD! REMIX (feat. Denzel Curry)")
+        ]
+    );
+}
+
+#[test]
+fn case_policies() {
+    let title = CaseFixer {
+        policy: CasePolicy::Title,
+        exceptions: vec!["JPEGMAFIA".to_string(), "deadmau5".to_string()],
+    };
+    assert_eq!(title.name("the lord of THE rings"), "The Lord of the Rings");
+    assert_eq!(
+        title.name("jay-z - don't (dj mix)"),
+        "Jay-Z - Don't (Dj Mix)"
+    );
+    assert_eq!(
+        title.name("jpegmafia and DEADMAU5"),
+        "JPEGMAFIA and deadmau5"
+    );
+    let lastfm = CaseFixer {
+        policy: CasePolicy::LastFm,
+        exceptions: Vec::new(),
+    };
+    assert_eq!(
+        lastfm.name("songs of the iPod age"),
+        "Songs Of The IPod Age"
+    );
+    assert_eq!(lastfm.name("McCartney III"), "McCartney III");
+    assert_eq!(CaseFixer::default().name("sTaY"), "sTaY");
+}
+
+#[test]
+fn convert_millisecond_durations() {
+    let line = |duration: u32| {
+        Scrobble::new(&format!(
+            "Low\tC'mon\tNothing but Heart\t9\t{duration}\tL\t1699413807\t"
+        ))
+        .unwrap()
+    };
+    let seconds = vec![line(480), line(0), line(12_000)];
+    assert_eq!(detect_duration_unit(&seconds), DurationUnit::Seconds);
+    let milliseconds = vec![line(479_500), line(0), line(187_000), line(90)];
+    assert_eq!(
+        detect_duration_unit(&milliseconds),
+        DurationUnit::Milliseconds
+    );
+    let durations = |unit, scrobbles| -> Vec<u32> {
+        let fixer = DurationFixer {
+            unit,
+            ..DurationFixer::default()
+        };
+        let fixed = fixer.fix(scrobbles).unwrap();
+        fixed.iter().map(|s| s.song_duration).collect()
+    };
+    assert_eq!(
+        durations(DurationUnit::Auto, milliseconds.clone()),
+        [480, 0, 187, 0]
+    );
+    assert_eq!(
+        durations(DurationUnit::Seconds, milliseconds),
+        [479_500, 0, 187_000, 90]
+    );
+    assert_eq!(durations(DurationUnit::Milliseconds, seconds), [0, 0, 12]);
+}
+
+#[test]
+fn move_shifted_titles() {
+    let scrobbles = [
+        "Radiolab\tThe Cathedral\t\t\t1800\tL\t1699413807\t",
+        "\tAccidental Tech Podcast - 563: Cheap Shots\t\t\t7200\tL\t1699415607\t",
+        "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699422807\t",
+        "Low\t\t\t\t187\tS\t1699423000\t",
+    ]
+    .map(|line| Scrobble::new(line).unwrap());
+    let fixed = ShiftedTitleFixer.fix(scrobbles.into()).unwrap();
+    let fields: Vec<[&str; 3]> = fixed
+        .iter()
+        .map(|s| [s.artist.as_str(), s.album.as_str(), s.track.as_str()])
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            ["Radiolab", "", "The Cathedral"],
+            ["Accidental Tech Podcast", "", "563: Cheap Shots"],
+            ["Low", "Drums and Guns", "Breaker"],
+            ["Low", "", ""],
+        ]
+    );
+}
+
+#[test]
+fn rate_by_time_heard() {
+    let scrobbles: Vec<Scrobble> = [
+        "Low\tDrums and Guns\tBreaker\t5\t200\tL\t1699413800\t",
+        "Low\tDrums and Guns\tBelarus\t6\t200\tS\t1699413920\t",
+        "Low\tDrums and Guns\tHatchet\t7\t200\tS\t1699414100\t",
+        "Low\tDrums and Guns\tHandsome Man\t8\t200\tL\t1699414400\t",
+        "Low\tDrums and Guns\tAlways Fade\t9\t200\tS\t1699414400\t",
+    ]
+    .iter()
+    .map(|line| Scrobble::new(line).unwrap())
+    .collect();
+    assert_eq!(RatingFixer::played(&scrobbles, 0), Some(120));
+    let ratings: Vec<String> = RatingFixer { threshold: 0.8 }
+        .fix(scrobbles)
+        .unwrap()
+        .iter()
+        .map(|scrobble| scrobble.rating.to_string())
+        .collect();
+    // Heard 60% and 90%; then a gap past the end, a shared timestamp, and the last play.
+    assert_eq!(ratings, ["S", "L", "S", "L", "S"]);
+}
@@ -0,0 +1,166 @@
+//! Reading a large log, like an archive merged over years, without copying it into memory.
+//!
+//! The file is mapped read-only, so the kernel pages it in as it's read; [`Mapped::chunks`]
+//! hands out its lines a chunk at a time, telling the kernel (on Linux) it can let go of the
+//! chunks parsed already, so a multi-gigabyte log never needs more than a chunk of memory at once
+//! beyond the scrobbles read from it. Without `mmap`, on systems other than Unix, the file is read
+//! whole.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Bytes parsed before the pages holding them are let go of.
+pub const CHUNK: usize = 16 << 20;
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::c_void;
+
+    pub const PROT_READ: i32 = 1;
+    pub const MAP_PRIVATE: i32 = 2;
+    pub const MADV_SEQUENTIAL: i32 = 2;
+    #[cfg(target_os = "linux")]
+    pub const MADV_DONTNEED: i32 = 4;
+    #[cfg(target_os = "linux")]
+    pub const SC_PAGESIZE: i32 = 30;
+
+    extern "C" {
+        pub fn mmap(
+            address: *mut c_void,
+            length: usize,
+            protection: i32,
+            flags: i32,
+            fd: i32,
+            // `off_t`, as wide as a pointer where this is built.
+            offset: isize,
+        ) -> *mut c_void;
+        pub fn munmap(address: *mut c_void, length: usize) -> i32;
+        pub fn madvise(address: *mut c_void, length: usize, advice: i32) -> i32;
+        pub fn sysconf(name: i32) -> std::ffi::c_long;
+    }
+}
+
+/// A file's contents, mapped into memory.
+pub struct Mapped {
+    #[cfg(unix)]
+    address: *mut std::ffi::c_void,
+    #[cfg(unix)]
+    length: usize,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+}
+
+impl Mapped {
+    /// Map the file at `path`.
+    #[cfg(unix)]
+    pub fn open(path: &Path) -> io::Result<Mapped> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let length = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::OutOfMemory, "too large to map"))?;
+        if length == 0 {
+            // Nothing can be mapped; an empty slice needs no memory.
+            return Ok(Mapped {
+                address: std::ptr::null_mut(),
+                length,
+            });
+        }
+        // SAFETY: a new private, read-only mapping of an open file, which stays valid after the
+        // file is closed and is only unmapped on drop.
+        let address = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                length,
+                sys::PROT_READ,
+                sys::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if address as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: advice about the range just mapped; it changes nothing if it's ignored.
+        unsafe { sys::madvise(address, length, sys::MADV_SEQUENTIAL) };
+        Ok(Mapped { address, length })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(path: &Path) -> io::Result<Mapped> {
+        let mut data = Vec::new();
+        io::Read::read_to_end(&mut File::open(path)?, &mut data)?;
+        Ok(Mapped { data })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        #[cfg(unix)]
+        match self.length {
+            0 => &[],
+            // SAFETY: the mapping is `length` readable bytes until drop. A file truncated by
+            // someone else meanwhile is as undefined as with any mapping; logs are appended to.
+            _ => unsafe { std::slice::from_raw_parts(self.address.cast(), self.length) },
+        }
+        #[cfg(not(unix))]
+        &self.data
+    }
+
+    /// The file as text, failing if it isn't UTF-8.
+    pub fn text(&self) -> io::Result<&str> {
+        std::str::from_utf8(self.bytes()).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("not UTF-8 at byte {}", e.valid_up_to()),
+            )
+        })
+    }
+
+    /// `text`, a slice of [`Mapped::text`], in chunks of [`CHUNK`] bytes at line ends, letting go
+    /// of each chunk's memory as the next one is handed out. Text from anywhere else is chunked
+    /// the same way but left alone.
+    pub fn chunks<'a>(&'a self, text: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let mut parsed: Option<&str> = None;
+        scrobble_fix::line_chunks(text, CHUNK).inspect(move |chunk| {
+            if let Some(parsed) = parsed.replace(chunk) {
+                self.release(parsed);
+            }
+        })
+    }
+
+    /// Tell the kernel the whole pages within `text` aren't needed now, if it's a slice of the
+    /// mapping.
+    #[cfg(target_os = "linux")]
+    fn release(&self, text: &str) {
+        let mapping = self.address as usize..self.address as usize + self.length;
+        let slice = text.as_ptr() as usize..text.as_ptr() as usize + text.len();
+        if slice.start < mapping.start || slice.end > mapping.end {
+            return;
+        }
+        // SAFETY: sysconf only reads a setting.
+        let page = match unsafe { sys::sysconf(sys::SC_PAGESIZE) } {
+            size if size > 0 => size as usize,
+            _ => return,
+        };
+        let start = slice.start.next_multiple_of(page);
+        let end = slice.end / page * page;
+        if end > start {
+            // SAFETY: the pages are inside the read-only private file mapping, so dropping
+            // them only means they're read from the file again if anything still looks at them.
+            unsafe { sys::madvise(start as *mut _, end - start, sys::MADV_DONTNEED) };
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn release(&self, _text: &str) {}
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.length > 0 {
+            // SAFETY: the mapping made in `open`, which nothing borrows any more.
+            unsafe { sys::munmap(self.address, self.length) };
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! MusicBrainz recording lookups, for filling in missing track ids.
+//!
+//! Documented here:
+//! - <https://musicbrainz.org/doc/MusicBrainz_API/Search>
+
+use crate::{json, url, Scrobble};
+
+/// MusicBrainz asks every client to identify itself.
+pub const USER_AGENT: &str = concat!(
+    "scrobble-fix/",
+    env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/djanatyn/scrobble-fix )"
+);
+
+/// Search results scoring below this aren't trusted as a match.
+pub const MIN_SCORE: f64 = 90.0;
+
+/// Quote a value for a Lucene query.
+fn phrase(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The recording search for a scrobble's artist, track, and (when known) album.
+pub fn search_url(scrobble: &Scrobble) -> String {
+    let mut query = format!(
+        "recording:{} AND artist:{}",
+        phrase(&scrobble.track),
+        phrase(&scrobble.artist)
+    );
+    if !scrobble.album.is_empty() {
+        query.push_str(&format!(" AND release:{}", phrase(&scrobble.album)));
+    }
+    format!(
+        "https://musicbrainz.org/ws/2/recording/?{}",
+        url::query([("query", query.as_str()), ("fmt", "json"), ("limit", "1")])
+    )
+}
+
+/// A recording's id with the ids of its credited artists and the release it was matched on.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Recording {
+    pub id: String,
+    pub artist_ids: Vec<String>,
+    pub release_id: Option<String>,
+    /// How long the recording is, in seconds, when MusicBrainz knows.
+    pub length: Option<u32>,
+}
+
+/// The lookup of a known recording, including its artists and releases.
+pub fn lookup_url(id: &str) -> String {
+    format!(
+        "https://musicbrainz.org/ws/2/recording/{}?{}",
+        url::encode(id),
+        url::query([("inc", "artist-credits releases"), ("fmt", "json")])
+    )
+}
+
+/// Read a recording object, preferring the release titled `album` (case-insensitively), or the
+/// first one listed.
+fn parse_recording(recording: &json::Value, album: &str) -> Option<Recording> {
+    let id = recording.get("id")?.as_str()?.to_string();
+    let artist_ids = recording
+        .get("artist-credit")
+        .and_then(json::Value::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|credit| credit.get("artist")?.get("id")?.as_str())
+        .map(str::to_string)
+        .collect();
+    let releases = recording
+        .get("releases")
+        .and_then(json::Value::as_array)
+        .unwrap_or_default();
+    let title = |release: &&json::Value| {
+        release
+            .get("title")
+            .and_then(json::Value::as_str)
+            .is_some_and(|title| title.to_lowercase() == album.to_lowercase())
+    };
+    let release_id = releases
+        .iter()
+        .find(title)
+        .or(releases.first())
+        .and_then(|release| release.get("id")?.as_str())
+        .map(str::to_string);
+    // In milliseconds.
+    let length = recording
+        .get("length")
+        .and_then(json::Value::as_f64)
+        .map(|length| (length / 1000.0).round() as u32);
+    Some(Recording {
+        id,
+        artist_ids,
+        release_id,
+        length,
+    })
+}
+
+/// The best-scoring recording in a search response, if it scores at least [`MIN_SCORE`].
+pub fn best_recording(response: &str, album: &str) -> Result<Option<Recording>, String> {
+    let response = json::parse(response)?;
+    let recordings = response
+        .get("recordings")
+        .and_then(json::Value::as_array)
+        .ok_or("search response has no recordings")?;
+    Ok(recordings
+        .iter()
+        .filter(|recording| recording.get("score").and_then(json::Value::as_f64) >= Some(MIN_SCORE))
+        .find_map(|recording| parse_recording(recording, album)))
+}
+
+/// The recording in a lookup response.
+pub fn recording(response: &str, album: &str) -> Result<Recording, String> {
+    parse_recording(&json::parse(response)?, album)
+        .ok_or("lookup response has no recording id".into())
+}
+
+/// Lookup identity: artist, track, and album, case-folded with whitespace collapsed.
+pub fn cache_key(scrobble: &Scrobble) -> [String; 3] {
+    let normalize = |field: &str| {
+        field
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    [
+        normalize(&scrobble.artist),
+        normalize(&scrobble.track),
+        normalize(&scrobble.album),
+    ]
+}
+
+#[test]
+fn pick_confident_recordings() {
+    let response = r#"{"recordings":[{"id":"a1b2","score":95,"length":213400,
+        "artist-credit":[{"name":"Low","artist":{"id":"ar1"}}],
+        "releases":[{"id":"r1","title":"Live"},{"id":"r2","title":"The Great Destroyer"}]}]}"#;
+    let recording = best_recording(response, "the great destroyer")
+        .unwrap()
+        .unwrap();
+    assert_eq!(recording.id, "a1b2");
+    assert_eq!(recording.artist_ids, ["ar1"]);
+    assert_eq!(recording.release_id.as_deref(), Some("r2"));
+    assert_eq!(recording.length, Some(213));
+    let response = r#"{"recordings":[{"id":"a1b2","score":40}]}"#;
+    assert_eq!(best_recording(response, "").unwrap(), None);
+}
@@ -0,0 +1,249 @@
+//! HTTP requests, made by running curl so no TLS stack needs to be linked in.
+//!
+//! Every request goes through [`request`], which spaces out requests to services with rate limits
+//! and retries failures that may be temporary (connection trouble, 429, and 5xx responses) with
+//! exponential backoff.
+//!
+//! curl uses the proxy in `$https_proxy`, `$http_proxy` or `$ALL_PROXY` (leaving out the hosts
+//! in `$NO_PROXY`) unless told another with [`set_proxy`].
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::log;
+
+/// Least time between requests to a host.
+const RATE_LIMITS: [(&str, Duration); 3] = [
+    // One request per second.
+    ("musicbrainz.org", Duration::from_secs(1)),
+    // Five requests per second, averaged over five minutes.
+    ("ws.audioscrobbler.com", Duration::from_millis(200)),
+    ("api.listenbrainz.org", Duration::from_millis(100)),
+];
+
+/// Tries made of a request before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry; each later retry waits twice as long as the one before.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// curl exit codes worth retrying: the host couldn't be resolved or connected to, the transfer
+/// timed out, the TLS handshake failed, or the connection broke.
+const TRANSIENT_CURL_ERRORS: [i32; 7] = [6, 7, 28, 35, 52, 55, 56];
+
+/// Seconds each try may take, with 0 for no limit.
+static TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+/// The proxy of `--proxy`, for every request.
+static PROXY: Mutex<Option<String>> = Mutex::new(None);
+
+/// When each rate-limited host was last sent a request.
+static LAST_REQUEST: Mutex<Vec<(&str, Instant)>> = Mutex::new(Vec::new());
+
+/// Give up on a try after `seconds`, for every request from now on.
+pub fn set_timeout(seconds: u64) {
+    TIMEOUT.store(seconds, Ordering::Relaxed);
+}
+
+/// Send every request from now on through the proxy at `url`, like `socks5h://127.0.0.1:9050`.
+pub fn set_proxy(url: &str) {
+    *PROXY.lock().unwrap_or_else(|e| e.into_inner()) = Some(url.to_string());
+}
+
+/// The proxy to tell curl about for a request to `url`: the one set, or else `$HTTP_PROXY` for a
+/// plain `http://` URL, which curl only reads in lowercase.
+fn proxy(url: &str) -> Option<String> {
+    let set = PROXY.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let variable = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+    set.or_else(
+        || match url.starts_with("http://") && variable("http_proxy").is_none() {
+            true => variable("HTTP_PROXY"),
+            false => None,
+        },
+    )
+}
+
+/// The host part of a URL.
+fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let authority = authority.rsplit('@').next().unwrap_or(authority);
+    authority.split(':').next().unwrap_or(authority)
+}
+
+/// Sleep until the rate limit for the URL's host allows another request.
+fn wait_turn(url: &str) {
+    let host = host(url);
+    let Some(&(limited, interval)) = RATE_LIMITS
+        .iter()
+        .find(|(limited, _)| host == *limited || host.ends_with(&format!(".{limited}")))
+    else {
+        return;
+    };
+    let mut last = LAST_REQUEST.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, at)) = last.iter().find(|(host, _)| *host == limited) {
+        thread::sleep(interval.saturating_sub(at.elapsed()));
+    }
+    last.retain(|(host, _)| *host != limited);
+    last.push((limited, Instant::now()));
+}
+
+/// Why a try failed.
+struct Failure {
+    message: String,
+    /// Whether trying again might work.
+    transient: bool,
+}
+
+/// Make one try at a request, returning the HTTP status and response body.
+fn attempt(
+    url: &str,
+    user_agent: &str,
+    body: Option<(&str, &str)>,
+) -> Result<(u16, Vec<u8>), Failure> {
+    let mut curl = Command::new("curl");
+    curl.args(["--silent", "--show-error", "--location"])
+        .args(["--write-out", "\n%{http_code}"])
+        .args(["--user-agent", user_agent]);
+    match TIMEOUT.load(Ordering::Relaxed) {
+        0 => {}
+        seconds => {
+            curl.args(["--max-time", &seconds.to_string()]);
+        }
+    }
+    if let Some(proxy) = proxy(url) {
+        curl.args(["--proxy", &proxy]);
+    }
+    if let Some((content_type, _)) = body {
+        curl.args(["--header", &format!("Content-Type: {content_type}")])
+            .args(["--data-binary", "@-"]);
+    }
+    let failure = |message| Failure {
+        message,
+        transient: false,
+    };
+    let stdin = match body {
+        Some(_) => Stdio::piped(),
+        None => Stdio::null(),
+    };
+    let mut child = curl
+        .arg(url)
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| failure(format!("failed to run curl: {e}")))?;
+    if let Some((_, body)) = body {
+        child
+            .stdin
+            .take()
+            .expect("piped stdin")
+            .write_all(body.as_bytes())
+            .map_err(|e| failure(format!("{url}: {e}")))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| failure(format!("{url}: {e}")))?;
+    if !output.status.success() {
+        return Err(Failure {
+            message: format!("{url}: {}", String::from_utf8_lossy(&output.stderr).trim()),
+            transient: output
+                .status
+                .code()
+                .is_some_and(|code| TRANSIENT_CURL_ERRORS.contains(&code)),
+        });
+    }
+    let mut body = output.stdout;
+    let split = body.iter().rposition(|&byte| byte == b'\n').unwrap_or(0);
+    let status = String::from_utf8_lossy(&body[split..])
+        .trim()
+        .parse()
+        .map_err(|_| failure(format!("{url}: no HTTP status")))?;
+    body.truncate(split);
+    Ok((status, body))
+}
+
+/// Make a request, retrying temporary failures, and return the response body.
+///
+/// With `fail`, HTTP error statuses are failures; otherwise the body is returned whatever the
+/// status, once any retries are used up.
+fn request(
+    url: &str,
+    user_agent: &str,
+    body: Option<(&str, &str)>,
+    fail: bool,
+) -> Result<Vec<u8>, String> {
+    let mut backoff = FIRST_BACKOFF;
+    let mut attempts = 1;
+    loop {
+        wait_turn(url);
+        log::debug(format_args!(
+            "{} {} (try {attempts})",
+            if body.is_some() { "POST" } else { "GET" },
+            // Leave out the query, which can hold tokens.
+            url.split('?').next().unwrap_or(url)
+        ));
+        let result = attempt(url, user_agent, body);
+        let retry = match &result {
+            Ok((status, _)) if *status == 429 || *status >= 500 => {
+                Some(format!("{url}: HTTP {status}"))
+            }
+            Ok(_) => None,
+            Err(failure) if failure.transient => Some(failure.message.clone()),
+            Err(_) => None,
+        };
+        match retry {
+            Some(reason) if attempts < MAX_ATTEMPTS => {
+                log::warn(format_args!("{reason}; retrying in {}s", backoff.as_secs()));
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempts += 1;
+            }
+            _ => {
+                return match result {
+                    Ok((status, _)) if fail && status >= 400 => {
+                        Err(format!("{url}: HTTP {status}"))
+                    }
+                    Ok((_, body)) => Ok(body),
+                    Err(failure) => Err(failure.message),
+                };
+            }
+        }
+    }
+}
+
+/// A response body as text.
+fn text(url: &str, body: Vec<u8>) -> Result<String, String> {
+    String::from_utf8(body).map_err(|e| format!("{url}: {e}"))
+}
+
+/// Fetch a URL, returning the response body. HTTP errors are reported as failures.
+pub fn get(url: &str, user_agent: &str) -> Result<String, String> {
+    text(url, request(url, user_agent, None, true)?)
+}
+
+/// Fetch a URL, returning the response body whatever the HTTP status, like [`post_form`].
+pub fn get_api(url: &str, user_agent: &str) -> Result<String, String> {
+    text(url, request(url, user_agent, None, false)?)
+}
+
+/// Fetch a file that needn't be text, like a binary. HTTP errors are reported as failures.
+pub fn download(url: &str, user_agent: &str) -> Result<Vec<u8>, String> {
+    request(url, user_agent, None, true)
+}
+
+/// POST a JSON document to a URL, discarding the response body.
+pub fn post_json(url: &str, body: &str, user_agent: &str) -> Result<(), String> {
+    request(url, user_agent, Some(("application/json", body)), true).map(drop)
+}
+
+/// POST a form, returning the response body whatever the HTTP status, since APIs like Last.fm's
+/// explain their errors in it.
+pub fn post_form(url: &str, body: &str, user_agent: &str) -> Result<String, String> {
+    let form = ("application/x-www-form-urlencoded", body);
+    text(url, request(url, user_agent, Some(form), false)?)
+}
@@ -0,0 +1,45 @@
+//! Fixes applied to a whole log, in order.
+
+use crate::timestamps::TimestampFixer;
+use crate::Scrobble;
+
+/// One transformation over every scrobble in a log.
+pub trait Fixer {
+    fn fix(&self, scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String>;
+}
+
+/// An ordered list of fixers.
+pub struct Pipeline {
+    fixers: Vec<Box<dyn Fixer>>,
+}
+
+impl Default for Pipeline {
+    /// Just the timestamp fix, with the default cutoff, offset, and policy.
+    fn default() -> Self {
+        Pipeline::new().with(TimestampFixer::default())
+    }
+}
+
+impl Pipeline {
+    /// A pipeline that leaves scrobbles untouched.
+    pub fn new() -> Self {
+        Pipeline { fixers: Vec::new() }
+    }
+
+    /// Add a fixer to run after the existing ones.
+    pub fn with(mut self, fixer: impl Fixer + 'static) -> Self {
+        self.fixers.push(Box::new(fixer));
+        self
+    }
+
+    /// Run every fixer in order, numbering the scrobbles first, each with its
+    /// [`index`](Scrobble::index) in `scrobbles`.
+    pub fn run(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        for (index, scrobble) in scrobbles.iter_mut().enumerate() {
+            scrobble.index = Some(index);
+        }
+        self.fixers
+            .iter()
+            .try_fold(scrobbles, |scrobbles, fixer| fixer.fix(scrobbles))
+    }
+}
@@ -0,0 +1,132 @@
+//! Telling podcast episodes from music, so they can be kept out of music charts.
+//!
+//! Rockbox scrobbles whatever it plays, episodes included. An episode is by an artist on a list
+//! of podcasts, or runs long without a track position, as no album track does.
+
+use std::collections::HashSet;
+
+use crate::pipeline::Fixer;
+use crate::Scrobble;
+
+/// The comment tagging a podcast episode, above its record.
+pub const TAG: &str = "# podcast";
+
+/// Tells podcast episodes from music.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Classifier {
+    /// Artists whose every record is an episode, in lowercase.
+    artists: HashSet<String>,
+    /// Records without a track position at least this many seconds long are episodes.
+    pub min_duration: Option<u32>,
+}
+
+impl Classifier {
+    /// A classifier for the artists of a list, one per line, ignoring blank lines and `#`
+    /// comments, and for records at least `min_duration` seconds long without a track position.
+    pub fn new(artists: &str, min_duration: Option<u32>) -> Self {
+        Classifier {
+            artists: artists
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_lowercase)
+                .collect(),
+            min_duration,
+        }
+    }
+
+    /// Whether the artist is on the list, in any case.
+    pub fn is_podcast_artist(&self, artist: &str) -> bool {
+        self.artists.contains(&artist.to_lowercase())
+    }
+
+    pub fn is_podcast(&self, scrobble: &Scrobble) -> bool {
+        self.is_podcast_artist(&scrobble.artist)
+            || (scrobble.track_position.is_none()
+                && self
+                    .min_duration
+                    .is_some_and(|min| scrobble.song_duration >= min))
+    }
+}
+
+/// Whether a record has been tagged as an episode.
+pub fn is_tagged(scrobble: &Scrobble) -> bool {
+    scrobble.comments.iter().any(|comment| comment == TAG)
+}
+
+/// What to do with podcast episodes.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PodcastPolicy {
+    /// Tag each with a [`TAG`] comment.
+    #[default]
+    Tag,
+    /// Leave them out.
+    Drop,
+    /// Leave out everything else.
+    Only,
+}
+
+impl std::str::FromStr for PodcastPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tag" => Ok(PodcastPolicy::Tag),
+            "drop" => Ok(PodcastPolicy::Drop),
+            "only" => Ok(PodcastPolicy::Only),
+            other => Err(format!("unknown podcast policy: {other}")),
+        }
+    }
+}
+
+/// Applies a [`PodcastPolicy`] to the episodes a [`Classifier`] finds.
+#[derive(Debug, Clone)]
+pub struct PodcastFixer {
+    pub classifier: Classifier,
+    pub policy: PodcastPolicy,
+}
+
+impl Fixer for PodcastFixer {
+    fn fix(&self, scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        let fixed = scrobbles.into_iter().filter_map(|mut scrobble| {
+            let podcast = self.classifier.is_podcast(&scrobble);
+            match self.policy {
+                PodcastPolicy::Tag if podcast && !is_tagged(&scrobble) => {
+                    scrobble.comments.push(TAG.to_string())
+                }
+                PodcastPolicy::Drop if podcast => return None,
+                PodcastPolicy::Only if !podcast => return None,
+                _ => {}
+            }
+            Some(scrobble)
+        });
+        Ok(fixed.collect())
+    }
+}
+
+#[test]
+fn classify_podcasts() {
+    let classifier = Classifier::new("# shows\nThe Memory Palace\n\n", Some(1800));
+    let record = |line: &str| Scrobble::new(line).unwrap();
+    let scrobbles = vec![
+        record("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t"),
+        record("the memory palace\t\tThe Foot\t\t912\tL\t1699414000\t"),
+        record("99% Invisible\t\tThe Ice King\t\t2460\tL\t1699415000\t"),
+        record("Wendy Carlos\tSonic Seasonings\tSpring\t1\t1985\tL\t1699418000\t"),
+    ];
+    let podcasts: Vec<bool> = scrobbles.iter().map(|s| classifier.is_podcast(s)).collect();
+    assert_eq!(podcasts, [false, true, true, false]);
+
+    let fixer = |policy| PodcastFixer {
+        classifier: classifier.clone(),
+        policy,
+    };
+    let tagged = fixer(PodcastPolicy::Tag).fix(scrobbles.clone()).unwrap();
+    let tagged = fixer(PodcastPolicy::Tag).fix(tagged).unwrap();
+    assert_eq!(tagged[1].comments, [TAG]);
+    assert!(!is_tagged(&tagged[0]));
+    let music = fixer(PodcastPolicy::Drop).fix(scrobbles.clone()).unwrap();
+    assert_eq!(music.len(), 2);
+    let episodes = fixer(PodcastPolicy::Only).fix(scrobbles).unwrap();
+    assert_eq!(episodes[1].track, "The Ice King");
+}
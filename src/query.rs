@@ -0,0 +1,356 @@
+//! A small filter language for picking scrobbles out of an archive, like
+//! `artist = 'Low' AND year = 2007`.
+//!
+//! Comparisons are `FIELD OP VALUE`, combined with `AND`, `OR`, `NOT` and parentheses (`AND`
+//! binds tighter than `OR`), or `&&`, `||` and `!` as in a script. Text fields are `artist`,
+//! `album`, `track` and `rating` (`L` or `S`), compared with `=`, `!=`, or `~` (contains,
+//! ignoring case; or matches, when the value is a glob with `*`, `?` or `[...]`), against a
+//! value in single or double quotes. Number fields are `duration`, `position`, `timestamp`, and
+//! the local `year`, `month`, `day` and `hour`, compared with `=`, `!=`, `<`, `<=`, `>` or
+//! `>=`. `==` is `=`.
+
+use chrono::{Datelike, Timelike};
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case, take_while},
+    character::complete::{alpha1, char, i64 as integer, multispace0, multispace1},
+    combinator::{map, map_res, value},
+    multi::many0,
+    sequence::{delimited, preceded, terminated, tuple},
+    IResult,
+};
+
+use crate::ignore::Pattern;
+use crate::Scrobble;
+
+/// A field a query can look at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Artist,
+    Album,
+    Track,
+    Rating,
+    Duration,
+    Position,
+    Timestamp,
+    Year,
+    Month,
+    Day,
+    Hour,
+}
+
+impl std::str::FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "artist" => Ok(Field::Artist),
+            "album" => Ok(Field::Album),
+            "track" => Ok(Field::Track),
+            "rating" => Ok(Field::Rating),
+            "duration" => Ok(Field::Duration),
+            "position" => Ok(Field::Position),
+            "timestamp" => Ok(Field::Timestamp),
+            "year" => Ok(Field::Year),
+            "month" => Ok(Field::Month),
+            "day" => Ok(Field::Day),
+            "hour" => Ok(Field::Hour),
+            other => Err(format!("unknown field: {other}")),
+        }
+    }
+}
+
+impl Field {
+    fn is_text(self) -> bool {
+        matches!(
+            self,
+            Field::Artist | Field::Album | Field::Track | Field::Rating
+        )
+    }
+
+    fn text(self, scrobble: &Scrobble) -> String {
+        match self {
+            Field::Artist => scrobble.artist.clone(),
+            Field::Album => scrobble.album.clone(),
+            Field::Track => scrobble.track.clone(),
+            _ => scrobble.rating.to_string(),
+        }
+    }
+
+    fn number(self, scrobble: &Scrobble) -> Option<i64> {
+        let timestamp = scrobble.timestamp;
+        match self {
+            Field::Duration => Some(scrobble.song_duration.into()),
+            Field::Position => scrobble.track_position.map(i64::from),
+            Field::Timestamp => Some(timestamp.timestamp()),
+            Field::Year => Some(timestamp.year().into()),
+            Field::Month => Some(timestamp.month().into()),
+            Field::Day => Some(timestamp.day().into()),
+            Field::Hour => Some(timestamp.hour().into()),
+            _ => None,
+        }
+    }
+}
+
+/// How a field is compared with a value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Contains,
+}
+
+/// What a field is compared with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Text(String),
+    Number(i64),
+    /// A glob `~` matches the whole text with, ignoring case.
+    Glob(Pattern),
+}
+
+/// A parsed query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Compare(Field, Op, Literal),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+}
+
+impl Query {
+    pub fn matches(&self, scrobble: &Scrobble) -> bool {
+        match self {
+            Query::And(a, b) => a.matches(scrobble) && b.matches(scrobble),
+            Query::Or(a, b) => a.matches(scrobble) || b.matches(scrobble),
+            Query::Not(query) => !query.matches(scrobble),
+            Query::Compare(field, _, Literal::Glob(glob)) => glob.matches(&field.text(scrobble)),
+            Query::Compare(field, op, Literal::Text(wanted)) => {
+                let text = field.text(scrobble);
+                match op {
+                    Op::Equal => text == *wanted,
+                    Op::NotEqual => text != *wanted,
+                    _ => text.to_lowercase().contains(&wanted.to_lowercase()),
+                }
+            }
+            Query::Compare(field, op, Literal::Number(wanted)) => {
+                let Some(number) = field.number(scrobble) else {
+                    return false;
+                };
+                match op {
+                    Op::Equal => number == *wanted,
+                    Op::NotEqual => number != *wanted,
+                    Op::Less => number < *wanted,
+                    Op::LessOrEqual => number <= *wanted,
+                    Op::Greater => number > *wanted,
+                    _ => number >= *wanted,
+                }
+            }
+        }
+    }
+
+    /// The query with each `~` value that has a glob's `*`, `?` or `[` in it compiled as a glob;
+    /// done once it's parsed, so a bad glob can say what's wrong with it.
+    fn compile(self) -> Result<Query, String> {
+        Ok(match self {
+            Query::And(a, b) => Query::And(Box::new(a.compile()?), Box::new(b.compile()?)),
+            Query::Or(a, b) => Query::Or(Box::new(a.compile()?), Box::new(b.compile()?)),
+            Query::Not(query) => Query::Not(Box::new(query.compile()?)),
+            Query::Compare(field, Op::Contains, Literal::Text(text)) if is_glob(&text) => {
+                let glob = Pattern::glob(&text).map_err(|e| {
+                    let e = e.strip_prefix(&format!("{text}: ")).unwrap_or(&e);
+                    format!("{e} in glob '{text}'")
+                })?;
+                Query::Compare(field, Op::Contains, Literal::Glob(glob))
+            }
+            query => query,
+        })
+    }
+
+    /// Check that each comparison suits its field, so a query can't quietly match nothing.
+    fn check(&self) -> Result<(), String> {
+        match self {
+            Query::And(a, b) | Query::Or(a, b) => a.check().and_then(|_| b.check()),
+            Query::Not(query) => query.check(),
+            Query::Compare(field, op, literal) => {
+                let text = !matches!(literal, Literal::Number(_));
+                if field.is_text() != text {
+                    let wanted = if field.is_text() { "text" } else { "a number" };
+                    return Err(format!("{field:?} must be compared with {wanted}").to_lowercase());
+                }
+                let allowed = match text {
+                    true => matches!(op, Op::Equal | Op::NotEqual | Op::Contains),
+                    false => *op != Op::Contains,
+                };
+                match allowed {
+                    true => Ok(()),
+                    false => Err(format!("{op:?} doesn't apply to {field:?}").to_lowercase()),
+                }
+            }
+        }
+    }
+}
+
+fn op(input: &str) -> IResult<&str, Op> {
+    alt((
+        value(Op::NotEqual, tag("!=")),
+        value(Op::LessOrEqual, tag("<=")),
+        value(Op::GreaterOrEqual, tag(">=")),
+        value(Op::Equal, tag("==")),
+        value(Op::Equal, tag("=")),
+        value(Op::Less, tag("<")),
+        value(Op::Greater, tag(">")),
+        value(Op::Contains, tag("~")),
+    ))(input)
+}
+
+fn literal(input: &str) -> IResult<&str, Literal> {
+    let text = |text: &str| Literal::Text(text.to_string());
+    alt((
+        map(
+            delimited(char('\''), take_while(|c| c != '\''), char('\'')),
+            text,
+        ),
+        map(
+            delimited(char('"'), take_while(|c| c != '"'), char('"')),
+            text,
+        ),
+        map(integer, Literal::Number),
+    ))(input)
+}
+
+/// Whether `~` takes a value as a glob.
+fn is_glob(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+fn comparison(input: &str) -> IResult<&str, Query> {
+    map(
+        tuple((
+            map_res(alpha1, str::parse::<Field>),
+            delimited(multispace0, op, multispace0),
+            literal,
+        )),
+        |(field, op, literal)| Query::Compare(field, op, literal),
+    )(input)
+}
+
+fn unary(input: &str) -> IResult<&str, Query> {
+    alt((
+        map(
+            preceded(
+                alt((
+                    terminated(tag_no_case("NOT"), multispace1),
+                    terminated(tag("!"), multispace0),
+                )),
+                unary,
+            ),
+            |query| Query::Not(Box::new(query)),
+        ),
+        delimited(
+            terminated(char('('), multispace0),
+            or,
+            preceded(multispace0, char(')')),
+        ),
+        comparison,
+    ))(input)
+}
+
+/// Operands joined by a keyword, or the symbol for it, grouped from the left.
+fn joined<'a>(
+    keyword: &'static str,
+    symbol: &'static str,
+    operand: fn(&'a str) -> IResult<&'a str, Query>,
+    join: fn(Box<Query>, Box<Query>) -> Query,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Query> {
+    move |input| {
+        let (rest, first) = operand(input)?;
+        let (rest, others) = many0(preceded(
+            alt((
+                delimited(multispace1, tag_no_case(keyword), multispace1),
+                delimited(multispace0, tag(symbol), multispace0),
+            )),
+            operand,
+        ))(rest)?;
+        let query = others
+            .into_iter()
+            .fold(first, |a, b| join(Box::new(a), Box::new(b)));
+        Ok((rest, query))
+    }
+}
+
+fn and(input: &str) -> IResult<&str, Query> {
+    joined("AND", "&&", unary, Query::And)(input)
+}
+
+fn or(input: &str) -> IResult<&str, Query> {
+    joined("OR", "||", and, Query::Or)(input)
+}
+
+impl std::str::FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let query = match delimited(multispace0, or, multispace0)(s) {
+            Ok(("", query)) => query,
+            Ok((rest, _)) => Err(format!("can't understand the query from: {rest}"))?,
+            Err(e) => Err(format!("invalid query: {e}"))?,
+        };
+        let query = query.compile()?;
+        query.check()?;
+        Ok(query)
+    }
+}
+
+/// The scrobbles a query matches.
+pub fn filter<'a>(
+    query: &'a Query,
+    scrobbles: impl IntoIterator<Item = Scrobble> + 'a,
+) -> impl Iterator<Item = Scrobble> + 'a {
+    scrobbles
+        .into_iter()
+        .filter(move |scrobble| query.matches(scrobble))
+}
+
+#[test]
+fn query_scrobbles() {
+    let scrobbles: Vec<Scrobble> = [
+        "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1173000000\t",
+        "Low\tC'mon\tTry to Sleep\t1\t230\tS\t1300000000\t",
+        "Bedhead\tBeheaded\tLepidoptera\t\t283\tL\t1173000000\t",
+    ]
+    .map(|line| Scrobble::new(line).unwrap())
+    .into();
+    let tracks = |query: &str| -> Vec<String> {
+        let query: Query = query.parse().unwrap();
+        filter(&query, scrobbles.clone()).map(|s| s.track).collect()
+    };
+    assert_eq!(tracks("artist = 'Low' AND year = 2007"), ["Breaker"]);
+    assert_eq!(
+        tracks("album ~ \"c'MON\" or NOT (rating = 'L' and position >= 1)"),
+        ["Try to Sleep", "Lepidoptera"]
+    );
+    assert_eq!(
+        tracks("duration>200 AND duration<=283"),
+        ["Try to Sleep", "Lepidoptera"]
+    );
+    assert_eq!(
+        tracks("duration > 200 && rating == \"L\" && !(artist ~ 'low*')"),
+        ["Lepidoptera"]
+    );
+    assert_eq!(
+        tracks("track ~ 'try*' || track ~ '*[ae]r'"),
+        ["Breaker", "Try to Sleep"]
+    );
+    assert!("artist = 2007".parse::<Query>().is_err());
+    assert!("year ~ 2007".parse::<Query>().is_err());
+    assert!("artist = 'Low' AND".parse::<Query>().is_err());
+    assert_eq!(
+        "track ~ 'T[w'".parse::<Query>().unwrap_err(),
+        "unclosed [ in glob 'T[w'"
+    );
+}
@@ -0,0 +1,161 @@
+//! Finding and checking the releases `self-update` installs.
+//!
+//! Each GitHub release holds a binary per platform, named by [`asset_name`], a `SHA256SUMS` file
+//! listing their SHA-256 as `sha256sum` prints it, and `SHA256SUMS.sig`, an SSH signature of that
+//! file (`ssh-keygen -Y sign -n file`). A binary is only installed if the signature is good and
+//! the binary's hash is the one listed.
+
+use crate::{json, sha256};
+
+/// The GitHub repository releases are published to.
+pub const REPOSITORY: &str = "djanatyn/scrobble-fix";
+
+/// The asset listing every binary's SHA-256.
+pub const SUMS: &str = "SHA256SUMS";
+
+/// The asset holding the SSH signature of [`SUMS`].
+pub const SIGNATURE: &str = "SHA256SUMS.sig";
+
+/// The principal and namespace releases are signed as.
+pub const SIGNER: &str = "releases@scrobble-fix";
+pub const NAMESPACE: &str = "file";
+
+/// Where GitHub describes the latest release.
+pub fn latest_url() -> String {
+    format!("https://api.github.com/repos/{REPOSITORY}/releases/latest")
+}
+
+/// The binary built for an architecture and OS, as Rust names them (`aarch64`, `linux`):
+/// `scrobble-fix-aarch64-linux`, with `.exe` on Windows.
+pub fn asset_name(arch: &str, os: &str) -> String {
+    match os {
+        "windows" => format!("scrobble-fix-{arch}-{os}.exe"),
+        _ => format!("scrobble-fix-{arch}-{os}"),
+    }
+}
+
+/// A release's `MAJOR.MINOR.PATCH`, ordered oldest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+impl std::str::FromStr for Version {
+    type Err = String;
+
+    /// `1.2.3`, or a tag like `v1.2.3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let numbers: Vec<&str> = s.strip_prefix('v').unwrap_or(s).split('.').collect();
+        let number = |part: &str| part.parse().map_err(|_| format!("bad version: {s}"));
+        match numbers[..] {
+            [major, minor, patch] => Ok(Version(number(major)?, number(minor)?, number(patch)?)),
+            _ => Err(format!("bad version: {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// A file attached to a release.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asset {
+    pub name: String,
+    /// Where to download it.
+    pub url: String,
+}
+
+/// A published release.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Release {
+    pub version: Version,
+    pub assets: Vec<Asset>,
+}
+
+impl Release {
+    /// Read GitHub's description of a release.
+    pub fn parse(response: &str) -> Result<Release, String> {
+        let response = json::parse(response)?;
+        let tag = response
+            .get("tag_name")
+            .and_then(json::Value::as_str)
+            .ok_or("release has no tag")?;
+        let assets = response
+            .get("assets")
+            .and_then(json::Value::as_array)
+            .ok_or("release has no assets")?;
+        Ok(Release {
+            version: tag.parse()?,
+            assets: assets
+                .iter()
+                .filter_map(|asset| {
+                    Some(Asset {
+                        name: asset.get("name")?.as_str()?.to_string(),
+                        url: asset.get("browser_download_url")?.as_str()?.to_string(),
+                    })
+                })
+                .collect(),
+        })
+    }
+
+    pub fn asset(&self, name: &str) -> Result<&Asset, String> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or(format!("release {} has no {name}", self.version))
+    }
+}
+
+/// The SHA-256 a `sha256sum` listing gives for `name`, in lowercase hex.
+pub fn listed_sum(sums: &str, name: &str) -> Option<String> {
+    sums.lines().find_map(|line| {
+        let (sum, file) = line.split_once(char::is_whitespace)?;
+        // A `*` marks a file hashed in binary mode.
+        let file = file.trim_start().trim_start_matches('*');
+        (file == name).then(|| sum.to_lowercase())
+    })
+}
+
+/// Check that `binary` is the `name` a (verified) `sha256sum` listing lists.
+pub fn check_sum(binary: &[u8], sums: &str, name: &str) -> Result<(), String> {
+    let listed = listed_sum(sums, name).ok_or(format!("{SUMS} doesn't list {name}"))?;
+    match sha256::hex_digest(binary) == listed {
+        true => Ok(()),
+        false => Err(format!("{name} doesn't match its SHA-256 in {SUMS}")),
+    }
+}
+
+/// An `ssh-keygen` allowed-signers file trusting `key` (like `ssh-ed25519 AAAA...`) to sign
+/// releases.
+pub fn allowed_signers(key: &str) -> String {
+    format!("{SIGNER} namespaces=\"{NAMESPACE}\" {}\n", key.trim())
+}
+
+#[test]
+fn check_release() {
+    assert_eq!("v0.10.2".parse(), Ok(Version(0, 10, 2)));
+    assert!("0.1".parse::<Version>().is_err());
+    assert!(Version(0, 10, 0) > Version(0, 9, 7));
+    assert_eq!(asset_name("aarch64", "linux"), "scrobble-fix-aarch64-linux");
+
+    let response = r#"{"tag_name": "v0.2.0", "assets": [
+        {"name": "scrobble-fix-aarch64-linux", "browser_download_url": "https://example/a"},
+        {"name": "SHA256SUMS", "browser_download_url": "https://example/sums"}]}"#;
+    let release = Release::parse(response).unwrap();
+    assert_eq!(release.version, Version(0, 2, 0));
+    assert_eq!(release.asset(SUMS).unwrap().url, "https://example/sums");
+    assert!(release.asset(SIGNATURE).is_err());
+
+    let sums = format!(
+        "{}  scrobble-fix-x86_64-linux\n{} *scrobble-fix-aarch64-linux\n",
+        sha256::hex_digest(b"x86"),
+        sha256::hex_digest(b"arm").to_uppercase()
+    );
+    assert_eq!(
+        check_sum(b"arm", &sums, "scrobble-fix-aarch64-linux"),
+        Ok(())
+    );
+    assert!(check_sum(b"x86", &sums, "scrobble-fix-aarch64-linux").is_err());
+    assert!(check_sum(b"arm", &sums, "scrobble-fix-arm-linux").is_err());
+}
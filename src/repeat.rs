@@ -0,0 +1,83 @@
+//! Splitting a record of a track left on repeat into one scrobble per play.
+//!
+//! Rockbox sometimes logs a track played over and over as one record spanning every loop, an hour
+//! long for a three-minute track. An online scrobbler would have scrobbled each loop as it
+//! finished, so knowing the track's real length, the record can be split into those scrobbles.
+
+use chrono::Duration;
+
+use crate::{Rating, Scrobble};
+
+/// How likely the split-off plays' timestamps are to be right: the loops are back to back unless
+/// the player was paused.
+pub const SPLIT_CONFIDENCE: f64 = 0.8;
+
+/// The longest a play need last to count as a scrobble, however long the track: Last.fm's rule is
+/// half the track or four minutes.
+const SCROBBLE_POINT: u32 = 4 * 60;
+
+/// How many plays a record of `duration` seconds holds for a track `length` seconds long: every
+/// whole loop, and a last partial one if it lasted long enough to scrobble.
+pub fn plays(duration: u32, length: u32) -> u32 {
+    if length == 0 {
+        return 1;
+    }
+    let partial = duration % length >= (length / 2).min(SCROBBLE_POINT);
+    (duration / length + u32::from(partial)).max(1)
+}
+
+/// Split every listened record holding two plays or more of a track whose length `length` knows
+/// into one per play, each `length` seconds after the one before, with the record's comments
+/// kept above the first and its trailing comments below the last.
+pub fn split_loops(
+    scrobbles: Vec<Scrobble>,
+    length: impl Fn(&Scrobble) -> Option<u32>,
+) -> Vec<Scrobble> {
+    let mut split = Vec::with_capacity(scrobbles.len());
+    for scrobble in scrobbles {
+        let known = length(&scrobble).filter(|_| matches!(scrobble.rating, Rating::Listened));
+        let Some(length) = known.filter(|&length| plays(scrobble.song_duration, length) > 1) else {
+            split.push(scrobble);
+            continue;
+        };
+        let count = plays(scrobble.song_duration, length);
+        let mut first = scrobble;
+        first.song_duration = length;
+        let trailing = std::mem::take(&mut first.trailing_comments);
+        for play in 0..count {
+            let mut copy = first.clone();
+            if play > 0 {
+                copy.comments.clear();
+                let timestamp = first.timestamp + Duration::seconds(i64::from(play * length));
+                copy.correct(timestamp, "loop", SPLIT_CONFIDENCE);
+            }
+            split.push(copy);
+        }
+        if let Some(last) = split.last_mut() {
+            last.trailing_comments = trailing;
+        }
+    }
+    split
+}
+
+#[test]
+fn split_repeated_track() {
+    assert_eq!(
+        (plays(3600, 180), plays(275, 180), plays(200, 180)),
+        (20, 2, 1)
+    );
+    // Long tracks scrobble after four minutes, not half.
+    assert_eq!(plays(1200 + 250, 1200), 2);
+    let mut scrobble =
+        Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t3600\tL\t1699413807\t").unwrap();
+    scrobble.comments.push("# on repeat".to_string());
+    let skipped = Scrobble::new("Low\tDrums and Guns\tBelarus\t6\t3600\tS\t1699420000\t").unwrap();
+    let split = split_loops(vec![scrobble, skipped], |_| Some(180));
+    assert_eq!(split.len(), 21);
+    assert_eq!(split[0].comments, ["# on repeat"]);
+    assert!(split[1].comments.is_empty());
+    assert_eq!(split[19].timestamp.timestamp(), 1699413807 + 19 * 180);
+    assert_eq!(split[19].song_duration, 180);
+    assert_eq!(split[19].provenance.as_ref().unwrap().rule, "loop");
+    assert_eq!(split[20].song_duration, 3600);
+}
@@ -0,0 +1,232 @@
+//! Listening digests: what was played each week or month, for keeping a listening journal.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, Days, Months, NaiveDate};
+
+use crate::{json, Rating, Scrobble};
+
+/// Artists and albums listed per period.
+pub const TOP: usize = 5;
+
+/// How long each digest covers.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Period {
+    /// Weeks starting on Monday.
+    Weekly,
+    #[default]
+    Monthly,
+}
+
+impl std::str::FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weekly" => Ok(Period::Weekly),
+            "monthly" => Ok(Period::Monthly),
+            other => Err(format!("unknown period: {other}")),
+        }
+    }
+}
+
+impl Period {
+    /// The first day of the period holding `day`, or `None` if it's out of the range of dates.
+    fn start(self, day: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Period::Weekly => {
+                day.checked_sub_days(Days::new(day.weekday().num_days_from_monday().into()))
+            }
+            Period::Monthly => day.with_day(1),
+        }
+    }
+
+    /// The first day of the next period, or `None` if it's out of the range of dates.
+    fn end(self, start: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Period::Weekly => start.checked_add_days(Days::new(7)),
+            Period::Monthly => start.checked_add_months(Months::new(1)),
+        }
+    }
+}
+
+/// What was played in one period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Digest {
+    /// The period's first day.
+    pub start: NaiveDate,
+    /// The first day after it.
+    pub end: NaiveDate,
+    /// Tracks listened to, not counting skips.
+    pub plays: usize,
+    pub skips: usize,
+    /// Total duration of the plays, in seconds.
+    pub seconds: u64,
+    /// The most played artists with their plays, most first.
+    pub top_artists: Vec<(String, usize)>,
+    /// The most played albums with their artist and plays, most first.
+    pub top_albums: Vec<(String, String, usize)>,
+}
+
+impl Digest {
+    /// The period's name, like `November 2023` or `Week of 2023-11-06`.
+    pub fn title(&self) -> String {
+        match self.end - self.start {
+            length if length.num_days() == 7 => format!("Week of {}", self.start),
+            _ => self.start.format("%B %Y").to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        json::object([
+            ("start", json::string(&self.start.to_string())),
+            ("end", json::string(&self.end.to_string())),
+            ("plays", self.plays.to_string()),
+            ("skips", self.skips.to_string()),
+            ("seconds", self.seconds.to_string()),
+            (
+                "top_artists",
+                json::array(self.top_artists.iter().map(|(artist, plays)| {
+                    json::object([
+                        ("artist", json::string(artist)),
+                        ("plays", plays.to_string()),
+                    ])
+                })),
+            ),
+            (
+                "top_albums",
+                json::array(self.top_albums.iter().map(|(artist, album, plays)| {
+                    json::object([
+                        ("artist", json::string(artist)),
+                        ("album", json::string(album)),
+                        ("plays", plays.to_string()),
+                    ])
+                })),
+            ),
+        ])
+    }
+}
+
+/// The [`TOP`] most counted keys, most first, ties in order.
+fn top<K: Ord + Clone>(counts: HashMap<K, usize>) -> Vec<(K, usize)> {
+    let mut counts: Vec<(K, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    counts.truncate(TOP);
+    counts
+}
+
+/// A digest for every period with scrobbles in it, oldest first. Days are local.
+pub fn digests(scrobbles: &[Scrobble], period: Period) -> Result<Vec<Digest>, String> {
+    let out_of_range =
+        |day: NaiveDate| format!("the period holding {day} is out of the range of dates");
+    let mut periods: BTreeMap<NaiveDate, Vec<&Scrobble>> = BTreeMap::new();
+    for scrobble in scrobbles {
+        let day = scrobble.timestamp.date_naive();
+        let start = period.start(day).ok_or_else(|| out_of_range(day))?;
+        periods.entry(start).or_default().push(scrobble);
+    }
+    periods
+        .into_iter()
+        .map(|(start, scrobbles)| {
+            let (plays, skips): (Vec<&Scrobble>, Vec<&Scrobble>) = scrobbles
+                .into_iter()
+                .partition(|scrobble| matches!(scrobble.rating, Rating::Listened));
+            let mut artists: HashMap<String, usize> = HashMap::new();
+            let mut albums: HashMap<(String, String), usize> = HashMap::new();
+            for play in &plays {
+                *artists.entry(play.artist.clone()).or_default() += 1;
+                if !play.album.is_empty() {
+                    let artist = play.album_artist().unwrap_or(&play.artist).to_string();
+                    *albums.entry((artist, play.album.clone())).or_default() += 1;
+                }
+            }
+            Ok(Digest {
+                start,
+                end: period.end(start).ok_or_else(|| out_of_range(start))?,
+                plays: plays.len(),
+                skips: skips.len(),
+                seconds: plays.iter().map(|play| u64::from(play.song_duration)).sum(),
+                top_artists: top(artists),
+                top_albums: top(albums)
+                    .into_iter()
+                    .map(|((artist, album), plays)| (artist, album, plays))
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// A table cell, with the pipes that would end it escaped.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+/// The digests as a Markdown document, a section per period.
+pub fn markdown(digests: &[Digest]) -> String {
+    let mut document = String::from("# Listening report\n");
+    for digest in digests {
+        document.push_str(&format!(
+            "\n## {}\n\n{} plays, {}h{:02}m of music",
+            digest.title(),
+            digest.plays,
+            digest.seconds / 3600,
+            digest.seconds % 3600 / 60
+        ));
+        if digest.skips > 0 {
+            document.push_str(&format!(" ({} skipped)", digest.skips));
+        }
+        document.push('\n');
+        if !digest.top_artists.is_empty() {
+            document.push_str("\n| Artist | Plays |\n|---|---:|\n");
+            for (artist, plays) in &digest.top_artists {
+                document.push_str(&format!("| {} | {plays} |\n", cell(artist)));
+            }
+        }
+        if !digest.top_albums.is_empty() {
+            document.push_str("\n| Album | Artist | Plays |\n|---|---|---:|\n");
+            for (artist, album, plays) in &digest.top_albums {
+                document.push_str(&format!(
+                    "| {} | {} | {plays} |\n",
+                    cell(album),
+                    cell(artist)
+                ));
+            }
+        }
+    }
+    document
+}
+
+#[test]
+fn monthly_and_weekly_digests() {
+    // 2023-11-08, a Wednesday, then the next Monday and December.
+    let scrobbles: Vec<Scrobble> = [
+        "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699444800\t",
+        "Low\tDrums and Guns\tBelarus\t6\t192\tL\t1699448000\t",
+        "Bedhead\tBeheaded\tLepidoptera\t1\t283\tS\t1699450000\t",
+        "Bedhead\tBeheaded\tLepidoptera\t1\t283\tL\t1699876800\t",
+        "Low|Bedhead\t\tUntitled\t\t60\tL\t1702382400\t",
+    ]
+    .map(|line| Scrobble::new(line).unwrap())
+    .into();
+    let monthly = digests(&scrobbles, Period::Monthly).unwrap();
+    assert_eq!(monthly.len(), 2);
+    assert_eq!(monthly[0].title(), "November 2023");
+    assert_eq!((monthly[0].plays, monthly[0].skips), (3, 1));
+    assert_eq!(monthly[0].seconds, 187 + 192 + 283);
+    assert_eq!(
+        monthly[0].top_artists,
+        [("Low".to_string(), 2), ("Bedhead".to_string(), 1)]
+    );
+    assert!(monthly[1].top_albums.is_empty());
+    let weekly = digests(&scrobbles, Period::Weekly).unwrap();
+    assert_eq!(weekly[0].title(), "Week of 2023-11-06");
+    assert_eq!(weekly[1].start.to_string(), "2023-11-13");
+    let document = markdown(&monthly);
+    assert!(document.contains("## November 2023\n\n3 plays, 0h11m of music (1 skipped)\n"));
+    assert!(document.contains("| Drums and Guns | Low | 2 |"));
+    assert!(document.contains("| Low\\|Bedhead | 1 |"));
+
+    let mut last = [scrobbles[0].clone()];
+    last[0].timestamp = chrono::DateTime::<chrono::Utc>::MAX_UTC.with_timezone(&chrono::Local);
+    assert!(digests(&last, Period::Monthly).is_err() && digests(&last, Period::Weekly).is_err());
+}
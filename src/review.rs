@@ -0,0 +1,137 @@
+//! Pairing records as read with the records the fixes made of them, so each correction can be
+//! accepted or rejected.
+
+use crate::Scrobble;
+
+/// A record before and after the fixes. Either side is missing when a fix dropped the record, or
+/// (a pre-hook) added one.
+#[derive(Debug)]
+pub struct Row {
+    pub original: Option<Scrobble>,
+    pub corrected: Option<Scrobble>,
+    /// Whether the corrected side is kept; a rejected row keeps the original.
+    pub accepted: bool,
+}
+
+impl Row {
+    /// Whether the fixes touched the record.
+    pub fn changed(&self) -> bool {
+        match (&self.original, &self.corrected) {
+            (Some(original), Some(corrected)) => original.to_string() != corrected.to_string(),
+            _ => true,
+        }
+    }
+
+    /// Whether either side's artist, album or track contains `query`, ignoring case.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        [&self.original, &self.corrected]
+            .into_iter()
+            .flatten()
+            .any(|scrobble| {
+                [&scrobble.artist, &scrobble.album, &scrobble.track]
+                    .iter()
+                    .any(|field| field.to_lowercase().contains(&query))
+            })
+    }
+}
+
+/// Pair each original record with its corrected form, every row accepted. `corrected` is what
+/// [`Pipeline::run`](crate::Pipeline::run) made of `original`, whose records it paired by their
+/// [`index`](Scrobble::index), so fixes can change anything about a record.
+///
+/// Rows follow the original log; a corrected record with no original (one a pre-hook wrote back
+/// counts too, as its index is lost) comes after the row of the corrected record before it.
+pub fn rows(original: Vec<Scrobble>, corrected: Vec<Scrobble>) -> Vec<Row> {
+    let mut paired: Vec<Option<Scrobble>> = original.iter().map(|_| None).collect();
+    // Unpaired corrected records, by the original index they follow (0 for the start).
+    let mut added: Vec<Vec<Scrobble>> = (0..=original.len()).map(|_| Vec::new()).collect();
+    let mut after = 0;
+    for scrobble in corrected {
+        match scrobble
+            .index
+            .filter(|&index| paired.get(index).is_some_and(Option::is_none))
+        {
+            Some(index) => {
+                paired[index] = Some(scrobble);
+                after = index + 1;
+            }
+            None => added[after].push(scrobble),
+        }
+    }
+    let row = |original, corrected| Row {
+        original,
+        corrected,
+        accepted: true,
+    };
+    let mut added = added.into_iter();
+    let mut rows: Vec<Row> = added
+        .next()
+        .into_iter()
+        .flatten()
+        .map(|scrobble| row(None, Some(scrobble)))
+        .collect();
+    for ((original, corrected), added) in original.into_iter().zip(paired).zip(added) {
+        rows.push(row(Some(original), corrected));
+        rows.extend(added.into_iter().map(|scrobble| row(None, Some(scrobble))));
+    }
+    rows
+}
+
+/// The log the review settled on: each row's corrected record if accepted, otherwise its
+/// original.
+pub fn result(rows: Vec<Row>) -> Vec<Scrobble> {
+    rows.into_iter()
+        .filter_map(|row| match row.accepted {
+            true => row.corrected,
+            false => row.original,
+        })
+        .collect()
+}
+
+#[test]
+fn pair_corrections_with_originals() {
+    use crate::timestamps::{SuspiciousPolicy, TimestampFixer};
+    use crate::{Fixer, Pipeline};
+
+    let log = [
+        "A\tB\tOld\t1\t100\tL\t978307200\t",
+        "A\tB\tNew\t2\t100\tL\t1699413807\t",
+        "A\tB\tAlso old\t3\t100\tL\t978307300\t",
+    ];
+    let original: Vec<Scrobble> = log.iter().map(|l| Scrobble::new(l).unwrap()).collect();
+    let shifted = Pipeline::default().run(original.clone()).unwrap();
+    let mut reviewed = rows(original.clone(), shifted);
+    let changed: Vec<bool> = reviewed.iter().map(Row::changed).collect();
+    assert_eq!(changed, [true, false, true]);
+    reviewed[2].accepted = false;
+    let kept: Vec<String> = result(reviewed).iter().map(|s| s.to_string()).collect();
+    assert_eq!(kept[2], original[2].to_string());
+    assert_ne!(kept[0], original[0].to_string());
+
+    let dropping = Pipeline::new().with(TimestampFixer {
+        policy: SuspiciousPolicy::Drop,
+        ..TimestampFixer::default()
+    });
+    let dropped = dropping.run(original.clone()).unwrap();
+    let reviewed = rows(original.clone(), dropped);
+    assert!(reviewed[0].corrected.is_none() && reviewed[1].corrected.is_some());
+    assert!(reviewed[0].matches("OLD") && !reviewed[1].matches("old"));
+
+    // Renamed records are still paired with the ones they came from.
+    struct Rename;
+    impl Fixer for Rename {
+        fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+            scrobbles[2].track = "Also Old".to_string();
+            Ok(scrobbles)
+        }
+    }
+    let renamed = Pipeline::new().with(Rename).run(original.clone()).unwrap();
+    let mut reviewed = rows(original.clone(), renamed);
+    assert_eq!(reviewed.len(), 3);
+    let changed: Vec<bool> = reviewed.iter().map(Row::changed).collect();
+    assert_eq!(changed, [false, false, true]);
+    reviewed[2].accepted = false;
+    let kept: Vec<String> = result(reviewed).iter().map(|s| s.track.clone()).collect();
+    assert_eq!(kept, ["Old", "New", "Also old"]);
+}
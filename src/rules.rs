@@ -0,0 +1,253 @@
+//! Rewrite rules, read from a TOML file like the one `analyze artists --fuzzy` prints:
+//!
+//! ```toml
+//! [[rule]]
+//! field = "artist"
+//! match = ["Boards Of Canada", "Boards of Canada."]
+//! replace = "Boards of Canada"
+//! ```
+//!
+//! Only this much TOML is understood: `[[rule]]` tables of `key = value` lines, where values are
+//! basic strings or arrays of them (possibly over several lines), and `#` comments.
+
+use crate::pipeline::Fixer;
+use crate::{json, Scrobble};
+
+/// Which field a rule rewrites.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Artist,
+    Album,
+    Track,
+}
+
+impl std::str::FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "artist" => Ok(Field::Artist),
+            "album" => Ok(Field::Album),
+            "track" => Ok(Field::Track),
+            other => Err(format!("unknown field: {other}")),
+        }
+    }
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Field::Artist => write!(f, "artist"),
+            Field::Album => write!(f, "album"),
+            Field::Track => write!(f, "track"),
+        }
+    }
+}
+
+/// Replace a field that is exactly one of `matches`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub field: Field,
+    pub matches: Vec<String>,
+    pub replace: String,
+    /// Line of the rules file the rule starts on.
+    pub line: usize,
+}
+
+impl Rule {
+    /// Rewrite the scrobble if the rule matches it, returning whether it did.
+    pub fn apply(&self, scrobble: &mut Scrobble) -> bool {
+        let field = match self.field {
+            Field::Artist => &mut scrobble.artist,
+            Field::Album => &mut scrobble.album,
+            Field::Track => &mut scrobble.track,
+        };
+        if !self.matches.contains(field) {
+            return false;
+        }
+        field.clone_from(&self.replace);
+        true
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let matches: Vec<String> = self.matches.iter().map(|m| json::string(m)).collect();
+        write!(
+            f,
+            "{} {} -> {}",
+            self.field,
+            matches.join(" | "),
+            json::string(&self.replace)
+        )
+    }
+}
+
+/// A line without its comment, if it has one outside a string.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let (mut quoted, mut escaped) = (false, false);
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// A rule whose keys are still being read.
+#[derive(Default)]
+struct Partial {
+    line: usize,
+    field: Option<Field>,
+    matches: Option<Vec<String>>,
+    replace: Option<String>,
+}
+
+impl Partial {
+    fn finish(self) -> Result<Rule, String> {
+        let missing = |key| format!("line {}: rule has no {key}", self.line);
+        Ok(Rule {
+            field: self.field.ok_or(missing("field"))?,
+            matches: self.matches.ok_or(missing("match"))?,
+            replace: self.replace.ok_or(missing("replace"))?,
+            line: self.line,
+        })
+    }
+}
+
+/// Parse a rules file.
+pub fn parse(text: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    let mut current: Option<Partial> = None;
+    let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+    while let Some((number, line)) = lines.next() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "[[rule]]" {
+            if let Some(rule) = current.take() {
+                rules.push(rule.finish()?);
+            }
+            current = Some(Partial {
+                line: number,
+                ..Partial::default()
+            });
+            continue;
+        }
+        let error = |message: String| format!("line {number}: {message}");
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(error("expected `key = value`".to_string()))?;
+        let rule = current
+            .as_mut()
+            .ok_or(error("expected [[rule]] first".to_string()))?;
+        let mut value = value.trim().to_string();
+        // Arrays can carry on over the following lines until they're closed.
+        while value.starts_with('[') && json::parse(&value).is_err() {
+            let (_, more) = lines.next().ok_or(error("unclosed array".to_string()))?;
+            value.push(' ');
+            value.push_str(strip_comment(more).trim());
+            // TOML allows a trailing comma; JSON doesn't.
+            if value.ends_with(']') {
+                let inner = value[..value.len() - 1].trim_end();
+                if let Some(inner) = inner.strip_suffix(',') {
+                    value = format!("{inner}]");
+                }
+            }
+        }
+        let value = json::parse(&value).map_err(error)?;
+        let string = |value: &json::Value| {
+            value
+                .as_str()
+                .map(str::to_string)
+                .ok_or(error(format!("{} must be a string", key.trim())))
+        };
+        match key.trim() {
+            "field" => rule.field = Some(string(&value)?.parse().map_err(error)?),
+            "replace" => rule.replace = Some(string(&value)?),
+            "match" => {
+                rule.matches = Some(match value.as_array() {
+                    Some(values) => values.iter().map(string).collect::<Result<_, _>>()?,
+                    None => vec![string(&value)?],
+                })
+            }
+            other => Err(error(format!("unknown key: {other}")))?,
+        }
+    }
+    if let Some(rule) = current {
+        rules.push(rule.finish()?);
+    }
+    Ok(rules)
+}
+
+/// How many records each rule rewrote, applying every rule in order to every record.
+pub fn check(rules: &[Rule], scrobbles: &mut [Scrobble]) -> Vec<usize> {
+    let mut counts = vec![0; rules.len()];
+    for scrobble in scrobbles {
+        for (rule, count) in rules.iter().zip(&mut counts) {
+            if rule.apply(scrobble) {
+                *count += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// Applies rewrite rules, in order, to every record.
+#[derive(Debug, Clone)]
+pub struct RulesFixer {
+    pub rules: Vec<Rule>,
+}
+
+impl Fixer for RulesFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        check(&self.rules, &mut scrobbles);
+        Ok(scrobbles)
+    }
+}
+
+#[test]
+fn count_rule_matches() {
+    let rules = parse(
+        r#"
+# From analyze artists --fuzzy.
+[[rule]]
+field = "artist"
+match = "Boards Of Canada"
+replace = "Boards of Canada"
+
+[[rule]] # several spellings at once
+field = "album"
+match = [
+    "Geogaddi (Remastered)",
+    "Geogaddi #2",
+]
+replace = "Geogaddi"
+
+[[rule]]
+field = "track"
+match = ["Never Played"]
+replace = "Played"
+"#,
+    )
+    .unwrap();
+    assert_eq!(rules.len(), 3);
+    assert_eq!(rules[1].line, 8);
+    assert_eq!(rules[1].matches, ["Geogaddi (Remastered)", "Geogaddi #2"]);
+    let mut scrobbles: Vec<Scrobble> = [
+        "Boards Of Canada\tGeogaddi #2\tDawn Chorus\t\t397\tL\t1700000000\t",
+        "Boards Of Canada\tGeogaddi\tJulie and Candy\t\t330\tL\t1700000400\t",
+    ]
+    .iter()
+    .map(|line| Scrobble::new(line).unwrap())
+    .collect();
+    assert_eq!(check(&rules, &mut scrobbles), [2, 1, 0]);
+    assert_eq!(scrobbles[0].album, "Geogaddi");
+    assert!(parse("field = \"artist\"").is_err());
+    assert!(parse("[[rule]]\nfield = \"artist\"\nreplace = \"x\"").is_err());
+}
@@ -0,0 +1,133 @@
+//! JSON Schemas of what scrobble-fix writes as JSON, kept in `schemas/` for tools to validate
+//! and generate code against, and a check of a document against one.
+//!
+//! [`validate`] knows the keywords these schemas use, `type`, `enum`, `minimum`, `properties`,
+//! `required`, `additionalProperties` and `items`, and ignores the rest, like `format`.
+
+use crate::json::Value;
+
+/// Each schema, by the name `schema` prints it by.
+pub const SCHEMAS: [(&str, &str); 3] = [
+    ("record", include_str!("../schemas/record.schema.json")),
+    ("summary", include_str!("../schemas/summary.schema.json")),
+    ("audit", include_str!("../schemas/audit.schema.json")),
+];
+
+/// The schema named `name`.
+pub fn get(name: &str) -> Result<&'static str, String> {
+    SCHEMAS
+        .iter()
+        .find(|(schema, _)| *schema == name)
+        .map(|(_, schema)| *schema)
+        .ok_or_else(|| {
+            let names: Vec<&str> = SCHEMAS.iter().map(|(name, _)| *name).collect();
+            format!("unknown schema: {name} ({})", names.join(", "))
+        })
+}
+
+/// Whether `value` is of the JSON type `name`.
+fn is_type(value: &Value, name: &str) -> bool {
+    match (name, value) {
+        ("null", Value::Null) => true,
+        ("boolean", Value::Bool(_)) => true,
+        ("number", Value::Number(_)) => true,
+        ("integer", Value::Number(n)) => n.fract() == 0.0,
+        ("string", Value::String(_)) => true,
+        ("array", Value::Array(_)) => true,
+        ("object", Value::Object(_)) => true,
+        _ => false,
+    }
+}
+
+/// Check `value` against `schema`, naming the first place it doesn't match, like
+/// `provenance.rule`.
+pub fn validate(schema: &Value, value: &Value) -> Result<(), String> {
+    check(schema, value, "")
+}
+
+fn check(schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+    let place = |at: &str| match at {
+        "" => "the document".to_string(),
+        at => at.to_string(),
+    };
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(name)) => vec![name],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|name| is_type(value, name)) {
+        Err(format!("{} isn't {}", place(at), types.join(" or ")))?;
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            Err(format!("{} isn't one of the values allowed", place(at)))?;
+        }
+    }
+    if let (Some(minimum), Some(n)) = (
+        schema.get("minimum").and_then(Value::as_f64),
+        value.as_f64(),
+    ) {
+        if n < minimum {
+            Err(format!("{} is less than {minimum}", place(at)))?;
+        }
+    }
+    let within = |name: &str| match at {
+        "" => name.to_string(),
+        at => format!("{at}.{name}"),
+    };
+    if let Value::Object(members) = value {
+        let properties = schema.get("properties");
+        for name in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Value::as_str)
+        {
+            if value.get(name).is_none() {
+                Err(format!("{} is missing", within(name)))?;
+            }
+        }
+        for (name, member) in members {
+            match properties.and_then(|properties| properties.get(name)) {
+                Some(property) => check(property, member, &within(name))?,
+                None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    Err(format!("{} isn't expected", within(name)))?
+                }
+                None => {}
+            }
+        }
+    }
+    if let (Some(items), Value::Array(values)) = (schema.get("items"), value) {
+        for (n, item) in values.iter().enumerate() {
+            check(items, item, &format!("{}[{n}]", place(at)))?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn validate_records() {
+    let schema = crate::json::parse(get("record").unwrap()).unwrap();
+    let mut scrobble =
+        crate::Scrobble::new("Björk\tHomogenic\tJóga\t\t305\tS\t1699413807\t").unwrap();
+    scrobble.comments = vec!["# road trip".to_string()];
+    scrobble.provenance = Some(crate::Provenance {
+        original_timestamp: scrobble.timestamp,
+        rule: "cutoff".to_string(),
+        confidence: 0.9,
+    });
+    let json = crate::json::parse(&scrobble.to_json()).unwrap();
+    assert_eq!(validate(&schema, &json), Ok(()));
+
+    let json = scrobble.to_json().replace("\"S\"", "\"X\"");
+    let error = validate(&schema, &crate::json::parse(&json).unwrap()).unwrap_err();
+    assert_eq!(error, "rating isn't one of the values allowed");
+    let json = scrobble.to_json().replace("\"cutoff\"", "1");
+    let error = validate(&schema, &crate::json::parse(&json).unwrap()).unwrap_err();
+    assert_eq!(error, "provenance.rule isn't string");
+    let json = scrobble.to_json().replace("[\"# road trip\"]", "[1]");
+    let error = validate(&schema, &crate::json::parse(&json).unwrap()).unwrap_err();
+    assert_eq!(error, "comments[0] isn't string");
+    assert!(get("records").is_err());
+}
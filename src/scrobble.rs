@@ -0,0 +1,632 @@
+use crate::grammar::{self, Span, SyntaxError};
+use crate::json;
+use crate::serialize::ScrobbleSerializer;
+use crate::timestamps::TimestampFixer;
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use nom::combinator::all_consuming;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rating {
+    Listened,
+    Skipped,
+}
+
+impl std::fmt::Display for Rating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Rating::Listened => write!(f, "L"),
+            Rating::Skipped => write!(f, "S"),
+        }
+    }
+}
+
+/// What an output made for listens does with skipped scrobbles.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SkipPolicy {
+    /// Leave them out.
+    #[default]
+    Drop,
+    /// Send them as listens.
+    Listen,
+    /// Send them as listens marked as skipped, for outputs with a way to.
+    Flag,
+}
+
+impl SkipPolicy {
+    /// Whether the scrobble is sent at all.
+    pub fn keeps(self, scrobble: &Scrobble) -> bool {
+        scrobble.rating == Rating::Listened || self != SkipPolicy::Drop
+    }
+
+    /// Whether the scrobble is sent marked as skipped.
+    pub fn flags(self, scrobble: &Scrobble) -> bool {
+        scrobble.rating == Rating::Skipped && self == SkipPolicy::Flag
+    }
+}
+
+impl std::str::FromStr for SkipPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(SkipPolicy::Drop),
+            "listen" => Ok(SkipPolicy::Listen),
+            "flag" => Ok(SkipPolicy::Flag),
+            other => Err(format!(
+                "unknown policy for skipped scrobbles: {other} (drop, listen or flag)"
+            )),
+        }
+    }
+}
+
+/// How a scrobble's timestamp came to be corrected. Not part of the log format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// The timestamp as logged.
+    pub original_timestamp: DateTime<Local>,
+    /// Which corrections were applied, in order, e.g. `shift` or `reconstruct,nudge`.
+    pub rule: String,
+    /// How likely the corrected timestamp is to be right, from 0 to 1.
+    pub confidence: f64,
+}
+
+/// Parsed scrobble record.
+#[derive(Debug, Clone)]
+pub struct Scrobble {
+    pub artist: String,
+    pub album: String,
+    pub track: String,
+    pub track_position: Option<u32>,
+    pub song_duration: u32, // seconds
+    pub rating: Rating,
+    pub timestamp: DateTime<Local>,
+    pub track_id: Option<String>,
+    /// MusicBrainz ids of the credited artists, when enriched. Not part of the log format.
+    pub artist_mbids: Vec<String>,
+    /// MusicBrainz id of the release, when enriched. Not part of the log format.
+    pub release_mbid: Option<String>,
+    /// Columns after the track id, appended by some forks of the Rockbox plugin.
+    pub extras: Vec<String>,
+    /// Everything after the timestamp's tab, as it was, when it doesn't start with a track id;
+    /// written back in place of the track id and extras, so nothing unknown is lost.
+    pub unknown_suffix: Option<String>,
+    /// `#` comment lines found just above the record in the log, without their newlines.
+    pub comments: Vec<String>,
+    /// `#` comment lines after the record, when it's the last one in the log.
+    pub trailing_comments: Vec<String>,
+    /// Set once the timestamp has been corrected.
+    pub provenance: Option<Provenance>,
+    /// Where the record was among those [`Pipeline::run`](crate::Pipeline::run) was given, kept
+    /// through the fixes so what they make of it can be paired with it. Not part of the log
+    /// format.
+    pub index: Option<usize>,
+}
+
+impl std::fmt::Display for Scrobble {
+    /// The record as [`ScrobbleSerializer::default`] writes it.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let record = ScrobbleSerializer::default()
+            .record(self)
+            .map_err(|_| std::fmt::Error)?;
+        f.write_str(&record)
+    }
+}
+
+/// Scrobbles are equal when they're the same play: when they have the same
+/// [`fingerprint`](Scrobble::fingerprint), so the same timestamp and the same artist and track
+/// once trimmed, lowercased and their whitespace collapsed. Everything else, like the album or
+/// rating, may differ.
+impl PartialEq for Scrobble {
+    fn eq(&self, other: &Self) -> bool {
+        self.identity() == other.identity()
+    }
+}
+
+impl Eq for Scrobble {}
+
+/// Hashes what [`PartialEq`] compares, so a `HashSet` of scrobbles keeps one record a play.
+impl std::hash::Hash for Scrobble {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.identity().hash(state);
+    }
+}
+
+/// Oldest first; plays at the same time by artist, then by track, as [`PartialEq`] normalizes
+/// them.
+impl Ord for Scrobble {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.identity().cmp(&other.identity())
+    }
+}
+
+impl PartialOrd for Scrobble {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A name as fingerprints compare it.
+fn normalize(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// A scrobble borrowing its text from the log it was parsed from, for reading records without
+/// copying them. [`ScrobbleRef::to_owned`] makes a [`Scrobble`] of it, to change it.
+#[derive(Debug, Clone)]
+pub struct ScrobbleRef<'a> {
+    pub artist: &'a str,
+    pub album: &'a str,
+    pub track: &'a str,
+    pub track_position: Option<u32>,
+    pub song_duration: u32, // seconds
+    pub rating: Rating,
+    pub timestamp: DateTime<Local>,
+    pub track_id: Option<&'a str>,
+    pub extras: Vec<&'a str>,
+    pub unknown_suffix: Option<&'a str>,
+    pub comments: Vec<&'a str>,
+    pub trailing_comments: Vec<&'a str>,
+}
+
+impl std::fmt::Display for ScrobbleRef<'_> {
+    /// The record as [`Scrobble`] writes it.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\t{}\t{}\t", self.artist, self.album, self.track)?;
+        if let Some(position) = self.track_position {
+            write!(f, "{position}")?;
+        }
+        write!(
+            f,
+            "\t{}\t{}\t{}\t{}",
+            self.song_duration,
+            self.rating,
+            self.timestamp.timestamp(),
+            self.track_id.unwrap_or_default()
+        )?;
+        if let Some(suffix) = self.unknown_suffix {
+            write!(
+                f,
+                "{}{suffix}",
+                if self.track_id.is_some() { "\t" } else { "" }
+            )?;
+        }
+        self.extras
+            .iter()
+            .try_for_each(|extra| write!(f, "\t{extra}"))
+    }
+}
+
+impl<'a> ScrobbleRef<'a> {
+    /// Parse a scrobble from scrobbler.log
+    pub fn new(input: &'a str) -> Result<Self, String> {
+        Self::parse(input, false).map_err(|e| e.to_string())
+    }
+
+    /// Parse a record, from an AUDIOSCROBBLER/1.0 log if `legacy` (where the timestamp is the
+    /// last column).
+    pub(crate) fn parse(input: &'a str, legacy: bool) -> Result<Self, SyntaxError> {
+        let record = match legacy {
+            true => grammar::legacy_record(input)?,
+            false => grammar::record(input)?,
+        };
+        // The grammar checked the digits, but not that they fit.
+        fn number<T: std::str::FromStr>(line: &str, span: Span) -> Result<T, SyntaxError>
+        where
+            T::Err: std::fmt::Display,
+        {
+            span.text.parse().map_err(|e| SyntaxError {
+                offset: span.offset,
+                column: span.column(line),
+                message: format!("{:?}: {e}", span.text),
+            })
+        }
+        let track_id = record.track_id;
+        let unknown =
+            !track_id.text.is_empty() && all_consuming(grammar::uuid)(track_id.text).is_err();
+        Ok(ScrobbleRef {
+            artist: record.artist.text,
+            album: record.album.text,
+            track: record.track.text,
+            track_position: match record.position.text {
+                "" => None,
+                _ => Some(number(input, record.position)?),
+            },
+            song_duration: number(input, record.duration)?,
+            rating: match record.rating.text {
+                "S" => Rating::Skipped,
+                _ => Rating::Listened,
+            },
+            timestamp: chrono::Local
+                .timestamp_opt(number(input, record.timestamp)?, 0)
+                .single()
+                .ok_or_else(|| SyntaxError {
+                    offset: record.timestamp.offset,
+                    column: record.timestamp.column(input),
+                    message: format!("{:?}: out of the range of dates", record.timestamp.text),
+                })?,
+            track_id: Some(track_id.text).filter(|id| !id.is_empty() && !unknown),
+            extras: match unknown {
+                true => Vec::new(),
+                false => record.extras.iter().map(|extra| extra.text).collect(),
+            },
+            unknown_suffix: Some(&input[track_id.offset..]).filter(|_| unknown),
+            comments: Vec::new(),
+            trailing_comments: Vec::new(),
+        })
+    }
+
+    /// Copy the record into a [`Scrobble`].
+    pub fn to_owned(&self) -> Scrobble {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        Scrobble {
+            artist: self.artist.to_string(),
+            album: self.album.to_string(),
+            track: self.track.to_string(),
+            track_position: self.track_position,
+            song_duration: self.song_duration,
+            rating: self.rating,
+            timestamp: self.timestamp,
+            track_id: self.track_id.map(str::to_string),
+            artist_mbids: Vec::new(),
+            release_mbid: None,
+            extras: strings(&self.extras),
+            unknown_suffix: self.unknown_suffix.map(str::to_string),
+            comments: strings(&self.comments),
+            trailing_comments: strings(&self.trailing_comments),
+            provenance: None,
+            index: None,
+        }
+    }
+}
+
+impl Scrobble {
+    /// Parse a scrobble from scrobbler.log
+    pub fn new(input: &str) -> Result<Self, String> {
+        ScrobbleRef::new(input).map(|scrobble| scrobble.to_owned())
+    }
+
+    /// The album artist, for logs written by plugin forks that append it after the track id.
+    pub fn album_artist(&self) -> Option<&str> {
+        self.extras
+            .first()
+            .map(String::as_str)
+            .filter(|artist| !artist.is_empty())
+    }
+
+    /// The free-text metadata: artist, album, track, and any extra columns.
+    pub fn text_fields_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        [&mut self.artist, &mut self.album, &mut self.track]
+            .into_iter()
+            .chain(self.extras.iter_mut())
+    }
+
+    /// Every field as a JSON object, for scripts and exports.
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<&str>| value.map_or("null".to_string(), json::string);
+        let strings = |values: &[String]| json::array(values.iter().map(|v| json::string(v)));
+        json::object([
+            ("artist", json::string(&self.artist)),
+            ("album", json::string(&self.album)),
+            ("track", json::string(&self.track)),
+            (
+                "track_position",
+                self.track_position
+                    .map_or("null".to_string(), |p| p.to_string()),
+            ),
+            ("duration", self.song_duration.to_string()),
+            ("rating", json::string(&self.rating.to_string())),
+            ("timestamp", self.timestamp.timestamp().to_string()),
+            ("track_id", optional(self.track_id.as_deref())),
+            ("artist_mbids", strings(&self.artist_mbids)),
+            ("release_mbid", optional(self.release_mbid.as_deref())),
+            ("extras", strings(&self.extras)),
+            ("unknown_suffix", optional(self.unknown_suffix.as_deref())),
+            ("comments", strings(&self.comments)),
+            ("trailing_comments", strings(&self.trailing_comments)),
+            (
+                "provenance",
+                self.provenance.as_ref().map_or("null".to_string(), |p| {
+                    json::object([
+                        (
+                            "original_timestamp",
+                            p.original_timestamp.timestamp().to_string(),
+                        ),
+                        ("rule", json::string(&p.rule)),
+                        ("confidence", p.confidence.to_string()),
+                    ])
+                }),
+            ),
+        ])
+    }
+
+    /// Read a scrobble written by [`Scrobble::to_json`]. Only `artist`, `track`, `duration`,
+    /// `rating` and `timestamp` are required.
+    pub fn from_json(value: &json::Value) -> Result<Self, String> {
+        let string = |name| value.get(name).and_then(json::Value::as_str);
+        let number = |name| value.get(name).and_then(json::Value::as_f64);
+        let strings = |name| -> Vec<String> {
+            value
+                .get(name)
+                .and_then(json::Value::as_array)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(json::Value::as_str)
+                .map(str::to_string)
+                .collect()
+        };
+        let required = |name| format!("record has no {name}");
+        let local = |timestamp: f64| {
+            chrono::Local
+                .timestamp_opt(timestamp as i64, 0)
+                .single()
+                .ok_or("record timestamp is out of range")
+        };
+        let timestamp = local(number("timestamp").ok_or(required("timestamp"))?)?;
+        let provenance = match value.get("provenance") {
+            Some(provenance @ json::Value::Object(_)) => {
+                let field = |name| {
+                    provenance
+                        .get(name)
+                        .ok_or(format!("provenance has no {name}"))
+                };
+                Some(Provenance {
+                    original_timestamp: local(
+                        field("original_timestamp")?
+                            .as_f64()
+                            .ok_or("provenance timestamp must be a number")?,
+                    )?,
+                    rule: field("rule")?
+                        .as_str()
+                        .ok_or("provenance rule must be a string")?
+                        .to_string(),
+                    confidence: field("confidence")?
+                        .as_f64()
+                        .ok_or("provenance confidence must be a number")?,
+                })
+            }
+            _ => None,
+        };
+        Ok(Scrobble {
+            artist: string("artist").ok_or(required("artist"))?.to_string(),
+            album: string("album").unwrap_or_default().to_string(),
+            track: string("track").ok_or(required("track"))?.to_string(),
+            track_position: number("track_position").map(|p| p as u32),
+            song_duration: number("duration").ok_or(required("duration"))? as u32,
+            rating: match string("rating") {
+                Some("L") => Rating::Listened,
+                Some("S") => Rating::Skipped,
+                _ => Err("record rating must be \"L\" or \"S\"")?,
+            },
+            timestamp,
+            track_id: string("track_id").map(str::to_string),
+            artist_mbids: strings("artist_mbids"),
+            release_mbid: string("release_mbid").map(str::to_string),
+            extras: strings("extras"),
+            unknown_suffix: string("unknown_suffix").map(str::to_string),
+            comments: strings("comments"),
+            trailing_comments: strings("trailing_comments"),
+            provenance,
+            index: None,
+        })
+    }
+
+    /// Move the scrobble to `timestamp`, recording the correction in its provenance. Corrections
+    /// build on each other: the original timestamp is kept, and confidences multiply.
+    pub fn correct(&mut self, timestamp: DateTime<Local>, rule: &str, confidence: f64) {
+        if timestamp == self.timestamp {
+            return;
+        }
+        let provenance = self.provenance.get_or_insert(Provenance {
+            original_timestamp: self.timestamp,
+            rule: String::new(),
+            confidence: 1.0,
+        });
+        if !provenance.rule.is_empty() {
+            provenance.rule.push(',');
+        }
+        provenance.rule.push_str(rule);
+        provenance.confidence *= confidence;
+        self.timestamp = timestamp;
+    }
+
+    /// The scrobble's identity: an MD5 hex digest of its artist, track and timestamp, with the
+    /// names trimmed, lowercased and their whitespace collapsed. Records with the same
+    /// fingerprint are the same play however else they differ, and everything that matches
+    /// records up (merging into a master log, `db import`, resuming a submission) goes by it.
+    /// It doesn't change between versions or platforms, so it can be stored.
+    pub fn fingerprint(&self) -> String {
+        Scrobble::fingerprint_of(&self.artist, &self.track, self.timestamp.timestamp())
+    }
+
+    /// The [`fingerprint`](Scrobble::fingerprint) of a play of `track` by `artist` at
+    /// `timestamp` seconds since the epoch, for records kept in other forms.
+    pub fn fingerprint_of(artist: &str, track: &str, timestamp: i64) -> String {
+        let identity = format!("{}\t{}\t{timestamp}", normalize(artist), normalize(track));
+        crate::md5::hex_digest(identity.as_bytes())
+    }
+
+    /// What scrobbles are compared by, in order.
+    fn identity(&self) -> (i64, String, String) {
+        (
+            self.timestamp.timestamp(),
+            normalize(&self.artist),
+            normalize(&self.track),
+        )
+    }
+
+    /// Whether the two are the same play and the same record, field for field, where `==` only
+    /// asks the former.
+    pub fn identical(&self, other: &Scrobble) -> bool {
+        self.to_string() == other.to_string()
+            && self.artist_mbids == other.artist_mbids
+            && self.release_mbid == other.release_mbid
+            && self.comments == other.comments
+            && self.trailing_comments == other.trailing_comments
+            && self.provenance == other.provenance
+    }
+
+    /// Adjust the timestamps for suspicious scrobbles.
+    pub fn fix(self, cutoff: DateTime<FixedOffset>) -> Result<Self, String> {
+        let fixer = TimestampFixer {
+            cutoff,
+            ..TimestampFixer::default()
+        };
+        fixer.shift(self)
+    }
+}
+
+#[test]
+fn parse_line() {
+    let id = "6ba4a7a0-c9a4-4d64-b3a1-0fb720d0cf4c";
+    // A line's artist, position, rating, track id and count of extra columns, or `None` for a
+    // line that isn't a record.
+    type Expected<'a> = Option<(&'a str, Option<u32>, &'a str, Option<&'a str>, usize)>;
+    let cases: [(String, Expected); 10] = [
+        (
+            format!("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t{id}"),
+            Some(("Low", Some(5), "L", Some(id), 0)),
+        ),
+        (
+            "Low\tDrums and Guns\tBreaker\t\t187\tL\t1699413807\t".to_string(),
+            Some(("Low", None, "L", None, 0)),
+        ),
+        (
+            "Low\tDrums and Guns\tBreaker\t5\t187\tS\t1699413807\t".to_string(),
+            Some(("Low", Some(5), "S", None, 0)),
+        ),
+        (
+            "\t\tBreaker\t\t187\tL\t1699413807\t".to_string(),
+            Some(("", None, "L", None, 0)),
+        ),
+        (
+            format!("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t{id}\t"),
+            Some(("Low", Some(5), "L", Some(id), 1)),
+        ),
+        (
+            "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807".to_string(),
+            None,
+        ),
+        (
+            "Low\tDrums and Guns\tBreaker\t5\t187\tX\t1699413807\t".to_string(),
+            None,
+        ),
+        (
+            "Low\tDrums and Guns\tBreaker\tfive\t187\tL\t1699413807\t".to_string(),
+            None,
+        ),
+        ("Low\tDrums and Guns\tBreaker".to_string(), None),
+        (
+            "Low\tDrums and Guns\tBreaker\t5\t187\tL\t99999999999999999\t".to_string(),
+            None,
+        ),
+    ];
+    for (line, expected) in cases {
+        let parsed = Scrobble::new(&line);
+        let Some((artist, position, rating, track_id, extras)) = expected else {
+            assert!(parsed.is_err(), "{line:?} parsed");
+            continue;
+        };
+        let scrobble = parsed.unwrap_or_else(|e| panic!("{line:?}: {e}"));
+        assert_eq!(scrobble.artist, artist, "{line:?}");
+        assert_eq!(scrobble.track, "Breaker", "{line:?}");
+        assert_eq!(scrobble.track_position, position, "{line:?}");
+        assert_eq!(scrobble.song_duration, 187, "{line:?}");
+        assert_eq!(scrobble.rating.to_string(), rating, "{line:?}");
+        assert_eq!(scrobble.timestamp.timestamp(), 1699413807, "{line:?}");
+        assert_eq!(scrobble.track_id.as_deref(), track_id, "{line:?}");
+        assert_eq!(scrobble.extras.len(), extras, "{line:?}");
+        assert_eq!(scrobble.to_string(), line);
+    }
+}
+
+#[test]
+fn round_trip_extra_columns() {
+    let line = "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t\tLow";
+    let scrobble = Scrobble::new(line).unwrap();
+    assert_eq!(scrobble.track_id, None);
+    assert_eq!(scrobble.album_artist(), Some("Low"));
+    assert_eq!(scrobble.to_string(), line);
+}
+
+#[test]
+fn keep_unknown_suffix() {
+    let line = "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\tb0a1\tLow\t";
+    let mut scrobble = Scrobble::new(line).unwrap();
+    assert_eq!(
+        (scrobble.track_id.as_deref(), scrobble.extras.len()),
+        (None, 0)
+    );
+    assert_eq!(scrobble.unknown_suffix.as_deref(), Some("b0a1\tLow\t"));
+    assert_eq!(scrobble.to_string(), line);
+    assert_eq!(ScrobbleRef::new(line).unwrap().to_string(), line);
+    let value = json::parse(&scrobble.to_json()).unwrap();
+    assert_eq!(Scrobble::from_json(&value).unwrap().to_string(), line);
+
+    let id = "6ba4a7a0-c9a4-4d64-b3a1-0fb720d0cf4c";
+    scrobble.track_id = Some(id.to_string());
+    assert!(scrobble
+        .to_string()
+        .ends_with(&format!("\t{id}\tb0a1\tLow\t")));
+}
+
+#[test]
+fn round_trip_json() {
+    let line = "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t\tLow";
+    let scrobble = Scrobble::new(line).unwrap();
+    let value = json::parse(&scrobble.to_json()).unwrap();
+    assert_eq!(Scrobble::from_json(&value).unwrap().to_string(), line);
+}
+
+#[test]
+fn fingerprint_ignores_case_and_spacing() {
+    let scrobble = Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t").unwrap();
+    let respelled =
+        Scrobble::new(" LOW \tDrums and Guns (Deluxe)\tbreaker\t\t0\tS\t1699413807\t").unwrap();
+    assert_eq!(scrobble.fingerprint(), respelled.fingerprint());
+    assert_eq!(
+        scrobble.fingerprint(),
+        Scrobble::fingerprint_of("low", "Breaker", 1699413807)
+    );
+    assert_eq!(scrobble.fingerprint(), "37c82d4f1107d605e6a9a06d774b5a7e");
+    assert_ne!(
+        scrobble.fingerprint(),
+        Scrobble::fingerprint_of("Low", "Breaker", 1699413808)
+    );
+}
+
+#[test]
+fn compare_plays() {
+    let scrobble = Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t").unwrap();
+    let respelled =
+        Scrobble::new(" LOW \tDrums and Guns (Deluxe)\tbreaker\t\t0\tS\t1699413807\t").unwrap();
+    let later = Scrobble::new("Arca\tKiCk i\tNonbinary\t1\t158\tL\t1699413808\t").unwrap();
+    assert_eq!(scrobble, respelled);
+    assert!(!scrobble.identical(&respelled));
+    assert!(scrobble.identical(&scrobble.clone()));
+
+    let plays: std::collections::HashSet<_> = [&scrobble, &respelled, &later].into_iter().collect();
+    assert_eq!(plays.len(), 2);
+    let mut sorted = vec![later.clone(), scrobble.clone()];
+    sorted.sort();
+    assert_eq!(sorted, [scrobble.clone(), later]);
+    let same_time = Scrobble {
+        artist: "Arca".to_string(),
+        ..scrobble.clone()
+    };
+    assert!(same_time < scrobble);
+}
+
+#[test]
+fn borrow_records() {
+    let line = "Low\tDrums and Guns\tBreaker\t\t187\tL\t1699413807\t\tLow";
+    let borrowed = ScrobbleRef::new(line).unwrap();
+    assert_eq!((borrowed.track, borrowed.extras[0]), ("Breaker", "Low"));
+    assert_eq!(borrowed.to_string(), line);
+    assert_eq!(borrowed.to_owned().to_string(), line);
+    let legacy = ScrobbleRef::parse("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807", true);
+    assert_eq!(legacy.unwrap().track_id, None);
+    assert!(ScrobbleRef::new("Low\tDrums and Guns\tBreaker\t").is_err());
+}
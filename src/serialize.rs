@@ -0,0 +1,284 @@
+//! Writing scrobbles as scrobbler.log records, with the options `Display` can't take.
+//!
+//! [`Scrobble`]'s `Display` is [`ScrobbleSerializer::default`]; configure one to change line
+//! endings, to escape or refuse fields that would break the format, to write timestamps as a
+//! clock in some timezone would, or to leave out the columns only plugin forks write.
+
+use std::borrow::Cow;
+
+use chrono::{DateTime, FixedOffset, Local, Offset};
+
+use crate::header::Timezone;
+use crate::timestamps::parse_utc_offset;
+use crate::{escape, LogHeader, Scrobble};
+
+/// What to do with a field containing a tab or line break, which would split it in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Delimiters {
+    /// Write it as it is, as Rockbox would.
+    #[default]
+    Keep,
+    /// Backslash-escape it, like [`escape::escape`].
+    Escape,
+    /// Fail, naming the record.
+    Reject,
+}
+
+/// How lines end.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// What time a record's timestamp field gives. Records hold instants; a `#TZ/UNKNOWN` log's
+/// timestamps are instead the time on the player's clock, read as if it were UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EmitTz {
+    /// Seconds since the epoch, as held.
+    #[default]
+    Utc,
+    /// The time on a clock in this system's timezone, with the offset at each scrobble's own
+    /// time, across daylight saving changes.
+    Local,
+    /// The time on a clock this far from UTC.
+    Fixed(FixedOffset),
+}
+
+impl EmitTz {
+    /// The timestamp field for `time`.
+    pub fn epoch(self, time: &DateTime<Local>) -> i64 {
+        let offset = match self {
+            EmitTz::Utc => 0,
+            EmitTz::Local => time.offset().fix().local_minus_utc(),
+            EmitTz::Fixed(offset) => offset.local_minus_utc(),
+        };
+        time.timestamp() + i64::from(offset)
+    }
+}
+
+impl std::str::FromStr for EmitTz {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utc" => Ok(EmitTz::Utc),
+            "local" => Ok(EmitTz::Local),
+            _ => match s.strip_prefix("fixed:") {
+                Some(offset) => Ok(EmitTz::Fixed(parse_utc_offset(offset)?)),
+                None => Err(format!(
+                    "unknown timezone {s}, expected utc, local or fixed:OFFSET"
+                )),
+            },
+        }
+    }
+}
+
+/// Writes records and logs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrobbleSerializer {
+    pub line_ending: LineEnding,
+    pub delimiters: Delimiters,
+    /// Write the columns after the track id that some plugin forks append. Without them, records
+    /// have the eight columns stock Rockbox writes.
+    pub extras: bool,
+    pub timezone: EmitTz,
+}
+
+impl Default for ScrobbleSerializer {
+    fn default() -> Self {
+        ScrobbleSerializer {
+            line_ending: LineEnding::Lf,
+            delimiters: Delimiters::Keep,
+            extras: true,
+            timezone: EmitTz::Utc,
+        }
+    }
+}
+
+impl ScrobbleSerializer {
+    /// A field as it's written.
+    fn field<'a>(&self, scrobble: &Scrobble, field: &'a str) -> Result<Cow<'a, str>, String> {
+        if !field.contains(['\t', '\n', '\r']) {
+            return Ok(Cow::Borrowed(field));
+        }
+        match self.delimiters {
+            Delimiters::Keep => Ok(Cow::Borrowed(field)),
+            Delimiters::Escape => Ok(Cow::Owned(escape::escape(field))),
+            Delimiters::Reject => Err(format!(
+                "{} – {} @ {}: a field contains a tab or line break",
+                scrobble.artist,
+                scrobble.track,
+                scrobble.timestamp.timestamp()
+            )),
+        }
+    }
+
+    /// One record, without a line ending.
+    pub fn record(&self, scrobble: &Scrobble) -> Result<String, String> {
+        let text = |field| self.field(scrobble, field);
+        let mut fields = vec![
+            text(&scrobble.artist)?,
+            text(&scrobble.album)?,
+            text(&scrobble.track)?,
+            Cow::Owned(
+                scrobble
+                    .track_position
+                    .map_or(String::new(), |p| p.to_string()),
+            ),
+            Cow::Owned(scrobble.song_duration.to_string()),
+            Cow::Owned(scrobble.rating.to_string()),
+            Cow::Owned(self.timezone.epoch(&scrobble.timestamp).to_string()),
+            Cow::Borrowed(scrobble.track_id.as_deref().unwrap_or_default()),
+        ];
+        // In the track id's column, or after it if one has been found since.
+        if let Some(suffix) = scrobble.unknown_suffix.as_deref().filter(|_| self.extras) {
+            if scrobble.track_id.is_none() {
+                fields.pop();
+            }
+            fields.push(Cow::Borrowed(suffix));
+        }
+        if self.extras {
+            for extra in &scrobble.extras {
+                fields.push(text(extra)?);
+            }
+        }
+        Ok(fields.join("\t"))
+    }
+
+    /// A complete log under `header`, with the records' comments and without a final line
+    /// ending. The version is always 1.1, the format records are written in.
+    pub fn log(&self, header: &LogHeader, scrobbles: &[Scrobble]) -> Result<String, String> {
+        let mut header = LogHeader {
+            version: "1.1".to_string(),
+            ..header.clone()
+        };
+        if self.timezone != EmitTz::Utc {
+            header.tz = Timezone::Unknown;
+        }
+        let mut lines: Vec<String> = header.to_string().lines().map(str::to_string).collect();
+        for scrobble in scrobbles {
+            lines.extend(scrobble.comments.iter().cloned());
+            lines.push(self.record(scrobble)?);
+            lines.extend(scrobble.trailing_comments.iter().cloned());
+        }
+        let mut log = lines.join(self.line_ending.as_str());
+        if scrobbles.is_empty() {
+            log.push_str(self.line_ending.as_str());
+        }
+        Ok(log)
+    }
+}
+
+/// `log` in canonical form, for keeping logs in version control: the header as [`LogHeader`]
+/// writes it, at version 1.1, then every record as [`Scrobble`] writes it, with its comments,
+/// each line ending in LF, without a byte order mark or blank lines. Timestamps and text are
+/// kept as they are. Fails on the first line that isn't a record.
+pub fn canonical(log: &str) -> Result<String, String> {
+    let log = log
+        .strip_prefix('\u{feff}')
+        .unwrap_or(log)
+        .replace("\r\n", "\n");
+    let header = LogHeader::parse(&log)?;
+    let lines: Vec<&str> = log.lines().collect();
+    let mut scrobbles = Vec::new();
+    for (line, record) in crate::parse_numbered_records(&log) {
+        match record {
+            Ok(scrobble) => scrobbles.push(scrobble),
+            Err(_) if lines[line - 1].trim().is_empty() => {}
+            Err(e) => Err(e.to_string())?,
+        }
+    }
+    let log = ScrobbleSerializer::default().log(&header, &scrobbles)?;
+    Ok(match scrobbles.is_empty() {
+        true => log,
+        false => log + "\n",
+    })
+}
+
+#[test]
+fn serialize_with_options() {
+    let mut scrobble =
+        Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t\tLow").unwrap();
+    scrobble.comments.push("# road trip".to_string());
+    scrobble.album = "Drums\tand Guns".to_string();
+    let default = ScrobbleSerializer::default();
+    assert_eq!(default.record(&scrobble).unwrap(), scrobble.to_string());
+    let crlf = ScrobbleSerializer {
+        line_ending: LineEnding::CrLf,
+        delimiters: Delimiters::Escape,
+        extras: false,
+        timezone: EmitTz::Utc,
+    };
+    let log = crlf
+        .log(&LogHeader::default(), &[scrobble.clone()])
+        .unwrap();
+    assert_eq!(
+        log,
+        crate::HEADER.replace('\n', "\r\n")
+            + "# road trip\r\nLow\tDrums\\tand Guns\tBreaker\t5\t187\tL\t1699413807\t"
+    );
+    let strict = ScrobbleSerializer {
+        delimiters: Delimiters::Reject,
+        ..ScrobbleSerializer::default()
+    };
+    assert!(strict.record(&scrobble).is_err());
+}
+
+#[test]
+fn emit_timezones() {
+    use chrono::TimeZone;
+
+    let fixed = EmitTz::Fixed(FixedOffset::east_opt(2 * 3600).unwrap());
+    assert_eq!("fixed:+02:00".parse::<EmitTz>(), Ok(fixed));
+    assert!("fixed:CET".parse::<EmitTz>().is_err() && "cet".parse::<EmitTz>().is_err());
+    // Either side of the end of European summer time, 2023-10-29 01:00 UTC, and of the start of
+    // US daylight saving time, 2024-03-10 10:00 UTC: a fixed offset stays put, and local time
+    // follows whatever this system's timezone does at each instant.
+    for epoch in [1698541140, 1698541260, 1710064740, 1710064860] {
+        let time = Local.timestamp_opt(epoch, 0).unwrap();
+        assert_eq!(EmitTz::Utc.epoch(&time), epoch);
+        assert_eq!(fixed.epoch(&time), epoch + 7200);
+        // The local wall clock, read as if it were UTC.
+        let wall_clock = time.naive_local().and_utc().timestamp();
+        assert_eq!(EmitTz::Local.epoch(&time), wall_clock);
+    }
+    let scrobble = Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t").unwrap();
+    let serializer = ScrobbleSerializer {
+        timezone: fixed,
+        ..ScrobbleSerializer::default()
+    };
+    let utc = LogHeader {
+        tz: Timezone::Utc,
+        ..LogHeader::default()
+    };
+    assert_eq!(
+        serializer.log(&utc, &[scrobble]).unwrap(),
+        crate::HEADER.to_string() + "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699421007\t"
+    );
+}
+
+#[test]
+fn canonical_form() {
+    let log = "\u{feff}#AUDIOSCROBBLER/1.0\r\n#TZ/UTC\r\n#CLIENT/Rockbox sansae200\r\n\
+               # road trip\r\nLow\tDrums and Guns\tBreaker\t05\t187\tL\t1699413807\r\n\r\n";
+    let canonical = canonical(log).unwrap();
+    assert_eq!(
+        canonical,
+        "#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/Rockbox sansae200\n\
+         # road trip\nLow\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t\n"
+    );
+    assert_eq!(self::canonical(&canonical).unwrap(), canonical);
+    let error = self::canonical("#AUDIOSCROBBLER/1.1\n#TZ/UTC\n#CLIENT/x\nLow\tBreaker\n");
+    assert!(error.unwrap_err().starts_with("line 4"));
+}
@@ -0,0 +1,236 @@
+//! One line of a log spelled out: its bytes, each field's text and where it starts, the values
+//! parsed from them, and what the fixes make of the record, for working out why a single line is
+//! read or corrected the way it is.
+
+use std::fmt::Write;
+
+use crate::grammar::{self, Span};
+use crate::review::Row;
+use crate::Scrobble;
+
+/// Bytes in each line of the dump.
+const BYTES_PER_LINE: usize = 16;
+
+/// The width labels are padded to.
+const LABEL: usize = 12;
+
+fn field(out: &mut String, label: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "{label:LABEL$}{value}");
+}
+
+/// The line's bytes in hex, sixteen to a line, each line after its offset and followed by the
+/// bytes that are printable ASCII.
+fn hex_dump(out: &mut String, line: &str) {
+    for (row, chunk) in line.as_bytes().chunks(BYTES_PER_LINE).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+        let text: String = chunk
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+        let label = if row == 0 { "bytes" } else { "" };
+        let _ = writeln!(
+            out,
+            "{label:LABEL$}{:04x}  {:width$}  {text}",
+            row * BYTES_PER_LINE,
+            hex.join(" "),
+            width = BYTES_PER_LINE * 3 - 1
+        );
+    }
+}
+
+/// A field's text, quoted, and the column it starts at.
+fn located(out: &mut String, label: &str, line: &str, span: Span<'_>) {
+    let text = format!("{:?}", span.text);
+    field(
+        out,
+        label,
+        format!("{text:28} column {}", span.column(line)),
+    );
+}
+
+fn duration(seconds: u32) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// What the fixes changed in a record, a line per field.
+fn changes(original: &Scrobble, corrected: &Scrobble) -> Vec<String> {
+    let mut changes = Vec::new();
+    let mut text = |name: &str, before: &str, after: &str| {
+        if before != after {
+            changes.push(format!("{name} {before:?} -> {after:?}"));
+        }
+    };
+    text("artist", &original.artist, &corrected.artist);
+    text("album", &original.album, &corrected.album);
+    text("track", &original.track, &corrected.track);
+    let track_id = |scrobble: &Scrobble| scrobble.track_id.clone().unwrap_or_default();
+    text("track id", &track_id(original), &track_id(corrected));
+    let number = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+    if original.track_position != corrected.track_position {
+        changes.push(format!(
+            "position {:?} -> {:?}",
+            number(original.track_position),
+            number(corrected.track_position)
+        ));
+    }
+    if original.song_duration != corrected.song_duration {
+        changes.push(format!(
+            "duration {} -> {}",
+            original.song_duration, corrected.song_duration
+        ));
+    }
+    if original.rating != corrected.rating {
+        changes.push(format!(
+            "rating {} -> {}",
+            original.rating, corrected.rating
+        ));
+    }
+    if original.timestamp != corrected.timestamp {
+        let mut change = format!(
+            "timestamp {} -> {} ({:+}s)",
+            original.timestamp.timestamp(),
+            corrected.timestamp.timestamp(),
+            corrected.timestamp.timestamp() - original.timestamp.timestamp()
+        );
+        if let Some(provenance) = &corrected.provenance {
+            let _ = write!(
+                change,
+                ", by {} with confidence {:.2}",
+                provenance.rule, provenance.confidence
+            );
+        }
+        changes.push(change);
+    }
+    changes
+}
+
+/// Describe line `number` of a log, `line`, with the [`Row`] pairing the record parsed from it
+/// with what the fixes made of it; `None` when it didn't parse.
+pub fn describe(number: usize, line: &str, legacy: bool, row: Option<&Row>) -> String {
+    let mut out = String::new();
+    field(&mut out, "line", number);
+    // A tab is a character too, so columns line up.
+    field(&mut out, "text", line.replace('\t', "→"));
+    hex_dump(&mut out, line);
+    if line.starts_with('#') {
+        field(&mut out, "comment", "kept with the record after it");
+        return out;
+    }
+    let record = match legacy {
+        true => grammar::legacy_record(line),
+        false => grammar::record(line),
+    };
+    let record = match record {
+        Ok(record) => record,
+        Err(e) => {
+            field(&mut out, "error", &e);
+            field(&mut out, "", format!("{}^", " ".repeat(e.column - 1)));
+            return out;
+        }
+    };
+    located(&mut out, "artist", line, record.artist);
+    located(&mut out, "album", line, record.album);
+    located(&mut out, "track", line, record.track);
+    located(&mut out, "position", line, record.position);
+    located(&mut out, "duration", line, record.duration);
+    located(&mut out, "rating", line, record.rating);
+    located(&mut out, "timestamp", line, record.timestamp);
+    if !legacy {
+        located(&mut out, "track id", line, record.track_id);
+    }
+    for (n, extra) in record.extras.iter().enumerate() {
+        located(&mut out, &format!("extra {}", n + 1), line, *extra);
+    }
+    let Some(original) = row.and_then(|row| row.original.as_ref()) else {
+        field(&mut out, "error", "the record doesn't parse");
+        return out;
+    };
+    if let Some(suffix) = &original.unknown_suffix {
+        field(
+            &mut out,
+            "unknown",
+            format!("{suffix:?} isn't a track id, and is kept as it is"),
+        );
+    }
+    let position = original
+        .track_position
+        .map_or("none".to_string(), |n| n.to_string());
+    field(&mut out, "parsed", format!("position {position}"));
+    field(
+        &mut out,
+        "",
+        format!("duration {}", duration(original.song_duration)),
+    );
+    let rating = match original.rating {
+        crate::Rating::Listened => "listened",
+        crate::Rating::Skipped => "skipped",
+    };
+    field(&mut out, "", format!("rating {rating}"));
+    field(
+        &mut out,
+        "",
+        format!("played at {}", original.timestamp.to_rfc3339()),
+    );
+    match row.and_then(|row| row.corrected.as_ref()) {
+        None => field(&mut out, "fixed", "left out (ignored, or a podcast)"),
+        Some(corrected) => {
+            let changes = changes(original, corrected);
+            if changes.is_empty() {
+                field(&mut out, "fixed", "unchanged");
+            }
+            for (n, change) in changes.iter().enumerate() {
+                field(&mut out, if n == 0 { "fixed" } else { "" }, change);
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn describe_record() {
+    let line = "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t";
+    let original = Scrobble::new(line).unwrap();
+    let mut corrected = original.clone();
+    corrected.album = "Drums & Guns".to_string();
+    let row = Row {
+        original: Some(original),
+        corrected: Some(corrected),
+        accepted: true,
+    };
+    let shown = describe(4, line, false, Some(&row));
+    assert!(shown.starts_with("line        4\ntext        Low→Drums and Guns→Breaker→5→"));
+    assert!(shown.contains("\nbytes       0000  4c 6f 77 09"));
+    assert!(shown.contains("rating      \"L\"                          column 34\n"));
+    assert!(shown.contains("            duration 3:07\n"));
+    assert!(shown.contains("fixed       album \"Drums and Guns\" -> \"Drums & Guns\"\n"));
+
+    let line = "Low\tDrums and Guns\tBreaker\t5\t187\tX\t1699413807\t";
+    let shown = describe(5, line, false, None);
+    let lines: Vec<&str> = shown.lines().collect();
+    assert_eq!(
+        lines[lines.len() - 2],
+        "error       column 34: rating \"X\" isn't L or S"
+    );
+    let caret = lines[lines.len() - 1].find('^').unwrap();
+    assert_eq!(lines[1].chars().nth(caret), Some('X'));
+}
+
+#[test]
+fn describe_renamed_record() {
+    use crate::metadata::{CaseFixer, CasePolicy};
+
+    let line = "low\tdrums and guns\tbreaker\t5\t187\tL\t1699413807\t";
+    let original = vec![Scrobble::new(line).unwrap()];
+    let casing = crate::Pipeline::new().with(CaseFixer {
+        policy: CasePolicy::Title,
+        ..CaseFixer::default()
+    });
+    let corrected = casing.run(original.clone()).unwrap();
+    let rows = crate::review::rows(original, corrected);
+    let shown = describe(4, line, false, rows.first());
+    assert!(shown.contains("fixed       artist \"low\" -> \"Low\"\n"));
+    assert!(shown.contains("            track \"breaker\" -> \"Breaker\"\n"));
+}
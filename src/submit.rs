@@ -0,0 +1,516 @@
+//! Submitting scrobbles to Last.fm, or a server speaking the older Audioscrobbler protocol,
+//! remembering which have been sent.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use scrobble_fix::audioscrobbler::{self, Reply, Server, Session};
+use scrobble_fix::lastfm::{self, Credentials, RecentTrack};
+use scrobble_fix::{md5, metadata};
+use scrobble_fix::{Scrobble, SkipPolicy};
+
+use crate::audit;
+use crate::auth;
+use crate::cli::{Backend, Schedule};
+use crate::dirs;
+use crate::error::Error;
+use crate::files;
+use crate::log;
+use crate::net;
+
+const USER_AGENT: &str = concat!("scrobble-fix/", env!("CARGO_PKG_VERSION"));
+
+/// Where the scrobbles submitted so far are remembered, for the `--user` profile if there is one.
+pub fn progress_path(backend: Backend) -> io::Result<PathBuf> {
+    State::path(backend)
+}
+
+/// Where backends other than Last.fm are set up: `$XDG_CONFIG_HOME/scrobble-fix/config.toml`.
+pub fn config_path() -> io::Result<PathBuf> {
+    Ok(dirs::config()?.join("config.toml"))
+}
+
+/// Scrobbles submitted by earlier runs.
+struct State {
+    path: PathBuf,
+    /// The [`Scrobble::fingerprint`]s of the scrobbles submitted.
+    submitted: HashSet<String>,
+    /// When each submission was made, in the order they were made.
+    submitted_at: Vec<i64>,
+    lines: String,
+    /// Held while the state is loaded, so two runs can't submit the same scrobbles.
+    _lock: files::Lock,
+}
+
+impl State {
+    /// Where progress is kept between runs: `$XDG_STATE_HOME/scrobble-fix/lastfm-submitted.tsv`,
+    /// or `lastfm-submitted-NAME.tsv` for the `--user` profile NAME; `audioscrobbler-` instead
+    /// for the Audioscrobbler backend.
+    fn path(backend: Backend) -> io::Result<PathBuf> {
+        let backend = backend.name();
+        let name = match auth::profile() {
+            Some(profile) => format!("{backend}-submitted-{profile}.tsv"),
+            None => format!("{backend}-submitted.tsv"),
+        };
+        Ok(dirs::state()?.join(name))
+    }
+
+    /// Load the state, which has one line per submitted scrobble:
+    /// `artist\ttrack\ttimestamp\tsubmitted at`, both times in seconds since the epoch.
+    fn load(backend: Backend) -> io::Result<Self> {
+        let path = State::path(backend)?;
+        let lock = files::lock(&path)?;
+        let lines = match std::fs::read_to_string(&path) {
+            Ok(lines) => lines,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut state = State {
+            path,
+            submitted: HashSet::new(),
+            submitted_at: Vec::new(),
+            lines: String::new(),
+            _lock: lock,
+        };
+        for line in lines.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [artist, track, timestamp, submitted_at] = fields[..] else {
+                continue;
+            };
+            let (Ok(timestamp), Ok(submitted_at)) = (timestamp.parse(), submitted_at.parse())
+            else {
+                continue;
+            };
+            state
+                .submitted
+                .insert(Scrobble::fingerprint_of(artist, track, timestamp));
+            state.submitted_at.push(submitted_at);
+            state.lines.push_str(line);
+            state.lines.push('\n');
+        }
+        Ok(state)
+    }
+
+    fn contains(&self, scrobble: &Scrobble) -> bool {
+        self.submitted.contains(&scrobble.fingerprint())
+    }
+
+    fn record(&mut self, scrobble: &Scrobble, submitted_at: i64) {
+        let (artist, track) = (&scrobble.artist, &scrobble.track);
+        let timestamp = scrobble.timestamp.timestamp();
+        self.lines
+            .push_str(&format!("{artist}\t{track}\t{timestamp}\t{submitted_at}\n"));
+        self.submitted.insert(scrobble.fingerprint());
+        self.submitted_at.push(submitted_at);
+    }
+
+    /// Submissions made on a local day.
+    fn submitted_on(&self, day: NaiveDate) -> usize {
+        self.submitted_at
+            .iter()
+            .filter_map(|&at| Local.timestamp_opt(at, 0).single())
+            .filter(|at| at.date_naive() == day)
+            .count()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        files::replace(&self.path, &self.lines)
+    }
+}
+
+/// The start of the next local day.
+fn next_midnight(now: DateTime<Local>) -> DateTime<Local> {
+    let tomorrow = now
+        .date_naive()
+        .succ_opt()
+        .expect("a date before the end of time");
+    tomorrow
+        .and_hms_opt(0, 0, 0)
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+        .unwrap_or(now + chrono::Duration::days(1))
+}
+
+/// Scrobbles further apart than this are checked against the profile with separate requests.
+const RANGE_GAP: i64 = 24 * 60 * 60;
+
+/// The scrobbles already on `user`'s profile around the times of `pending`.
+///
+/// Pending scrobbles are grouped into ranges with no day-long gaps, and only those ranges (widened
+/// by the replay window) are fetched, rather than the whole profile.
+fn existing(pending: &[&Scrobble], user: &str, api_key: &str) -> Result<Vec<RecentTrack>, Error> {
+    let mut timestamps: Vec<i64> = pending.iter().map(|s| s.timestamp.timestamp()).collect();
+    timestamps.sort_unstable();
+    let mut ranges: Vec<(i64, i64)> = Vec::new();
+    for timestamp in timestamps {
+        match ranges.last_mut() {
+            Some((_, to)) if timestamp - *to <= RANGE_GAP => *to = timestamp,
+            _ => ranges.push((timestamp, timestamp)),
+        }
+    }
+    let mut existing = Vec::new();
+    for (from, to) in ranges {
+        let (from, to) = (from - lastfm::REPLAY_WINDOW, to + lastfm::REPLAY_WINDOW);
+        let mut page = 1;
+        loop {
+            let url = lastfm::recent_tracks_url(user, api_key, from, to, page);
+            let response = net::get(&url, USER_AGENT).map_err(Error::Network)?;
+            let (tracks, pages) = lastfm::parse_recent_tracks(&response).map_err(Error::Network)?;
+            existing.extend(tracks);
+            if page >= pages {
+                break;
+            }
+            page += 1;
+        }
+    }
+    Ok(existing)
+}
+
+/// The scrobbles in saved `user.getRecentTracks` pages.
+fn saved(files: &[PathBuf]) -> Result<Vec<RecentTrack>, Error> {
+    let mut saved = Vec::new();
+    for file in files {
+        let export = std::fs::read_to_string(file)?;
+        let tracks = lastfm::parse_recent_tracks_export(&export)
+            .map_err(|e| Error::Parse(format!("{}: {e}", file.display())))?;
+        saved.extend(tracks);
+    }
+    Ok(saved)
+}
+
+/// An Audioscrobbler 1.2 server, shaken hands with when first needed.
+struct Legacy {
+    server: Server,
+    password_md5: String,
+    session: Mutex<Option<Session>>,
+}
+
+impl Legacy {
+    /// The server set up in the config file, with its password from there or
+    /// `$AUDIOSCROBBLER_PASSWORD`.
+    fn load() -> Result<Self, Error> {
+        let path = config_path()?;
+        let config = match std::fs::read_to_string(&path) {
+            Ok(config) => config,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let server = Server::parse_config(&config)
+            .map_err(|e| Error::Parse(format!("{}: {e}", path.display())))?
+            .ok_or(Error::Usage(format!(
+                "--backend audioscrobbler needs an [audioscrobbler] table in {}",
+                path.display()
+            )))?;
+        let password_md5 = match (
+            &server.password_md5,
+            std::env::var("AUDIOSCROBBLER_PASSWORD"),
+        ) {
+            (Some(password_md5), _) => password_md5.clone(),
+            (None, Ok(password)) => md5::hex_digest(password.as_bytes()),
+            (None, Err(_)) => Err(Error::Usage(
+                "--backend audioscrobbler needs password_md5 in [audioscrobbler], or \
+                 $AUDIOSCROBBLER_PASSWORD"
+                    .to_string(),
+            ))?,
+        };
+        Ok(Legacy {
+            server,
+            password_md5,
+            session: Mutex::new(None),
+        })
+    }
+
+    /// The session, shaking hands for one if there's none yet or the last one is to be renewed.
+    fn session(&self, renew: bool) -> Result<Session, Error> {
+        let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(session) = session.as_ref().filter(|_| !renew) {
+            return Ok(session.clone());
+        }
+        let version = env!("CARGO_PKG_VERSION");
+        let url = self
+            .server
+            .handshake_url(&self.password_md5, version, Local::now().timestamp());
+        let response = net::get_api(&url, USER_AGENT).map_err(Error::Network)?;
+        let handshake = audioscrobbler::parse_handshake(&response)
+            .map_err(|e| Error::Network(format!("{}: {e}", self.server.handshake)))?;
+        log::debug(format_args!("shook hands with {}", self.server.handshake));
+        Ok(session.insert(handshake).clone())
+    }
+
+    /// Send one batch, shaking hands again once if the session has expired.
+    fn submit_batch(&self, batch: &[&Scrobble]) -> Result<(), Error> {
+        let mut renew = false;
+        loop {
+            let session = self.session(renew)?;
+            let params = audioscrobbler::submission_params(&session, batch);
+            let body = lastfm::form_body(&params);
+            let response = net::post_form(&session.submission_url, &body, USER_AGENT)
+                .map_err(Error::Network)?;
+            match audioscrobbler::parse_reply(&response).map_err(Error::Network)? {
+                Reply::Ok => break,
+                Reply::BadSession if !renew => renew = true,
+                Reply::BadSession => Err(Error::Network(
+                    "the server turned down a new session".to_string(),
+                ))?,
+            }
+        }
+        log::info(format_args!("submitted {} scrobbles", batch.len()));
+        Ok(())
+    }
+}
+
+/// Where batches are sent.
+enum Target {
+    LastFm(Credentials),
+    Audioscrobbler(Legacy),
+}
+
+impl Target {
+    fn load(backend: Backend) -> Result<Self, Error> {
+        Ok(match backend {
+            Backend::LastFm => Target::LastFm(auth::lastfm_credentials()?),
+            Backend::Audioscrobbler => Target::Audioscrobbler(Legacy::load()?),
+        })
+    }
+
+    /// Most scrobbles sent at once.
+    fn batch_size(&self) -> usize {
+        match self {
+            Target::LastFm(_) => lastfm::BATCH_SIZE,
+            Target::Audioscrobbler(_) => audioscrobbler::BATCH_SIZE,
+        }
+    }
+
+    /// Send one batch in a single request.
+    fn submit_batch(&self, batch: &[&Scrobble]) -> Result<(), Error> {
+        let credentials = match self {
+            Target::LastFm(credentials) => credentials,
+            Target::Audioscrobbler(server) => return server.submit_batch(batch),
+        };
+        let body = lastfm::form_body(&lastfm::scrobble_params(batch, credentials));
+        let response =
+            net::post_form(lastfm::API_URL, &body, USER_AGENT).map_err(Error::Network)?;
+        let submitted = lastfm::parse_scrobble_response(&response).map_err(Error::Network)?;
+        log::info(format_args!(
+            "submitted {} scrobbles ({} accepted, {} ignored)",
+            batch.len(),
+            submitted.accepted,
+            submitted.ignored
+        ));
+        Ok(())
+    }
+
+    /// Print the request that would send one batch, with the session key hidden: one
+    /// url-encoded `name=value` per line, which joined with `&` make the exact request body.
+    fn print_batch(&self, number: usize, batch: &[&Scrobble]) {
+        let (url, params) = match self {
+            Target::LastFm(credentials) => (
+                lastfm::API_URL,
+                lastfm::redact(&lastfm::scrobble_params(batch, credentials)),
+            ),
+            // There's no session without shaking hands, which a dry run doesn't.
+            Target::Audioscrobbler(_) => {
+                let session = Session {
+                    id: "REDACTED".to_string(),
+                    now_playing_url: String::new(),
+                    submission_url: String::new(),
+                };
+                (
+                    "(the submission URL of the handshake)",
+                    audioscrobbler::submission_params(&session, batch),
+                )
+            }
+        };
+        println!("# batch {number}: {} scrobbles", batch.len());
+        println!("POST {url}");
+        for (name, value) in &params {
+            println!("{}", lastfm::form_body(&[(name.clone(), value.clone())]));
+        }
+        println!();
+    }
+}
+
+/// Copies of the scrobbles with names Last.fm will take, warning about each change.
+fn sanitized(scrobbles: &[Scrobble]) -> Vec<Scrobble> {
+    scrobbles
+        .iter()
+        .map(|scrobble| {
+            let mut clean = scrobble.clone();
+            let changes = metadata::sanitize(&mut clean);
+            let _span = log::record(scrobble, None);
+            for change in changes {
+                log::warn(change);
+            }
+            clean
+        })
+        .collect()
+}
+
+/// How `submit` sends scrobbles.
+pub struct Options<'a> {
+    pub backend: Backend,
+    /// What's sent of skipped scrobbles, which none of the backends have.
+    pub skipped: SkipPolicy,
+    /// Caps the submissions made by this run or, with a daily `schedule`, on each local day
+    /// (counting earlier runs); a scheduled run then waits for midnight and carries on until
+    /// every scrobble is sent.
+    pub max: Option<usize>,
+    pub schedule: Option<Schedule>,
+    /// Leave out scrobbles already on the user's Last.fm profile (say, from an import that
+    /// stopped partway on another machine).
+    pub check_existing: bool,
+    /// Saved `user.getRecentTracks` pages to check against instead of the API.
+    pub snapshot: &'a [PathBuf],
+    /// Batches sent at once.
+    pub jobs: usize,
+    /// Print each batch's request instead of sending it and save nothing; a daily schedule
+    /// moves on to the next day without waiting.
+    pub dry_run: bool,
+}
+
+/// How far a run got.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Submitted {
+    pub sent: usize,
+    /// Scrobbles to send neither sent now nor before, left by `max`.
+    pub left: usize,
+}
+
+/// Submit the scrobbles the backend hasn't been sent yet, leaving out skipped ones unless
+/// `skipped` says otherwise. Progress is saved as batches
+/// finish, so an interrupted run loses nothing.
+pub fn submit(scrobbles: &[Scrobble], options: &Options) -> Result<Submitted, Error> {
+    let Options {
+        backend,
+        skipped,
+        max,
+        schedule,
+        check_existing,
+        snapshot,
+        jobs,
+        dry_run,
+    } = *options;
+    let scrobbles = sanitized(scrobbles);
+    let target = Target::load(backend)?;
+    let mut state = State::load(backend)?;
+    let mut pending: Vec<&Scrobble> = scrobbles
+        .iter()
+        .filter(|scrobble| skipped.keeps(scrobble))
+        .filter(|scrobble| !state.contains(scrobble))
+        .collect();
+    if let (true, false, Target::LastFm(credentials)) =
+        (check_existing, pending.is_empty(), &target)
+    {
+        let existing = match snapshot {
+            [] => {
+                let user = auth::lastfm_user()?.ok_or(Error::Usage(
+                    "--check-existing needs $LASTFM_USER, or `auth login lastfm`".to_string(),
+                ))?;
+                existing(&pending, &user, &credentials.api_key)?
+            }
+            files => saved(files)?,
+        };
+        let before = pending.len();
+        pending.retain(|scrobble| !lastfm::already_scrobbled(scrobble, &existing));
+        log::info(format_args!(
+            "{} scrobbles already on Last.fm",
+            before - pending.len()
+        ));
+    }
+    let mut remaining = &pending[..];
+    let mut sent = 0;
+    // A dry run's clock, which skips ahead to midnight instead of waiting for it.
+    let mut clock = Local::now();
+    let mut printed = 0;
+    loop {
+        let now = match dry_run {
+            true => clock,
+            false => Local::now(),
+        };
+        let used = match schedule {
+            Some(Schedule::Daily) => state.submitted_on(now.date_naive()),
+            None => sent,
+        };
+        let allowance = max
+            .map_or(remaining.len(), |max| max.saturating_sub(used))
+            .min(remaining.len());
+        let (today, later) = remaining.split_at(allowance);
+        let batches: Vec<&[&Scrobble]> = today.chunks(target.batch_size()).collect();
+        for group in batches.chunks(jobs.max(1)) {
+            // Each batch in the group goes out on its own thread; net keeps them within the
+            // rate limit.
+            let results: Vec<Result<(), Error>> = match dry_run {
+                true => group
+                    .iter()
+                    .map(|batch| {
+                        printed += 1;
+                        target.print_batch(printed, batch);
+                        Ok(())
+                    })
+                    .collect(),
+                false => thread::scope(|scope| {
+                    let handles: Vec<_> = group
+                        .iter()
+                        .map(|batch| scope.spawn(|| target.submit_batch(batch)))
+                        .collect();
+                    handles
+                        .into_iter()
+                        .map(|handle| handle.join().expect("submission thread panicked"))
+                        .collect()
+                }),
+            };
+            let submitted_at = match dry_run {
+                true => now.timestamp(),
+                false => Local::now().timestamp(),
+            };
+            let mut failure = None;
+            for (batch, result) in group.iter().zip(results) {
+                match result {
+                    Ok(()) => {
+                        if !dry_run {
+                            audit::submitted(backend.name(), batch);
+                        }
+                        for scrobble in batch.iter() {
+                            state.record(scrobble, submitted_at);
+                        }
+                        sent += batch.len();
+                    }
+                    Err(e) => failure = failure.or(Some(e)),
+                }
+            }
+            if !dry_run {
+                state.save()?;
+            }
+            if let Some(e) = failure {
+                return Err(e);
+            }
+        }
+        remaining = later;
+        if remaining.is_empty() || schedule.is_none() {
+            break;
+        }
+        let midnight = next_midnight(now);
+        log::info(format_args!(
+            "{} scrobbles left, waiting until {}",
+            remaining.len(),
+            midnight.format("%Y-%m-%d %H:%M")
+        ));
+        match dry_run {
+            true => clock = midnight,
+            false => thread::sleep((midnight - Local::now()).to_std().unwrap_or_default()),
+        }
+    }
+    if !remaining.is_empty() {
+        log::info(format_args!(
+            "{} scrobbles left for the next run",
+            remaining.len()
+        ));
+    }
+    Ok(Submitted {
+        sent,
+        left: remaining.len(),
+    })
+}
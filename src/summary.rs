@@ -0,0 +1,89 @@
+//! What a run did, for notifications.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use chrono::{SecondsFormat, Utc};
+use scrobble_fix::json;
+
+use crate::error;
+
+/// Outcome of one invocation.
+#[derive(Debug)]
+pub struct Summary {
+    pub command: &'static str,
+    pub input: PathBuf,
+    /// Scrobbles parsed from the input.
+    pub read: usize,
+    /// Records left out because they couldn't be parsed.
+    pub skipped: usize,
+    /// Scrobbles written to the output (or appended to the master log).
+    pub written: usize,
+    /// Problems `lint` found that don't break the format.
+    pub warnings: usize,
+    /// Batch inputs that couldn't be read or fixed, and were left out.
+    pub failed: usize,
+    /// Web requests that failed.
+    pub network_failures: usize,
+    /// There were no scrobbles to work on, or no new ones to append.
+    pub nothing_to_do: bool,
+    /// Why the run failed, if it did.
+    pub error: Option<String>,
+}
+
+impl Summary {
+    pub fn new(command: &'static str, input: PathBuf) -> Self {
+        Summary {
+            command,
+            input,
+            read: 0,
+            skipped: 0,
+            written: 0,
+            warnings: 0,
+            failed: 0,
+            network_failures: 0,
+            nothing_to_do: false,
+            error: None,
+        }
+    }
+
+    /// Exit code for a run that didn't stop with an error.
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(if self.network_failures > 0 {
+            error::NETWORK
+        } else if self.skipped > 0 || self.warnings > 0 || self.failed > 0 {
+            error::PARTIAL
+        } else if self.nothing_to_do {
+            error::NOTHING_TO_DO
+        } else {
+            error::SUCCESS
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        json::object([
+            ("command", json::string(self.command)),
+            ("input", json::string(&self.input.to_string_lossy())),
+            (
+                "status",
+                json::string(if self.error.is_some() { "error" } else { "ok" }),
+            ),
+            ("read", self.read.to_string()),
+            ("skipped", self.skipped.to_string()),
+            ("written", self.written.to_string()),
+            ("warnings", self.warnings.to_string()),
+            ("failed", self.failed.to_string()),
+            ("network_failures", self.network_failures.to_string()),
+            (
+                "error",
+                self.error
+                    .as_deref()
+                    .map_or("null".to_string(), json::string),
+            ),
+            (
+                "finished_at",
+                json::string(&Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+            ),
+        ])
+    }
+}
@@ -0,0 +1,133 @@
+//! Human-readable table rendering for quick inspection in a terminal.
+
+use crate::Scrobble;
+
+/// Column headings, in display order.
+const HEADINGS: [&str; 6] = ["Artist", "Album", "Track", "Len", "Rating", "Date"];
+
+/// Columns that may be shortened to fit the terminal: Artist, Album and Track.
+const SHRINKABLE: [usize; 3] = [0, 1, 2];
+
+/// Shrinkable columns never get narrower than this.
+const MIN_WIDTH: usize = 6;
+
+/// Space between columns.
+const SEPARATOR: &str = "  ";
+
+/// Render scrobbles as an aligned table.
+///
+/// With a `max_width`, the Artist/Album/Track columns are truncated (marked with `…`) so each row
+/// fits; `None` keeps every field whole.
+pub fn render(scrobbles: &[Scrobble], max_width: Option<usize>) -> String {
+    let rows: Vec<[String; 6]> = scrobbles.iter().map(row).collect();
+    let mut widths = HEADINGS.map(display_width);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(display_width(cell));
+        }
+    }
+    if let Some(max_width) = max_width {
+        shrink(&mut widths, max_width);
+    }
+    let mut table = format_row(&HEADINGS.map(str::to_string), &widths);
+    table.push_str(&format_row(&widths.map(|width| "-".repeat(width)), &widths));
+    for row in &rows {
+        table.push_str(&format_row(row, &widths));
+    }
+    table
+}
+
+/// Table cells for one scrobble.
+fn row(scrobble: &Scrobble) -> [String; 6] {
+    [
+        scrobble.artist.clone(),
+        scrobble.album.clone(),
+        scrobble.track.clone(),
+        format!(
+            "{}:{:02}",
+            scrobble.song_duration / 60,
+            scrobble.song_duration % 60
+        ),
+        scrobble.rating.to_string(),
+        scrobble.timestamp.format("%Y-%m-%d %H:%M").to_string(),
+    ]
+}
+
+/// Narrow the widest shrinkable column until the row fits, or nothing can shrink further.
+fn shrink(widths: &mut [usize; 6], max_width: usize) {
+    let total = |widths: &[usize; 6]| widths.iter().sum::<usize>() + SEPARATOR.len() * 5;
+    while total(widths) > max_width {
+        let widest = SHRINKABLE
+            .into_iter()
+            .max_by_key(|&column| widths[column])
+            .expect("shrinkable columns");
+        if widths[widest] <= MIN_WIDTH {
+            break;
+        }
+        widths[widest] -= 1;
+    }
+}
+
+/// Pad (or truncate) each cell to its column width.
+fn format_row(cells: &[String; 6], widths: &[usize; 6]) -> String {
+    let line = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, &width)| {
+            let cell = truncate(cell, width);
+            let padding = width - display_width(&cell);
+            format!("{cell}{}", " ".repeat(padding))
+        })
+        .collect::<Vec<String>>()
+        .join(SEPARATOR);
+    format!("{}\n", line.trim_end())
+}
+
+/// Cut a cell down to `width` columns, ending it with an ellipsis when anything was dropped.
+pub fn truncate(cell: &str, width: usize) -> String {
+    if display_width(cell) <= width {
+        return cell.to_string();
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in cell.chars() {
+        if used + char_width(c) + 1 > width {
+            break;
+        }
+        used += char_width(c);
+        truncated.push(c);
+    }
+    truncated.push('…');
+    truncated
+}
+
+/// Terminal columns taken by a string.
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Terminal columns taken by a character: East Asian wide and fullwidth characters take two.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+#[test]
+fn truncate_wide_characters() {
+    assert_eq!(truncate("식료품groceries", 7), "식료품…");
+    assert_eq!(truncate("short", 7), "short");
+}
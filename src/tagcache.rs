@@ -0,0 +1,306 @@
+//! Reading the Rockbox tagcache database (`.rockbox/database_*.tcd`), whose artist, album and
+//! title strings are the device's own spelling of each track's tags.
+//!
+//! `database_idx.tcd` holds one entry per track: for each tag, an offset into that tag's
+//! `database_N.tcd`, where the string is kept. The files are in the device's byte order, told by
+//! their magic number. Only the string tags needed to correct scrobbles are read, and the play
+//! count the runtime database keeps in the index.
+
+use std::collections::HashMap;
+
+use crate::pipeline::Fixer;
+use crate::Scrobble;
+
+/// The string tags read, numbered like their files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tag {
+    Artist = 0,
+    Album = 1,
+    Title = 3,
+    AlbumArtist = 7,
+}
+
+impl Tag {
+    pub const ALL: [Tag; 4] = [Tag::Artist, Tag::Album, Tag::Title, Tag::AlbumArtist];
+
+    /// The file the tag's strings are in, like `database_0.tcd`.
+    pub fn file_name(self) -> String {
+        format!("database_{}.tcd", self as usize)
+    }
+}
+
+/// What Rockbox stores for a tag the file doesn't have.
+const UNTAGGED: &str = "<Untagged>";
+
+/// The numeric tag the runtime database counts plays in, kept in the index entry itself.
+const PLAYCOUNT: usize = 14;
+
+/// Set in an index entry's flags once the track is removed.
+const FLAG_DELETED: u32 = 1;
+
+/// Bytes before the first index entry: the common header, then serial, commit id and dirty flag.
+const INDEX_HEADER: usize = 24;
+
+/// A track in the database.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+    /// Empty when untagged, or when the database has no album artists.
+    pub album_artist: String,
+    /// Plays counted by the runtime database; 0 when it's off.
+    pub playcount: u32,
+}
+
+/// A database file's byte order, from its magic number: `TCH` and a format version.
+fn big_endian(file: &[u8]) -> Result<bool, String> {
+    match file.get(..4) {
+        Some([b'T', b'C', b'H', _]) => Ok(true),
+        Some([_, b'H', b'C', b'T']) => Ok(false),
+        _ => Err("not a tagcache file".to_string()),
+    }
+}
+
+fn u32_at(file: &[u8], offset: usize, big_endian: bool) -> Result<u32, String> {
+    let bytes: [u8; 4] = file
+        .get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(format!("truncated at byte {offset}"))?;
+    Ok(match big_endian {
+        true => u32::from_be_bytes(bytes),
+        false => u32::from_le_bytes(bytes),
+    })
+}
+
+/// The string of the tag file entry at `offset`: its length, the index entry it belongs to, then
+/// the NUL-terminated string, padded.
+fn string_at(file: &[u8], offset: usize) -> Result<String, String> {
+    let big_endian = big_endian(file)?;
+    let length = u32_at(file, offset, big_endian)? as usize;
+    let data = file
+        .get(offset + 8..offset + 8 + length)
+        .ok_or(format!("string at byte {offset} runs past the end"))?;
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let string = String::from_utf8_lossy(&data[..end]);
+    Ok(match string.as_ref() {
+        UNTAGGED => String::new(),
+        _ => string.into_owned(),
+    })
+}
+
+/// Read the tracks from the master index and the tag files, leaving out deleted ones. Only
+/// [`Tag::AlbumArtist`]'s file may be missing.
+pub fn parse(index: &[u8], tag_files: &HashMap<Tag, Vec<u8>>) -> Result<Vec<Track>, String> {
+    let big_endian = big_endian(index).map_err(|e| format!("database_idx.tcd: {e}"))?;
+    let size = u32_at(index, 4, big_endian)? as usize;
+    let count = u32_at(index, 8, big_endian)? as usize;
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    // Newer firmware has more tags, so the entry size comes from the header.
+    let entry = size / count;
+    if !entry.is_multiple_of(4) || entry < 4 * (PLAYCOUNT + 2) {
+        Err(format!("database_idx.tcd: unexpected entry size {entry}"))?;
+    }
+    let length = count
+        .checked_mul(entry)
+        .and_then(|length| length.checked_add(INDEX_HEADER));
+    if length.is_none_or(|length| length > index.len()) {
+        Err(format!(
+            "database_idx.tcd: {count} entries of {entry} bytes run past the end"
+        ))?;
+    }
+    let mut tracks = Vec::with_capacity(count);
+    for number in 0..count {
+        let start = INDEX_HEADER + number * entry;
+        let flags = u32_at(index, start + entry - 4, big_endian)?;
+        if flags & FLAG_DELETED != 0 {
+            continue;
+        }
+        let tag = |tag: Tag| -> Result<String, String> {
+            let Some(file) = tag_files.get(&tag) else {
+                return match tag {
+                    Tag::AlbumArtist => Ok(String::new()),
+                    _ => Err(format!("{} is missing", tag.file_name())),
+                };
+            };
+            let offset = u32_at(index, start + 4 * tag as usize, big_endian)? as usize;
+            string_at(file, offset).map_err(|e| format!("{}: {e}", tag.file_name()))
+        };
+        tracks.push(Track {
+            artist: tag(Tag::Artist)?,
+            album: tag(Tag::Album)?,
+            title: tag(Tag::Title)?,
+            album_artist: tag(Tag::AlbumArtist)?,
+            playcount: u32_at(index, start + 4 * PLAYCOUNT, big_endian)?,
+        });
+    }
+    Ok(tracks)
+}
+
+/// Case-fold and collapse whitespace, to match logged names with the database's.
+pub(crate) fn normalize(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Replaces the artist, album and track of every scrobble matching a track in the device's
+/// database (by artist and title, ignoring case and spacing, and by album too when several
+/// match) with the database's spelling.
+#[derive(Debug, Clone)]
+pub struct TagcacheFixer {
+    tracks: Vec<Track>,
+    /// Indices into `tracks` by normalized artist and title.
+    by_name: HashMap<(String, String), Vec<usize>>,
+}
+
+impl TagcacheFixer {
+    pub fn new(tracks: Vec<Track>) -> Self {
+        let mut by_name: HashMap<(String, String), Vec<usize>> = HashMap::new();
+        for (i, track) in tracks.iter().enumerate() {
+            let key = (normalize(&track.artist), normalize(&track.title));
+            by_name.entry(key).or_default().push(i);
+        }
+        TagcacheFixer { tracks, by_name }
+    }
+
+    /// The index of the database's track for a scrobble, if any.
+    fn find(&self, scrobble: &Scrobble) -> Option<usize> {
+        let key = (normalize(&scrobble.artist), normalize(&scrobble.track));
+        let candidates = self.by_name.get(&key)?;
+        let album = normalize(&scrobble.album);
+        candidates
+            .iter()
+            .find(|&&i| normalize(&self.tracks[i].album) == album)
+            .or(candidates.first())
+            .copied()
+    }
+}
+
+impl Fixer for TagcacheFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        for scrobble in &mut scrobbles {
+            let Some(i) = self.find(scrobble) else {
+                continue;
+            };
+            let track = &self.tracks[i];
+            scrobble.artist.clone_from(&track.artist);
+            scrobble.track.clone_from(&track.title);
+            if !track.album.is_empty() {
+                scrobble.album.clone_from(&track.album);
+            }
+        }
+        Ok(scrobbles)
+    }
+}
+
+/// A track's plays as the runtime database counted them and as a log has them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayCount {
+    pub track: Track,
+    /// Records of the track in the log, listened or skipped.
+    pub logged: usize,
+}
+
+impl PlayCount {
+    /// Plays counted but never logged, as when the player crashed before writing them.
+    pub fn lost(&self) -> usize {
+        (self.track.playcount as usize).saturating_sub(self.logged)
+    }
+}
+
+/// Compare the database's play counts with the plays logged of each track, matched as
+/// [`TagcacheFixer`] matches them, most plays lost first. Only tracks played (by either count)
+/// are listed.
+pub fn reconcile(tracks: Vec<Track>, scrobbles: &[Scrobble]) -> Vec<PlayCount> {
+    let fixer = TagcacheFixer::new(tracks);
+    let mut logged = vec![0; fixer.tracks.len()];
+    for i in scrobbles.iter().filter_map(|scrobble| fixer.find(scrobble)) {
+        logged[i] += 1;
+    }
+    let mut counts: Vec<PlayCount> = fixer
+        .tracks
+        .into_iter()
+        .zip(logged)
+        .map(|(track, logged)| PlayCount { track, logged })
+        .filter(|count| count.track.playcount > 0 || count.logged > 0)
+        .collect();
+    counts.sort_by_key(|count| std::cmp::Reverse(count.lost()));
+    counts
+}
+
+#[test]
+fn read_tagcache() {
+    // A tag file holding `strings`, little-endian, and where each one starts.
+    let tag_file = |strings: &[&str]| {
+        let mut file = vec![0x0f, b'H', b'C', b'T', 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut offsets = Vec::new();
+        for (id, string) in strings.iter().enumerate() {
+            offsets.push(file.len() as u32);
+            let mut data = string.as_bytes().to_vec();
+            data.resize((data.len() + 4) / 4 * 4, 0);
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend((id as u32).to_le_bytes());
+            file.extend(data);
+        }
+        (file, offsets)
+    };
+    let (artists, artist) = tag_file(&["Low", "Bedhead"]);
+    let (albums, album) = tag_file(&["Drums and Guns", "Beheaded"]);
+    let (titles, title) = tag_file(&["Breaker", "Lepidoptera", "Belarus"]);
+    let (album_artists, album_artist) = tag_file(&["<Untagged>"]);
+    // Rockbox 3.15 has 22 tags per entry, then the flags.
+    let entries = [
+        (artist[0], album[0], title[0], 3, 0),
+        (artist[1], album[1], title[1], 0, 0),
+        (artist[0], album[0], title[2], 1, FLAG_DELETED),
+    ];
+    let mut index = vec![0x0f, b'H', b'C', b'T'];
+    index.extend((entries.len() as u32 * 23 * 4).to_le_bytes());
+    index.extend((entries.len() as u32).to_le_bytes());
+    index.resize(INDEX_HEADER, 0);
+    for (artist, album, title, playcount, flags) in entries {
+        let mut seeks = [0u32; 23];
+        seeks[Tag::Artist as usize] = artist;
+        seeks[Tag::Album as usize] = album;
+        seeks[Tag::Title as usize] = title;
+        seeks[Tag::AlbumArtist as usize] = album_artist[0];
+        seeks[PLAYCOUNT] = playcount;
+        seeks[22] = flags;
+        index.extend(seeks.iter().flat_map(|seek| seek.to_le_bytes()));
+    }
+    let files = HashMap::from([
+        (Tag::Artist, artists),
+        (Tag::Album, albums),
+        (Tag::Title, titles),
+        (Tag::AlbumArtist, album_artists),
+    ]);
+    let tracks = parse(&index, &files).unwrap();
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(
+        (tracks[1].artist.as_str(), tracks[1].title.as_str()),
+        ("Bedhead", "Lepidoptera")
+    );
+    assert_eq!(tracks[0].album_artist, "");
+    assert!(parse(b"not a database", &files).is_err());
+    assert_eq!(
+        parse(&index[..index.len() - 4], &files),
+        Err("database_idx.tcd: 3 entries of 92 bytes run past the end".to_string())
+    );
+
+    assert_eq!((tracks[0].playcount, tracks[1].playcount), (3, 0));
+
+    let fixer = TagcacheFixer::new(tracks.clone());
+    let scrobble = Scrobble::new("LOW\t\tbreaker \t5\t187\tL\t1699413807\t").unwrap();
+    let counts = reconcile(tracks, std::slice::from_ref(&scrobble));
+    assert_eq!(counts.len(), 1);
+    assert_eq!((counts[0].logged, counts[0].lost()), (1, 2));
+    let fixed = fixer.fix(vec![scrobble]).unwrap();
+    assert_eq!(
+        fixed[0].to_string(),
+        "Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t"
+    );
+}
@@ -0,0 +1,154 @@
+//! Output templates, for writing each scrobble as a line like `{artist} - {track} ({date})`.
+//!
+//! A template is text with fields in braces, replaced by the record's values: `artist`, `album`,
+//! `track`, `album_artist`, `position`, `duration` (seconds), `length` (`m:ss`), `rating` (`L` or
+//! `S`), `track_id`, `timestamp` (epoch seconds), and the local `date` (`YYYY-MM-DD`) and `time`
+//! (`HH:MM`). `date` and `time` take a strftime format after a colon instead, like
+//! `{date:%B %Y}`. `{{` and `}}` write a brace.
+
+use chrono::format::{Item, StrftimeItems};
+
+use crate::Scrobble;
+
+/// A part of a template.
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Artist,
+    Album,
+    Track,
+    AlbumArtist,
+    Position,
+    Duration,
+    Length,
+    Rating,
+    TrackId,
+    Timestamp,
+    /// The local time, in a strftime format.
+    Time(String),
+}
+
+impl Part {
+    fn field(name: &str, format: Option<&str>) -> Result<Part, String> {
+        let part = match name {
+            "artist" => Part::Artist,
+            "album" => Part::Album,
+            "track" => Part::Track,
+            "album_artist" => Part::AlbumArtist,
+            "position" => Part::Position,
+            "duration" => Part::Duration,
+            "length" => Part::Length,
+            "rating" => Part::Rating,
+            "track_id" => Part::TrackId,
+            "timestamp" => Part::Timestamp,
+            "date" => Part::Time(format.unwrap_or("%Y-%m-%d").to_string()),
+            "time" => Part::Time(format.unwrap_or("%H:%M").to_string()),
+            other => Err(format!("unknown template field: {other}"))?,
+        };
+        match (&part, format) {
+            (Part::Time(format), _)
+                if StrftimeItems::new(format).any(|item| item == Item::Error) =>
+            {
+                Err(format!("bad date format in template: {format}"))?
+            }
+            (Part::Time(_), _) => {}
+            (_, Some(_)) => Err(format!("only date and time take a format, not {name}"))?,
+            _ => {}
+        }
+        Ok(part)
+    }
+}
+
+/// A parsed template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or("unclosed { in template")?;
+                    let (name, format) = match rest[..end].split_once(':') {
+                        Some((name, format)) => (name, Some(format)),
+                        None => (&rest[..end], None),
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::field(name.trim(), format)?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => Err("unmatched } in template; write }} for a brace")?,
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    /// The template filled in with a scrobble's fields, without a line ending.
+    pub fn render(&self, scrobble: &Scrobble) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => line.push_str(text),
+                Part::Artist => line.push_str(&scrobble.artist),
+                Part::Album => line.push_str(&scrobble.album),
+                Part::Track => line.push_str(&scrobble.track),
+                Part::AlbumArtist => line.push_str(scrobble.album_artist().unwrap_or_default()),
+                Part::Position => line.push_str(
+                    &scrobble
+                        .track_position
+                        .map_or(String::new(), |p| p.to_string()),
+                ),
+                Part::Duration => line.push_str(&scrobble.song_duration.to_string()),
+                Part::Length => line.push_str(&format!(
+                    "{}:{:02}",
+                    scrobble.song_duration / 60,
+                    scrobble.song_duration % 60
+                )),
+                Part::Rating => line.push_str(&scrobble.rating.to_string()),
+                Part::TrackId => line.push_str(scrobble.track_id.as_deref().unwrap_or_default()),
+                Part::Timestamp => line.push_str(&scrobble.timestamp.timestamp().to_string()),
+                Part::Time(format) => line.push_str(&scrobble.timestamp.format(format).to_string()),
+            }
+        }
+        line
+    }
+}
+
+#[test]
+fn render_templates() {
+    let scrobble = Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t").unwrap();
+    let template: Template = "{artist} - {track} [{length}] {{{rating}}}"
+        .parse()
+        .unwrap();
+    assert_eq!(template.render(&scrobble), "Low - Breaker [3:07] {L}");
+    let dated: Template = "{date:%Y}/{position}: {timestamp}".parse().unwrap();
+    assert_eq!(dated.render(&scrobble), "2023/5: 1699413807");
+    assert!("{artist".parse::<Template>().is_err());
+    assert!("{genre}".parse::<Template>().is_err());
+    assert!("{artist:%Y}".parse::<Template>().is_err());
+    assert!("a } b".parse::<Template>().is_err());
+}
@@ -0,0 +1,153 @@
+//! Charts of scrobbles per day, which make the stretches a clock reset moved stand out.
+//!
+//! Each series gets a panel of daily bars, and the panels share one date axis and one scale, so
+//! a log drawn as logged above its corrected self shows plays moving years along.
+
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, NaiveDate};
+
+use crate::Scrobble;
+
+const WIDTH: f64 = 960.0;
+/// Room for the count labels left of the bars.
+const LEFT: f64 = 48.0;
+const RIGHT: f64 = 16.0;
+/// Room for a panel's title above its bars.
+const TOP: f64 = 28.0;
+const BARS: f64 = 110.0;
+/// Room for the date labels below the bars.
+const BOTTOM: f64 = 24.0;
+const PANEL: f64 = TOP + BARS + BOTTOM;
+
+/// Scrobbles per local calendar day.
+pub fn daily_counts(scrobbles: &[Scrobble]) -> BTreeMap<NaiveDate, usize> {
+    let mut days = BTreeMap::new();
+    for scrobble in scrobbles {
+        *days.entry(scrobble.timestamp.date_naive()).or_default() += 1;
+    }
+    days
+}
+
+/// Escape text for SVG and HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A chart of scrobbles per day in each series (a title and its scrobbles), one panel below
+/// another, as an SVG document.
+pub fn svg(series: &[(&str, &[Scrobble])]) -> String {
+    let counts: Vec<BTreeMap<NaiveDate, usize>> = series
+        .iter()
+        .map(|(_, scrobbles)| daily_counts(scrobbles))
+        .collect();
+    let days = || counts.iter().flat_map(|days| days.keys().copied());
+    let most = counts
+        .iter()
+        .flat_map(|days| days.values().copied())
+        .max()
+        .unwrap_or(0);
+    let height = PANEL * series.len() as f64;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{height}\" \
+         viewBox=\"0 0 {WIDTH} {height}\" font-family=\"sans-serif\" font-size=\"11\">\n"
+    );
+    let (Some(first), Some(last)) = (days().min(), days().max()) else {
+        for (index, (title, _)) in series.iter().enumerate() {
+            let y = PANEL * index as f64 + TOP / 2.0;
+            let title = escape(title);
+            svg.push_str(&format!(
+                "<text x=\"{LEFT}\" y=\"{y}\">{title}: no scrobbles</text>\n"
+            ));
+        }
+        return svg + "</svg>\n";
+    };
+    let span = (last - first).num_days() as f64 + 1.0;
+    let plot = WIDTH - LEFT - RIGHT;
+    let x = |day: NaiveDate| LEFT + (day - first).num_days() as f64 / span * plot;
+    let bar = (plot / span).max(1.0);
+    for (index, ((title, _), days)) in series.iter().zip(&counts).enumerate() {
+        let top = PANEL * index as f64 + TOP;
+        let bottom = top + BARS;
+        svg.push_str(&format!(
+            "<text x=\"{LEFT}\" y=\"{:.1}\" font-size=\"13\">{}</text>\n",
+            top - 10.0,
+            escape(title)
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{most}</text>\n\
+             <text x=\"{:.1}\" y=\"{bottom:.1}\" text-anchor=\"end\">0</text>\n\
+             <line x1=\"{LEFT}\" y1=\"{bottom:.1}\" x2=\"{:.1}\" y2=\"{bottom:.1}\" stroke=\"#999\"/>\n",
+            LEFT - 6.0,
+            top + 8.0,
+            LEFT - 6.0,
+            WIDTH - RIGHT
+        ));
+        for (&day, &count) in days {
+            let height = count as f64 / most as f64 * BARS;
+            svg.push_str(&format!(
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{bar:.1}\" height=\"{height:.1}\" \
+                 fill=\"#3b6ea5\"><title>{day}: {count}</title></rect>\n",
+                x(day),
+                bottom - height
+            ));
+        }
+        // Label the ends, and each new year in between when the chart spans several.
+        let mut labels = vec![first, last];
+        if span > 2.0 * 365.0 {
+            labels.extend(
+                (first.year() + 1..=last.year())
+                    .filter_map(|year| NaiveDate::from_ymd_opt(year, 1, 1))
+                    .filter(|&day| x(day) - x(first) > 60.0 && x(last) - x(day) > 60.0),
+            );
+        }
+        for day in labels {
+            let text = match day == first || day == last {
+                true => day.to_string(),
+                false => day.year().to_string(),
+            };
+            let anchor = match day == last && day != first {
+                true => "end",
+                false => "start",
+            };
+            svg.push_str(&format!(
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{anchor}\" fill=\"#555\">{text}</text>\n",
+                x(day) + if anchor == "end" { bar } else { 0.0 },
+                bottom + 16.0
+            ));
+        }
+    }
+    svg + "</svg>\n"
+}
+
+/// The [`svg`] chart in a standalone HTML page.
+pub fn html(title: &str, series: &[(&str, &[Scrobble])]) -> String {
+    let title = escape(title);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         </head>\n<body>\n<h1>{title}</h1>\n{}</body>\n</html>\n",
+        svg(series)
+    )
+}
+
+#[test]
+fn chart_daily_counts() {
+    let scrobbles: Vec<Scrobble> = [
+        "Low\tDrums and Guns\tBreaker\t5\t187\tL\t962790469\t",
+        "Low\tDrums and Guns\tBelarus\t6\t192\tL\t962790700\t",
+        "Low\tC'mon\tTry to Sleep\t1\t230\tL\t1699413807\t",
+    ]
+    .map(|line| Scrobble::new(line).unwrap())
+    .into();
+    let counts = daily_counts(&scrobbles);
+    assert_eq!(counts.values().copied().collect::<Vec<_>>(), [2, 1]);
+    let chart = svg(&[("as logged", &scrobbles), ("<corrected>", &scrobbles[2..])]);
+    assert!(chart.starts_with("<svg ") && chart.ends_with("</svg>\n"));
+    assert_eq!(chart.matches("<rect ").count(), 3);
+    assert!(chart.contains("&lt;corrected&gt;") && chart.contains(">2010</text>"));
+    assert!(svg(&[("empty", &[])]).contains("empty: no scrobbles"));
+    assert!(html("timeline", &[("as logged", &scrobbles)]).contains("<h1>timeline</h1>"));
+}
@@ -0,0 +1,900 @@
+//! Detecting and fixing scrobbles logged while the device's clock was wrong.
+
+use std::collections::HashSet;
+
+use chrono::{
+    DateTime, Days, Duration, FixedOffset, Local, NaiveDate, NaiveTime, TimeZone, Timelike, Utc,
+};
+
+use crate::generate::Random;
+use crate::pipeline::Fixer;
+use crate::{Scrobble, SCROBBLE_CUTOFF, SCROBBLE_DAYS_OFFSET};
+
+/// How likely a shifted timestamp is to be right: the offset is only an estimate of how far the
+/// clock was reset.
+pub const SHIFT_CONFIDENCE: f64 = 0.8;
+
+/// How likely a reconstructed timestamp is to be right: it assumes the tracks were played back
+/// to back.
+pub const RECONSTRUCT_CONFIDENCE: f64 = 0.6;
+
+/// How likely a nudged timestamp is to be right: it moves by seconds, or at most one track.
+pub const NUDGE_CONFIDENCE: f64 = 0.95;
+
+/// How likely a timestamp moved to UTC from the clock's zone is to be right: the zone is the
+/// user's to give.
+pub const TIMEZONE_CONFIDENCE: f64 = 1.0;
+
+/// What to do with a scrobble older than the cutoff.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SuspiciousPolicy {
+    /// Add the fixed days offset.
+    #[default]
+    Shift,
+    /// Leave it out of the output.
+    Drop,
+    /// Leave it as logged.
+    Keep,
+    /// Rebuild the timestamps of each suspicious run from its trustworthy neighbours, playing the
+    /// tracks back to back.
+    Reconstruct,
+}
+
+impl std::str::FromStr for SuspiciousPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shift" => Ok(SuspiciousPolicy::Shift),
+            "drop" => Ok(SuspiciousPolicy::Drop),
+            "keep" => Ok(SuspiciousPolicy::Keep),
+            "reconstruct" => Ok(SuspiciousPolicy::Reconstruct),
+            other => Err(format!("unknown suspicious action: {other}")),
+        }
+    }
+}
+
+/// Days either side of a reset epoch that [`Detector::NearReset`] flags, unless told otherwise.
+pub const RESET_WINDOW_DAYS: i64 = 30;
+
+/// A way of telling that a scrobble's timestamp can't be trusted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Detector {
+    /// At or before the fixer's cutoff.
+    Cutoff,
+    /// Within some days of a date the device's clock is known to reset to.
+    NearReset { epoch: DateTime<Utc>, days: i64 },
+    /// Earlier, by more than some seconds, than the last trustworthy record before it in the log.
+    Backwards { seconds: i64 },
+    /// Exactly the Unix epoch, which is what an unset clock reads.
+    Zero,
+    /// At or after a date, for a clock that ran ahead into the future.
+    After { date: DateTime<Utc> },
+}
+
+/// A date given on the command line: seconds since the epoch, `YYYY-MM-DD`, or RFC 3339.
+fn parse_date(date: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(seconds) = date.parse::<i64>() {
+        return Utc
+            .timestamp_opt(seconds, 0)
+            .single()
+            .ok_or(format!("date out of range: {date}"));
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Ok(day.and_hms_opt(0, 0, 0).expect("midnight").and_utc());
+    }
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|e| format!("{date}: {e}"))
+}
+
+impl std::str::FromStr for Detector {
+    type Err = String;
+
+    /// `cutoff`, `near-reset:DATE[:DAYS]`, `backwards:SECONDS`, `zero`, or `after:DATE`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let number = |part: Option<&str>, what| {
+            part.ok_or(format!("{s}: missing {what}"))?
+                .parse::<i64>()
+                .map_err(|e| format!("{s}: {what}: {e}"))
+        };
+        let detector = match parts.next() {
+            Some("cutoff") => Detector::Cutoff,
+            Some("zero") => Detector::Zero,
+            Some("backwards") => Detector::Backwards {
+                seconds: number(parts.next(), "seconds")?,
+            },
+            Some("after") => {
+                let date = parts.by_ref().collect::<Vec<_>>().join(":");
+                Detector::After {
+                    date: parse_date(&date)?,
+                }
+            }
+            Some("near-reset") => {
+                // RFC 3339 dates contain colons of their own, so the window comes off the end.
+                let rest: Vec<&str> = parts.by_ref().collect();
+                let (date, days) = match rest.split_last() {
+                    Some((days, date)) if !date.is_empty() && days.parse::<i64>().is_ok() => {
+                        (date.join(":"), number(Some(days), "days")?)
+                    }
+                    _ => (rest.join(":"), RESET_WINDOW_DAYS),
+                };
+                Detector::NearReset {
+                    epoch: parse_date(&date)?,
+                    days,
+                }
+            }
+            _ => Err(format!("unknown detector: {s}"))?,
+        };
+        match parts.next() {
+            Some(_) => Err(format!("{s}: too many fields")),
+            None => Ok(detector),
+        }
+    }
+}
+
+/// Share of the trustworthy plays the learned listening hours must cover.
+pub const LISTENING_HOURS_COVERAGE: f64 = 0.95;
+
+/// The local times of day the device gets played, from `start` until `end` (past midnight if
+/// `end` is earlier).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl std::str::FromStr for Window {
+    type Err = String;
+
+    /// `HH:MM-HH:MM`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or(format!("{s}: expected HH:MM-HH:MM"))?;
+        let time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M").map_err(|e| format!("{s}: {time}: {e}"))
+        };
+        Ok(Window {
+            start: time(start)?,
+            end: time(end)?,
+        })
+    }
+}
+
+impl Window {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        match self.start <= self.end {
+            true => self.start <= time && time < self.end,
+            false => self.start <= time || time < self.end,
+        }
+    }
+
+    /// The last time at or before `at`, on the same day or the one before, that's `time` o'clock.
+    fn last(at: DateTime<Local>, time: NaiveTime) -> DateTime<Local> {
+        let day = at.date_naive();
+        [Some(day), day.checked_sub_days(Days::new(1))]
+            .into_iter()
+            .flatten()
+            .filter_map(|day| Local.from_local_datetime(&day.and_time(time)).earliest())
+            .find(|candidate| *candidate <= at)
+            .unwrap_or(at)
+    }
+
+    /// The first time at or after `at` that's `time` o'clock.
+    fn next(at: DateTime<Local>, time: NaiveTime) -> DateTime<Local> {
+        let day = at.date_naive();
+        [Some(day), day.checked_add_days(Days::new(1))]
+            .into_iter()
+            .flatten()
+            .filter_map(|day| Local.from_local_datetime(&day.and_time(time)).earliest())
+            .find(|candidate| *candidate >= at)
+            .unwrap_or(at)
+    }
+
+    /// `at` if a play can start then, or else the latest start before it that finishes a play of
+    /// `duration` by the end of a window, or `None` if that's out of the range of dates.
+    pub fn latest_start(&self, at: DateTime<Local>, duration: Duration) -> Option<DateTime<Local>> {
+        match self.contains(at.time()) {
+            true => Some(at),
+            false => Window::last(at, self.end).checked_sub_signed(duration),
+        }
+    }
+
+    /// `at` if a play can start then, or else the start of the next window.
+    pub fn earliest_start(&self, at: DateTime<Local>) -> DateTime<Local> {
+        match self.contains(at.time()) {
+            true => at,
+            false => Window::next(at, self.start),
+        }
+    }
+
+    /// The fewest whole hours, wrapping past midnight, holding [`LISTENING_HOURS_COVERAGE`] of
+    /// the plays, or `None` if there are no plays or they need the whole day.
+    pub fn learn<'a>(plays: impl IntoIterator<Item = &'a Scrobble>) -> Option<Window> {
+        let mut hours = [0usize; 24];
+        for play in plays {
+            hours[play.timestamp.hour() as usize] += 1;
+        }
+        let total: usize = hours.iter().sum();
+        let needed = (total as f64 * LISTENING_HOURS_COVERAGE).ceil() as usize;
+        let (start, length) = (1..24)
+            .flat_map(|length| (0..24).map(move |start| (start, length)))
+            .find(|&(start, length)| {
+                (start..start + length)
+                    .map(|hour| hours[hour % 24])
+                    .sum::<usize>()
+                    >= needed
+            })
+            .filter(|_| total > 0)?;
+        let hour = |hour: usize| NaiveTime::from_hms_opt((hour % 24) as u32, 0, 0).expect("hour");
+        Some(Window {
+            start: hour(start),
+            end: hour(start + length),
+        })
+    }
+}
+
+/// When reconstructed plays may start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListeningHours {
+    Between(Window),
+    /// The hours most trustworthy plays in the same log started in.
+    Learned,
+}
+
+impl std::str::FromStr for ListeningHours {
+    type Err = String;
+
+    /// `HH:MM-HH:MM`, or `auto` to learn them.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ListeningHours::Learned),
+            window => window.parse().map(ListeningHours::Between),
+        }
+    }
+}
+
+/// Extra seconds put before each reconstructed play, so a stretch of them isn't spaced exactly
+/// by track lengths, as a server might collapse. Drawn from a seeded generator, so the same log
+/// and seed always give the same timestamps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Jitter {
+    pub min: u32,
+    pub max: u32,
+    pub seed: u64,
+}
+
+impl Jitter {
+    /// The longest jitter allowed, to keep plays near where they were reconstructed.
+    pub const LIMIT: u32 = 30;
+}
+
+impl std::str::FromStr for Jitter {
+    type Err = String;
+
+    /// `MIN..MAX` or just `MAX`, in seconds with an optional `s`, at most [`Jitter::LIMIT`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let seconds = |bound: &str| -> Result<u32, String> {
+            let bound = bound.trim();
+            bound
+                .strip_suffix('s')
+                .unwrap_or(bound)
+                .parse()
+                .map_err(|e| format!("jitter {s}: {e}"))
+        };
+        let (min, max) = match s.split_once("..") {
+            Some((min, max)) => (seconds(min)?, seconds(max)?),
+            None => (0, seconds(s)?),
+        };
+        if min > max || max > Jitter::LIMIT {
+            Err(format!(
+                "jitter {s}: expected 0..{}s at most",
+                Jitter::LIMIT
+            ))?;
+        }
+        Ok(Jitter { min, max, seed: 1 })
+    }
+}
+
+/// A signed length of time, negative when a clock ran ahead.
+pub type SignedDuration = Duration;
+
+/// How far a clock that showed `wrong` when the real time was `correct` was off: add the result
+/// to its timestamps to fix them.
+pub fn offset_between<A: TimeZone, B: TimeZone>(
+    wrong: DateTime<A>,
+    correct: DateTime<B>,
+) -> SignedDuration {
+    // Any two representable times are well within the range of a duration.
+    correct.with_timezone(&Utc) - wrong.with_timezone(&Utc)
+}
+
+/// Move a scrobble by exactly `offset`, recording the shift in its provenance.
+///
+/// Unlike [`TimestampFixer`]'s offset in days, this doesn't keep the local time of day across
+/// daylight saving changes, so an offset found with [`offset_between`] undoes the same error.
+pub fn apply_offset(mut scrobble: Scrobble, offset: SignedDuration) -> Result<Scrobble, String> {
+    let timestamp = scrobble
+        .timestamp
+        .checked_add_signed(offset)
+        .ok_or(format!(
+            "{} moved by {}s is out of range",
+            scrobble.timestamp,
+            offset.num_seconds()
+        ))?;
+    scrobble.correct(timestamp, "shift", SHIFT_CONFIDENCE);
+    Ok(scrobble)
+}
+
+/// Applies a [`SuspiciousPolicy`] to every scrobble one of its [`Detector`]s flags.
+#[derive(Debug, Clone)]
+pub struct TimestampFixer {
+    pub cutoff: DateTime<FixedOffset>,
+    /// Days to move suspicious scrobbles by; negative to pull back those from a clock that ran
+    /// ahead.
+    pub offset: i64,
+    pub policy: SuspiciousPolicy,
+    /// A scrobble is suspicious if any of these flag it.
+    pub detectors: Vec<Detector>,
+    /// With [`SuspiciousPolicy::Reconstruct`], start plays only in these hours, skipping the
+    /// rest of the day.
+    pub listening_hours: Option<ListeningHours>,
+    /// With [`SuspiciousPolicy::Reconstruct`], leave random gaps between reconstructed plays.
+    pub jitter: Option<Jitter>,
+}
+
+impl Default for TimestampFixer {
+    fn default() -> Self {
+        TimestampFixer {
+            cutoff: DateTime::parse_from_rfc3339(SCROBBLE_CUTOFF).expect("valid cutoff"),
+            offset: SCROBBLE_DAYS_OFFSET as i64,
+            policy: SuspiciousPolicy::default(),
+            detectors: vec![Detector::Cutoff],
+            listening_hours: None,
+            jitter: None,
+        }
+    }
+}
+
+impl TimestampFixer {
+    /// Add the offset to a scrobble if it's suspicious on its own (detectors comparing it with
+    /// the records around it can't flag it).
+    pub fn shift(&self, scrobble: Scrobble) -> Result<Scrobble, String> {
+        match self.suspicious(std::slice::from_ref(&scrobble))[0] {
+            true => self.apply_offset(scrobble),
+            false => Ok(scrobble),
+        }
+    }
+
+    fn apply_offset(&self, mut scrobble: Scrobble) -> Result<Scrobble, String> {
+        let days = Days::new(self.offset.unsigned_abs());
+        let timestamp = match self.offset < 0 {
+            true => scrobble.timestamp.checked_sub_days(days),
+            false => scrobble.timestamp.checked_add_days(days),
+        }
+        .ok_or("failed to apply offset")?;
+        scrobble.correct(timestamp, "shift", SHIFT_CONFIDENCE);
+        Ok(scrobble)
+    }
+
+    /// Pair each scrobble with whether it's suspicious.
+    fn flagged(&self, scrobbles: Vec<Scrobble>) -> impl Iterator<Item = (Scrobble, bool)> {
+        let suspicious = self.suspicious(&scrobbles);
+        scrobbles.into_iter().zip(suspicious)
+    }
+
+    /// Which scrobbles, in log order, any detector flags.
+    pub fn suspicious(&self, scrobbles: &[Scrobble]) -> Vec<bool> {
+        let mut trusted: Option<i64> = None;
+        scrobbles
+            .iter()
+            .map(|scrobble| {
+                let timestamp = scrobble.timestamp.timestamp();
+                let flagged = self.detectors.iter().any(|detector| match detector {
+                    Detector::Cutoff => scrobble.timestamp <= self.cutoff,
+                    Detector::NearReset { epoch, days } => {
+                        (timestamp - epoch.timestamp()).abs() <= days * 24 * 60 * 60
+                    }
+                    Detector::Backwards { seconds } => {
+                        trusted.is_some_and(|trusted| timestamp < trusted - seconds)
+                    }
+                    Detector::Zero => timestamp == 0,
+                    Detector::After { date } => scrobble.timestamp >= *date,
+                });
+                if !flagged {
+                    trusted = Some(timestamp);
+                }
+                flagged
+            })
+            .collect()
+    }
+
+    /// Why the fixer leaves each scrobble's timestamp alone, in log order: what every detector
+    /// found trustworthy about it, or that the policy keeps suspicious ones. `None` for those it
+    /// changes (or drops).
+    pub fn explain(&self, scrobbles: &[Scrobble]) -> Vec<Option<String>> {
+        let mut trusted: Option<DateTime<Local>> = None;
+        let suspicious = self.suspicious(scrobbles);
+        scrobbles
+            .iter()
+            .zip(suspicious)
+            .map(|(scrobble, suspicious)| {
+                if suspicious {
+                    return match self.policy {
+                        SuspiciousPolicy::Keep => {
+                            Some("suspicious, but the policy keeps it as logged".to_string())
+                        }
+                        _ => None,
+                    };
+                }
+                let reasons: Vec<String> = self
+                    .detectors
+                    .iter()
+                    .map(|detector| match detector {
+                        Detector::Cutoff => format!("after the cutoff {}", self.cutoff),
+                        Detector::NearReset { epoch, days } => {
+                            format!("more than {days} days from {}", epoch.date_naive())
+                        }
+                        Detector::Backwards { seconds } => match trusted {
+                            Some(trusted) => format!(
+                                "not more than {seconds}s before the last trustworthy record, at {trusted}"
+                            ),
+                            None => "the first trustworthy record".to_string(),
+                        },
+                        Detector::Zero => "not at the epoch".to_string(),
+                        Detector::After { date } => format!("before {date}"),
+                    })
+                    .collect();
+                trusted = Some(scrobble.timestamp);
+                Some(format!("trustworthy: {}", reasons.join("; ")))
+            })
+            .collect()
+    }
+
+    /// Rebuild each run of suspicious scrobbles so it ends where the next trustworthy one starts,
+    /// or, for a run at the end of the log, starts where the previous one finished.
+    fn reconstruct(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        let suspicious = self.suspicious(&scrobbles);
+        let window = match self.listening_hours {
+            None => None,
+            Some(ListeningHours::Between(window)) => Some(window),
+            Some(ListeningHours::Learned) => Window::learn(
+                scrobbles
+                    .iter()
+                    .zip(&suspicious)
+                    .filter(|(_, &flagged)| !flagged)
+                    .map(|(scrobble, _)| scrobble),
+            ),
+        };
+        let mut random = self.jitter.map(|jitter| (jitter, Random::new(jitter.seed)));
+        let mut gap = || {
+            random
+                .as_mut()
+                .map_or(Duration::zero(), |(jitter, random)| {
+                    let spread = (jitter.max - jitter.min) as usize + 1;
+                    Duration::seconds((jitter.min as usize + random.below(spread)) as i64)
+                })
+        };
+        let out_of_range = |from: DateTime<Local>| {
+            format!("timestamps reconstructed from {from} are out of the range of dates")
+        };
+        let mut start = 0;
+        while start < scrobbles.len() {
+            if !suspicious[start] {
+                start += 1;
+                continue;
+            }
+            let end = suspicious[start..]
+                .iter()
+                .position(|&flagged| !flagged)
+                .map_or(scrobbles.len(), |length| start + length);
+            if end < scrobbles.len() {
+                let from = scrobbles[end].timestamp;
+                let mut timestamp = from;
+                for scrobble in scrobbles[start..end].iter_mut().rev() {
+                    let duration = Duration::seconds(scrobble.song_duration.into());
+                    timestamp = timestamp
+                        .checked_sub_signed(duration + gap())
+                        .ok_or_else(|| out_of_range(from))?;
+                    if let Some(window) = window {
+                        timestamp = window
+                            .latest_start(timestamp, duration)
+                            .ok_or_else(|| out_of_range(from))?;
+                    }
+                    scrobble.correct(timestamp, "reconstruct", RECONSTRUCT_CONFIDENCE);
+                }
+            } else if start > 0 {
+                let previous = &scrobbles[start - 1];
+                let from = previous.timestamp;
+                let mut timestamp = from
+                    .checked_add_signed(Duration::seconds(previous.song_duration.into()))
+                    .ok_or_else(|| out_of_range(from))?;
+                for scrobble in scrobbles[start..end].iter_mut() {
+                    timestamp = timestamp
+                        .checked_add_signed(gap())
+                        .ok_or_else(|| out_of_range(from))?;
+                    if let Some(window) = window {
+                        timestamp = window.earliest_start(timestamp);
+                    }
+                    scrobble.correct(timestamp, "reconstruct", RECONSTRUCT_CONFIDENCE);
+                    timestamp = timestamp
+                        .checked_add_signed(Duration::seconds(scrobble.song_duration.into()))
+                        .ok_or_else(|| out_of_range(from))?;
+                }
+            } else {
+                Err("no trustworthy scrobble to reconstruct timestamps from")?;
+            }
+            start = end;
+        }
+        Ok(scrobbles)
+    }
+}
+
+impl Fixer for TimestampFixer {
+    fn fix(&self, scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        match self.policy {
+            SuspiciousPolicy::Shift => self
+                .flagged(scrobbles)
+                .map(|(scrobble, suspicious)| match suspicious {
+                    true => self.apply_offset(scrobble),
+                    false => Ok(scrobble),
+                })
+                .collect(),
+            SuspiciousPolicy::Drop => Ok(self
+                .flagged(scrobbles)
+                .filter(|&(_, suspicious)| !suspicious)
+                .map(|(scrobble, _)| scrobble)
+                .collect()),
+            SuspiciousPolicy::Keep => Ok(scrobbles),
+            SuspiciousPolicy::Reconstruct => self.reconstruct(scrobbles),
+        }
+    }
+}
+
+/// How far to move a scrobble whose timestamp is already taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nudge {
+    /// To when the scrobble before it finished playing.
+    Duration,
+    /// One second at a time.
+    Second,
+}
+
+impl std::str::FromStr for Nudge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "duration" => Ok(Nudge::Duration),
+            "second" => Ok(Nudge::Second),
+            other => Err(format!("unknown nudge: {other}")),
+        }
+    }
+}
+
+/// A UTC offset like `+05:30`, `-08:00`, `+1` or `UTC`.
+pub fn parse_utc_offset(s: &str) -> Result<FixedOffset, String> {
+    let invalid = || format!("not a UTC offset: {s}");
+    if s.eq_ignore_ascii_case("utc") || s == "Z" {
+        return Ok(FixedOffset::east_opt(0).expect("UTC is a valid offset"));
+    }
+    let (sign, rest) = match s.split_at_checked(1).ok_or_else(invalid)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => Err(invalid())?,
+    };
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let (Ok(hours), Ok(minutes)) = (hours.parse::<i32>(), minutes.parse::<i32>()) else {
+        return Err(invalid());
+    };
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        Err(invalid())?;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+/// Moves every scrobble from the time on a clock `offset` from UTC, as a log with `#TZ/UNKNOWN`
+/// records it, to the real time.
+#[derive(Debug, Clone)]
+pub struct ClockZoneFixer {
+    pub offset: FixedOffset,
+}
+
+impl Fixer for ClockZoneFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        let offset = Duration::seconds(self.offset.local_minus_utc().into());
+        for scrobble in &mut scrobbles {
+            let timestamp = scrobble
+                .timestamp
+                .checked_sub_signed(offset)
+                .ok_or(format!(
+                    "{} moved to UTC is out of the range of dates",
+                    scrobble.timestamp
+                ))?;
+            scrobble.correct(timestamp, "timezone", TIMEZONE_CONFIDENCE);
+        }
+        Ok(scrobbles)
+    }
+}
+
+/// Moves scrobbles forward until no two share a timestamp, since Last.fm rejects the second one.
+#[derive(Debug, Clone)]
+pub struct CollisionFixer {
+    pub nudge: Nudge,
+}
+
+impl Fixer for CollisionFixer {
+    fn fix(&self, mut scrobbles: Vec<Scrobble>) -> Result<Vec<Scrobble>, String> {
+        let mut taken = HashSet::new();
+        for index in 0..scrobbles.len() {
+            let at = scrobbles[index].timestamp;
+            let out_of_range = || format!("{at} nudged is out of the range of dates");
+            let mut timestamp = at;
+            if taken.contains(&timestamp) && self.nudge == Nudge::Duration && index > 0 {
+                let previous = &scrobbles[index - 1];
+                let finished = previous
+                    .timestamp
+                    .checked_add_signed(Duration::seconds(previous.song_duration.into()))
+                    .ok_or_else(out_of_range)?;
+                timestamp = timestamp.max(finished);
+            }
+            while taken.contains(&timestamp) {
+                timestamp = timestamp
+                    .checked_add_signed(Duration::seconds(1))
+                    .ok_or_else(out_of_range)?;
+            }
+            taken.insert(timestamp);
+            scrobbles[index].correct(timestamp, "nudge", NUDGE_CONFIDENCE);
+        }
+        Ok(scrobbles)
+    }
+}
+
+#[test]
+fn nudge_collisions() {
+    let scrobbles = || {
+        [
+            "A\tB\tOne\t1\t100\tL\t1699413807\t",
+            "A\tB\tTwo\t2\t200\tL\t1699413807\t",
+            "A\tB\tThree\t3\t300\tL\t1699413807\t",
+        ]
+        .map(|line| Scrobble::new(line).unwrap())
+        .into()
+    };
+    let timestamps = |nudge| {
+        let fixed = CollisionFixer { nudge }.fix(scrobbles()).unwrap();
+        fixed
+            .iter()
+            .map(|s| s.timestamp.timestamp() - 1699413807)
+            .collect::<Vec<i64>>()
+    };
+    assert_eq!(timestamps(Nudge::Duration), [0, 100, 300]);
+    assert_eq!(timestamps(Nudge::Second), [0, 1, 2]);
+
+    let last = || {
+        let mut scrobble = Scrobble::new("A\tB\tOne\t1\t100\tL\t1699413807\t").unwrap();
+        scrobble.timestamp = DateTime::<Utc>::MAX_UTC.with_timezone(&Local);
+        scrobble
+    };
+    for nudge in [Nudge::Duration, Nudge::Second] {
+        assert!(CollisionFixer { nudge }.fix(vec![last(), last()]).is_err());
+    }
+}
+
+#[test]
+fn reconstruct_back_to_back() {
+    let fixer = TimestampFixer {
+        policy: SuspiciousPolicy::Reconstruct,
+        ..TimestampFixer::default()
+    };
+    let scrobbles = [
+        "A\tB\tOne\t1\t100\tL\t962790469\t",
+        "A\tB\tTwo\t2\t200\tL\t962790469\t",
+        "A\tB\tThree\t3\t300\tL\t1699413807\t",
+    ]
+    .map(|line| Scrobble::new(line).unwrap());
+    let fixed = fixer.fix(scrobbles.into()).unwrap();
+    let timestamps: Vec<i64> = fixed.iter().map(|s| s.timestamp.timestamp()).collect();
+    assert_eq!(timestamps, [1699413507, 1699413607, 1699413807]);
+    let provenance = fixed[0].provenance.as_ref().unwrap();
+    assert_eq!(provenance.original_timestamp.timestamp(), 962790469);
+    assert_eq!(provenance.rule, "reconstruct");
+    assert!(fixed[2].provenance.is_none());
+
+    let mut scrobbles = [
+        "A\tB\tOne\t1\t100\tL\t1699413807\t",
+        "A\tB\tTwo\t2\t200\tL\t962790469\t",
+    ]
+    .map(|line| Scrobble::new(line).unwrap());
+    scrobbles[0].timestamp = DateTime::<Utc>::MAX_UTC.with_timezone(&Local);
+    let error = fixer.fix(scrobbles.into()).unwrap_err();
+    assert!(error.ends_with("are out of the range of dates"), "{error}");
+}
+
+#[test]
+fn jitter_reconstructed_plays() {
+    let jitter: Jitter = "5..30s".parse().unwrap();
+    assert_eq!(
+        ("12".parse(), "31".parse::<Jitter>().is_err()),
+        (
+            Ok(Jitter {
+                min: 0,
+                max: 12,
+                seed: 1
+            }),
+            true
+        )
+    );
+    let fixer = |seed| TimestampFixer {
+        policy: SuspiciousPolicy::Reconstruct,
+        jitter: Some(Jitter { seed, ..jitter }),
+        ..TimestampFixer::default()
+    };
+    let mut lines: Vec<String> = (0..20)
+        .map(|track| format!("A\tB\tT{track}\t{track}\t100\tL\t962790469\t"))
+        .collect();
+    lines.push("A\tB\tAnchor\t1\t100\tL\t1699413807\t".to_string());
+    let timestamps = |seed| -> Vec<i64> {
+        let scrobbles = lines.iter().map(|line| Scrobble::new(line).unwrap());
+        let fixed = fixer(seed).fix(scrobbles.collect()).unwrap();
+        fixed.iter().map(|s| s.timestamp.timestamp()).collect()
+    };
+    let first = timestamps(7);
+    assert_eq!(first, timestamps(7));
+    assert_ne!(first, timestamps(8));
+    assert!(first
+        .windows(2)
+        .all(|pair| (100 + 5..=100 + 30).contains(&(pair[1] - pair[0]))));
+}
+
+#[test]
+fn combine_detectors() {
+    let fixer = TimestampFixer {
+        policy: SuspiciousPolicy::Drop,
+        detectors: ["zero", "near-reset:2000-07-05:2", "backwards:3600"]
+            .map(|spec| spec.parse().unwrap())
+            .into(),
+        ..TimestampFixer::default()
+    };
+    let scrobbles = [
+        "A\tB\tOne\t1\t100\tL\t1699413807\t",
+        "A\tB\tZero\t2\t100\tL\t0\t",
+        "A\tB\tReset\t3\t100\tL\t962790469\t",
+        "A\tB\tBehind\t4\t100\tL\t1690000000\t",
+        "A\tB\tClose\t5\t100\tL\t1699413000\t",
+    ]
+    .map(|line| Scrobble::new(line).unwrap());
+    let kept: Vec<String> = fixer
+        .fix(scrobbles.into())
+        .unwrap()
+        .into_iter()
+        .map(|s| s.track)
+        .collect();
+    assert_eq!(kept, ["One", "Close"]);
+}
+
+#[test]
+fn offset_from_anchor() {
+    let wrong = Utc.timestamp_opt(962790469, 0).unwrap();
+    let correct = DateTime::parse_from_rfc3339("2023-11-08T03:30:07+00:00").unwrap();
+    let offset = offset_between(wrong, correct);
+    assert_eq!(offset.num_seconds(), 1699414207 - 962790469);
+    assert_eq!(offset_between(correct, wrong), -offset);
+    let scrobble = Scrobble::new("A\tB\tOne\t1\t100\tL\t962790469\t").unwrap();
+    let fixed = apply_offset(scrobble.clone(), offset).unwrap();
+    assert_eq!(fixed.timestamp.timestamp(), 1699414207);
+    assert_eq!(fixed.provenance.unwrap().rule, "shift");
+    assert!(apply_offset(scrobble, Duration::days(200_000_000)).is_err());
+}
+
+#[test]
+fn reconstruct_in_listening_hours() {
+    let window: Window = "07:00-23:00".parse().unwrap();
+    let fixer = TimestampFixer {
+        policy: SuspiciousPolicy::Reconstruct,
+        listening_hours: Some(ListeningHours::Between(window)),
+        ..TimestampFixer::default()
+    };
+    let mut lines: Vec<String> = (0..30)
+        .map(|track| format!("A\tB\tT{track}\t{track}\t3600\tL\t962790469\t"))
+        .collect();
+    lines.push("A\tB\tAnchor\t1\t100\tL\t1699413807\t".to_string());
+    let scrobbles = lines.iter().map(|line| Scrobble::new(line).unwrap());
+    let fixed = fixer.fix(scrobbles.collect()).unwrap();
+    assert!(fixed[..30]
+        .iter()
+        .all(|scrobble| window.contains(scrobble.timestamp.time())));
+    assert!(fixed
+        .windows(2)
+        .all(|pair| { pair[0].timestamp + Duration::seconds(3600) <= pair[1].timestamp }));
+    // Thirty hours of music, with at least one night skipped.
+    assert!(fixed[0].timestamp <= fixed[30].timestamp - Duration::hours(38));
+
+    let at = |hour| {
+        let time = fixed[30]
+            .timestamp
+            .date_naive()
+            .and_hms_opt(hour, 30, 0)
+            .unwrap();
+        let timestamp = Local.from_local_datetime(&time).unwrap().timestamp();
+        Scrobble::new(&format!("A\tB\tT\t1\t100\tL\t{timestamp}\t")).unwrap()
+    };
+    let plays: Vec<Scrobble> = [22, 23, 23, 0, 1].map(at).into();
+    let learned = Window::learn(&plays).unwrap();
+    assert_eq!(learned, "22:00-02:00".parse().unwrap());
+    assert!(learned.contains(NaiveTime::from_hms_opt(0, 10, 0).unwrap()));
+    assert_eq!(Window::learn(&[]), None);
+}
+
+#[test]
+fn explain_unchanged_timestamps() {
+    let scrobbles = [
+        "A\tB\tOne\t1\t100\tL\t1699413807\t",
+        "A\tB\tTwo\t2\t100\tL\t962790469\t",
+        "A\tB\tThree\t3\t100\tL\t1699413000\t",
+    ]
+    .map(|line| Scrobble::new(line).unwrap());
+    let mut fixer = TimestampFixer {
+        detectors: vec![Detector::Cutoff, Detector::Backwards { seconds: 3600 }],
+        ..TimestampFixer::default()
+    };
+    let explained = fixer.explain(&scrobbles);
+    assert_eq!(explained[1], None);
+    assert!(explained[0]
+        .as_deref()
+        .unwrap()
+        .ends_with("; the first trustworthy record"));
+    assert!(explained[2]
+        .as_deref()
+        .unwrap()
+        .contains("not more than 3600s before the last trustworthy record"));
+    fixer.policy = SuspiciousPolicy::Keep;
+    assert_eq!(
+        fixer.explain(&scrobbles)[1].as_deref(),
+        Some("suspicious, but the policy keeps it as logged")
+    );
+}
+
+#[test]
+fn pull_back_future_scrobbles() {
+    let fixer = TimestampFixer {
+        offset: -(SCROBBLE_DAYS_OFFSET as i64),
+        detectors: vec!["after:2030-01-01".parse().unwrap()],
+        ..TimestampFixer::default()
+    };
+    let ahead = 1699413807 + SCROBBLE_DAYS_OFFSET as i64 * 24 * 60 * 60;
+    let scrobbles = [
+        "A\tB\tOne\t1\t100\tL\t1699413000\t".to_string(),
+        format!("A\tB\tTwo\t2\t100\tL\t{ahead}\t"),
+    ]
+    .map(|line| Scrobble::new(&line).unwrap());
+    let fixed = fixer.fix(scrobbles.into()).unwrap();
+    let timestamps: Vec<i64> = fixed.iter().map(|s| s.timestamp.timestamp()).collect();
+    assert_eq!(timestamps, [1699413000, 1699413807]);
+    assert!("after:soon".parse::<Detector>().is_err());
+}
+
+#[test]
+fn move_clock_zone_to_utc() {
+    assert_eq!(parse_utc_offset("+05:30").unwrap().local_minus_utc(), 19800);
+    assert_eq!(parse_utc_offset("-8").unwrap().local_minus_utc(), -28800);
+    assert_eq!(parse_utc_offset("UTC").unwrap().local_minus_utc(), 0);
+    assert!(parse_utc_offset("5").is_err() && parse_utc_offset("+05:75").is_err());
+    assert!(parse_utc_offset("+24").is_err() && parse_utc_offset("+999999999").is_err());
+    let scrobble = Scrobble::new("Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\t").unwrap();
+    let fixer = ClockZoneFixer {
+        offset: parse_utc_offset("+01:00").unwrap(),
+    };
+    let fixed = fixer.fix(vec![scrobble]).unwrap();
+    assert_eq!(fixed[0].timestamp.timestamp(), 1699413807 - 3600);
+    assert_eq!(fixed[0].provenance.as_ref().unwrap().rule, "timezone");
+
+    let mut scrobble = fixed[0].clone();
+    scrobble.timestamp = DateTime::<Utc>::MAX_UTC.with_timezone(&Local);
+    let fixer = ClockZoneFixer {
+        offset: parse_utc_offset("-05:00").unwrap(),
+    };
+    assert!(fixer.fix(vec![scrobble]).is_err());
+}
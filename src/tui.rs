@@ -0,0 +1,355 @@
+//! The `review` screen: original records on the left, corrected ones on the right.
+//!
+//! Drawn with plain ANSI escapes on `/dev/tty`, which `stty` puts in raw mode, so stdout stays free
+//! for the reviewed log.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::Command;
+
+use scrobble_fix::review::{self, Row};
+use scrobble_fix::table::{display_width, truncate};
+use scrobble_fix::Scrobble;
+
+use crate::error::Error;
+
+const HELP: &str = "j/k move  tab next change  a accept  r reject  space toggle  A/R all  \
+                    / search  n/N next/previous match  w write  q quit";
+
+/// Between the panes.
+const DIVIDER: &str = " │ ";
+
+/// A key press, as far as the review cares.
+#[derive(Debug, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Tab,
+    Enter,
+    Backspace,
+    Escape,
+    /// Ctrl-C, or the terminal hanging up.
+    Interrupt,
+    Char(char),
+}
+
+/// Decode the keys in one read from the terminal.
+fn keys(input: &[u8]) -> Vec<Key> {
+    let text = String::from_utf8_lossy(input);
+    let mut keys = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' if chars.peek() == Some(&'[') => {
+                chars.next();
+                // Parameters, then a final letter or `~`.
+                let mut sequence = String::new();
+                for c in chars.by_ref() {
+                    sequence.push(c);
+                    if c.is_ascii_alphabetic() || c == '~' {
+                        break;
+                    }
+                }
+                match sequence.as_str() {
+                    "A" => Key::Up,
+                    "B" => Key::Down,
+                    "5~" => Key::PageUp,
+                    "6~" => Key::PageDown,
+                    "H" | "1~" | "7~" => Key::Home,
+                    "F" | "4~" | "8~" => Key::End,
+                    _ => continue,
+                }
+            }
+            '\x1b' => Key::Escape,
+            '\t' => Key::Tab,
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            // Ctrl-C, which raw mode delivers instead of a signal.
+            '\x03' => Key::Interrupt,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+/// Run `stty` on the controlling terminal.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(File::open("/dev/tty")?)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("stty {} failed", args.join(" "))));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The terminal in raw mode on the alternate screen, put back as it was when dropped.
+struct Terminal {
+    tty: File,
+    saved: String,
+}
+
+impl Terminal {
+    fn open() -> Result<Self, Error> {
+        let tty = File::options()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(|e| Error::Usage(format!("review needs a terminal: {e}")))?;
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        let mut terminal = Terminal { tty, saved };
+        write!(terminal.tty, "\x1b[?1049h\x1b[?25l")?;
+        Ok(terminal)
+    }
+
+    /// Rows and columns, falling back to 24x80.
+    fn size(&self) -> (usize, usize) {
+        let size = stty(&["size"]).unwrap_or_default();
+        let mut numbers = size.split_whitespace().map(str::parse);
+        match (numbers.next(), numbers.next()) {
+            (Some(Ok(rows)), Some(Ok(columns))) => (rows, columns),
+            _ => (24, 80),
+        }
+    }
+
+    fn read_keys(&mut self) -> io::Result<Vec<Key>> {
+        let mut buffer = [0; 64];
+        match self.tty.read(&mut buffer)? {
+            // The terminal hung up, and every read from now on would be empty.
+            0 => Ok(vec![Key::Interrupt]),
+            read => Ok(keys(&buffer[..read])),
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = write!(self.tty, "\x1b[?25h\x1b[?1049l");
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Pad or cut `text` to exactly `width` columns.
+fn fit(text: &str, width: usize) -> String {
+    let text = truncate(text, width);
+    let padding = width.saturating_sub(display_width(&text));
+    format!("{text}{}", " ".repeat(padding))
+}
+
+/// One side of a row.
+fn describe(scrobble: Option<&Scrobble>, missing: &str) -> String {
+    match scrobble {
+        Some(scrobble) => format!(
+            "{}  {} – {}",
+            scrobble.timestamp.format("%Y-%m-%d %H:%M"),
+            scrobble.artist,
+            scrobble.track
+        ),
+        None => missing.to_string(),
+    }
+}
+
+/// Where the review is.
+struct Review {
+    rows: Vec<Row>,
+    cursor: usize,
+    top: usize,
+    query: String,
+    /// The search being typed, if any.
+    search: Option<String>,
+    message: String,
+}
+
+impl Review {
+    fn changes(&self) -> usize {
+        self.rows.iter().filter(|row| row.changed()).count()
+    }
+
+    fn rejected(&self) -> usize {
+        self.rows
+            .iter()
+            .filter(|row| row.changed() && !row.accepted)
+            .count()
+    }
+
+    /// Move to the next (or previous) row after the cursor matching `wanted`, wrapping around.
+    fn seek(&mut self, forward: bool, wanted: impl Fn(&Row) -> bool) -> bool {
+        let count = self.rows.len();
+        let found = (1..=count)
+            .map(|step| match forward {
+                true => (self.cursor + step) % count,
+                false => (self.cursor + count - step) % count,
+            })
+            .find(|&index| wanted(&self.rows[index]));
+        if let Some(index) = found {
+            self.cursor = index;
+        }
+        found.is_some()
+    }
+
+    fn find(&mut self, forward: bool) {
+        if self.query.is_empty() {
+            return;
+        }
+        let query = self.query.clone();
+        if !self.seek(forward, |row| row.matches(&query)) {
+            self.message = format!("no match for {query}");
+        }
+    }
+
+    fn set(&mut self, accepted: bool) {
+        if let Some(row) = self.rows.get_mut(self.cursor) {
+            row.accepted = accepted;
+        }
+    }
+
+    fn draw(&mut self, terminal: &mut Terminal) -> io::Result<()> {
+        let (height, width) = terminal.size();
+        let body = height.saturating_sub(2).max(1);
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + body {
+            self.top = self.cursor + 1 - body;
+        }
+        let pane = width.saturating_sub(display_width(DIVIDER) + 2) / 2;
+        let mut screen = String::from("\x1b[H");
+        let heading = format!(
+            "  {}{DIVIDER}{}",
+            fit("original", pane),
+            fit(
+                &format!(
+                    "corrected ({} changes, {} rejected)",
+                    self.changes(),
+                    self.rejected()
+                ),
+                pane
+            )
+        );
+        screen.push_str(&format!("\x1b[1m{}\x1b[0m\x1b[K\r\n", fit(&heading, width)));
+        for line in 0..body {
+            let index = self.top + line;
+            let Some(row) = self.rows.get(index) else {
+                screen.push_str("\x1b[K\r\n");
+                continue;
+            };
+            let marker = match (row.changed(), row.accepted) {
+                (false, _) => ' ',
+                (true, true) => '+',
+                (true, false) => 'x',
+            };
+            // Dim whichever side won't be written.
+            let dim = |kept: bool| match row.changed() && !kept {
+                true => "\x1b[2m",
+                false => "",
+            };
+            let original = fit(&describe(row.original.as_ref(), "(added)"), pane);
+            let corrected = fit(&describe(row.corrected.as_ref(), "(dropped)"), pane);
+            let selected = match index == self.cursor {
+                true => "\x1b[7m",
+                false => "",
+            };
+            screen.push_str(&format!(
+                "{selected}{marker} {}{original}\x1b[22m{DIVIDER}{}{corrected}\x1b[0m\x1b[K\r\n",
+                dim(!row.accepted),
+                dim(row.accepted),
+            ));
+        }
+        let status = match &self.search {
+            Some(search) => format!("/{search}"),
+            None if !self.message.is_empty() => self.message.clone(),
+            None => HELP.to_string(),
+        };
+        screen.push_str(&format!("{}\x1b[K", truncate(&status, width)));
+        terminal.tty.write_all(screen.as_bytes())?;
+        terminal.tty.flush()
+    }
+
+    /// Act on a key, returning whether to write (`Some(true)`) or quit (`Some(false)`).
+    fn press(&mut self, key: Key, page: usize) -> Option<bool> {
+        if key == Key::Interrupt {
+            return Some(false);
+        }
+        if let Some(search) = &mut self.search {
+            match key {
+                Key::Char(c) => search.push(c),
+                Key::Backspace => {
+                    search.pop();
+                }
+                Key::Enter => {
+                    self.query = self.search.take().unwrap_or_default();
+                    self.find(true);
+                }
+                Key::Escape => self.search = None,
+                _ => {}
+            }
+            return None;
+        }
+        self.message.clear();
+        let last = self.rows.len().saturating_sub(1);
+        match key {
+            Key::Up | Key::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.cursor = (self.cursor + 1).min(last),
+            Key::PageUp => self.cursor = self.cursor.saturating_sub(page),
+            Key::PageDown => self.cursor = (self.cursor + page).min(last),
+            Key::Home | Key::Char('g') => self.cursor = 0,
+            Key::End | Key::Char('G') => self.cursor = last,
+            Key::Tab if !self.seek(true, Row::changed) => {
+                self.message = "nothing was changed".to_string();
+            }
+            Key::Char('a') => self.set(true),
+            Key::Char('r') => self.set(false),
+            Key::Char(' ') => {
+                let accepted = self.rows.get(self.cursor).is_some_and(|row| row.accepted);
+                self.set(!accepted);
+            }
+            Key::Char('A') => self.rows.iter_mut().for_each(|row| row.accepted = true),
+            Key::Char('R') => self.rows.iter_mut().for_each(|row| row.accepted = false),
+            Key::Char('/') => self.search = Some(String::new()),
+            Key::Char('n') => self.find(true),
+            Key::Char('N') => self.find(false),
+            Key::Char('w') => return Some(true),
+            Key::Char('q') => return Some(false),
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Let the user accept or reject each correction, returning the records to write, or `None` if
+/// they quit without writing.
+pub fn review(
+    original: Vec<Scrobble>,
+    corrected: Vec<Scrobble>,
+) -> Result<Option<Vec<Scrobble>>, Error> {
+    let mut review = Review {
+        rows: review::rows(original, corrected),
+        cursor: 0,
+        top: 0,
+        query: String::new(),
+        search: None,
+        message: String::new(),
+    };
+    if review.rows.is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+    review.cursor = review.rows.iter().position(Row::changed).unwrap_or(0);
+    let mut terminal = Terminal::open()?;
+    loop {
+        review.draw(&mut terminal)?;
+        let page = terminal.size().0.saturating_sub(2).max(1);
+        for key in terminal.read_keys()? {
+            match review.press(key, page) {
+                Some(true) => return Ok(Some(review::result(review.rows))),
+                Some(false) => return Ok(None),
+                None => {}
+            }
+        }
+    }
+}
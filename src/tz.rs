@@ -0,0 +1,171 @@
+//! The timezone local times are in, settled before anything reads the clock.
+//!
+//! chrono finds the local timezone from `$TZ` or `/etc/localtime`, and falls back to UTC without
+//! a word when neither can be read, as in containers without tzdata. [`init`] resolves the zone
+//! itself, from `--tz` or those same places, and sets `$TZ` to it, so a zone that can't be found
+//! is an error, and a run gives the same local times on every machine it's told the zone on.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use scrobble_fix::timestamps::parse_utc_offset;
+
+use crate::error::Error;
+use crate::log;
+
+/// Where tzdata is installed, as glibc and chrono look for it.
+const ZONEINFO_DIRECTORIES: [&str; 4] = [
+    "/usr/share/zoneinfo",
+    "/share/zoneinfo",
+    "/etc/zoneinfo",
+    "/usr/share/lib/zoneinfo",
+];
+
+/// The system's timezone, when `$TZ` isn't set.
+const LOCALTIME: &str = "/etc/localtime";
+
+/// Whether `path` is a compiled timezone file, which starts `TZif`.
+fn is_tzif(path: &Path) -> bool {
+    fs::read(path).is_ok_and(|data| data.starts_with(b"TZif"))
+}
+
+/// The directories to look for a zone in: `$TZDIR`, then the usual places.
+fn zoneinfo() -> Vec<PathBuf> {
+    let tzdir = std::env::var_os("TZDIR").map(PathBuf::from);
+    tzdir
+        .into_iter()
+        .chain(ZONEINFO_DIRECTORIES.iter().map(PathBuf::from))
+        .collect()
+}
+
+/// Whether `zone` is a POSIX TZ string, like `CET-1CEST,M3.5.0,M10.5.0/3`: a name of three
+/// letters or more (or anything in angle brackets), then an offset.
+fn is_posix(zone: &str) -> bool {
+    let rest = match zone.strip_prefix('<') {
+        Some(quoted) => quoted.split_once('>').map_or("", |(_, rest)| rest),
+        None => {
+            let letters = zone.chars().take_while(char::is_ascii_alphabetic).count();
+            match letters >= 3 {
+                true => &zone[letters..],
+                false => "",
+            }
+        }
+    };
+    let offset = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+    offset.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// `$TZ` for a zone: `UTC`, a UTC offset like `+02:00`, a zone name like `Europe/Berlin` (or the
+/// path of its file), or a POSIX TZ string.
+fn resolve(zone: &str) -> Result<String, String> {
+    resolve_in(zone, &zoneinfo())
+}
+
+/// [`resolve`], looking for zone names in `directories`.
+fn resolve_in(zone: &str, directories: &[PathBuf]) -> Result<String, String> {
+    if zone.eq_ignore_ascii_case("utc") || zone == "Z" {
+        return Ok("UTC0".to_string());
+    }
+    if zone.starts_with(['+', '-']) {
+        let seconds = parse_utc_offset(zone)?.local_minus_utc();
+        let (sign, west) = match seconds < 0 {
+            true => ('-', '+'),
+            false => ('+', '-'),
+        };
+        let (hours, minutes) = (seconds.abs() / 3600, seconds.abs() / 60 % 60);
+        // POSIX counts offsets west of UTC as positive.
+        return Ok(format!(
+            "<{sign}{hours:02}{minutes:02}>{west}{hours:02}:{minutes:02}"
+        ));
+    }
+    let name = zone.strip_prefix(':').unwrap_or(zone);
+    let name = match name {
+        "localtime" => LOCALTIME,
+        _ => name,
+    };
+    if Path::new(name).is_absolute() {
+        return match is_tzif(Path::new(name)) {
+            true => Ok(format!(":{name}")),
+            false => Err(format!("{name} isn't a readable timezone file")),
+        };
+    }
+    let found = directories
+        .iter()
+        .map(|directory| directory.join(name))
+        .find(|path| !name.contains("..") && is_tzif(path));
+    match found {
+        Some(path) => Ok(format!(":{}", path.display())),
+        None if is_posix(name) => Ok(name.to_string()),
+        None if directories.iter().any(|directory| directory.is_dir()) => Err(format!(
+            "unknown timezone {name}: it isn't in {}; give a name like Europe/Berlin, an offset \
+             like +02:00, or UTC",
+            directories
+                .iter()
+                .filter(|directory| directory.is_dir())
+                .map(|directory| directory.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        None => Err(format!(
+            "unknown timezone {name}: there's no timezone database on this system (install \
+             tzdata, or set $TZDIR to one); give an offset like +02:00, or UTC"
+        )),
+    }
+}
+
+/// Resolve the local timezone, from `zone` (`--tz`), `$TZ`, or `/etc/localtime`, and set `$TZ`
+/// to it for the rest of the run. Without any, local times are in UTC, with a warning.
+pub fn init(zone: Option<&str>) -> Result<(), Error> {
+    let tz = match zone {
+        Some(zone) => resolve(zone).map_err(|e| Error::Usage(format!("--tz: {e}")))?,
+        None => match std::env::var("TZ") {
+            Ok(zone) if !zone.is_empty() => resolve(&zone).map_err(|e| {
+                Error::Usage(format!("$TZ: {e}; unset it, or choose a zone with --tz"))
+            })?,
+            _ if is_tzif(Path::new(LOCALTIME)) => return Ok(()),
+            _ => {
+                log::warn(format_args!(
+                    "the local timezone is unknown ({LOCALTIME} can't be read, and $TZ \
+                     isn't set), so local times are in UTC; choose a zone with --tz"
+                ));
+                "UTC0".to_string()
+            }
+        },
+    };
+    std::env::set_var("TZ", tz);
+    Ok(())
+}
+
+#[test]
+fn resolve_zones() {
+    let dir = std::env::temp_dir().join(format!("scrobble-fix-tz-{}", std::process::id()));
+    let zones = dir.join("zoneinfo");
+    fs::create_dir_all(zones.join("Europe")).unwrap();
+    fs::write(zones.join("Europe/Berlin"), b"TZif2").unwrap();
+    fs::write(dir.join("outside"), b"TZif2").unwrap();
+    let directories = [zones.clone()];
+
+    assert_eq!(resolve_in("+05:30", &[]).as_deref(), Ok("<+0530>-05:30"));
+    assert_eq!(resolve_in("-08:00", &[]).as_deref(), Ok("<-0800>+08:00"));
+    assert!(is_posix("<+0530>-05:30") && is_posix("CET-1CEST,M3.5.0,M10.5.0/3"));
+    assert_eq!(resolve_in("UTC", &[]), resolve_in("Z", &[]));
+    assert_eq!(resolve_in("utc", &[]).as_deref(), Ok("UTC0"));
+    let berlin = format!(":{}", zones.join("Europe/Berlin").display());
+    assert_eq!(resolve_in("Europe/Berlin", &directories), Ok(berlin));
+    // Names can't reach files outside the zoneinfo directory.
+    assert!(resolve_in("../outside", &directories).is_err());
+    assert_eq!(
+        resolve_in("CET-1CEST,M3.5.0,M10.5.0/3", &directories).as_deref(),
+        Ok("CET-1CEST,M3.5.0,M10.5.0/3")
+    );
+
+    assert!(!is_posix("Europe/Cairo") && !is_posix("EU1") && !is_posix("<+01"));
+    let unknown = resolve_in("Europe/Cairo", &directories).unwrap_err();
+    assert!(unknown.contains("it isn't in"), "{unknown}");
+    let unknown = resolve_in("Europe/Cairo", &[dir.join("missing")]).unwrap_err();
+    assert!(
+        unknown.contains("there's no timezone database"),
+        "{unknown}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,123 @@
+//! Replacing the running binary with the latest release, for devices nobody builds on, like a
+//! Raspberry Pi beside the dock.
+//!
+//! Signatures are checked by running `ssh-keygen -Y verify` against the key the binary was built
+//! with, from `$SCROBBLE_FIX_RELEASE_KEY` at build time, so no signature scheme needs to be
+//! linked in. A build without a key can't update itself.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use scrobble_fix::release::{self, Release, Version};
+
+use crate::error::Error;
+use crate::{files, log, net};
+
+const USER_AGENT: &str = concat!("scrobble-fix/", env!("CARGO_PKG_VERSION"));
+
+/// The key releases are signed with.
+const RELEASE_KEY: Option<&str> = option_env!("SCROBBLE_FIX_RELEASE_KEY");
+
+/// Run `ssh-keygen` to check `signature` signs `sums` with a key in the `signers` file.
+fn ssh_keygen_verify(signers: &Path, signature: &Path, sums: &[u8]) -> Result<(), Error> {
+    let mut child = Command::new("ssh-keygen")
+        .args(["-Y", "verify", "-f"])
+        .arg(signers)
+        .args(["-I", release::SIGNER, "-n", release::NAMESPACE, "-s"])
+        .arg(signature)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Usage(format!("failed to run ssh-keygen: {e}")))?;
+    child.stdin.take().expect("piped stdin").write_all(sums)?;
+    let output = child.wait_with_output()?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(Error::Parse(format!(
+            "{} isn't signed by the release key: {}",
+            release::SUMS,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+/// A new directory for the files `ssh-keygen` checks, that only this user can get into: one
+/// made by someone else before it, who could swap the allowed signers, is refused.
+fn private_dir() -> Result<PathBuf, Error> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos());
+    let dir = std::env::temp_dir().join(format!(
+        "scrobble-fix-update-{}-{nanos}",
+        std::process::id()
+    ));
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir).map_err(|e| {
+        Error::Io(io::Error::new(
+            e.kind(),
+            format!("can't make {}: {e}", dir.display()),
+        ))
+    })?;
+    Ok(dir)
+}
+
+/// Check `sums` was signed by the release key with `signature`.
+fn verify(key: &str, sums: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let dir = private_dir()?;
+    let signers = dir.join("allowed_signers");
+    let signature_path = dir.join(release::SIGNATURE);
+    let verified = files::replace(&signers, release::allowed_signers(key))
+        .and_then(|()| files::replace(&signature_path, signature))
+        .map_err(Error::from)
+        .and_then(|()| ssh_keygen_verify(&signers, &signature_path, sums));
+    let _ = std::fs::remove_dir_all(&dir);
+    verified
+}
+
+/// Install the latest release if it's newer than this binary; with `dry_run`, only say whether
+/// there is one.
+pub fn self_update(dry_run: bool) -> Result<(), Error> {
+    let current: Version = env!("CARGO_PKG_VERSION").parse().map_err(Error::Parse)?;
+    let response = net::get(&release::latest_url(), USER_AGENT).map_err(Error::Network)?;
+    let latest = Release::parse(&response).map_err(Error::Parse)?;
+    if latest.version <= current {
+        log::info(format_args!("scrobble-fix {current} is the latest release"));
+        return Ok(());
+    }
+    let name = release::asset_name(std::env::consts::ARCH, std::env::consts::OS);
+    let asset = latest.asset(&name).map_err(Error::Usage)?;
+    if dry_run {
+        log::info(format_args!(
+            "scrobble-fix {} is out (this is {current}): {}",
+            latest.version, asset.url
+        ));
+        return Ok(());
+    }
+    let key = RELEASE_KEY.ok_or(Error::Usage(
+        "this build has no release key to check updates with; build it with \
+         $SCROBBLE_FIX_RELEASE_KEY set, or install the release by hand"
+            .to_string(),
+    ))?;
+    let fetch = |name| -> Result<Vec<u8>, Error> {
+        let asset = latest.asset(name).map_err(Error::Usage)?;
+        log::info(format_args!("downloading {}", asset.url));
+        net::download(&asset.url, USER_AGENT).map_err(Error::Network)
+    };
+    let sums = fetch(release::SUMS)?;
+    verify(key, &sums, &fetch(release::SIGNATURE)?)?;
+    let binary = fetch(&name)?;
+    release::check_sum(&binary, &String::from_utf8_lossy(&sums), &name).map_err(Error::Parse)?;
+    let exe = std::env::current_exe()?;
+    // Replacing keeps the permissions, so the new binary runs as the old one did.
+    files::replace(&exe, &binary)?;
+    log::info(format_args!(
+        "updated {} from {current} to {}",
+        exe.display(),
+        latest.version
+    ));
+    Ok(())
+}
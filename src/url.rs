@@ -0,0 +1,28 @@
+//! URL building for the web APIs.
+
+/// Percent-encode everything except RFC 3986 unreserved characters.
+pub fn encode(component: &str) -> String {
+    component
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// A query string from key/value pairs.
+pub fn query<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    pairs
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+#[test]
+fn encode_components() {
+    assert_eq!(encode("AC/DC & café"), "AC%2FDC%20%26%20caf%C3%A9");
+}
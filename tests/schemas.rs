@@ -0,0 +1,50 @@
+//! What scrobble-fix writes as JSON checked against the schemas in `schemas/`, so neither can
+//! change without the other.
+
+use std::process::Command;
+
+use scrobble_fix::{json, schema};
+
+fn check(name: &str, document: &str) {
+    let schema = json::parse(schema::get(name).unwrap()).unwrap();
+    let value = json::parse(document).unwrap();
+    if let Err(e) = schema::validate(&schema, &value) {
+        panic!("{name} doesn't match its schema: {e}\n{document}");
+    }
+}
+
+#[test]
+fn outputs_match_schemas() {
+    let dir = std::env::temp_dir().join(format!("scrobble-fix-schemas-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // A 1.0 log with CRLF line endings, which fmt rewrites.
+    let log = "#AUDIOSCROBBLER/1.0\r\n#TZ/UTC\r\n#CLIENT/Rockbox\r\n\
+               Low\tDrums and Guns\tBreaker\t5\t187\tL\t1699413807\r\n";
+    std::fs::write(dir.join("scrobbler.log"), log).unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_scrobble-fix"))
+            .args(args)
+            .current_dir(&dir)
+            .env("TZ", "UTC")
+            .env("XDG_CONFIG_HOME", &dir)
+            .env("XDG_STATE_HOME", &dir)
+            .output()
+            .expect("scrobble-fix runs")
+    };
+
+    let output = run(&["fmt", "--post-hook", "cat > summary.json"]);
+    assert!(output.status.success(), "{output:?}");
+    check(
+        "summary",
+        &std::fs::read_to_string(dir.join("summary.json")).unwrap(),
+    );
+    let audit = std::fs::read_to_string(dir.join("scrobble-fix/audit.jsonl")).unwrap();
+    assert!(!audit.is_empty());
+    audit.lines().for_each(|entry| check("audit", entry));
+
+    let output = run(&["--format", "json"]);
+    let records = String::from_utf8(output.stdout).unwrap();
+    assert!(!records.is_empty());
+    records.lines().for_each(|record| check("record", record));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,124 @@
+//! Whole-output snapshots of scrobble-fix run over the logs in `tests/fixtures`, so a change to
+//! parsing, fixing or serialization can't alter what's printed unnoticed.
+//!
+//! Snapshots are kept in `tests/snapshots`. After an intended change, rewrite them with
+//! `UPDATE_SNAPSHOTS=1 cargo test --test snapshots` and review the diff.
+
+use std::path::Path;
+use std::process::Command;
+
+/// A log with nothing to fix, one with unparseable lines, one with several clock resets, and one
+/// with names from many scripts.
+const FIXTURES: [&str; 4] = ["clean", "corrupted", "resets", "unicode"];
+
+/// Run scrobble-fix in the fixtures directory, in UTC, and describe everything it printed.
+fn run(args: &[&str]) -> String {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO_BIN_EXE_scrobble-fix"))
+        .args(args)
+        .current_dir(root.join("tests/fixtures"))
+        .env("TZ", "UTC")
+        // Keep the user's default rewrite rules out of it.
+        .env("XDG_CONFIG_HOME", root.join("tests/fixtures"))
+        .env_remove("COLUMNS")
+        .output()
+        .expect("scrobble-fix runs");
+    format!(
+        "exit status: {}\n--- stdout\n{}--- stderr\n{}",
+        output.status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+/// Run the command over every fixture, comparing each output with its snapshot (or writing it,
+/// with `UPDATE_SNAPSHOTS`), and fail naming every one that differs.
+fn check(name: &str, args: &[&str]) {
+    let snapshots = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut changed = Vec::new();
+    for fixture in FIXTURES {
+        let log = format!("{fixture}.log");
+        let actual = run(&[args, &[log.as_str()]].concat());
+        let path = snapshots.join(format!("{fixture}-{name}.txt"));
+        if update {
+            std::fs::write(&path, &actual).expect("snapshot written");
+            continue;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        if actual != expected {
+            eprintln!("{}:\n{actual}", path.display());
+            changed.push(path.display().to_string());
+        }
+    }
+    assert!(
+        changed.is_empty(),
+        "output differs from the snapshots (rerun with UPDATE_SNAPSHOTS=1 if that's intended): {}",
+        changed.join(", ")
+    );
+}
+
+#[test]
+fn fix() {
+    check("fix", &[]);
+}
+
+#[test]
+fn fix_strict() {
+    check("fix-strict", &["--strict"]);
+}
+
+#[test]
+fn fix_quiet() {
+    check("fix-quiet", &["--quiet"]);
+}
+
+#[test]
+fn preserve_lines() {
+    check("preserve-lines", &["--preserve-lines"]);
+}
+
+#[test]
+fn reconstruct() {
+    check("reconstruct", &["--suspicious-action", "reconstruct"]);
+}
+
+#[test]
+fn format_table() {
+    check("table", &["--format", "table"]);
+}
+
+#[test]
+fn format_json() {
+    check("json", &["--format", "json"]);
+}
+
+#[test]
+fn format_listenbrainz() {
+    check("listenbrainz", &["--format", "listenbrainz"]);
+}
+
+#[test]
+fn analyze_days() {
+    check("analyze-days", &["analyze", "days"]);
+}
+
+#[test]
+fn analyze_artists() {
+    check("analyze-artists", &["analyze", "artists", "--fuzzy"]);
+}
+
+#[test]
+fn analyze_resets() {
+    check("analyze-resets", &["analyze", "resets"]);
+}
+
+#[test]
+fn lint() {
+    check("lint", &["lint"]);
+}
+
+#[test]
+fn doctor() {
+    check("doctor", &["doctor"]);
+}